@@ -0,0 +1,137 @@
+use std::fmt;
+
+use crate::signature::{SignatureScheme, UnkModel, UNK};
+use crate::tree::Tree;
+
+/// Difference between a sentence and the words of the tree that was printed for it.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Misalignment {
+    /// The tree doesn't have as many words as the sentence.
+    Length { sentence: usize, tree: usize },
+    /// The first word of the tree that differs from the sentence.
+    Word {
+        position: usize,
+        sentence: String,
+        tree: String,
+    },
+}
+
+impl fmt::Display for Misalignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Misalignment::Length { sentence, tree } => write!(
+                f,
+                "the sentence has {} words, but the tree has {}",
+                sentence, tree
+            ),
+            Misalignment::Word {
+                position,
+                sentence,
+                tree,
+            } => write!(
+                f,
+                "word {} is {} in the sentence, but {} in the tree",
+                position + 1,
+                sentence,
+                tree
+            ),
+        }
+    }
+}
+
+/// Checks that the words of `tree` are the words of `sentence`. Words that were replaced by
+/// unking or smoothing and not restored, i.e. `UNK` or a signature of the word under any
+/// `UnkModel`, are accepted.
+pub fn check_alignment<W: AsRef<str>, A: AsRef<str>>(
+    sentence: &[W],
+    tree: &Tree<A>,
+) -> Option<Misalignment> {
+    check_alignment_with(sentence, tree, UNK, UNK)
+}
+
+/// Like `check_alignment`, with `token` instead of `UNK` and signatures that start with `prefix`.
+pub fn check_alignment_with<W: AsRef<str>, A: AsRef<str>>(
+    sentence: &[W],
+    tree: &Tree<A>,
+    token: &str,
+    prefix: &str,
+) -> Option<Misalignment> {
+    let schemes: Vec<_> = UnkModel::ALL
+        .iter()
+        .map(|model| SignatureScheme {
+            prefix: prefix.to_string(),
+            ..SignatureScheme::from(*model)
+        })
+        .collect();
+    let leaves = tree.leaves();
+    if leaves.len() != sentence.len() {
+        return Some(Misalignment::Length {
+            sentence: sentence.len(),
+            tree: leaves.len(),
+        });
+    }
+
+    sentence
+        .iter()
+        .zip(leaves)
+        .enumerate()
+        .find(|(i, (word, leaf))| {
+            let (word, leaf) = (word.as_ref(), leaf.as_ref());
+            word != leaf
+                && leaf != token
+                && schemes
+                    .iter()
+                    .all(|scheme| leaf != scheme.signature(word, *i))
+        })
+        .map(|(position, (word, leaf))| Misalignment::Word {
+            position,
+            sentence: word.as_ref().to_string(),
+            tree: leaf.as_ref().to_string(),
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use smallstr::SmallString;
+
+    use super::*;
+    use crate::SExp;
+
+    fn tree(s: &str) -> Tree<SmallString<[u8; 8]>> {
+        Tree::try_from(SExp::from_str(s).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn yields() {
+        let sentence = ["the", "dog", "barks"];
+        assert_eq!(
+            None,
+            check_alignment(&sentence, &tree("(S (NP (DT the) (NN UNK)) (VBZ barks))"))
+        );
+        assert_eq!(
+            None,
+            check_alignment(&sentence, &tree("(NOPARSE the dog barks)"))
+        );
+        assert_eq!(
+            Some(Misalignment::Length {
+                sentence: 3,
+                tree: 2
+            }),
+            check_alignment(&sentence, &tree("(S (DT the) (NN dog))"))
+        );
+        assert_eq!(
+            "word 3 is barks in the sentence, but bark in the tree",
+            check_alignment(&sentence, &tree("(S (DT the) (NN dog) (VB bark))"))
+                .unwrap()
+                .to_string()
+        );
+        let unked = tree("(S (DT the) (NN <unk>) (VBZ SIG-L-s))");
+        assert_eq!(
+            None,
+            check_alignment_with(&sentence, &unked, "<unk>", "SIG")
+        );
+        assert!(check_alignment(&sentence, &unked).is_some());
+    }
+}
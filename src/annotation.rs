@@ -0,0 +1,65 @@
+use std::fmt::Display;
+
+use crate::sentence::Sentence;
+use crate::tree::{NodeType, Tree};
+
+impl<A: AsRef<str> + From<String>> Sentence<A> {
+    /// Splits every word at the first occurrence of `separator`. The word keeps the
+    /// part in front of the separator, the annotations behind it are returned by position.
+    /// Words that start with the separator, e.g. the token `#`, are kept as they are.
+    pub fn split_annotations(&mut self, separator: char) -> Vec<Option<String>> {
+        self.iter_mut()
+            .map(|word| {
+                let (stripped, annotation) = match word.as_ref().split_once(separator) {
+                    Some((w, a)) if !w.is_empty() => (w.to_string(), a.to_string()),
+                    _ => return None,
+                };
+                *word = stripped.into();
+                Some(annotation)
+            })
+            .collect()
+    }
+}
+
+impl<N, T: Display + From<String>> Tree<NodeType<N, T>> {
+    /// Re-attaches annotations split off with `Sentence::split_annotations` to the terminals.
+    pub fn attach_annotations(&mut self, annotations: Vec<Option<String>>, separator: char) {
+        for (leaf, annotation) in self.leaves_mut().drain(..).zip(annotations) {
+            if let (NodeType::Terminal(word), Some(annotation)) = (leaf, annotation) {
+                *word = format!("{}{}{}", word, separator, annotation).into();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn annotation_round_trip() {
+        let mut sentence = Sentence::from_str("Dogs#dog bark#bark .").unwrap();
+        let annotations = sentence.split_annotations('#');
+        assert_eq!(Sentence::from_str("Dogs bark .").unwrap(), sentence);
+        assert_eq!(
+            vec![Some("dog".to_string()), Some("bark".to_string()), None],
+            annotations
+        );
+
+        let mut tree = sentence.into_noparse();
+        tree.attach_annotations(annotations, '#');
+        assert_eq!(
+            "(NOPARSE Dogs#dog bark#bark .)".to_string(),
+            format!("{}", tree)
+        );
+
+        let mut sentence = Sentence::from_str("# 5 #x £#pound").unwrap();
+        let annotations = sentence.split_annotations('#');
+        assert_eq!(Sentence::from_str("# 5 #x £").unwrap(), sentence);
+        assert_eq!(
+            vec![None, None, None, Some("pound".to_string())],
+            annotations
+        );
+    }
+}
@@ -1,34 +1,49 @@
-use super::node::{Binarized, MarkovizedNode};
+use super::node::{Binarized, LabelEquivalence};
+use crate::error::{Error, Result};
 use crate::tree::Tree;
 
 impl<A> Tree<Binarized<A>> {
-    pub fn debinarize(mut self) -> Tree<A> {
-        if self.is_leaf() {
-            let root = match self.root {
+    pub fn debinarize(self) -> Result<Tree<A>> {
+        Ok(self
+            .debinarize_with(LabelEquivalence::Base)?
+            .map(&mut |node| match node {
                 Binarized::Bare(a) => a,
-                Binarized::Markovized(_) => panic!("Leaf node cannot be markovized!"),
-            };
+                Binarized::Markovized(node) => node.label,
+            }))
+    }
 
-            Tree {
-                root,
-                children: vec![],
+    /// Like `debinarize`, but the labels keep the annotations that are not ignored by
+    /// `equivalence`, e.g. the ancestors added by vertical markovisation with
+    /// `LabelEquivalence::Exact`. Fails for trees with markovised leaves.
+    pub fn debinarize_with(mut self, equivalence: LabelEquivalence) -> Result<Tree<Binarized<A>>> {
+        if self.is_leaf() {
+            if self.root.is_markovized() {
+                return Err(Error::Format(String::from(
+                    "a word can't have the label of a markovised node",
+                )));
             }
+
+            Ok(self)
         } else if self.children.iter().last().unwrap().root.is_markovized()
             && self.children.iter().last().unwrap().children.len() == 2
         {
             let last = self.children.pop().unwrap();
             self.children.extend(last.children);
-            self.debinarize()
+            self.debinarize_with(equivalence)
+        } else if self.children[0].root.is_markovized() && self.children[0].children.len() == 2 {
+            // Trees binarised to the left group the children on the left.
+            let first = self.children.remove(0);
+            self.children.splice(0..0, first.children);
+            self.debinarize_with(equivalence)
         } else {
-            let root = match self.root {
-                Binarized::Bare(a) => a,
-                Binarized::Markovized(MarkovizedNode { label: a, .. }) => a,
-            };
-
-            Tree {
-                root,
-                children: self.children.drain(..).map(|c| c.debinarize()).collect(),
-            }
+            Ok(Tree {
+                root: equivalence.project(self.root),
+                children: self
+                    .children
+                    .drain(..)
+                    .map(|c| c.debinarize_with(equivalence))
+                    .collect::<Result<_>>()?,
+            })
         }
     }
 }
@@ -36,38 +51,83 @@ impl<A> Tree<Binarized<A>> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::binarized::node::MarkovizedNode;
     use crate::sexp::SExp;
     use std::str::FromStr;
 
     #[test]
     fn debinarize_successful() {
-        let binarized_tree = Tree::from(
+        let binarized_tree = Tree::try_from(
             SExp::from_str("(ROOT (FRAG^<ROOT> (RB (Not)) (FRAG|<NP-TMP,.>^<ROOT> (NP-TMP^<FRAG,ROOT> (DT (this)) (NN (year))) (. (.)))))")
                 .unwrap(),
-        );
+        ).unwrap();
         let binarized_tree = binarized_tree.parse_markovized();
         assert_eq!(
             "(ROOT (FRAG (RB Not) (NP-TMP (DT this) (NN year)) (. .)))".to_string(),
-            format!("{}", binarized_tree.debinarize())
+            format!("{}", binarized_tree.debinarize().unwrap())
         );
 
-        let binarized_tree2 = Tree::from(
+        let binarized_tree2 = Tree::try_from(
             SExp::from_str(
                 "(S (A (A1 a) (A|<A2,A3> (A2 a) (A3 a))) (S|<B,C,D> (B b) (S|<C,D> (C c) (D d))))",
             )
             .unwrap(),
-        );
+        )
+        .unwrap();
         let binarized_tree2 = binarized_tree2.parse_markovized();
         assert_eq!(
             "(S (A (A1 a) (A2 a) (A3 a)) (B b) (C c) (D d))".to_string(),
-            format!("{}", binarized_tree2.debinarize())
+            format!("{}", binarized_tree2.debinarize().unwrap())
         );
 
-        let binarized_tree3 = Tree::from(SExp::from_str("(S (A a) (S|<B,C,D> (B^<S> (BB^<B,S> (BBB^<BB,B> (B1 b) (BBB|<B2,B3>^<BB,B> (B2 b) (B3 b))))) (S|<C,D> (C c) (D d))))").unwrap());
+        let binarized_tree3 = Tree::try_from(SExp::from_str("(S (A a) (S|<B,C,D> (B^<S> (BB^<B,S> (BBB^<BB,B> (B1 b) (BBB|<B2,B3>^<BB,B> (B2 b) (B3 b))))) (S|<C,D> (C c) (D d))))").unwrap()).unwrap();
         let binarized_tree3 = binarized_tree3.parse_markovized();
         assert_eq!(
             "(S (A a) (B (BB (BBB (B1 b) (B2 b) (B3 b)))) (C c) (D d))".to_string(),
-            format!("{}", binarized_tree3.debinarize())
+            format!("{}", binarized_tree3.debinarize().unwrap())
         );
+
+        let binarized_tree4 = Tree::try_from(
+            SExp::from_str("(S (NP^<S> (DT a) (NP|<NN,NN>^<S> (NN b) (NN c))) (VP^<S> (V d)))")
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            "(S (NP^<S> (DT a) (NN b) (NN c)) (VP^<S> (V d)))".to_string(),
+            format!(
+                "{}",
+                binarized_tree4
+                    .parse_markovized()
+                    .debinarize_with(LabelEquivalence::Exact)
+                    .unwrap()
+            )
+        );
+
+        // Labels and words without the syntax of markovised labels are kept.
+        let binarized_tree5 =
+            Tree::try_from(SExp::from_str("(S (PRT|ADVP a|<b>) (| c))").unwrap()).unwrap();
+        assert_eq!(
+            "(S (PRT|ADVP a|<b>) (| c))".to_string(),
+            format!(
+                "{}",
+                binarized_tree5.parse_markovized().debinarize().unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn markovized_leaves() {
+        let tree = Tree {
+            root: Binarized::Bare("S"),
+            children: vec![Tree {
+                root: Binarized::Markovized(MarkovizedNode {
+                    label: "S",
+                    children: vec!["NP"],
+                    ancestors: vec![],
+                }),
+                children: vec![],
+            }],
+        };
+        assert!(matches!(tree.debinarize(), Err(Error::Format(_))));
     }
 }
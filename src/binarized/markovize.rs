@@ -1,18 +1,127 @@
 use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead};
 use std::str::FromStr;
 
+use fxhash::FxHashMap;
 use smallstr::SmallString;
 
 use super::node::{Binarized, MarkovizedNode};
 use crate::tree::Tree;
 
+/// Direction in which the nodes introduced by binarisation branch.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// The leftmost child is split off and the remaining children are grouped to the right.
+    Right,
+    /// The rightmost child is split off and the remaining children are grouped to the left.
+    Left,
+}
+
+/// Orders of markovisation: the vertical order `v` and horizontal order `h` of the nodes of
+/// every label category that differs from the default ones.
+#[derive(Clone, Debug)]
+pub struct MarkovParams {
+    pub vertical: usize,
+    pub horizontal: usize,
+    /// Direction of the binarisation, `Direction::Right` by default.
+    pub direction: Direction,
+    labels: FxHashMap<String, (usize, usize)>,
+}
+
+impl MarkovParams {
+    pub fn uniform(vertical: usize, horizontal: usize) -> Self {
+        Self {
+            vertical,
+            horizontal,
+            direction: Direction::Right,
+            labels: FxHashMap::default(),
+        }
+    }
+
+    pub fn with_direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    pub fn insert(&mut self, label: &str, vertical: usize, horizontal: usize) {
+        self.labels
+            .insert(label.to_string(), (vertical, horizontal));
+    }
+
+    /// Reads the orders of label categories, one `LABEL V H` line per category, separated by
+    /// whitespace. Empty lines and lines starting with `#` are skipped.
+    pub fn read<R: BufRead>(mut self, reader: R) -> io::Result<Self> {
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+            match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                [] => {}
+                [label, v, h] => match (v.parse(), h.parse()) {
+                    (Ok(v), Ok(h)) => self.insert(label, v, h),
+                    _ => return Err(invalid_params(&line)),
+                },
+                _ => return Err(invalid_params(&line)),
+            }
+        }
+        Ok(self)
+    }
+
+    /// Orders of the nodes with `label`: those given for the label itself, otherwise those of
+    /// its category, i.e. the label without function tags (`NP` for `NP-SBJ`), otherwise the
+    /// default ones.
+    pub fn get(&self, label: &str) -> (usize, usize) {
+        let category = match label.split_once(['-', '=']) {
+            Some((category, _)) if !category.is_empty() => category,
+            _ => label,
+        };
+        self.labels
+            .get(label)
+            .or_else(|| self.labels.get(category))
+            .copied()
+            .unwrap_or((self.vertical, self.horizontal))
+    }
+
+    /// Highest vertical order of all nodes, i.e. the number of ancestors that have to be
+    /// passed down.
+    fn max_vertical(&self) -> usize {
+        self.labels
+            .values()
+            .map(|(v, _)| *v)
+            .fold(self.vertical, usize::max)
+    }
+}
+
+fn invalid_params(line: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("expected a label and two orders: {}", line),
+    )
+}
+
 impl Tree<SmallString<[u8; 8]>> {
     pub fn markovize(
-        mut self,
+        self,
         v: usize,
         h: usize,
         parents: &[SmallString<[u8; 8]>],
     ) -> Tree<Binarized<SmallString<[u8; 8]>>> {
+        self.markovize_with(&MarkovParams::uniform(v, h), parents)
+    }
+
+    /// Markovises the tree with the orders that `params` gives for the label of every node.
+    /// A node is annotated with as many ancestors as its own vertical order allows, and the
+    /// siblings in the labels of the nodes introduced for it are limited by its own horizontal
+    /// order. These are the siblings next to the child that is split off.
+    pub fn markovize_with(
+        mut self,
+        params: &MarkovParams,
+        parents: &[SmallString<[u8; 8]>],
+    ) -> Tree<Binarized<SmallString<[u8; 8]>>> {
+        let max_v = params.max_vertical();
+
         // is preterminal
         if self.children.iter().all(|c| c.is_leaf()) {
             Tree {
@@ -27,68 +136,135 @@ impl Tree<SmallString<[u8; 8]>> {
                     .collect(),
             }
         } else if self.children.len() <= 2 {
-            let new_root = Binarized::from_str(&self.root).unwrap();
+            // Labels that don't follow the syntax of markovised labels, e.g. `PRT|ADVP`, are
+            // kept as they are, as in `parse_markovized`.
+            let new_root = Binarized::from_str(&self.root)
+                .unwrap_or_else(|_| Binarized::Bare(self.root.clone()));
+            let (v, _) = params.get(new_root.extract_label());
 
-            let parents_augmented = augment_parents(parents, new_root.extract_label().clone(), v);
+            let parents_augmented =
+                augment_parents(parents, new_root.extract_label().clone(), max_v);
 
             Tree {
                 root: match new_root {
                     Binarized::Markovized(mut a) => {
-                        a.ancestors = Vec::from(parents);
+                        a.ancestors = own_ancestors(parents, v);
                         Binarized::Markovized(a)
                     }
                     Binarized::Bare(a) => Binarized::Markovized(MarkovizedNode {
                         label: a,
                         children: vec![],
-                        ancestors: Vec::from(parents),
+                        ancestors: own_ancestors(parents, v),
                     }),
                 },
                 children: self
                     .children
                     .drain(..)
-                    .map(|c| c.markovize(v, h, &parents_augmented))
+                    .map(|c| c.markovize_with(params, &parents_augmented))
                     .collect(),
             }
         } else {
+            let label = Binarized::from_str(&self.root)
+                .unwrap_or_else(|_| Binarized::Bare(self.root.clone()))
+                .extract_label()
+                .clone();
+            let (v, h) = params.get(&label);
+            let n = self.children.len();
+            // The child that is split off and the children grouped below a new node.
+            let (single, grouped, siblings) = match params.direction {
+                Direction::Right => (0, 1..n, 1..(1 + h).min(n)),
+                Direction::Left => (n - 1, 0..n - 1, (n - 1).saturating_sub(h)..n - 1),
+            };
             let augmented_label = MarkovizedNode {
-                label: Binarized::from_str(&self.root)
-                    .unwrap()
-                    .extract_label()
-                    .clone(),
-                children: self
-                    .children
+                label,
+                children: self.children[siblings]
                     .iter()
-                    .skip(1)
-                    .take(h)
                     .map(|c| &c.root)
                     .cloned()
                     .collect(),
                 ancestors: vec![],
             };
 
-            let parents_augmented = augment_parents(parents, augmented_label.label.clone(), v);
+            let parents_augmented = augment_parents(parents, augmented_label.label.clone(), max_v);
+
+            let single = self.children[single]
+                .clone()
+                .markovize_with(params, &parents_augmented);
+            let group = Tree {
+                // We have to convert the markovized node back into a string
+                // to make the recursion work.
+                root: format!("{}", augmented_label).into(),
+                children: self.children[grouped].to_vec(),
+            }
+            .markovize_with(params, parents);
 
             Tree {
                 root: Binarized::Markovized(MarkovizedNode {
                     label: self.root.clone(),
                     children: vec![],
-                    ancestors: Vec::from(parents),
+                    ancestors: own_ancestors(parents, v),
                 }),
-                children: vec![
-                    self.children[0].clone().markovize(v, h, &parents_augmented),
-                    Tree {
-                        // We have to convert the markovized node back into a string
-                        // to make the recursion work.
-                        root: format!("{}", augmented_label).into(),
-                        children: self.children[1..].to_vec(),
-                    }
-                    .markovize(v, h, parents),
-                ],
+                children: match params.direction {
+                    Direction::Right => vec![single, group],
+                    Direction::Left => vec![group, single],
+                },
+            }
+        }
+    }
+}
+
+/// Reason why a tree can't be used as a binarised tree.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BinaryViolation {
+    TooManyChildren {
+        label: String,
+        children: usize,
+    },
+    /// The label doesn't parse back into the same markovised node.
+    Unparsable(String),
+}
+
+impl fmt::Display for BinaryViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BinaryViolation::TooManyChildren { label, children } => {
+                write!(f, "node {} has {} children", label, children)
             }
+            BinaryViolation::Unparsable(label) => write!(f, "label {} can't be parsed", label),
         }
     }
 }
 
+/// Checks that every inner node of a binarised tree, as read back from its printed form,
+/// has at most two children and that markovised labels, those with `|<` or `^<`, parse back
+/// into the same node. Other labels may contain `|` or `^`, as in `PRT|ADVP`.
+pub fn verify_binary<A: AsRef<str>>(tree: &Tree<A>) -> Result<(), BinaryViolation> {
+    if tree.is_leaf() {
+        return Ok(());
+    }
+
+    let label = tree.root.as_ref();
+    if tree.children.len() > 2 {
+        return Err(BinaryViolation::TooManyChildren {
+            label: label.to_string(),
+            children: tree.children.len(),
+        });
+    }
+    if label.contains("|<") || label.contains("^<") {
+        match Binarized::<String>::parse(label) {
+            Ok(node) if node.to_string() == label => {}
+            _ => return Err(BinaryViolation::Unparsable(label.to_string())),
+        }
+    }
+
+    tree.children.iter().try_for_each(verify_binary)
+}
+
+/// The ancestors that a node with vertical order `v` is annotated with.
+fn own_ancestors<T: Clone>(parents: &[T], v: usize) -> Vec<T> {
+    parents.iter().take(v.saturating_sub(1)).cloned().collect()
+}
+
 fn augment_parents<T: Clone>(parents: &[T], augmenter: T, v: usize) -> Vec<T> {
     if v == 0 || v == 1 {
         vec![]
@@ -103,15 +279,17 @@ fn augment_parents<T: Clone>(parents: &[T], augmenter: T, v: usize) -> Vec<T> {
 
 #[cfg(test)]
 mod test {
+    use super::*;
     use crate::sexp::SExp;
     use crate::tree::Tree;
     use std::str::FromStr;
 
     #[test]
     fn markovization_valid() {
-        let tree = Tree::from(
+        let tree = Tree::try_from(
             SExp::from_str("(ROOT (FRAG (RB Not) (NP-TMP (DT this) (NN year)) (. .)))").unwrap(),
-        );
+        )
+        .unwrap();
 
         let markovized_tree = tree.clone().markovize(4, 999, &[]);
         assert_eq!("(ROOT (FRAG^<ROOT> (RB Not) (FRAG|<NP-TMP,.>^<ROOT> (NP-TMP^<FRAG,ROOT> (DT this) (NN year)) (. .))))".to_string(), format!("{}", markovized_tree));
@@ -138,13 +316,105 @@ mod test {
             format!("{}", markovized_tree5)
         );
 
-        let markovized_tree6 = Tree::from(
+        let markovized_tree6 = Tree::try_from(
             SExp::from_str("(S (A a) (B (BB (BBB (B1 b) (B2 b) (B3 b)))) (C c) (D d))").unwrap(),
         )
+        .unwrap()
         .markovize(1, 999, &[]);
         assert_eq!(
             "(S (A a) (S|<B,C,D> (B (BB (BBB (B1 b) (BBB|<B2,B3> (B2 b) (B3 b))))) (S|<C,D> (C c) (D d))))".to_string(),
             format!("{}", markovized_tree6)
         );
     }
+
+    #[test]
+    fn left_markovization() {
+        let tree = Tree::try_from(
+            SExp::from_str("(S (A a) (B (B1 b) (B2 b) (B3 b)) (C c) (D d))").unwrap(),
+        )
+        .unwrap();
+        let params = MarkovParams::uniform(2, 1).with_direction(Direction::Left);
+        let binarised = tree.clone().markovize_with(&params, &[]).to_string();
+        assert_eq!(
+            "(S (S|<C> (S|<B> (A a) (B^<S> (B|<B2>^<S> (B1 b) (B2 b)) (B3 b))) (C c)) (D d))",
+            binarised
+        );
+
+        let binarised = Tree::try_from(SExp::from_str(&binarised).unwrap()).unwrap();
+        assert_eq!(Ok(()), verify_binary(&binarised));
+        assert_eq!(tree, binarised.parse_markovized().debinarize().unwrap());
+    }
+
+    #[test]
+    fn per_label_markovization() {
+        let tree = Tree::try_from(
+            SExp::from_str("(ROOT (S (NP-SBJ (DT the) (JJ big) (NN dog)) (VP (VBZ barks) (ADVP (RB loudly)) (PP (IN at) (NP (PRP it))))))")
+                .unwrap(),
+        ).unwrap();
+        let mut params = MarkovParams::uniform(1, 999);
+        params.insert("VP", 3, 1);
+        params.insert("NP", 1, 0);
+        // NP-SBJ uses the orders of its category NP.
+        assert_eq!(
+            "(ROOT (S (NP-SBJ (DT the) (NP-SBJ (JJ big) (NN dog))) (VP^<S,ROOT> (VBZ barks) (VP|<ADVP>^<S,ROOT> (ADVP (RB loudly)) (PP (IN at) (NP (PRP it)))))))",
+            tree.markovize_with(&params, &[]).to_string()
+        );
+
+        let params = MarkovParams::uniform(1, 999)
+            .read(&b"# label v h\nVP 3 1\n\nNP 1 0\n"[..])
+            .unwrap();
+        assert_eq!((3, 1), params.get("VP"));
+        assert_eq!((1, 0), params.get("NP-SBJ"));
+        assert_eq!((1, 999), params.get("S"));
+        assert!(MarkovParams::uniform(1, 1).read(&b"VP 3\n"[..]).is_err());
+    }
+
+    #[test]
+    fn binary_verification() {
+        let tree = Tree::try_from(
+            SExp::from_str("(ROOT (S (NP (DT the) (NN dog)) (VP (VBZ barks)) (. .)))").unwrap(),
+        )
+        .unwrap();
+        let printed = tree.clone().markovize(2, 1, &[]).to_string();
+        let binarised = Tree::try_from(SExp::from_str(&printed).unwrap()).unwrap();
+        assert_eq!(Ok(()), verify_binary(&binarised));
+
+        assert_eq!(
+            Err(BinaryViolation::TooManyChildren {
+                label: "S".to_string(),
+                children: 3
+            }),
+            verify_binary(&tree)
+        );
+        let plain = Tree::try_from(SExp::from_str("(ROOT (PRT|ADVP (RB up)))").unwrap()).unwrap();
+        assert_eq!(Ok(()), verify_binary(&plain));
+        let unparsable = Tree::try_from(SExp::from_str("(ROOT (S|<NP (RB up)))").unwrap()).unwrap();
+        assert_eq!(
+            Err(BinaryViolation::Unparsable("S|<NP".to_string())),
+            verify_binary(&unparsable)
+        );
+    }
+
+    #[test]
+    fn labels_with_bars() {
+        let tree = Tree::try_from(
+            SExp::from_str("(ROOT (S (PRT|ADVP (RB up) (RB here)) (VP (VB go))))").unwrap(),
+        )
+        .unwrap();
+        let binarised = tree.clone().markovize(1, 999, &[]);
+        assert_eq!(
+            "(ROOT (S (PRT|ADVP (RB up) (RB here)) (VP (VB go))))",
+            binarised.to_string()
+        );
+        assert_eq!(tree, binarised.parse_markovized().debinarize().unwrap());
+
+        // Nodes with more children are binarised as well, without panicking.
+        let tree =
+            Tree::try_from(SExp::from_str("(ROOT (PRT|ADVP (RB up) (RB here) (RB now)))").unwrap())
+                .unwrap();
+        assert_eq!(
+            "(ROOT (PRT|ADVP (RB up) (PRT|ADVP|<RB,RB> (RB here) (RB now))))",
+            tree.markovize(1, 999, &[]).to_string()
+        );
+    }
 }
@@ -1,3 +1,20 @@
+//! Node labels of binarised trees, following this syntax:
+//!
+//! ```text
+//! node  = label [ "|" list ] [ "^" list ]
+//! list  = "<" [ entry { "," entry } ] ">"
+//! entry = "," | name
+//! ```
+//!
+//! A `label` is any non-empty text without `|` and `^`, a `name` additionally
+//! excludes `<`, `>` and `,`. The `|` list holds the siblings folded into the node
+//! by horizontal markovisation, the `^` list the ancestors added by vertical markovisation.
+//! Labels without either list are bare nodes.
+//!
+//! Since `,` is the usual label of commas in treebanks, a list entry may consist of a
+//! single comma: `NP|<,,NN>` has the siblings `,` and `NN`, `NP|<NN,,>` has `NN` and `,`.
+//! Other labels containing commas can't be used inside of lists.
+
 use std::borrow::Borrow;
 use std::fmt;
 use std::str::FromStr;
@@ -47,12 +64,85 @@ impl<A> Binarized<A> {
     }
 }
 
-impl FromStr for Binarized<SmallString<[u8; 8]>> {
-    type Err = NError<String>;
+/// Decides which markovisation annotations are taken into account when comparing labels,
+/// e.g. so that `NP|<DT,NN>^<S>`, `NP^<S>` and `NP` can be treated as the same label
+/// when comparing trees that were not debinarised.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LabelEquivalence {
+    /// Labels are equal if they are identical, including all annotations.
+    Exact,
+    /// Ancestors from vertical markovisation are ignored.
+    IgnoreAncestors,
+    /// Only the labels without any annotations are compared (see `extract_label`).
+    Base,
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+impl LabelEquivalence {
+    /// The node without the annotations that are ignored. Equal nodes have the same projection.
+    pub fn project<A>(&self, node: Binarized<A>) -> Binarized<A> {
+        match (*self, node) {
+            (LabelEquivalence::Exact, node) => node,
+            (_, Binarized::Bare(a)) => Binarized::Bare(a),
+            (LabelEquivalence::IgnoreAncestors, Binarized::Markovized(node))
+                if !node.children.is_empty() =>
+            {
+                Binarized::Markovized(MarkovizedNode {
+                    ancestors: vec![],
+                    ..node
+                })
+            }
+            (_, Binarized::Markovized(node)) => Binarized::Bare(node.label),
+        }
+    }
+
+    pub fn equal<A: PartialEq>(&self, a: &Binarized<A>, b: &Binarized<A>) -> bool {
+        match self {
+            LabelEquivalence::Exact => a == b,
+            LabelEquivalence::IgnoreAncestors => {
+                a.extract_label() == b.extract_label() && a.siblings() == b.siblings()
+            }
+            LabelEquivalence::Base => a.extract_label() == b.extract_label(),
+        }
+    }
+}
+
+impl<A> Binarized<A> {
+    /// Siblings folded into this node by horizontal markovisation.
+    pub fn siblings(&self) -> &[A] {
+        match self {
+            Binarized::Markovized(MarkovizedNode { children, .. }) => children,
+            Binarized::Bare(_) => &[],
+        }
+    }
+}
+
+impl<A: Clone> Binarized<A> {
+    /// Returns the label this node would have with horizontal markovisation `h` and vertical
+    /// markovisation `v`, if it has been markovised with larger parameters. Used to map the
+    /// labels of a fine grammar onto those of a coarse grammar.
+    pub fn coarsen(&self, h: usize, v: usize) -> Self {
+        match self {
+            Binarized::Markovized(node) => Binarized::Markovized(MarkovizedNode {
+                label: node.label.clone(),
+                children: node.children.iter().take(h).cloned().collect(),
+                ancestors: node
+                    .ancestors
+                    .iter()
+                    .take(v.saturating_sub(1))
+                    .cloned()
+                    .collect(),
+            }),
+            Binarized::Bare(a) => Binarized::Bare(a.clone()),
+        }
+    }
+}
+
+impl<A: for<'a> From<&'a str>> Binarized<A> {
+    /// Parses a node label following the syntax described in the module documentation.
+    /// Surrounding whitespace is ignored.
+    pub fn parse(s: &str) -> Result<Self, NError<String>> {
         match all_consuming(parse_binarized_node)(s.trim()).finish() {
-            Ok((_, sentence)) => Ok(sentence),
+            Ok((_, node)) => Ok(node),
             Err(NError { input, code }) => Err(NError {
                 input: input.to_string(),
                 code,
@@ -61,50 +151,56 @@ impl FromStr for Binarized<SmallString<[u8; 8]>> {
     }
 }
 
-fn parse_binarized_node(input: &str) -> IResult<&str, Binarized<SmallString<[u8; 8]>>> {
+impl FromStr for Binarized<SmallString<[u8; 8]>> {
+    type Err = NError<String>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+fn parse_binarized_node<A: for<'a> From<&'a str>>(input: &str) -> IResult<&str, Binarized<A>> {
     alt((parse_bare_node, parse_markovized_node))(input)
 }
 
-fn parse_bare_node(input: &str) -> IResult<&str, Binarized<SmallString<[u8; 8]>>> {
-    all_consuming(is_not("|^"))(input).map(|(i, o)| (i, Binarized::Bare(SmallString::from(o))))
+fn parse_bare_node<A: for<'a> From<&'a str>>(input: &str) -> IResult<&str, Binarized<A>> {
+    all_consuming(is_not("|^"))(input).map(|(i, o)| (i, Binarized::Bare(A::from(o))))
 }
 
-fn parse_markovized_node(input: &str) -> IResult<&str, Binarized<SmallString<[u8; 8]>>> {
+fn parse_markovized_node<A: for<'a> From<&'a str>>(input: &str) -> IResult<&str, Binarized<A>> {
     tuple((
         is_not("|^"),
-        opt(preceded(
-            tag("|"),
-            delimited(
-                tag("<"),
-                separated_list0(tag(","), alt((tag(","), is_not("|^<>,")))),
-                tag(">"),
-            ),
-        )),
-        opt(preceded(
-            tag("^"),
-            delimited(
-                tag("<"),
-                separated_list0(tag(","), alt((tag(","), is_not("|^<>,")))),
-                tag(">"),
-            ),
-        )),
+        opt(|i| parse_label_list("|", i)),
+        opt(|i| parse_label_list("^", i)),
     ))(input)
     .map(|(i, (label, children, ancestors))| {
         (
             i,
             Binarized::Markovized(MarkovizedNode {
-                label: SmallString::from(label),
-                children: children
-                    .map(|mut v| v.drain(..).map(SmallString::from).collect())
-                    .unwrap_or_default(),
-                ancestors: ancestors
-                    .map(|mut v| v.drain(..).map(SmallString::from).collect())
-                    .unwrap_or_default(),
+                label: A::from(label),
+                children: children.unwrap_or_default(),
+                ancestors: ancestors.unwrap_or_default(),
             }),
         )
     })
 }
 
+/// Parses a list of labels like `|<a,b>`, where a label may be a single comma.
+fn parse_label_list<'a, A: for<'b> From<&'b str>>(
+    marker: &'static str,
+    input: &'a str,
+) -> IResult<&'a str, Vec<A>> {
+    preceded(
+        tag(marker),
+        delimited(
+            tag("<"),
+            separated_list0(tag(","), alt((tag(","), is_not("|^<>,")))),
+            tag(">"),
+        ),
+    )(input)
+    .map(|(i, mut labels)| (i, labels.drain(..).map(A::from).collect()))
+}
+
 impl<A: fmt::Display> fmt::Display for MarkovizedNode<A> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut result = write!(f, "{}", self.label);
@@ -149,15 +245,19 @@ impl<A: fmt::Display> fmt::Display for Binarized<A> {
 }
 
 impl<A: Borrow<str>> Tree<A> {
+    /// Reads the markovisation markers of the labels. Words and labels that don't follow the
+    /// syntax of markovised labels, e.g. `PRT|ADVP`, are kept as they are.
     pub fn parse_markovized(mut self) -> Tree<Binarized<SmallString<[u8; 8]>>> {
+        let label = self.root.borrow();
         if self.is_leaf() {
             Tree {
-                root: Binarized::from_str(self.root.borrow()).unwrap(),
+                root: Binarized::Bare(SmallString::from(label)),
                 children: vec![],
             }
         } else {
             Tree {
-                root: Binarized::from_str(self.root.borrow()).unwrap(),
+                root: Binarized::from_str(label)
+                    .unwrap_or_else(|_| Binarized::Bare(SmallString::from(label))),
                 children: self
                     .children
                     .drain(..)
@@ -254,4 +354,53 @@ mod test {
             Binarized::from_str("label|<,,p>^<p,,>").unwrap()
         );
     }
+
+    #[test]
+    fn binarized_parse_round_trip() {
+        let node: Binarized<String> = Binarized::parse("NP|<,,NN>^<S>").unwrap();
+        assert_eq!(
+            Binarized::Markovized(MarkovizedNode {
+                label: "NP".to_string(),
+                children: vec![",".to_string(), "NN".to_string()],
+                ancestors: vec!["S".to_string()]
+            }),
+            node
+        );
+        assert_eq!("NP|<,,NN>^<S>".to_string(), format!("{}", node));
+
+        assert!(Binarized::<String>::parse("NP|<NN").is_err());
+    }
+
+    #[test]
+    fn label_equivalence() {
+        let full: Binarized<String> = Binarized::parse("NP|<DT,NN>^<S>").unwrap();
+        let folded: Binarized<String> = Binarized::parse("NP|<DT,NN>").unwrap();
+        let parent: Binarized<String> = Binarized::parse("NP^<S>").unwrap();
+        let bare: Binarized<String> = Binarized::parse("NP").unwrap();
+
+        assert!(LabelEquivalence::Exact.equal(&full, &full));
+        assert!(!LabelEquivalence::Exact.equal(&full, &folded));
+
+        assert!(LabelEquivalence::IgnoreAncestors.equal(&full, &folded));
+        assert!(LabelEquivalence::IgnoreAncestors.equal(&parent, &bare));
+        assert!(!LabelEquivalence::IgnoreAncestors.equal(&full, &bare));
+
+        assert!(LabelEquivalence::Base.equal(&full, &bare));
+        assert!(LabelEquivalence::Base.equal(&parent, &folded));
+
+        assert_eq!(
+            "NP|<DT,NN>",
+            LabelEquivalence::IgnoreAncestors.project(full).to_string()
+        );
+        assert_eq!(bare, LabelEquivalence::IgnoreAncestors.project(parent));
+        assert_eq!(bare, LabelEquivalence::Base.project(folded));
+    }
+
+    #[test]
+    fn coarsen_label() {
+        let node: Binarized<String> = Binarized::parse("NP|<DT,JJ,NN>^<S,VP>").unwrap();
+        assert_eq!("NP|<DT>^<S>", node.coarsen(1, 2).to_string());
+        assert_eq!("NP", node.coarsen(0, 1).to_string());
+        assert_eq!(node, node.coarsen(999, 999));
+    }
 }
@@ -0,0 +1,114 @@
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use fxhash::FxHasher;
+
+/// Distinguishes the temporary files of results that are written at the same time.
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Content-addressed cache of parse results on disk. Every grammar (together with the options
+/// that influence the results) gets its own directory, named after its hash, so results of
+/// other grammars are never used. Within it, every result is stored in a file named after the
+/// hash of the input line. The line is stored with the result to detect hash collisions, and
+/// so is whether the sentence couldn't be parsed.
+pub struct ParseCache {
+    dir: PathBuf,
+}
+
+impl ParseCache {
+    /// Opens the cache for the grammar with the given hash below `root`.
+    pub fn new(root: &Path, grammar_hash: u64) -> io::Result<Self> {
+        let dir = root.join(format!("{:016x}", grammar_hash));
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, line: &str) -> PathBuf {
+        let mut hasher = FxHasher::default();
+        hasher.write(line.as_bytes());
+        self.dir.join(format!("{:016x}", hasher.finish()))
+    }
+
+    /// Returns the cached result for the input line and whether it is a NOPARSE, if there is
+    /// one.
+    pub fn get(&self, line: &str) -> Option<(String, bool)> {
+        let content = fs::read_to_string(self.path(line)).ok()?;
+        let (cached_line, content) = content.split_once('\n')?;
+        let (noparse, result) = content.split_once('\n')?;
+        let noparse = match noparse {
+            "0" => false,
+            "1" => true,
+            _ => return None,
+        };
+        if cached_line == line {
+            Some((result.to_string(), noparse))
+        } else {
+            None
+        }
+    }
+
+    /// Stores the result for the input line. The file is written under a temporary name
+    /// first, so that concurrent or interrupted runs never see partial results.
+    pub fn insert(&self, line: &str, result: &str, noparse: bool) -> io::Result<()> {
+        let path = self.path(line);
+        let tmp_path = path.with_extension(format!(
+            "{}-{}.tmp",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(
+            &tmp_path,
+            format!("{}\n{}\n{}", line, noparse as u8, result),
+        )?;
+        fs::rename(tmp_path, path)
+    }
+}
+
+/// Hashes the contents of the given files together with `settings`,
+/// which should describe all options that influence the results.
+pub fn grammar_hash(files: &[&Path], settings: &str) -> io::Result<u64> {
+    let mut hasher = FxHasher::default();
+    for file in files {
+        let content = fs::read(file)?;
+        hasher.write_usize(content.len());
+        hasher.write(&content);
+    }
+    hasher.write(settings.as_bytes());
+    Ok(hasher.finish())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cache_roundtrip() {
+        let root = std::env::temp_dir().join(format!("pcfg_tool_cache_{}", std::process::id()));
+        let cache = ParseCache::new(&root, 42).unwrap();
+
+        assert_eq!(cache.get("the dog barks"), None);
+        cache
+            .insert("the dog barks", "(S (NP the dog) (V barks))", false)
+            .unwrap();
+        assert_eq!(
+            cache.get("the dog barks"),
+            Some(("(S (NP the dog) (V barks))".to_string(), false))
+        );
+        cache
+            .insert("dog the barks", "(NOPARSE dog the barks)", true)
+            .unwrap();
+        assert_eq!(
+            cache.get("dog the barks"),
+            Some(("(NOPARSE dog the barks)".to_string(), true))
+        );
+        assert_eq!(
+            ParseCache::new(&root, 43).unwrap().get("the dog barks"),
+            None
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,187 @@
+use std::io::{self, BufRead, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::rng::XorShift;
+
+/// Message of the read errors injected by `ChaosReader`.
+pub const INJECTED_ERROR: &str = "injected read error";
+
+/// Counts of the lines read and damaged by `ChaosReader`s.
+#[derive(Debug, Default)]
+pub struct ChaosStats {
+    pub lines: AtomicUsize,
+    pub corrupted: AtomicUsize,
+    pub failed: AtomicUsize,
+}
+
+impl ChaosStats {
+    pub const fn new() -> Self {
+        Self {
+            lines: AtomicUsize::new(0),
+            corrupted: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// Developer tool: a reader that damages a share of the lines of `inner` to exercise the
+/// error handling of the subcommands. A damaged line is either truncated, gets a stray bracket,
+/// or is preceded by a read error. It is still delivered as a line, so that the output of
+/// subcommands that report and skip invalid lines stays aligned with their input.
+pub struct ChaosReader<'a, R> {
+    inner: R,
+    rate: f64,
+    rng: XorShift,
+    stats: &'a ChaosStats,
+    line: Vec<u8>,
+    pos: usize,
+    fail: bool,
+}
+
+impl<'a, R: BufRead> ChaosReader<'a, R> {
+    /// Every line is damaged with probability `rate`.
+    pub fn new(inner: R, rate: f64, seed: u64, stats: &'a ChaosStats) -> Self {
+        Self {
+            inner,
+            rate,
+            rng: XorShift::new(seed),
+            stats,
+            line: vec![],
+            pos: 0,
+            fail: false,
+        }
+    }
+
+    fn next_line(&mut self) -> io::Result<()> {
+        self.line.clear();
+        self.pos = 0;
+        if self.inner.read_until(b'\n', &mut self.line)? == 0 {
+            return Ok(());
+        }
+        self.stats.lines.fetch_add(1, Ordering::Relaxed);
+        if self.rng.weight() > self.rate {
+            return Ok(());
+        }
+
+        let newline = self.line.last() == Some(&b'\n');
+        if newline {
+            self.line.pop();
+        }
+        match self.rng.below(3) {
+            0 => {
+                self.fail = true;
+                self.stats.failed.fetch_add(1, Ordering::Relaxed);
+            }
+            1 => {
+                // Lines are only cut at character boundaries, so that they stay valid UTF-8.
+                let text = String::from_utf8_lossy(&self.line).into_owned();
+                let boundaries: Vec<_> = text.char_indices().map(|(i, _)| i).collect();
+                let cut = match boundaries.len() {
+                    0 => 0,
+                    n => boundaries[self.rng.below(n)],
+                };
+                self.line = text.as_bytes()[..cut].to_vec();
+                self.stats.corrupted.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {
+                let text = String::from_utf8_lossy(&self.line).into_owned();
+                let boundaries: Vec<_> = text
+                    .char_indices()
+                    .map(|(i, _)| i)
+                    .chain([text.len()])
+                    .collect();
+                let at = boundaries[self.rng.below(boundaries.len())];
+                let bracket = if self.rng.below(2) == 0 { "(" } else { ")" };
+                self.line = format!("{}{}{}", &text[..at], bracket, &text[at..]).into_bytes();
+                self.stats.corrupted.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        if newline {
+            self.line.push(b'\n');
+        }
+        Ok(())
+    }
+}
+
+impl<'a, R: BufRead> Read for ChaosReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<'a, R: BufRead> BufRead for ChaosReader<'a, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.line.len() {
+            self.next_line()?;
+        }
+        // The error is only returned before the first byte of the line, which is read
+        // again afterwards.
+        if self.fail && self.pos == 0 {
+            self.fail = false;
+            return Err(io::Error::other(INJECTED_ERROR));
+        }
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.line.len());
+    }
+}
+
+/// Whether `e` was injected by a `ChaosReader`.
+pub fn is_injected(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::Other && e.to_string() == INJECTED_ERROR
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn damaged_lines_stay_aligned() {
+        let input: String = (0..200).map(|i| format!("the dog {} barks\n", i)).collect();
+        let stats = ChaosStats::new();
+        let reader = ChaosReader::new(input.as_bytes(), 0.5, 7, &stats);
+
+        let mut errors = 0;
+        let mut lines = vec![];
+        for line in reader.lines() {
+            match line {
+                Ok(line) => lines.push(line),
+                Err(e) => {
+                    assert!(is_injected(&e));
+                    errors += 1;
+                }
+            }
+        }
+
+        assert_eq!(200, lines.len());
+        assert_eq!(200, stats.lines.load(Ordering::Relaxed));
+        assert_eq!(errors, stats.failed.load(Ordering::Relaxed));
+        let corrupted = stats.corrupted.load(Ordering::Relaxed);
+        assert!(errors > 0 && corrupted > 0);
+        assert!(errors + corrupted < 200);
+        assert_eq!(
+            200 - corrupted,
+            lines
+                .iter()
+                .zip(input.lines())
+                .filter(|(l, i)| l == i)
+                .count()
+        );
+
+        let stats = ChaosStats::new();
+        let reader = ChaosReader::new(input.as_bytes(), 0.0, 7, &stats);
+        assert_eq!(
+            input,
+            reader
+                .lines()
+                .map(|l| l.unwrap() + "\n")
+                .collect::<String>()
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use std::hash::Hash;
+
+use float_ord::FloatOrd;
+use fxhash::{FxHashMap, FxHashSet};
+
+/// Marks the start and the end of a word.
+const BOUNDARY: char = '\u{0}';
+
+/// Character bigrams of the words of a single tag.
+#[derive(Debug, Default)]
+struct TagModel {
+    bigrams: FxHashMap<(char, char), f64>,
+    contexts: FxHashMap<char, f64>,
+}
+
+/// Character-level sub-grammar for words that are neither in the lexicon nor covered by a
+/// signature. For every tag it is a right-linear grammar generating the words of the tag
+/// character by character, where each character depends on the previous one.
+/// All words of the lexicon count once per tag, so that the model resembles rare words.
+#[derive(Debug)]
+pub struct CharModel<N> {
+    tags: FxHashMap<N, TagModel>,
+    alphabet: FxHashSet<char>,
+}
+
+impl<N: Eq + Hash + Clone> CharModel<N> {
+    pub fn new() -> Self {
+        Self {
+            tags: FxHashMap::default(),
+            alphabet: FxHashSet::default(),
+        }
+    }
+
+    /// Adds a word of the lexicon with one of its tags.
+    pub fn insert(&mut self, tag: N, word: &str) {
+        let model = self.tags.entry(tag).or_default();
+
+        let mut prev = BOUNDARY;
+        for c in word.chars().chain(std::iter::once(BOUNDARY)) {
+            self.alphabet.insert(c);
+            *model.bigrams.entry((prev, c)).or_insert(0.0) += 1.0;
+            *model.contexts.entry(prev).or_insert(0.0) += 1.0;
+            prev = c;
+        }
+    }
+
+    /// Probability of `word` being generated by `tag`, with add-one smoothing.
+    pub fn probability(&self, tag: &N, word: &str) -> f64 {
+        let model = match self.tags.get(tag) {
+            Some(model) => model,
+            None => return 0.0,
+        };
+        let alphabet = (self.alphabet.len() + 1) as f64;
+
+        let mut prev = BOUNDARY;
+        let mut result = 1.0;
+        for c in word.chars().chain(std::iter::once(BOUNDARY)) {
+            let count = model.bigrams.get(&(prev, c)).copied().unwrap_or(0.0);
+            let context = model.contexts.get(&prev).copied().unwrap_or(0.0);
+            result *= (count + 1.0) / (context + alphabet);
+            prev = c;
+        }
+
+        result
+    }
+
+    /// The `n` tags most likely to generate `word`, with the probability of generating it.
+    pub fn best_tags(&self, word: &str, n: usize) -> Vec<(N, f64)> {
+        let mut tags: Vec<_> = self
+            .tags
+            .keys()
+            .map(|tag| (tag.clone(), self.probability(tag, word)))
+            .collect();
+        tags.sort_by_key(|(_, p)| std::cmp::Reverse(FloatOrd(*p)));
+        tags.truncate(n);
+        tags
+    }
+}
+
+impl<N: Eq + Hash + Clone> Default for CharModel<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn char_model_tags() {
+        let mut model = CharModel::new();
+        for word in ["walking", "talking", "running"] {
+            model.insert("VBG", word);
+        }
+        for word in ["dog", "cat", "house"] {
+            model.insert("NN", word);
+        }
+
+        assert_eq!(model.best_tags("jumping", 1)[0].0, "VBG");
+        assert_eq!(model.best_tags("bat", 1)[0].0, "NN");
+        assert_eq!(model.best_tags("bat", 5).len(), 2);
+        assert_eq!(model.probability(&"JJ", "bat"), 0.0);
+    }
+}
@@ -0,0 +1,187 @@
+use std::fmt;
+
+use crate::eval::{is_preterminal, ratio};
+use crate::tree::Tree;
+
+/// Phrases that are chunked by default, as in the chunking task of CoNLL-2000.
+pub const CHUNK_LABELS: [&str; 5] = ["NP", "VP", "PP", "ADJP", "ADVP"];
+
+/// Base phrase covering the words from `start` to before `end`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Chunk {
+    pub label: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Projects a tree to its base phrases. Every run of words whose preterminals are direct
+/// children of the same node with one of `labels` is a chunk, where function tags and
+/// annotations of the label (e.g. `NP-SBJ` or `NP^S`) are ignored. Phrases between the words
+/// of a node split it into several chunks.
+pub fn chunks<A: AsRef<str>, L: AsRef<str>>(tree: &Tree<A>, labels: &[L]) -> Vec<Chunk> {
+    let mut chunks = vec![];
+    collect_chunks(tree, labels, &mut 0, &mut chunks);
+    chunks
+}
+
+fn collect_chunks<A: AsRef<str>, L: AsRef<str>>(
+    tree: &Tree<A>,
+    labels: &[L],
+    position: &mut usize,
+    chunks: &mut Vec<Chunk>,
+) {
+    if tree.is_leaf() {
+        *position += 1;
+        return;
+    }
+
+    let label = base_label(tree.root.as_ref());
+    let chunked = labels.iter().any(|l| l.as_ref() == label);
+    let mut start = None;
+    for child in &tree.children {
+        if child.is_leaf() || is_preterminal(child) {
+            if chunked && start.is_none() {
+                start = Some(*position);
+            }
+            *position += 1;
+        } else {
+            if let Some(start) = start.take() {
+                chunks.push(Chunk {
+                    label: label.to_string(),
+                    start,
+                    end: *position,
+                });
+            }
+            collect_chunks(child, labels, position, chunks);
+        }
+    }
+    if let Some(start) = start {
+        chunks.push(Chunk {
+            label: label.to_string(),
+            start,
+            end: *position,
+        });
+    }
+}
+
+/// Label without function tags and annotations. Labels starting with `-`, e.g. `-NONE-`, are
+/// kept.
+pub fn base_label(label: &str) -> &str {
+    if label.starts_with('-') {
+        return label;
+    }
+    label.split(['-', '=', '^', '|']).next().unwrap_or(label)
+}
+
+/// IOB tag of every word of a sentence with `len` words: `B-X` for the first word of a chunk
+/// with label X, `I-X` for the following ones and `O` for words outside of chunks.
+pub fn iob_tags(chunks: &[Chunk], len: usize) -> Vec<String> {
+    let mut tags = vec![String::from("O"); len];
+    for chunk in chunks {
+        for (i, tag) in tags[chunk.start..chunk.end].iter_mut().enumerate() {
+            *tag = format!("{}-{}", if i == 0 { "B" } else { "I" }, chunk.label);
+        }
+    }
+    tags
+}
+
+/// Chunk scores of predicted trees against their gold trees, accumulated over a corpus.
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct ChunkEvaluation {
+    pub sentences: usize,
+    pub matched_chunks: usize,
+    pub predicted_chunks: usize,
+    pub gold_chunks: usize,
+    pub correct_tags: usize,
+    pub words: usize,
+}
+
+impl ChunkEvaluation {
+    /// Adds the chunks of a sentence with `len` words.
+    pub fn add(&mut self, predicted: &[Chunk], gold: &[Chunk], len: usize) {
+        self.sentences += 1;
+        self.matched_chunks += predicted.iter().filter(|c| gold.contains(c)).count();
+        self.predicted_chunks += predicted.len();
+        self.gold_chunks += gold.len();
+
+        let predicted_tags = iob_tags(predicted, len);
+        let gold_tags = iob_tags(gold, len);
+        self.words += len;
+        self.correct_tags += predicted_tags
+            .iter()
+            .zip(&gold_tags)
+            .filter(|(p, g)| p == g)
+            .count();
+    }
+
+    pub fn precision(&self) -> f64 {
+        ratio(self.matched_chunks, self.predicted_chunks)
+    }
+
+    pub fn recall(&self) -> f64 {
+        ratio(self.matched_chunks, self.gold_chunks)
+    }
+
+    pub fn f1(&self) -> f64 {
+        let (p, r) = (self.precision(), self.recall());
+        if p + r == 0.0 {
+            0.0
+        } else {
+            2.0 * p * r / (p + r)
+        }
+    }
+
+    pub fn tag_accuracy(&self) -> f64 {
+        ratio(self.correct_tags, self.words)
+    }
+}
+
+impl fmt::Display for ChunkEvaluation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Sentences:           {}", self.sentences)?;
+        writeln!(f, "Chunk recall:        {:.2}", 100.0 * self.recall())?;
+        writeln!(f, "Chunk precision:     {:.2}", 100.0 * self.precision())?;
+        writeln!(f, "Chunk F1:            {:.2}", 100.0 * self.f1())?;
+        writeln!(f, "IOB accuracy:        {:.2}", 100.0 * self.tag_accuracy())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use smallstr::SmallString;
+
+    use super::*;
+    use crate::SExp;
+
+    fn tree(s: &str) -> Tree<SmallString<[u8; 8]>> {
+        Tree::try_from(SExp::from_str(s).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn iob_projection() {
+        let t = tree(
+            "(S (NP-SBJ (DT the) (NN dog)) (VP (VBZ sleeps) (PP (IN on) (NP (DT the) (NN mat)))) \
+             (. .))",
+        );
+        let chunks = chunks(&t, &CHUNK_LABELS);
+        assert_eq!(
+            vec!["B-NP", "I-NP", "B-VP", "B-PP", "B-NP", "I-NP", "O"],
+            iob_tags(&chunks, 7)
+        );
+
+        // Phrases inside a node split its chunk.
+        let t = tree("(NP (DT the) (ADJP (JJ big)) (NN dog))");
+        assert_eq!(
+            vec!["B-NP", "B-ADJP", "B-NP"],
+            iob_tags(&super::chunks(&t, &CHUNK_LABELS), 3)
+        );
+
+        let mut evaluation = ChunkEvaluation::default();
+        evaluation.add(&chunks[1..], &chunks, 7);
+        assert_eq!(1.0, evaluation.precision());
+        assert_eq!(0.75, evaluation.recall());
+        assert_eq!(5, evaluation.correct_tags);
+    }
+}
@@ -0,0 +1,106 @@
+//! Transparent compression of inputs and outputs, as treebanks and sentence files are usually
+//! stored compressed. Inputs are recognised by their first bytes, outputs by their extension.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use flate2::bufread::MultiGzDecoder;
+use flate2::write::GzEncoder;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Compression of a file by its extension, `.gz` or `.zst`.
+    pub fn of_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Compression of a stream that starts with `bytes`.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        } else if bytes.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Decompresses `reader` if it starts like a gzip or zstd stream, otherwise reads it as it is.
+/// Concatenated gzip members, as written by `pigz` or `cat a.gz b.gz`, are read one after the
+/// other.
+pub fn decompress<'a, R: BufRead + 'a>(mut reader: R) -> io::Result<Box<dyn BufRead + 'a>> {
+    // The magic numbers are short enough to be in the first buffer of any reader.
+    Ok(match Compression::detect(reader.fill_buf()?) {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+        Compression::Zstd => Box::new(BufReader::new(zstd::stream::read::Decoder::with_buffer(
+            reader,
+        )?)),
+    })
+}
+
+/// Opens a file for reading, decompressed if it is compressed.
+pub fn open(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    decompress(BufReader::new(File::open(path)?))
+}
+
+/// Compresses everything written into `writer`. The stream is finished when the returned writer
+/// is dropped.
+pub fn compress<'a, W: Write + 'a>(
+    writer: W,
+    compression: Compression,
+) -> io::Result<Box<dyn Write + 'a>> {
+    Ok(match compression {
+        Compression::None => Box::new(writer),
+        Compression::Gzip => Box::new(GzEncoder::new(writer, flate2::Compression::default())),
+        Compression::Zstd => Box::new(zstd::stream::write::Encoder::new(writer, 0)?.auto_finish()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compressed_round_trips() {
+        let text = "(ROOT (NN a))\n(ROOT (NN b))\n";
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            let mut compressed = vec![];
+            {
+                let mut out = compress(&mut compressed, compression).unwrap();
+                out.write_all(text.as_bytes()).unwrap();
+            }
+            assert_eq!(compression, Compression::detect(&compressed));
+
+            let lines: Vec<_> = decompress(&compressed[..])
+                .unwrap()
+                .lines()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert_eq!(vec!["(ROOT (NN a))", "(ROOT (NN b))"], lines);
+        }
+        assert_eq!(
+            Compression::Zstd,
+            Compression::of_path(Path::new("wsj.mrg.zst"))
+        );
+        assert_eq!(
+            Compression::None,
+            Compression::of_path(Path::new("wsj.mrg"))
+        );
+    }
+}
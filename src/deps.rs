@@ -0,0 +1,301 @@
+use std::io::{self, BufRead, Write};
+
+use fxhash::FxHashMap;
+
+use crate::chunk::base_label;
+use crate::eval::is_preterminal;
+use crate::tree::Tree;
+
+/// Head rules of Collins (1999) for the labels of the Penn Treebank, as used by Yamada and
+/// Matsumoto (2003), one `LABEL SEARCH CATEGORY...` line per rule. The rules of NP replace the
+/// special case of Collins with searches that find the same heads in most phrases.
+const COLLINS_HEAD_RULES: &str = "\
+ADJP left NNS QP NN $ ADVP JJ VBN VBG ADJP JJR NP JJS DT FW RBR RBS SBAR RB
+ADVP right RB RBR RBS FW ADVP TO CD JJR JJ IN NP JJS NN
+CONJP right CC RB IN
+FRAG right
+INTJ left
+LST right LS :
+NAC left NN NNS NNP NNPS NP NAC EX $ CD QP PRP VBG JJ JJS JJR ADJP FW
+NP rightdis NN NNP NNPS NNS NX POS JJR
+NP left NP
+NP rightdis $ ADJP PRN
+NP right CD
+NP rightdis JJ JJS RB QP
+NX rightdis NN NNP NNPS NNS NX POS JJR
+NX left NX
+PP right IN TO VBG VBN RP FW
+PRN left
+PRT right RP
+QP left $ IN NNS NN JJ RB DT CD NCD QP JJR JJS
+RRC right VP NP ADVP ADJP PP
+S left TO IN VP S SBAR ADJP UCP NP
+SBAR left WHNP WHPP WHADVP WHADJP IN DT S SQ SINV SBAR FRAG
+SBARQ left SQ S SINV SBARQ FRAG
+SINV left VBZ VBD VBP VB MD VP S SINV ADJP NP
+SQ left VBZ VBD VBP VB MD VP SQ
+UCP right
+VP left TO VBD VBN MD VBZ VB VBG VBP VP ADJP NN NNS NP
+WHADJP left CC WRB JJ ADJP
+WHADVP right CC WRB
+WHNP left WDT WP WP$ WHADJP WHPP WHNP
+WHPP right IN TO FW
+";
+
+/// How the children of a node are searched for its head.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HeadSearch {
+    /// For every category in turn, the children are searched from left to right.
+    Left,
+    /// For every category in turn, the children are searched from right to left.
+    Right,
+    /// The children are searched from left to right for any of the categories.
+    LeftDis,
+    /// The children are searched from right to left for any of the categories.
+    RightDis,
+}
+
+#[derive(Clone, Debug)]
+struct HeadRule {
+    search: HeadSearch,
+    categories: Vec<String>,
+}
+
+/// Rules that choose the head child of every phrase, tried in order until one finds a child.
+/// Without a matching child, the head is the first child in the search direction of the first
+/// rule of the label, and the leftmost child for labels without rules.
+#[derive(Clone, Debug, Default)]
+pub struct HeadRules {
+    labels: FxHashMap<String, Vec<HeadRule>>,
+}
+
+impl HeadRules {
+    /// The head rules of Collins for the Penn Treebank.
+    pub fn collins() -> Self {
+        Self::default()
+            .read(COLLINS_HEAD_RULES.as_bytes())
+            .expect("the built-in head rules are valid")
+    }
+
+    /// Reads rules with one `LABEL SEARCH CATEGORY...` line per rule, separated by whitespace,
+    /// where SEARCH is `left`, `right`, `leftdis` or `rightdis`. The rules of a label replace
+    /// those it already has. Empty lines and lines starting with `#` are skipped.
+    pub fn read<R: BufRead>(mut self, reader: R) -> io::Result<Self> {
+        let mut read: FxHashMap<String, Vec<HeadRule>> = FxHashMap::default();
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (label, search) = match (fields.next(), fields.next()) {
+                (None, _) => continue,
+                (Some(label), Some(search)) => (label, search),
+                _ => return Err(invalid_rule(&line)),
+            };
+            let search = match search {
+                "left" => HeadSearch::Left,
+                "right" => HeadSearch::Right,
+                "leftdis" => HeadSearch::LeftDis,
+                "rightdis" => HeadSearch::RightDis,
+                _ => return Err(invalid_rule(&line)),
+            };
+            read.entry(label.to_string()).or_default().push(HeadRule {
+                search,
+                categories: fields.map(str::to_string).collect(),
+            });
+        }
+        self.labels.extend(read);
+        Ok(self)
+    }
+
+    /// Index of the head among the children of a node with `label`. Function tags and
+    /// annotations of the labels are ignored.
+    pub fn head_child<A: AsRef<str>>(&self, label: &str, children: &[Tree<A>]) -> usize {
+        let rules = match self.labels.get(base_label(label)) {
+            Some(rules) if !rules.is_empty() => rules,
+            _ => return 0,
+        };
+        let categories: Vec<&str> = children
+            .iter()
+            .map(|c| base_label(c.root.as_ref()))
+            .collect();
+        let from_left: Vec<usize> = (0..children.len()).collect();
+        let from_right: Vec<usize> = (0..children.len()).rev().collect();
+
+        for rule in rules {
+            let order = match rule.search {
+                HeadSearch::Left | HeadSearch::LeftDis => &from_left,
+                HeadSearch::Right | HeadSearch::RightDis => &from_right,
+            };
+            let found = match rule.search {
+                HeadSearch::Left | HeadSearch::Right => rule
+                    .categories
+                    .iter()
+                    .find_map(|c| order.iter().copied().find(|&i| categories[i] == c.as_str())),
+                HeadSearch::LeftDis | HeadSearch::RightDis => order
+                    .iter()
+                    .copied()
+                    .find(|&i| rule.categories.iter().any(|c| categories[i] == c.as_str())),
+            };
+            if let Some(i) = found {
+                return i;
+            }
+        }
+
+        match rules[0].search {
+            HeadSearch::Left | HeadSearch::LeftDis => 0,
+            HeadSearch::Right | HeadSearch::RightDis => children.len() - 1,
+        }
+    }
+}
+
+fn invalid_rule(line: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "expected a label, a search direction and categories: {}",
+            line
+        ),
+    )
+}
+
+/// Word of a sentence with the position of its head, counting from 1, or 0 for the root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dependency {
+    pub word: String,
+    /// Preterminal of the word, if it has one.
+    pub tag: Option<String>,
+    pub head: usize,
+    /// `root` for the root, otherwise the label of the largest phrase headed by the word,
+    /// without function tags.
+    pub relation: String,
+}
+
+/// Converts a constituent tree to the dependencies of its words: the head word of every phrase
+/// is the head word of its head child, and the head words of the other children depend on it.
+pub fn dependencies<A: AsRef<str>>(tree: &Tree<A>, rules: &HeadRules) -> Vec<Dependency> {
+    let mut dependencies = vec![];
+    let root = collect_dependencies(tree, rules, &mut dependencies);
+    dependencies[root].relation = String::from("root");
+    dependencies
+}
+
+/// Returns the index of the head word of `tree` in `dependencies`.
+fn collect_dependencies<A: AsRef<str>>(
+    tree: &Tree<A>,
+    rules: &HeadRules,
+    dependencies: &mut Vec<Dependency>,
+) -> usize {
+    if tree.is_leaf() || is_preterminal(tree) {
+        let (word, tag) = match tree.children.first() {
+            Some(word) => (word.root.as_ref(), Some(tree.root.as_ref().to_string())),
+            None => (tree.root.as_ref(), None),
+        };
+        dependencies.push(Dependency {
+            word: word.to_string(),
+            tag,
+            head: 0,
+            relation: String::new(),
+        });
+        return dependencies.len() - 1;
+    }
+
+    let heads: Vec<usize> = tree
+        .children
+        .iter()
+        .map(|c| collect_dependencies(c, rules, dependencies))
+        .collect();
+    let head = heads[rules.head_child(tree.root.as_ref(), &tree.children)];
+    for (child, &dependent) in tree.children.iter().zip(&heads) {
+        if dependent != head {
+            dependencies[dependent].head = head + 1;
+            dependencies[dependent].relation = base_label(child.root.as_ref()).to_string();
+        }
+    }
+    head
+}
+
+/// Column layout of the dependency rows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConllFormat {
+    /// Ten columns of CoNLL-U, with the tag as XPOS.
+    Conllu,
+    /// Ten columns of CoNLL-X, with the tag as CPOSTAG and POSTAG.
+    Conllx,
+}
+
+/// Writes one row per word, separated by tabs, and an empty line after the sentence.
+/// Columns without a value are `_`.
+pub fn write_conll<W: Write>(
+    out: &mut W,
+    dependencies: &[Dependency],
+    format: ConllFormat,
+) -> io::Result<()> {
+    for (i, d) in dependencies.iter().enumerate() {
+        let tag = d.tag.as_deref().unwrap_or("_");
+        match format {
+            ConllFormat::Conllu => writeln!(
+                out,
+                "{}\t{}\t_\t_\t{}\t_\t{}\t{}\t_\t_",
+                i + 1,
+                d.word,
+                tag,
+                d.head,
+                d.relation
+            )?,
+            ConllFormat::Conllx => writeln!(
+                out,
+                "{}\t{}\t_\t{}\t{}\t_\t{}\t{}\t_\t_",
+                i + 1,
+                d.word,
+                tag,
+                tag,
+                d.head,
+                d.relation
+            )?,
+        }
+    }
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use smallstr::SmallString;
+
+    use super::*;
+    use crate::SExp;
+
+    fn tree(s: &str) -> Tree<SmallString<[u8; 8]>> {
+        Tree::try_from(SExp::from_str(s).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn collins_heads() {
+        let t = tree(
+            "(ROOT (S (NP-SBJ (DT the) (NN dog)) (VP (VBZ sleeps) (PP (IN on) (NP (DT the) \
+             (NN mat)))) (. .)))",
+        );
+        let deps = dependencies(&t, &HeadRules::collins());
+        let heads: Vec<_> = deps.iter().map(|d| d.head).collect();
+        assert_eq!(vec![2, 3, 0, 3, 6, 4, 3], heads);
+        let relations: Vec<_> = deps.iter().map(|d| d.relation.as_str()).collect();
+        assert_eq!(vec!["DT", "NP", "root", "PP", "DT", "NP", "."], relations);
+
+        let mut out = vec![];
+        write_conll(&mut out, &deps[..1], ConllFormat::Conllx).unwrap();
+        assert_eq!(
+            "1\tthe\t_\tDT\tDT\t_\t2\tDT\t_\t_\n\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        // Rules of a table replace the built-in ones of their label.
+        let rules = HeadRules::collins()
+            .read(&b"# VPs are headed by their objects\nVP right PP\n"[..])
+            .unwrap();
+        assert_eq!(4, dependencies(&t, &rules)[2].head);
+        assert!(HeadRules::default().read(&b"VP up VB\n"[..]).is_err());
+    }
+}
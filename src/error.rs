@@ -0,0 +1,79 @@
+use std::fmt;
+use std::io;
+
+/// Error that ends a subcommand. Every kind has its own exit code, see `Error::exit_code`.
+#[derive(Debug)]
+pub enum Error {
+    /// A check of the subcommand found problems, e.g. misaligned sentences. Exit code 1.
+    Check(String),
+    /// Options that are invalid or can't be combined. Exit code 2.
+    Usage(String),
+    /// Input that doesn't have the expected format, e.g. a grammar file with rules of the
+    /// wrong kind. Exit code 3.
+    Format(String),
+    /// Reading or writing failed. Exit code 4.
+    Io(io::Error),
+    /// An option that isn't implemented, e.g. the deductive parsing paradigm. Exit code 22.
+    Unsupported(String),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Check(_) => 1,
+            Error::Usage(_) => 2,
+            Error::Format(_) => 3,
+            Error::Io(_) => 4,
+            Error::Unsupported(_) => 22,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Check(message) | Error::Usage(message) | Error::Format(message) => {
+                write!(f, "{}", message)
+            }
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Unsupported(message) => write!(f, "{} is not supported", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn exit_codes() {
+        let e = Error::from(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+        assert_eq!(4, e.exit_code());
+        assert_eq!("no such file", e.to_string());
+        assert_eq!(
+            22,
+            Error::Unsupported(String::from("Deductive parsing")).exit_code()
+        );
+        assert_eq!(
+            "Deductive parsing is not supported",
+            Error::Unsupported(String::from("Deductive parsing")).to_string()
+        );
+    }
+}
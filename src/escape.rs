@@ -0,0 +1,123 @@
+//! Escapes of the Penn Treebank for words that are brackets, e.g. `-LRB-` for `(`. Words that
+//! are round brackets can't be written in S-expressions, so trees and sentences are escaped when
+//! they are read, and words are unescaped again where they are printed outside of trees.
+
+use std::borrow::Cow;
+
+/// Brackets and their escapes.
+pub const BRACKET_ESCAPES: [(&str, &str); 6] = [
+    ("(", "-LRB-"),
+    (")", "-RRB-"),
+    ("[", "-LSB-"),
+    ("]", "-RSB-"),
+    ("{", "-LCB-"),
+    ("}", "-RCB-"),
+];
+
+/// The escape of a word that is a bracket, otherwise the word itself.
+pub fn escape_word(word: &str) -> &str {
+    BRACKET_ESCAPES
+        .iter()
+        .find(|(bracket, _)| *bracket == word)
+        .map_or(word, |(_, escape)| *escape)
+}
+
+/// The bracket of an escape, otherwise the word itself.
+pub fn unescape_word(word: &str) -> &str {
+    BRACKET_ESCAPES
+        .iter()
+        .find(|(_, escape)| *escape == word)
+        .map_or(word, |(bracket, _)| *bracket)
+}
+
+/// Escapes the words of a sentence, separated by whitespace.
+pub fn escape_sentence(line: &str) -> Cow<str> {
+    if !line.contains(['(', ')', '[', ']', '{', '}']) {
+        return Cow::Borrowed(line);
+    }
+    Cow::Owned(
+        line.split_whitespace()
+            .map(escape_word)
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// Escapes the words of a tree in a single line. A round bracket is taken as word where it is the
+/// only child of a node, as in `(-LRB- ()`; other words are escaped if they are brackets.
+pub fn escape_tree(line: &str) -> Cow<str> {
+    if !line.contains(['[', ']', '{', '}']) && !line.contains(" ()") && !line.contains(" ))") {
+        return Cow::Borrowed(line);
+    }
+
+    let chars: Vec<char> = line.chars().collect();
+    let is_atom = |c: char| !c.is_whitespace() && c != '(' && c != ')';
+    let skip_whitespace = |mut i: usize| {
+        while i < chars.len() && chars[i].is_whitespace() {
+            i += 1;
+        }
+        i
+    };
+    let mut escaped = String::with_capacity(line.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '(' {
+            // A node `(LABEL B)` whose word B is a round bracket.
+            let mut label_end = i + 1;
+            while label_end < chars.len() && is_atom(chars[label_end]) {
+                label_end += 1;
+            }
+            let word = skip_whitespace(label_end);
+            if label_end > i + 1
+                && word > label_end
+                && word < chars.len()
+                && (chars[word] == '(' || chars[word] == ')')
+            {
+                let end = skip_whitespace(word + 1);
+                if end < chars.len() && chars[end] == ')' {
+                    escaped.push('(');
+                    escaped.extend(&chars[i + 1..label_end]);
+                    escaped.push(' ');
+                    escaped.push_str(escape_word(&chars[word].to_string()));
+                    escaped.push(')');
+                    i = end + 1;
+                    continue;
+                }
+            }
+            escaped.push(c);
+            i += 1;
+        } else if is_atom(c) {
+            let start = i;
+            while i < chars.len() && is_atom(chars[i]) {
+                i += 1;
+            }
+            let atom: String = chars[start..i].iter().collect();
+            escaped.push_str(escape_word(&atom));
+        } else {
+            escaped.push(c);
+            i += 1;
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bracket_escapes() {
+        assert_eq!("-LRB-", escape_word("("));
+        assert_eq!("dog", escape_word("dog"));
+        assert_eq!("}", unescape_word("-RCB-"));
+        assert_eq!("-NONE-", unescape_word("-NONE-"));
+
+        assert_eq!("-LRB- a -RRB- b", escape_sentence("( a )  b"));
+        assert_eq!(
+            "(NP (-LRB- -LRB-) (NN a) (-RRB- -RRB-) (SYM -LSB-))",
+            escape_tree("(NP (-LRB- () (NN a) (-RRB- )) (SYM [))")
+        );
+        assert!(matches!(escape_tree("(S (NN a) (NN b))"), Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,303 @@
+use std::fmt;
+use std::hash::Hash;
+
+use fxhash::FxHashMap;
+
+use crate::tree::Tree;
+
+/// Tags whose words are ignored with `ignore_punctuation`, as in the COLLINS.prm file of evalb.
+pub const PUNCTUATION_TAGS: [&str; 5] = ["''", "``", ".", ":", ","];
+
+/// Root label of the trees of sentences that couldn't be parsed.
+pub const NOPARSE: &str = "NOPARSE";
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct EvalConfig {
+    /// Words tagged with one of the `PUNCTUATION_TAGS` in the gold tree are removed
+    /// from both trees before scoring.
+    pub ignore_punctuation: bool,
+    /// The bracket of the root node isn't scored.
+    pub ignore_root: bool,
+}
+
+/// PARSEVAL scores of predicted trees against their gold trees, accumulated over a corpus.
+/// Preterminals are not counted as brackets, but scored by the tagging accuracy.
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct Evaluation {
+    pub sentences: usize,
+    /// Sentences whose trees don't have the same words.
+    pub skipped: usize,
+    pub matched_brackets: usize,
+    pub predicted_brackets: usize,
+    pub gold_brackets: usize,
+    pub exact_matches: usize,
+    pub correct_tags: usize,
+    pub words: usize,
+}
+
+impl Evaluation {
+    pub fn add<A: AsRef<str> + Eq + Hash + Clone>(
+        &mut self,
+        predicted: &Tree<A>,
+        gold: &Tree<A>,
+        config: &EvalConfig,
+    ) {
+        let gold_tags = tags(gold);
+        let predicted_tags = tags(predicted);
+        if gold.leaves() != predicted.leaves() || gold_tags.len() != predicted_tags.len() {
+            self.skipped += 1;
+            return;
+        }
+        self.sentences += 1;
+
+        let keep: Vec<bool> = gold_tags
+            .iter()
+            .map(|t| {
+                !config.ignore_punctuation
+                    || !t.is_some_and(|t| PUNCTUATION_TAGS.contains(&t.as_ref()))
+            })
+            .collect();
+        // Position of every word, counting only the words that are kept.
+        let mut offsets = vec![0];
+        for k in &keep {
+            offsets.push(offsets.last().unwrap() + *k as usize);
+        }
+
+        let mut gold_brackets = brackets(gold, &offsets, config.ignore_root);
+        let predicted_brackets = brackets(predicted, &offsets, config.ignore_root);
+        let gold_count: usize = gold_brackets.values().sum();
+        let predicted_count: usize = predicted_brackets.values().sum();
+        let mut matched = 0;
+        for (bracket, count) in predicted_brackets {
+            if let Some(gold_count) = gold_brackets.get_mut(&bracket) {
+                let m = count.min(*gold_count);
+                *gold_count -= m;
+                matched += m;
+            }
+        }
+
+        self.matched_brackets += matched;
+        self.predicted_brackets += predicted_count;
+        self.gold_brackets += gold_count;
+        if matched == gold_count && matched == predicted_count {
+            self.exact_matches += 1;
+        }
+
+        for ((p, g), k) in predicted_tags.iter().zip(&gold_tags).zip(&keep) {
+            if *k {
+                self.words += 1;
+                if p.is_some() && p == g {
+                    self.correct_tags += 1;
+                }
+            }
+        }
+    }
+
+    pub fn precision(&self) -> f64 {
+        ratio(self.matched_brackets, self.predicted_brackets)
+    }
+
+    pub fn recall(&self) -> f64 {
+        ratio(self.matched_brackets, self.gold_brackets)
+    }
+
+    pub fn f1(&self) -> f64 {
+        let (p, r) = (self.precision(), self.recall());
+        if p + r == 0.0 {
+            0.0
+        } else {
+            2.0 * p * r / (p + r)
+        }
+    }
+
+    pub fn exact_match(&self) -> f64 {
+        ratio(self.exact_matches, self.sentences)
+    }
+
+    pub fn tagging_accuracy(&self) -> f64 {
+        ratio(self.correct_tags, self.words)
+    }
+}
+
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Sentences:           {}", self.sentences)?;
+        writeln!(f, "Skipped sentences:   {}", self.skipped)?;
+        writeln!(f, "Labeled recall:      {:.2}", 100.0 * self.recall())?;
+        writeln!(f, "Labeled precision:   {:.2}", 100.0 * self.precision())?;
+        writeln!(f, "Labeled F1:          {:.2}", 100.0 * self.f1())?;
+        writeln!(f, "Exact match:         {:.2}", 100.0 * self.exact_match())?;
+        writeln!(
+            f,
+            "Tagging accuracy:    {:.2}",
+            100.0 * self.tagging_accuracy()
+        )
+    }
+}
+
+pub fn ratio(a: usize, b: usize) -> f64 {
+    if b == 0 {
+        0.0
+    } else {
+        a as f64 / b as f64
+    }
+}
+
+pub fn is_preterminal<A>(tree: &Tree<A>) -> bool {
+    matches!(tree.children.as_slice(), [c] if c.is_leaf())
+}
+
+/// Tag of every word, or `None` for words without preterminal, e.g. in NOPARSE trees.
+pub fn tags<A>(tree: &Tree<A>) -> Vec<Option<&A>> {
+    if is_preterminal(tree) {
+        vec![Some(&tree.root)]
+    } else if tree.is_leaf() {
+        vec![None]
+    } else {
+        tree.children.iter().flat_map(tags).collect()
+    }
+}
+
+/// Labeled spans `(label, start, end)` of all non-terminals except the preterminals, in
+/// preorder. The words are counted from 0 and `end` is the position after the last word.
+pub fn labeled_spans<A>(tree: &Tree<A>) -> Vec<(&A, usize, usize)> {
+    let mut spans = vec![];
+    collect_spans(tree, 0, &mut spans);
+    spans
+}
+
+/// Returns the position after the last word of `tree`.
+fn collect_spans<'a, A>(
+    tree: &'a Tree<A>,
+    start: usize,
+    spans: &mut Vec<(&'a A, usize, usize)>,
+) -> usize {
+    if tree.is_leaf() {
+        return start + 1;
+    }
+
+    let i = spans.len();
+    if !is_preterminal(tree) {
+        spans.push((&tree.root, start, start));
+    }
+    let mut end = start;
+    for child in &tree.children {
+        end = collect_spans(child, end, spans);
+    }
+    if !is_preterminal(tree) {
+        spans[i].2 = end;
+    }
+    end
+}
+
+/// Counts the labeled spans of all non-terminals that cover at least one kept word.
+fn brackets<A: AsRef<str> + Eq + Hash + Clone>(
+    tree: &Tree<A>,
+    offsets: &[usize],
+    ignore_root: bool,
+) -> FxHashMap<(A, usize, usize), usize> {
+    let mut brackets = FxHashMap::default();
+    if tree.root.as_ref() == NOPARSE {
+        return brackets;
+    }
+
+    if ignore_root {
+        let mut start = 0;
+        for child in &tree.children {
+            start = collect_brackets(child, start, offsets, &mut brackets);
+        }
+    } else {
+        collect_brackets(tree, 0, offsets, &mut brackets);
+    }
+    brackets
+}
+
+/// Returns the position after the last word of `tree`.
+fn collect_brackets<A: Eq + Hash + Clone>(
+    tree: &Tree<A>,
+    start: usize,
+    offsets: &[usize],
+    brackets: &mut FxHashMap<(A, usize, usize), usize>,
+) -> usize {
+    if tree.is_leaf() {
+        return start + 1;
+    }
+
+    let mut end = start;
+    for child in &tree.children {
+        end = collect_brackets(child, end, offsets, brackets);
+    }
+    if !is_preterminal(tree) && offsets[start] < offsets[end] {
+        *brackets
+            .entry((tree.root.clone(), offsets[start], offsets[end]))
+            .or_insert(0) += 1;
+    }
+    end
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use smallstr::SmallString;
+
+    use super::*;
+    use crate::SExp;
+
+    fn tree(s: &str) -> Tree<SmallString<[u8; 8]>> {
+        Tree::try_from(SExp::from_str(s).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn parseval() {
+        let gold = tree("(ROOT (S (NP (DT the) (NN dog)) (VP (VBZ barks)) (. .)))");
+        let predicted = tree("(ROOT (S (DT the) (VP (NN dog) (VBP barks)) (. .)))");
+
+        let mut eval = Evaluation::default();
+        eval.add(&predicted, &gold, &EvalConfig::default());
+        // ROOT and S match, VP has the wrong span and NP is missing.
+        assert_eq!(
+            (2, 3, 4),
+            (
+                eval.matched_brackets,
+                eval.predicted_brackets,
+                eval.gold_brackets
+            )
+        );
+        assert_eq!((3, 4), (eval.correct_tags, eval.words));
+        assert_eq!(0, eval.exact_matches);
+
+        let config = EvalConfig {
+            ignore_punctuation: true,
+            ignore_root: true,
+        };
+        let mut eval = Evaluation::default();
+        eval.add(&gold, &gold, &config);
+        assert_eq!((3, 3), (eval.matched_brackets, eval.gold_brackets));
+        assert_eq!((3, 3), (eval.correct_tags, eval.words));
+        assert_eq!(1.0, eval.exact_match());
+
+        eval.add(&tree("(NOPARSE the dog barks .)"), &gold, &config);
+        assert_eq!(
+            (3, 3, 6),
+            (
+                eval.matched_brackets,
+                eval.predicted_brackets,
+                eval.gold_brackets
+            )
+        );
+        assert_eq!(0.5, eval.recall());
+
+        eval.add(&tree("(ROOT (NN dog))"), &gold, &config);
+        assert_eq!(1, eval.skipped);
+
+        let spans: Vec<_> = labeled_spans(&gold)
+            .into_iter()
+            .map(|(label, start, end)| (label.as_str(), start, end))
+            .collect();
+        assert_eq!(
+            vec![("ROOT", 0, 4), ("S", 0, 4), ("NP", 0, 2), ("VP", 2, 3)],
+            spans
+        );
+    }
+}
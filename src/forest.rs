@@ -0,0 +1,130 @@
+use std::fmt;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+
+use smallstr::SmallString;
+
+use crate::grammar::parse::SpanPosteriors;
+use crate::Sentence;
+
+/// The labeled spans of a sentence with their posterior probabilities, as printed by
+/// `parse --forest`. Written as a line with the words of the sentence, separated by spaces,
+/// followed by one `START END LABEL POSTERIOR` line per span, separated by tabs, and an empty
+/// line. END is the position after the last word of the span. Sentences that couldn't be parsed
+/// have no spans.
+#[derive(Debug, PartialEq)]
+pub struct Forest<N, T> {
+    pub sentence: Sentence<T>,
+    pub spans: SpanPosteriors<N>,
+}
+
+type Label = SmallString<[u8; 8]>;
+
+impl Forest<Label, Label> {
+    /// Reads the next forest and leaves the reader at the start of the following one.
+    /// Returns `None` at the end of the reader. If a line is invalid, the rest of the forest
+    /// is skipped and an error is returned.
+    pub fn from_reader<R: BufRead>(reader: &mut R) -> io::Result<Option<Self>> {
+        let mut sentence = None;
+        let mut spans = vec![];
+        let mut error = None;
+        let mut started = false;
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.trim().is_empty() {
+                // Empty lines before a forest are skipped.
+                if started {
+                    break;
+                }
+                continue;
+            }
+
+            if !started {
+                started = true;
+                match Sentence::from_str(line) {
+                    Ok(s) => sentence = Some(s),
+                    Err(e) => {
+                        error = Some(invalid_data(format!("invalid sentence: {:?}", e)));
+                    }
+                }
+                continue;
+            }
+
+            match parse_span(line) {
+                Some(span) => spans.push(span),
+                None => {
+                    error.get_or_insert(invalid_data(format!("invalid span: {}", line)));
+                }
+            }
+        }
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok(sentence.map(|sentence| Self {
+                sentence,
+                spans: SpanPosteriors(spans),
+            })),
+        }
+    }
+}
+
+impl<N: fmt::Display, T: fmt::Display> fmt::Display for Forest<N, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let words: Vec<_> = self.sentence.iter().map(|w| w.to_string()).collect();
+        writeln!(f, "{}", words.join(" "))?;
+        write!(f, "{}", self.spans)
+    }
+}
+
+fn parse_span(line: &str) -> Option<(usize, usize, Label, f64)> {
+    match line.split('\t').collect::<Vec<_>>().as_slice() {
+        [start, end, label, posterior] => Some((
+            start.parse().ok()?,
+            end.parse().ok()?,
+            Label::from(*label),
+            posterior.parse().ok()?,
+        )),
+        _ => None,
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn forest_roundtrip() {
+        let input = "dogs bark\n0\t1\tNP\t1\n0\t2\tS\t0.5\n\nbark\n\n";
+        let mut reader = input.as_bytes();
+
+        let first = Forest::from_reader(&mut reader).unwrap().unwrap();
+        assert_eq!(2, first.sentence.len());
+        assert_eq!((0, 2, Label::from("S"), 0.5), first.spans.0[1]);
+        assert_eq!("dogs bark\n0\t1\tNP\t1\n0\t2\tS\t0.5\n", first.to_string());
+
+        let second = Forest::from_reader(&mut reader).unwrap().unwrap();
+        assert!(second.spans.0.is_empty());
+        assert!(Forest::from_reader(&mut reader).unwrap().is_none());
+
+        let mut reader = &b"dogs\n0\t1\tNP\n0\t1\tNP\t1\n\ndogs\n"[..];
+        assert!(Forest::from_reader(&mut reader).is_err());
+        assert_eq!(
+            1,
+            Forest::from_reader(&mut reader)
+                .unwrap()
+                .unwrap()
+                .sentence
+                .len()
+        );
+    }
+}
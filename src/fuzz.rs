@@ -0,0 +1,310 @@
+use std::fmt;
+
+use float_ord::FloatOrd;
+use fxhash::FxHashMap;
+
+use crate::grammar::parse::GrammarParse;
+use crate::grammar::prune::PruneMode;
+use crate::grammar::rule::{Rule, WeightedRule};
+use crate::rng::XorShift;
+use crate::sentence::Sentence;
+use crate::tree::{NodeType, Tree};
+
+/// Relative difference up to which two Viterbi scores are considered equal.
+const EPSILON: f64 = 1e-9;
+
+/// Size limits of the generated grammars and sentences.
+pub struct FuzzConfig {
+    pub nonterminals: usize,
+    pub terminals: usize,
+    pub max_length: usize,
+}
+
+/// A random grammar together with a random sentence to parse with it.
+/// The initial non-terminal is always `N0`.
+pub struct FuzzCase {
+    pub rules: Vec<WeightedRule<String, String, FloatOrd<f64>>>,
+    pub sentence: Sentence<String>,
+}
+
+/// Outcome of a case on which the parser disagrees with the reference.
+pub struct Mismatch {
+    pub expected: Option<f64>,
+    pub found: Option<f64>,
+    pub tree: Option<Tree<NodeType<String, String>>>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected Viterbi score {:?}, CYK found {:?}",
+            self.expected, self.found
+        )?;
+        if let Some(tree) = &self.tree {
+            write!(f, " with tree {}", tree)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for FuzzCase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for weighted_rule in &self.rules {
+            match &weighted_rule.rule {
+                Rule::NonLexical { lhs, rhs } => {
+                    writeln!(f, "{} -> {} {}", lhs, rhs.join(" "), weighted_rule.weight.0)?
+                }
+                Rule::Lexical { lhs, rhs } => {
+                    writeln!(f, "{} {} {}", lhs, rhs, weighted_rule.weight.0)?
+                }
+            }
+        }
+        write!(f, "{}", self.sentence.0.join(" "))
+    }
+}
+
+impl FuzzCase {
+    pub fn random(rng: &mut XorShift, config: &FuzzConfig) -> Self {
+        let nonterminal = |i: usize| format!("N{}", i);
+        let terminal = |i: usize| format!("t{}", i);
+        let mut rules = Vec::new();
+
+        for lhs in 0..config.nonterminals {
+            for _ in 0..rng.below(3) + 1 {
+                rules.push(WeightedRule {
+                    rule: Rule::NonLexical {
+                        lhs: nonterminal(lhs),
+                        rhs: vec![
+                            nonterminal(rng.below(config.nonterminals)),
+                            nonterminal(rng.below(config.nonterminals)),
+                        ],
+                    },
+                    weight: FloatOrd(rng.weight()),
+                });
+            }
+            if rng.below(2) == 0 {
+                rules.push(WeightedRule {
+                    rule: Rule::NonLexical {
+                        lhs: nonterminal(lhs),
+                        rhs: vec![nonterminal(rng.below(config.nonterminals))],
+                    },
+                    weight: FloatOrd(rng.weight()),
+                });
+            }
+            for _ in 0..rng.below(2) + 1 {
+                rules.push(WeightedRule {
+                    rule: Rule::Lexical {
+                        lhs: nonterminal(lhs),
+                        rhs: terminal(rng.below(config.terminals)),
+                    },
+                    weight: FloatOrd(rng.weight()),
+                });
+            }
+        }
+
+        // Duplicate rules would make the grammar ambiguous about its weights.
+        let mut seen = Vec::new();
+        rules.retain(|r| {
+            if seen.contains(&r.rule) {
+                false
+            } else {
+                seen.push(r.rule.clone());
+                true
+            }
+        });
+
+        let length = rng.below(config.max_length) + 1;
+        let sentence = Sentence(
+            (0..length)
+                .map(|_| terminal(rng.below(config.terminals)))
+                .collect(),
+        );
+
+        FuzzCase { rules, sentence }
+    }
+
+    /// Parses the sentence with CYK and compares the weight of the returned tree
+    /// against the Viterbi score of the exhaustive reference parser.
+    pub fn check(&self) -> Result<(), Mismatch> {
+        let mut grammar = GrammarParse::new("N0".to_string());
+        self.rules.iter().for_each(|r| {
+            grammar
+                .insert_rule(WeightedRule {
+                    rule: r.rule.clone(),
+                    weight: r.weight,
+                })
+                .expect("generated grammars are binarised")
+        });
+
+        let expected = self.viterbi_reference();
+        let mode = PruneMode::empty();
+        let tree = grammar.cyk(&self.sentence, &mode);
+        let found = tree.as_ref().and_then(|t| self.tree_weight(t));
+
+        let agrees = match (expected, found) {
+            (Some(e), Some(f)) => (e - f).abs() <= EPSILON * e.max(f),
+            (None, None) => true,
+            _ => false,
+        };
+
+        let well_formed = match &tree {
+            Some(t) => self.yields_sentence(t),
+            None => true,
+        };
+
+        if agrees && well_formed {
+            Ok(())
+        } else {
+            Err(Mismatch {
+                expected,
+                found,
+                tree,
+            })
+        }
+    }
+
+    fn rule_weight(&self, rule: &Rule<String, String>) -> Option<f64> {
+        self.rules
+            .iter()
+            .find(|r| &r.rule == rule)
+            .map(|r| r.weight.0)
+    }
+
+    /// Product of the rule weights in the tree, `None` if it uses a rule not in the grammar.
+    fn tree_weight(&self, tree: &Tree<NodeType<String, String>>) -> Option<f64> {
+        let lhs = match &tree.root {
+            NodeType::NonTerminal(n) => n.clone(),
+            NodeType::Terminal(_) => return None,
+        };
+
+        match tree.children.as_slice() {
+            [Tree {
+                root: NodeType::Terminal(t),
+                ..
+            }] => self.rule_weight(&Rule::Lexical {
+                lhs,
+                rhs: t.clone(),
+            }),
+            children => {
+                let rhs = children
+                    .iter()
+                    .map(|c| match &c.root {
+                        NodeType::NonTerminal(n) => Some(n.clone()),
+                        NodeType::Terminal(_) => None,
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                let children_weight = children
+                    .iter()
+                    .map(|c| self.tree_weight(c))
+                    .product::<Option<f64>>()?;
+                self.rule_weight(&Rule::NonLexical { lhs, rhs })
+                    .map(|w| w * children_weight)
+            }
+        }
+    }
+
+    fn yields_sentence(&self, tree: &Tree<NodeType<String, String>>) -> bool {
+        tree.root == NodeType::NonTerminal("N0".to_string())
+            && tree
+                .leaves()
+                .iter()
+                .map(|l| match l {
+                    NodeType::Terminal(t) => Some(t),
+                    NodeType::NonTerminal(_) => None,
+                })
+                .eq(self.sentence.0.iter().map(Some))
+    }
+
+    /// Straightforward Viterbi computation over all spans, independent of the chart layout
+    /// and rule indexing of `GrammarParse`.
+    fn viterbi_reference(&self) -> Option<f64> {
+        let n = self.sentence.0.len();
+        let mut best: FxHashMap<(usize, usize, &str), f64> = FxHashMap::default();
+
+        for span in 1..=n {
+            for start in 0..=n - span {
+                let mut cell: FxHashMap<&str, f64> = FxHashMap::default();
+
+                for weighted_rule in &self.rules {
+                    let w = weighted_rule.weight.0;
+                    let score = match &weighted_rule.rule {
+                        Rule::Lexical { rhs, .. } if span == 1 => {
+                            if rhs == &self.sentence.0[start] {
+                                Some(w)
+                            } else {
+                                None
+                            }
+                        }
+                        Rule::NonLexical { rhs, .. } if rhs.len() == 2 => (1..span)
+                            .filter_map(|m| {
+                                let left = best.get(&(start, m, rhs[0].as_str()))?;
+                                let right = best.get(&(start + m, span - m, rhs[1].as_str()))?;
+                                Some(w * left * right)
+                            })
+                            .max_by_key(|s| FloatOrd(*s)),
+                        _ => None,
+                    };
+                    if let Some(score) = score {
+                        let lhs = match &weighted_rule.rule {
+                            Rule::Lexical { lhs, .. } | Rule::NonLexical { lhs, .. } => {
+                                lhs.as_str()
+                            }
+                        };
+                        let entry = cell.entry(lhs).or_insert(0.0);
+                        *entry = entry.max(score);
+                    }
+                }
+
+                // Relax chain rules until nothing improves. All weights are at most 1,
+                // so cycles never improve a score and this terminates.
+                let mut changed = true;
+                while changed {
+                    changed = false;
+                    for weighted_rule in &self.rules {
+                        if let Rule::NonLexical { lhs, rhs } = &weighted_rule.rule {
+                            if let [b] = rhs.as_slice() {
+                                if let Some(&b_score) = cell.get(b.as_str()) {
+                                    let score = weighted_rule.weight.0 * b_score;
+                                    let entry = cell.entry(lhs.as_str()).or_insert(0.0);
+                                    if score > *entry {
+                                        *entry = score;
+                                        changed = true;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+
+                for (nt, score) in cell {
+                    best.insert((start, span, nt), score);
+                }
+            }
+        }
+
+        best.get(&(0, n, "N0")).copied()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fuzz_cyk() {
+        let config = FuzzConfig {
+            nonterminals: 4,
+            terminals: 3,
+            max_length: 6,
+        };
+        let mut rng = XorShift::new(42);
+
+        for _ in 0..200 {
+            let case = FuzzCase::random(&mut rng, &config);
+            if let Err(mismatch) = case.check() {
+                panic!("{}\n{}", case, mismatch);
+            }
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use super::rule::Rule;
+use super::rule::{Rule, WeightScale};
 use crate::tree::Tree;
 
 use fxhash::{FxHashMap, FxHashSet};
@@ -7,6 +7,10 @@ use multimap::MultiMap;
 use std::fmt::Display;
 use std::hash::Hash;
 use std::io::{self, Write};
+use std::ops::Add;
+
+/// Prefix of the non-terminals of the rules induced by `GrammarBare::tag_bigrams`.
+pub const TAG_SEQUENCE_PREFIX: &str = "~";
 
 #[derive(Debug)]
 pub struct GrammarBare<N, T, W>
@@ -110,6 +114,140 @@ where
     }
 }
 
+impl<A: Eq + Hash> GrammarBare<A, A, u32> {
+    /// Induces only lexical rules from a sentence of (word, tag) pairs.
+    pub fn from_tagged<I: IntoIterator<Item = (A, A)>>(tokens: I) -> Self {
+        let mut rule_set = GrammarBare {
+            rules: FxHashMap::default(),
+        };
+
+        for (word, tag) in tokens {
+            rule_set.insert(Rule::Lexical {
+                lhs: tag,
+                rhs: word,
+            });
+        }
+
+        rule_set
+    }
+
+    /// Induces the rules of a bigram model of the tags of a sentence as a right-branching
+    /// grammar: `initial -> ~T1`, `~Ti -> Ti ~Ti+1` and `~Tn -> Tn`, where `~T` derives the
+    /// rest of the sentence starting with the tag `T` (see `TAG_SEQUENCE_PREFIX`).
+    pub fn tag_bigrams(tags: &[A], initial: &A) -> Self
+    where
+        A: Clone + Display + for<'a> From<&'a str>,
+    {
+        let mut rule_set = GrammarBare {
+            rules: FxHashMap::default(),
+        };
+        let sequence = |tag: &A| A::from(format!("{}{}", TAG_SEQUENCE_PREFIX, tag).as_str());
+
+        if let Some(first) = tags.first() {
+            rule_set.insert(Rule::NonLexical {
+                lhs: initial.clone(),
+                rhs: vec![sequence(first)],
+            });
+        }
+        for (i, tag) in tags.iter().enumerate() {
+            let mut rhs = vec![tag.clone()];
+            rhs.extend(tags.get(i + 1).map(sequence));
+            rule_set.insert(Rule::NonLexical {
+                lhs: sequence(tag),
+                rhs,
+            });
+        }
+
+        rule_set
+    }
+
+    /// Adds the counts of the lexical rules of the words that occur at most `threshold` times
+    /// to the rules of their tags with `unknown(word)`, e.g. `UNK` or the signature of the word.
+    /// After normalisation, unknown words get the tags of the rare words, without unking the
+    /// trees. Returns the number of rare words.
+    pub fn add_unknown_words<F: FnMut(&A) -> A>(&mut self, threshold: u32, mut unknown: F) -> usize
+    where
+        A: Clone,
+    {
+        let mut words: FxHashMap<&A, u32> = FxHashMap::default();
+        for (rule, count) in &self.rules {
+            if let Rule::Lexical { rhs, .. } = rule {
+                *words.entry(rhs).or_insert(0) += count;
+            }
+        }
+        let rare: Vec<_> = self
+            .rules
+            .iter()
+            .filter_map(|(rule, count)| match rule {
+                Rule::Lexical { lhs, rhs } if words[rhs] <= threshold => {
+                    Some((lhs.clone(), rhs.clone(), *count))
+                }
+                _ => None,
+            })
+            .collect();
+        let rare_words = words.values().filter(|c| **c <= threshold).count();
+
+        for (lhs, word, count) in rare {
+            let rhs = unknown(&word);
+            self.insert_with_weight(Rule::Lexical { lhs, rhs }, count);
+        }
+        rare_words
+    }
+}
+
+impl<A, W> GrammarBare<A, A, W>
+where
+    A: Eq + Hash + Clone + Display + for<'a> From<&'a str>,
+    W: Copy + Add<Output = W>,
+{
+    /// Moves the lexical rules of the non-terminals that also have non-lexical rules, i.e. of
+    /// phrases that directly dominate a word as in `(NP dog)`, below a synthetic preterminal
+    /// labeled with the non-terminal followed by `suffix`. The phrase gets a chain rule to the
+    /// preterminal with the weight of the moved rules, so the probabilities of trees don't
+    /// change. Returns the number of phrases that got a preterminal.
+    pub fn split_preterminals(&mut self, suffix: &str) -> usize {
+        let phrases: FxHashSet<A> = self
+            .rules
+            .keys()
+            .filter_map(|rule| match rule {
+                Rule::NonLexical { lhs, .. } => Some(lhs.clone()),
+                Rule::Lexical { .. } => None,
+            })
+            .collect();
+        let moved: Vec<_> = self
+            .rules
+            .keys()
+            .filter(|rule| matches!(rule, Rule::Lexical { lhs, .. } if phrases.contains(lhs)))
+            .cloned()
+            .collect();
+
+        let mut split = FxHashSet::default();
+        for rule in moved {
+            let weight = self.rules.remove(&rule).unwrap();
+            if let Rule::Lexical { lhs, rhs } = rule {
+                let preterminal = A::from(format!("{}{}", lhs, suffix).as_str());
+                split.insert(lhs.clone());
+                for rule in [
+                    Rule::NonLexical {
+                        lhs,
+                        rhs: vec![preterminal.clone()],
+                    },
+                    Rule::Lexical {
+                        lhs: preterminal,
+                        rhs,
+                    },
+                ] {
+                    self.rules
+                        .entry(rule)
+                        .and_modify(|w| *w = *w + weight)
+                        .or_insert(weight);
+                }
+            }
+        }
+        split.len()
+    }
+}
+
 impl<N: Eq + Hash + Display, T: Eq + Hash + Display, W: Display> Default for GrammarBare<N, T, W> {
     fn default() -> Self {
         Self::new()
@@ -181,9 +319,223 @@ impl<A: Eq + Hash + Clone> From<GrammarBare<A, A, u32>> for GrammarBare<A, A, f6
     }
 }
 
+impl<A: Eq + Hash + Clone> GrammarBare<A, A, f64> {
+    /// Adds the counts of `counts`, e.g. of a single tree, multiplied by `weight`, so that
+    /// fractional counts can be accumulated and normalised afterwards.
+    pub fn absorb_weighted(&mut self, counts: GrammarBare<A, A, u32>, weight: f64) {
+        for (rule, count) in counts.rules {
+            *self.rules.entry(rule).or_insert(0.0) += weight * count as f64;
+        }
+    }
+
+    /// Normalises the weights of the rules of every non-terminal to add up to 1.
+    /// Rules with weight 0 are left out.
+    pub fn normalised(self) -> Self {
+        let mut totals: FxHashMap<A, f64> = FxHashMap::default();
+        for (rule, weight) in &self.rules {
+            let lhs = match rule {
+                Rule::Lexical { lhs, .. } | Rule::NonLexical { lhs, .. } => lhs,
+            };
+            *totals.entry(lhs.clone()).or_insert(0.0) += weight;
+        }
+
+        let rules = self
+            .rules
+            .into_iter()
+            .filter(|(_, weight)| *weight > 0.0)
+            .map(|(rule, weight)| {
+                let lhs = match &rule {
+                    Rule::Lexical { lhs, .. } | Rule::NonLexical { lhs, .. } => lhs,
+                };
+                let weight = weight / totals[lhs];
+                (rule, weight)
+            })
+            .collect();
+
+        GrammarBare { rules }
+    }
+
+    /// Adds the rules of `gold`, e.g. counted in gold trees, that the grammar lacks. Every missing
+    /// rule gets `smoothing` times its count as weight, so the grammar should be normalised
+    /// afterwards. Returns the number of added rules.
+    pub fn add_missing(&mut self, gold: GrammarBare<A, A, u32>, smoothing: f64) -> usize {
+        let mut added = 0;
+        for (rule, count) in gold.rules {
+            self.rules.entry(rule).or_insert_with(|| {
+                added += 1;
+                smoothing * count as f64
+            });
+        }
+        added
+    }
+
+    /// Weights on `scale` instead of probabilities, to be written for other toolkits.
+    pub fn rescaled(mut self, scale: WeightScale) -> Self {
+        for weight in self.rules.values_mut() {
+            *weight = scale.encode(*weight);
+        }
+        self
+    }
+
+    /// Mixes normalised grammars with the given weights, which should add up to 1.
+    /// A non-terminal that only occurs in some of the grammars gets its rules from those,
+    /// with their weights scaled up, so that the rules of every non-terminal still add up to 1.
+    pub fn interpolate<I: IntoIterator<Item = (Self, f64)>>(grammars: I) -> Self {
+        let mut rules: FxHashMap<Rule<A, A>, f64> = FxHashMap::default();
+        let mut lhs_weights: FxHashMap<A, f64> = FxHashMap::default();
+
+        for (grammar, mixture_weight) in grammars {
+            let mut seen = FxHashSet::default();
+            for (rule, weight) in grammar.rules {
+                let lhs = match &rule {
+                    Rule::Lexical { lhs, .. } | Rule::NonLexical { lhs, .. } => lhs.clone(),
+                };
+                if seen.insert(lhs.clone()) {
+                    *lhs_weights.entry(lhs).or_insert(0.0) += mixture_weight;
+                }
+                *rules.entry(rule).or_insert(0.0) += mixture_weight * weight;
+            }
+        }
+
+        for (rule, weight) in rules.iter_mut() {
+            let lhs = match rule {
+                Rule::Lexical { lhs, .. } | Rule::NonLexical { lhs, .. } => lhs,
+            };
+            *weight /= lhs_weights[lhs];
+        }
+
+        GrammarBare { rules }
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use std::str::FromStr;
+
     use super::*;
+    use crate::SExp;
+
+    #[test]
+    fn interpolation() {
+        let grammar = |rules: &[(&str, &str, f64)]| GrammarBare {
+            rules: rules
+                .iter()
+                .map(|(lhs, rhs, w)| {
+                    (
+                        Rule::Lexical {
+                            lhs: lhs.to_string(),
+                            rhs: rhs.to_string(),
+                        },
+                        *w,
+                    )
+                })
+                .collect(),
+        };
+        let news = grammar(&[
+            ("N", "stock", 0.5),
+            ("N", "market", 0.5),
+            ("V", "falls", 1.0),
+        ]);
+        let web = grammar(&[("N", "stock", 0.25), ("N", "lol", 0.75)]);
+
+        let mixed = GrammarBare::interpolate([(news, 0.8), (web, 0.2)]);
+        let weight = |lhs: &str, rhs: &str| {
+            mixed.rules[&Rule::Lexical {
+                lhs: lhs.to_string(),
+                rhs: rhs.to_string(),
+            }]
+        };
+
+        assert!((weight("N", "stock") - (0.8 * 0.5 + 0.2 * 0.25)).abs() < 1e-12);
+        assert!((weight("N", "market") - 0.8 * 0.5).abs() < 1e-12);
+        assert!((weight("N", "lol") - 0.2 * 0.75).abs() < 1e-12);
+        // V only occurs in the first grammar.
+        assert!((weight("V", "falls") - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn weighted_counts() {
+        let lexical = |lhs: &str, rhs: &str| Rule::Lexical {
+            lhs: lhs.to_string(),
+            rhs: rhs.to_string(),
+        };
+        let tagged = |words: &[&str]| {
+            GrammarBare::from_tagged(words.iter().map(|w| (w.to_string(), "N".to_string())))
+        };
+        let mut grammar = GrammarBare::new();
+        grammar.absorb_weighted(tagged(&["dog", "cat"]), 0.25);
+        grammar.absorb_weighted(tagged(&["dog"]), 0.5);
+        grammar.absorb_weighted(tagged(&["fish"]), 0.0);
+
+        assert!((grammar.rules[&lexical("N", "dog")] - 0.75).abs() < 1e-12);
+        let grammar = grammar.normalised();
+        assert!((grammar.rules[&lexical("N", "dog")] - 0.75).abs() < 1e-12);
+        assert!((grammar.rules[&lexical("N", "cat")] - 0.25).abs() < 1e-12);
+        assert!(!grammar.rules.contains_key(&lexical("N", "fish")));
+    }
+
+    #[test]
+    fn missing_rules() {
+        let lexical = |lhs: &str, rhs: &str| Rule::Lexical {
+            lhs: lhs.to_string(),
+            rhs: rhs.to_string(),
+        };
+        let mut grammar = GrammarBare {
+            rules: [(lexical("N", "dog"), 1.0)].into_iter().collect(),
+        };
+        let mut gold = GrammarBare::new();
+        gold.insert(lexical("N", "dog"));
+        gold.insert(lexical("N", "cat"));
+        gold.insert(lexical("N", "cat"));
+
+        assert_eq!(1, grammar.add_missing(gold, 0.01));
+        let grammar = grammar.normalised();
+        assert!((grammar.rules[&lexical("N", "cat")] - 0.02 / 1.02).abs() < 1e-12);
+        assert!((grammar.rules[&lexical("N", "dog")] - 1.0 / 1.02).abs() < 1e-12);
+    }
+
+    #[test]
+    fn tag_bigram_rules() {
+        let tags: Vec<_> = ["DT", "NN", "NN"].iter().map(|t| t.to_string()).collect();
+        let grammar = GrammarBare::tag_bigrams(&tags, &"ROOT".to_string());
+        let rule = |lhs: &str, rhs: &[&str]| Rule::NonLexical {
+            lhs: lhs.to_string(),
+            rhs: rhs.iter().map(|n| n.to_string()).collect(),
+        };
+
+        assert_eq!(4, grammar.len());
+        assert_eq!(1, grammar.rules[&rule("ROOT", &["~DT"])]);
+        assert_eq!(1, grammar.rules[&rule("~DT", &["DT", "~NN"])]);
+        assert_eq!(1, grammar.rules[&rule("~NN", &["NN", "~NN"])]);
+        assert_eq!(1, grammar.rules[&rule("~NN", &["NN"])]);
+    }
+
+    #[test]
+    fn split_preterminals() {
+        let tree = |s: &str| {
+            Tree::try_from(SExp::from_str(s).unwrap())
+                .unwrap()
+                .map(&mut |n| n.to_string())
+        };
+        let mut grammar = GrammarBare::from(tree("(S (NP (DT the) (NN dog)) (VP barks))"));
+        grammar.absorb(GrammarBare::from(tree("(S (NP dogs) (VP (VBP bark)))")));
+        let lexical = |lhs: &str, rhs: &str| Rule::Lexical {
+            lhs: lhs.to_string(),
+            rhs: rhs.to_string(),
+        };
+        let chain = |lhs: &str, rhs: &str| Rule::NonLexical {
+            lhs: lhs.to_string(),
+            rhs: vec![rhs.to_string()],
+        };
+
+        assert_eq!(2, grammar.split_preterminals("-POS"));
+        assert_eq!(1, grammar.rules[&chain("NP", "NP-POS")]);
+        assert_eq!(1, grammar.rules[&lexical("NP-POS", "dogs")]);
+        assert_eq!(1, grammar.rules[&chain("VP", "VP-POS")]);
+        assert_eq!(1, grammar.rules[&lexical("VP-POS", "barks")]);
+        assert_eq!(1, grammar.rules[&lexical("DT", "the")]);
+        assert!(!grammar.rules.contains_key(&lexical("NP", "dogs")));
+    }
 
     #[test]
     fn basic_rule_induction_from_tree() {
@@ -333,4 +685,46 @@ mod test {
             Some(&1.0)
         );
     }
+
+    #[test]
+    fn lexicon_induction_from_tagged() {
+        let rule_set = GrammarBare::from_tagged(vec![
+            ("the".to_string(), "D".to_string()),
+            ("ball".to_string(), "N".to_string()),
+            ("the".to_string(), "D".to_string()),
+        ]);
+
+        assert_eq!(rule_set.len(), 2);
+        assert_eq!(
+            rule_set.rules.get(&Rule::Lexical {
+                lhs: "D".to_string(),
+                rhs: "the".to_string()
+            }),
+            Some(&2)
+        );
+    }
+
+    #[test]
+    fn unknown_words() {
+        let mut rule_set = GrammarBare::from_tagged(vec![
+            ("the".to_string(), "D".to_string()),
+            ("the".to_string(), "D".to_string()),
+            ("ball".to_string(), "N".to_string()),
+            ("run".to_string(), "N".to_string()),
+            ("run".to_string(), "V".to_string()),
+        ]);
+
+        assert_eq!(1, rule_set.add_unknown_words(1, |_| "UNK".to_string()));
+        let lexical = |lhs: &str, rhs: &str| Rule::Lexical {
+            lhs: lhs.to_string(),
+            rhs: rhs.to_string(),
+        };
+        assert_eq!(Some(&1), rule_set.rules.get(&lexical("N", "UNK")));
+        assert_eq!(None, rule_set.rules.get(&lexical("V", "UNK")));
+        assert_eq!(Some(&1), rule_set.rules.get(&lexical("N", "ball")));
+
+        rule_set.add_unknown_words(2, |_| "UNK".to_string());
+        assert_eq!(Some(&3), rule_set.rules.get(&lexical("N", "UNK")));
+        assert_eq!(Some(&1), rule_set.rules.get(&lexical("V", "UNK")));
+    }
 }
@@ -0,0 +1,103 @@
+use std::io::{self, Read, Write};
+
+/// Start of grammars compiled by `GrammarParse::write_compiled`. The null byte makes
+/// `GrammarFormat::detect` recognise them as binary.
+pub const COMPILED_MAGIC: &[u8; 8] = b"PCFG\0BIN";
+/// Changed whenever the layout of compiled grammars changes.
+pub const COMPILED_VERSION: u32 = 2;
+
+pub fn write_u32<W: Write>(buf: &mut W, n: u32) -> io::Result<()> {
+    buf.write_all(&n.to_le_bytes())
+}
+
+pub fn write_u64<W: Write>(buf: &mut W, n: u64) -> io::Result<()> {
+    buf.write_all(&n.to_le_bytes())
+}
+
+pub fn write_f64<W: Write>(buf: &mut W, x: f64) -> io::Result<()> {
+    buf.write_all(&x.to_le_bytes())
+}
+
+/// Strings are written with their length in bytes in front.
+pub fn write_str<W: Write>(buf: &mut W, s: &str) -> io::Result<()> {
+    write_u32(buf, s.len() as u32)?;
+    buf.write_all(s.as_bytes())
+}
+
+pub fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub fn read_f64<R: Read>(reader: &mut R) -> io::Result<f64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+pub fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let mut bytes = vec![0; read_u32(reader)? as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Checks that the reader is at the start of a compiled grammar of the current version.
+pub fn read_header<R: Read>(reader: &mut R) -> io::Result<()> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != COMPILED_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a compiled grammar",
+        ));
+    }
+
+    let version = read_u32(reader)?;
+    if version != COMPILED_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "grammar was compiled with version {} of the format, but {} is expected, \
+                 compile it again",
+                version, COMPILED_VERSION
+            ),
+        ));
+    }
+    Ok(())
+}
+
+pub fn write_header<W: Write>(buf: &mut W) -> io::Result<()> {
+    buf.write_all(COMPILED_MAGIC)?;
+    write_u32(buf, COMPILED_VERSION)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::grammar::format::GrammarFormat;
+
+    #[test]
+    fn primitives_roundtrip() {
+        let mut buf = vec![];
+        write_header(&mut buf).unwrap();
+        write_str(&mut buf, "NP-SBJ").unwrap();
+        write_u64(&mut buf, 1 << 40).unwrap();
+        write_f64(&mut buf, -0.5).unwrap();
+        assert_eq!(GrammarFormat::Binary, GrammarFormat::detect(&buf));
+
+        let mut reader = &buf[..];
+        read_header(&mut reader).unwrap();
+        assert_eq!("NP-SBJ", read_string(&mut reader).unwrap());
+        assert_eq!(1 << 40, read_u64(&mut reader).unwrap());
+        assert_eq!(-0.5, read_f64(&mut reader).unwrap());
+        assert!(read_u32(&mut reader).is_err());
+        assert!(read_header(&mut &b"S -> NP VP 1.0"[..]).is_err());
+    }
+}
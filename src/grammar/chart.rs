@@ -1,5 +1,6 @@
 use std::ops::{Index, IndexMut};
 
+#[derive(Clone)]
 pub struct Chart<T> {
     data: Vec<T>,
     sentence_len: usize,
@@ -36,17 +37,38 @@ where
         &mut self.data[start..(start + self.num_nonterminals)]
     }
 
+    /// Splits the chart into the cells of all spans shorter than `span` and the cells of `span`,
+    /// ordered by their start position. Cells only depend on cells of shorter spans, so the
+    /// cells of one span can be filled in parallel.
+    pub fn split_span_mut(&mut self, span: usize) -> (&[T], &mut [T]) {
+        let start = self.cell_start_index(0, span);
+        let end = start + (self.sentence_len - span + 1) * self.num_nonterminals;
+        let (shorter, rest) = self.data.split_at_mut(start);
+        (shorter, &mut rest[..(end - start)])
+    }
+
     /// Calculates the index for the corresponding cell.
     /// Individual cells are further subdivided for each entry.
     /// This offset has to be added afterwards.
     pub const fn cell_start_index(&self, start_pos: usize, span: usize) -> ChartIdx {
-        let rows_subtract = self.sentence_len - span + 1;
-        let base_cells_subtract = (rows_subtract * (rows_subtract + 1)) / 2;
-        let num_base_cells = (self.sentence_len * (self.sentence_len + 1)) / 2;
-        (num_base_cells - base_cells_subtract + start_pos) * self.num_nonterminals
+        cell_start_index(self.sentence_len, self.num_nonterminals, start_pos, span)
     }
 }
 
+/// Index of a cell in a chart with the given dimensions, see `Chart::cell_start_index`.
+/// Used where the chart itself is borrowed, e.g. after `Chart::split_span_mut`.
+pub const fn cell_start_index(
+    sentence_len: usize,
+    num_nonterminals: usize,
+    start_pos: usize,
+    span: usize,
+) -> ChartIdx {
+    let rows_subtract = sentence_len - span + 1;
+    let base_cells_subtract = (rows_subtract * (rows_subtract + 1)) / 2;
+    let num_base_cells = (sentence_len * (sentence_len + 1)) / 2;
+    (num_base_cells - base_cells_subtract + start_pos) * num_nonterminals
+}
+
 impl<T> Index<usize> for Chart<T> {
     type Output = T;
 
@@ -60,3 +82,61 @@ impl<T> IndexMut<usize> for Chart<T> {
         &mut self.data[index]
     }
 }
+
+/// Index of the non-terminals whose entries survived pruning, per cell and in increasing order.
+/// It only serves to iterate over the entries of a complete cell without scanning all of its
+/// non-terminals; the entries themselves stay in the dense chart.
+pub struct SurvivorIndex {
+    cells: Vec<Vec<u32>>,
+    num_nonterminals: usize,
+}
+
+impl SurvivorIndex {
+    pub fn new(sentence_len: usize, num_nonterminals: usize) -> Self {
+        Self {
+            cells: vec![vec![]; sentence_len * (sentence_len + 1) / 2],
+            num_nonterminals,
+        }
+    }
+
+    /// Records the entries of `cell`, which starts at index `start` of the chart, for which
+    /// `is_set` holds.
+    pub fn insert_cell<T, F: Fn(&T) -> bool>(&mut self, start: ChartIdx, cell: &[T], is_set: F) {
+        self.cells[start / self.num_nonterminals] = cell
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| is_set(entry))
+            .map(|(a, _)| a as u32)
+            .collect();
+    }
+
+    /// The non-terminals of the cell starting at index `start` of the chart.
+    pub fn entries(&self, start: ChartIdx) -> &[u32] {
+        &self.cells[start / self.num_nonterminals]
+    }
+}
+
+/// Marks the entries of a chart that may be used, e.g. those that survived the pass of
+/// a coarse grammar.
+#[derive(Clone)]
+pub struct SpanMask {
+    allowed: Chart<bool>,
+}
+
+impl SpanMask {
+    /// Creates a mask that allows no entries.
+    pub fn new(sentence_len: usize, num_nonterminals: usize) -> Self {
+        Self {
+            allowed: Chart::new(sentence_len, num_nonterminals),
+        }
+    }
+
+    pub fn allow(&mut self, start: usize, span: usize, nt: usize) {
+        let idx = self.allowed.cell_start_index(start, span) + nt;
+        self.allowed[idx] = true;
+    }
+
+    pub fn is_allowed(&self, start: usize, span: usize, nt: usize) -> bool {
+        self.allowed[self.allowed.cell_start_index(start, span) + nt]
+    }
+}
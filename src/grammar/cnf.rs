@@ -0,0 +1,689 @@
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+
+use float_ord::FloatOrd;
+use fxhash::{FxHashMap, FxHashSet};
+
+use super::parse::UnaryCycle;
+use super::rule::{Rule, WeightedRule};
+use crate::tree::Tree;
+
+/// Iterations after which the computation of the probabilities of empty derivations stops.
+const MAX_EPSILON_ITERATIONS: usize = 1000;
+
+type Weighted<N> = WeightedRule<N, N, FloatOrd<f64>>;
+/// Chains of unary rules that rules stand for, see `CnfMapping`.
+type Chains<N> = FxHashMap<Rule<N, N>, Vec<N>>;
+/// Rules in strict CNF and how to restore trees of the original grammar.
+type StrictCnf<N> = (Vec<Weighted<N>>, CnfMapping<N>);
+type WithoutChains<N> = (Vec<Weighted<N>>, Chains<N>);
+
+/// Ways in which a non-lexical rule violates the requirements of the CYK parser.
+/// Chain rules are allowed, since the parser computes the unary closure.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CnfViolation<N> {
+    /// More than two non-terminals on the RHS.
+    NAry,
+    /// Empty RHS.
+    Epsilon,
+    /// Terminal on the RHS of a non-lexical rule.
+    Terminal(N),
+}
+
+impl<N: fmt::Display> fmt::Display for CnfViolation<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CnfViolation::NAry => write!(f, "more than two symbols on the RHS"),
+            CnfViolation::Epsilon => write!(f, "empty RHS"),
+            CnfViolation::Terminal(t) => write!(f, "terminal {} on the RHS", t),
+        }
+    }
+}
+
+/// Lists the violations of a rule. `terminals` are the symbols that are never on a LHS.
+pub fn cnf_violations<N: Eq + Hash + Clone, T: Eq + Hash>(
+    rule: &Rule<N, T>,
+    terminals: &FxHashSet<N>,
+) -> Vec<CnfViolation<N>> {
+    let mut violations = vec![];
+
+    if let Rule::NonLexical { rhs, .. } = rule {
+        if rhs.is_empty() {
+            violations.push(CnfViolation::Epsilon);
+        }
+        if rhs.len() > 2 {
+            violations.push(CnfViolation::NAry);
+        }
+        for n in rhs.iter().filter(|n| terminals.contains(*n)) {
+            violations.push(CnfViolation::Terminal(n.clone()));
+        }
+    }
+
+    violations
+}
+
+/// Rewrites a grammar, so that it only has lexical rules, chain rules and binary rules.
+///
+/// Terminals in non-lexical rules are replaced by new preterminals `TERM-t`.
+/// Longer rules are split into binary ones with new non-terminals in the
+/// markovisation format, e.g. `A -> B C D` into `A -> B A|<C,D>` and `A|<C,D> -> C D`.
+/// Finally rules with empty RHS are removed. To preserve the weights of non-empty
+/// derivations, every rule is copied for each way its RHS can derive the empty word.
+pub fn to_cnf<N>(rules: Vec<Weighted<N>>, terminals: &FxHashSet<N>) -> Vec<Weighted<N>>
+where
+    N: Eq + Hash + Clone + fmt::Display + for<'a> From<&'a str>,
+{
+    to_cnf_introducing(rules, terminals, &mut FxHashSet::default())
+}
+
+/// Rewrites a grammar into strict CNF, with only binary and lexical rules: `to_cnf` followed
+/// by the removal of chain rules. For every chain of unary rules from `A` down to `B` and rule
+/// `B -> C D` or `B t`, the rule `A -> C D` or `A t` is added with the weight of the chain and
+/// the rule. Of several derivations of the same rule only the most probable one is kept, so
+/// that parsing finds the same most probable trees, but the weights are no longer normalised.
+/// Fails if chain rules form a cycle with a weight of at least 1.
+pub fn to_strict_cnf<N>(
+    rules: Vec<Weighted<N>>,
+    terminals: &FxHashSet<N>,
+) -> Result<StrictCnf<N>, UnaryCycle<N>>
+where
+    N: Eq + Hash + Clone + fmt::Display + for<'a> From<&'a str>,
+{
+    let mut introduced = FxHashSet::default();
+    let rules = to_cnf_introducing(rules, terminals, &mut introduced);
+    let (rules, chains) = remove_chains(rules)?;
+
+    Ok((rules, CnfMapping { introduced, chains }))
+}
+
+fn to_cnf_introducing<N>(
+    rules: Vec<Weighted<N>>,
+    terminals: &FxHashSet<N>,
+    introduced: &mut FxHashSet<N>,
+) -> Vec<Weighted<N>>
+where
+    N: Eq + Hash + Clone + fmt::Display + for<'a> From<&'a str>,
+{
+    let mut result = vec![];
+    let mut preterminals = FxHashSet::default();
+    let mut intermediates = FxHashSet::default();
+
+    for weighted_rule in rules {
+        match weighted_rule.rule {
+            Rule::NonLexical { lhs, rhs } => {
+                let rhs: Vec<N> = rhs
+                    .into_iter()
+                    .map(|n| {
+                        if terminals.contains(&n) {
+                            let preterminal = N::from(format!("TERM-{}", n).as_str());
+                            if preterminals.insert(preterminal.clone()) {
+                                result.push(WeightedRule {
+                                    rule: Rule::Lexical {
+                                        lhs: preterminal.clone(),
+                                        rhs: n,
+                                    },
+                                    weight: FloatOrd(1.0),
+                                });
+                            }
+                            preterminal
+                        } else {
+                            n
+                        }
+                    })
+                    .collect();
+
+                binarise(
+                    lhs,
+                    rhs,
+                    weighted_rule.weight,
+                    &mut intermediates,
+                    &mut result,
+                );
+            }
+            rule => result.push(WeightedRule {
+                rule,
+                weight: weighted_rule.weight,
+            }),
+        }
+    }
+
+    introduced.extend(preterminals);
+    introduced.extend(intermediates);
+    remove_epsilon(result)
+}
+
+fn binarise<N>(
+    lhs: N,
+    mut rhs: Vec<N>,
+    weight: FloatOrd<f64>,
+    intermediates: &mut FxHashSet<N>,
+    result: &mut Vec<Weighted<N>>,
+) where
+    N: Eq + Hash + Clone + fmt::Display + for<'a> From<&'a str>,
+{
+    if rhs.len() <= 2 {
+        result.push(WeightedRule {
+            rule: Rule::NonLexical { lhs, rhs },
+            weight,
+        });
+        return;
+    }
+
+    let rest = rhs.split_off(1);
+    let label = format!(
+        "{}|<{}>",
+        lhs,
+        rest.iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    );
+    let intermediate = N::from(label.as_str());
+
+    rhs.push(intermediate.clone());
+    result.push(WeightedRule {
+        rule: Rule::NonLexical { lhs, rhs },
+        weight,
+    });
+
+    // Equal labels have equal expansions, which only have to be added once.
+    if intermediates.insert(intermediate.clone()) {
+        binarise(intermediate, rest, FloatOrd(1.0), intermediates, result);
+    }
+}
+
+fn remove_epsilon<N>(rules: Vec<Weighted<N>>) -> Vec<Weighted<N>>
+where
+    N: Eq + Hash + Clone,
+{
+    // Probability that a non-terminal derives the empty word.
+    let mut empty: FxHashMap<N, f64> = FxHashMap::default();
+
+    if rules
+        .iter()
+        .any(|r| matches!(&r.rule, Rule::NonLexical { rhs, .. } if rhs.is_empty()))
+    {
+        for _ in 0..MAX_EPSILON_ITERATIONS {
+            let mut next: FxHashMap<N, f64> = FxHashMap::default();
+            for weighted_rule in &rules {
+                if let Rule::NonLexical { lhs, rhs } = &weighted_rule.rule {
+                    let p: f64 = rhs
+                        .iter()
+                        .map(|n| empty.get(n).copied().unwrap_or(0.0))
+                        .product();
+                    *next.entry(lhs.clone()).or_insert(0.0) += weighted_rule.weight.0 * p;
+                }
+            }
+
+            let converged = next
+                .iter()
+                .all(|(n, p)| (p - empty.get(n).copied().unwrap_or(0.0)).abs() < 1e-12);
+            empty = next;
+            if converged {
+                break;
+            }
+        }
+        empty.retain(|_, p| *p > 0.0);
+    }
+
+    if empty.is_empty() {
+        return rules;
+    }
+
+    let e = |n: &N| empty.get(n).copied().unwrap_or(0.0);
+    let mut result: FxHashMap<Rule<N, N>, f64> = FxHashMap::default();
+
+    for weighted_rule in rules {
+        match weighted_rule.rule {
+            Rule::NonLexical { lhs, rhs } => {
+                if e(&lhs) >= 1.0 {
+                    continue;
+                }
+                let norm = 1.0 - e(&lhs);
+
+                // Every subset of the RHS that is kept, as a bit mask.
+                for mask in 1..(1usize << rhs.len()) {
+                    let mut weight = weighted_rule.weight.0 / norm;
+                    let mut kept = vec![];
+                    for (i, n) in rhs.iter().enumerate() {
+                        if mask & (1 << i) != 0 {
+                            weight *= 1.0 - e(n);
+                            kept.push(n.clone());
+                        } else {
+                            weight *= e(n);
+                        }
+                    }
+
+                    // A chain rule to itself only adds to derivations of the same item.
+                    if weight > 0.0 && !(kept.len() == 1 && kept[0] == lhs) {
+                        *result
+                            .entry(Rule::NonLexical {
+                                lhs: lhs.clone(),
+                                rhs: kept,
+                            })
+                            .or_insert(0.0) += weight;
+                    }
+                }
+            }
+            Rule::Lexical { lhs, rhs } => {
+                if e(&lhs) < 1.0 {
+                    let weight = weighted_rule.weight.0 / (1.0 - e(&lhs));
+                    *result.entry(Rule::Lexical { lhs, rhs }).or_insert(0.0) += weight;
+                }
+            }
+        }
+    }
+
+    result
+        .drain()
+        .map(|(rule, weight)| WeightedRule {
+            rule,
+            weight: FloatOrd(weight),
+        })
+        .collect()
+}
+
+/// Replaces the chain rules of a grammar, see `to_strict_cnf`. Returns the chains that the
+/// kept rules stand for, without their top non-terminal.
+fn remove_chains<N>(rules: Vec<Weighted<N>>) -> Result<WithoutChains<N>, UnaryCycle<N>>
+where
+    N: Eq + Hash + Clone,
+{
+    let (chain_rules, rules): (Vec<_>, Vec<_>) = rules
+        .into_iter()
+        .partition(|r| matches!(&r.rule, Rule::NonLexical { rhs, .. } if rhs.len() == 1));
+
+    // The non-terminals of chain rules are numbered, with the parents of every non-terminal.
+    let mut labels: Vec<N> = vec![];
+    let mut index: FxHashMap<N, usize> = FxHashMap::default();
+    let mut parents: Vec<Vec<(usize, f64)>> = vec![];
+    for weighted_rule in &chain_rules {
+        if let Rule::NonLexical { lhs, rhs } = &weighted_rule.rule {
+            let [a, b] = [lhs, &rhs[0]].map(|n| {
+                *index.entry(n.clone()).or_insert_with(|| {
+                    labels.push(n.clone());
+                    parents.push(vec![]);
+                    labels.len() - 1
+                })
+            });
+            parents[b].push((a, weighted_rule.weight.0));
+        }
+    }
+    let num_nt = labels.len();
+
+    // Best chains ending in every non-terminal: top, weight and the non-terminals below the top.
+    let mut chains_to: Vec<Vec<(usize, f64, Vec<N>)>> = vec![vec![]; num_nt];
+    for (b, chains) in chains_to.iter_mut().enumerate() {
+        let mut best = vec![0.0; num_nt];
+        // Next non-terminal on the best chain towards `b`.
+        let mut next: Vec<Option<usize>> = vec![None; num_nt];
+        let mut queue = BinaryHeap::new();
+        queue.push((FloatOrd(1.0), b, None, 0));
+
+        while let Some((FloatOrd(w), a, below, len)) = queue.pop() {
+            // Longer chains contain a cycle that doesn't go through `b`. It is found
+            // when starting from one of its non-terminals.
+            if w <= best[a] || len > num_nt {
+                continue;
+            }
+            best[a] = w;
+            next[a] = below;
+
+            for (parent, rule_weight) in &parents[a] {
+                let weight = rule_weight * w;
+                if *parent == b && weight >= 1.0 {
+                    let mut chain = vec![labels[b].clone(), labels[a].clone()];
+                    let mut current = a;
+                    while let Some(n) = next[current] {
+                        chain.push(labels[n].clone());
+                        current = n;
+                    }
+                    return Err(UnaryCycle { chain, weight });
+                }
+                queue.push((FloatOrd(weight), *parent, Some(a), len + 1));
+            }
+        }
+
+        for a in (0..num_nt).filter(|a| *a != b && best[*a] > 0.0) {
+            let mut path = vec![];
+            let mut current = a;
+            while let Some(n) = next[current] {
+                path.push(labels[n].clone());
+                current = n;
+            }
+            chains.push((a, best[a], path));
+        }
+    }
+
+    // The best derivation of every rule, with the chain it stands for.
+    let mut best = FxHashMap::default();
+    let mut keep = |rule: Rule<N, N>, weight: f64, path: Option<Vec<N>>| {
+        let entry = best.entry(rule).or_insert((0.0, None));
+        // The rule itself is preferred over chains of the same weight.
+        if weight > entry.0 || (weight == entry.0 && path.is_none()) {
+            *entry = (weight, path);
+        }
+    };
+    for weighted_rule in rules {
+        let (Rule::Lexical { lhs, .. } | Rule::NonLexical { lhs, .. }) = &weighted_rule.rule;
+        if let Some(b) = index.get(lhs) {
+            for (a, chain_weight, path) in &chains_to[*b] {
+                let top = labels[*a].clone();
+                let rule = match &weighted_rule.rule {
+                    Rule::Lexical { rhs, .. } => Rule::Lexical {
+                        lhs: top,
+                        rhs: rhs.clone(),
+                    },
+                    Rule::NonLexical { rhs, .. } => Rule::NonLexical {
+                        lhs: top,
+                        rhs: rhs.clone(),
+                    },
+                };
+                keep(
+                    rule,
+                    chain_weight * weighted_rule.weight.0,
+                    Some(path.clone()),
+                );
+            }
+        }
+        keep(weighted_rule.rule, weighted_rule.weight.0, None);
+    }
+
+    let mut chains = FxHashMap::default();
+    let rules = best
+        .into_iter()
+        .map(|(rule, (weight, path))| {
+            if let Some(path) = path {
+                chains.insert(rule.clone(), path);
+            }
+            WeightedRule {
+                rule,
+                weight: FloatOrd(weight),
+            }
+        })
+        .collect();
+
+    Ok((rules, chains))
+}
+
+/// What `to_strict_cnf` changed in a grammar, to restore the trees of the original grammar
+/// from the parse trees of the new one. Written with one line per non-terminal or rule,
+/// `introduced LABEL` or `chain RULE PATH` with the rule in the format of the grammar files,
+/// separated by tabs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CnfMapping<N: Eq + Hash> {
+    /// Non-terminals that the transformation introduced. Their nodes are removed.
+    pub introduced: FxHashSet<N>,
+    /// Chains of unary rules that rules stand for, without their top non-terminal,
+    /// which is the LHS of the rule. The nodes of the chain are inserted below its top.
+    pub chains: Chains<N>,
+}
+
+impl<N: Eq + Hash + Clone> CnfMapping<N> {
+    /// Turns a parse tree of the CNF grammar into a tree of the original grammar.
+    /// Constituents without words, which the original grammar may derive, are not restored.
+    pub fn restore(&self, tree: Tree<N>) -> Tree<N> {
+        let root = tree.root.clone();
+        let mut restored = self.restore_node(tree);
+        match restored.len() {
+            1 => restored.remove(0),
+            _ => Tree {
+                root,
+                children: restored,
+            },
+        }
+    }
+
+    /// The restored node, or its restored children if it was introduced.
+    fn restore_node(&self, tree: Tree<N>) -> Vec<Tree<N>> {
+        if tree.is_leaf() {
+            return vec![tree];
+        }
+
+        let rule = match tree.children.as_slice() {
+            [word] if word.is_leaf() => Rule::Lexical {
+                lhs: tree.root.clone(),
+                rhs: word.root.clone(),
+            },
+            children => Rule::NonLexical {
+                lhs: tree.root.clone(),
+                rhs: children.iter().map(|c| c.root.clone()).collect(),
+            },
+        };
+        let mut children: Vec<_> = tree
+            .children
+            .into_iter()
+            .flat_map(|c| self.restore_node(c))
+            .collect();
+        if let Some(path) = self.chains.get(&rule) {
+            for n in path.iter().rev().filter(|n| !self.introduced.contains(*n)) {
+                children = vec![Tree {
+                    root: n.clone(),
+                    children,
+                }];
+            }
+        }
+
+        if self.introduced.contains(&tree.root) {
+            children
+        } else {
+            vec![Tree {
+                root: tree.root,
+                children,
+            }]
+        }
+    }
+}
+
+impl<N: Eq + Hash + fmt::Display> fmt::Display for CnfMapping<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Sorted, so that the same grammar always gives the same file.
+        let mut lines: Vec<_> = self
+            .introduced
+            .iter()
+            .map(|n| format!("introduced\t{}", n))
+            .chain(self.chains.iter().map(|(rule, path)| {
+                let rule = match rule {
+                    Rule::Lexical { lhs, rhs } => format!("{} {}", lhs, rhs),
+                    Rule::NonLexical { lhs, rhs } => format!(
+                        "{} -> {}",
+                        lhs,
+                        rhs.iter()
+                            .map(|n| n.to_string())
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    ),
+                };
+                let path: Vec<_> = path.iter().map(|n| n.to_string()).collect();
+                format!("chain\t{}\t{}", rule, path.join(" "))
+            }))
+            .collect();
+        lines.sort();
+
+        for line in lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl<N: Eq + Hash + for<'a> From<&'a str>> FromStr for CnfMapping<N> {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mapping = CnfMapping {
+            introduced: FxHashSet::default(),
+            chains: FxHashMap::default(),
+        };
+
+        for (idx, line) in s.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+            let invalid = || format!("invalid CNF mapping in line {}: {}", idx + 1, line);
+            match line.split('\t').collect::<Vec<_>>().as_slice() {
+                ["introduced", n] => {
+                    mapping.introduced.insert(N::from(n.trim()));
+                }
+                ["chain", rule, path] => {
+                    let symbols: Vec<_> = rule.split_whitespace().collect();
+                    let rule = match symbols.as_slice() {
+                        [lhs, "->", rhs @ ..] if !rhs.is_empty() => Rule::NonLexical {
+                            lhs: N::from(lhs),
+                            rhs: rhs.iter().map(|n| N::from(n)).collect(),
+                        },
+                        [lhs, rhs] => Rule::Lexical {
+                            lhs: N::from(lhs),
+                            rhs: N::from(rhs),
+                        },
+                        _ => return Err(invalid()),
+                    };
+                    let path: Vec<_> = path.split_whitespace().map(N::from).collect();
+                    if path.is_empty() {
+                        return Err(invalid());
+                    }
+                    mapping.chains.insert(rule, path);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(mapping)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rule(lhs: &str, rhs: &[&str], weight: f64) -> Weighted<String> {
+        WeightedRule {
+            rule: Rule::NonLexical {
+                lhs: lhs.to_string(),
+                rhs: rhs.iter().map(|n| n.to_string()).collect(),
+            },
+            weight: FloatOrd(weight),
+        }
+    }
+
+    fn weight_of(rules: &[Weighted<String>], lhs: &str, rhs: &[&str]) -> Option<f64> {
+        rules
+            .iter()
+            .find(|r| r.rule == rule(lhs, rhs, 0.0).rule)
+            .map(|r| r.weight.0)
+    }
+
+    #[test]
+    fn violations() {
+        let terminals: FxHashSet<_> = ["a".to_string()].into_iter().collect();
+
+        assert_eq!(
+            cnf_violations(&rule("S", &["A", "a", "B"], 1.0).rule, &terminals),
+            vec![CnfViolation::NAry, CnfViolation::Terminal("a".to_string())]
+        );
+        assert_eq!(
+            cnf_violations(&rule("S", &[], 1.0).rule, &terminals),
+            vec![CnfViolation::Epsilon]
+        );
+        assert!(cnf_violations(&rule("S", &["A"], 1.0).rule, &terminals).is_empty());
+    }
+
+    #[test]
+    fn conversion() {
+        let terminals: FxHashSet<_> = ["a".to_string()].into_iter().collect();
+        let rules = to_cnf(
+            vec![
+                rule("S", &["A", "a", "B"], 1.0),
+                rule("B", &["A"], 0.75),
+                rule("B", &[], 0.25),
+            ],
+            &terminals,
+        );
+
+        assert!(rules
+            .iter()
+            .all(|r| cnf_violations(&r.rule, &terminals).is_empty()));
+
+        // S -> A S|<a,B> with S|<a,B> -> TERM-a B, where B may be empty.
+        assert_eq!(weight_of(&rules, "S", &["A", "S|<TERM-a,B>"]), Some(1.0));
+        assert_eq!(
+            weight_of(&rules, "S|<TERM-a,B>", &["TERM-a", "B"]),
+            Some(0.75)
+        );
+        assert_eq!(weight_of(&rules, "S|<TERM-a,B>", &["TERM-a"]), Some(0.25));
+        assert_eq!(weight_of(&rules, "B", &["A"]), Some(1.0));
+        assert_eq!(weight_of(&rules, "B", &[]), None);
+    }
+
+    #[test]
+    fn strict_conversion() {
+        let lexical = |lhs: &str, rhs: &str, weight| WeightedRule {
+            rule: Rule::Lexical {
+                lhs: lhs.to_string(),
+                rhs: rhs.to_string(),
+            },
+            weight: FloatOrd(weight),
+        };
+        let terminals: FxHashSet<_> = ["a".to_string(), "b".to_string()].into_iter().collect();
+        let (rules, mapping) = to_strict_cnf(
+            vec![
+                rule("ROOT", &["S"], 1.0),
+                rule("S", &["NP", "VP", "X"], 0.5),
+                rule("S", &["VP"], 0.5),
+                rule("VP", &["V"], 0.4),
+                rule("VP", &["V", "NP"], 0.6),
+                rule("X", &["a"], 0.5),
+                rule("X", &[], 0.5),
+                lexical("NP", "b", 1.0),
+                lexical("V", "b", 1.0),
+            ],
+            &terminals,
+        )
+        .unwrap();
+
+        assert!(rules.iter().all(|r| match &r.rule {
+            Rule::NonLexical { rhs, .. } => rhs.len() == 2 && !terminals.contains(&rhs[0]),
+            Rule::Lexical { .. } => true,
+        }));
+        assert!((weight_of(&rules, "ROOT", &["V", "NP"]).unwrap() - 0.3).abs() < 1e-9);
+        assert_eq!(
+            Some(&vec!["S".to_string(), "VP".to_string()]),
+            mapping.chains.get(&rule("ROOT", &["V", "NP"], 0.0).rule)
+        );
+
+        let leaf = |w: &str| Tree {
+            root: w.to_string(),
+            children: vec![],
+        };
+        let node = |n: &str, children| Tree {
+            root: n.to_string(),
+            children,
+        };
+        // ROOT -> S -> NP S|<VP,X> with S|<VP,X> -> VP X, X -> TERM-a and VP -> V.
+        let parsed = node(
+            "ROOT",
+            vec![
+                node("NP", vec![leaf("b")]),
+                node(
+                    "S|<VP,X>",
+                    vec![node("VP", vec![leaf("b")]), node("X", vec![leaf("a")])],
+                ),
+            ],
+        );
+        assert_eq!(
+            "(ROOT (S (NP b) (VP (V b)) (X a)))",
+            mapping.restore(parsed).to_string()
+        );
+
+        let written = mapping.to_string();
+        assert!(written.contains("introduced\tTERM-a\n"));
+        assert_eq!(mapping, CnfMapping::from_str(&written).unwrap());
+        assert!(CnfMapping::<String>::from_str("chain\tA -> B").is_err());
+
+        let cycle = to_strict_cnf(
+            vec![rule("A", &["B"], 1.0), rule("B", &["A"], 1.0)],
+            &terminals,
+        )
+        .unwrap_err();
+        assert_eq!(3, cycle.chain.len());
+    }
+}
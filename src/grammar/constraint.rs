@@ -0,0 +1,145 @@
+use std::hash::Hash;
+use std::str::FromStr;
+
+use nom::branch::alt;
+use nom::bytes::complete::{is_not, tag};
+use nom::character::complete::{multispace1, u64 as parse_u64};
+use nom::combinator::{all_consuming, map, value};
+use nom::error::Error as NError;
+use nom::multi::separated_list1;
+use nom::sequence::{preceded, separated_pair, terminated, tuple};
+use nom::{Finish, IResult};
+use smallstr::SmallString;
+
+use super::rule::Rule;
+
+/// Restriction on where a non-lexical rule may be applied in the chart.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RuleConstraint {
+    /// Only applies to the span covering the whole sentence.
+    Root,
+    /// Only applies to spans of at least the given length.
+    MinSpan(usize),
+    /// Never applies to spans containing punctuation.
+    NoPunctuation,
+}
+
+impl RuleConstraint {
+    pub fn is_satisfied<T: AsRef<str>>(&self, start: usize, span: usize, sentence: &[T]) -> bool {
+        match self {
+            RuleConstraint::Root => start == 0 && span == sentence.len(),
+            RuleConstraint::MinSpan(k) => span >= *k,
+            RuleConstraint::NoPunctuation => !sentence[start..start + span]
+                .iter()
+                .any(|w| is_punctuation(w.as_ref())),
+        }
+    }
+}
+
+pub fn is_punctuation(word: &str) -> bool {
+    !word.is_empty() && word.chars().all(|c| c.is_ascii_punctuation())
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub struct ConstrainedRule<N: Eq + Hash, T: Eq + Hash> {
+    pub constraint: RuleConstraint,
+    pub rule: Rule<N, T>,
+}
+
+type ParsedConstrainedRule = ConstrainedRule<SmallString<[u8; 8]>, SmallString<[u8; 8]>>;
+
+/// Parses lines of the form `root S -> NP VP`, `min-span 3 NP -> NP PP`
+/// or `no-punctuation NP -> NP NP`.
+impl FromStr for ParsedConstrainedRule {
+    type Err = NError<String>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match all_consuming(parse_constrained_rule)(s.trim()).finish() {
+            Ok((_, rule)) => Ok(rule),
+            Err(NError { input, code }) => Err(NError {
+                input: input.to_string(),
+                code,
+            }),
+        }
+    }
+}
+
+fn parse_constrained_rule(input: &str) -> IResult<&str, ParsedConstrainedRule> {
+    tuple((
+        terminated(parse_constraint, multispace1),
+        separated_pair(
+            terminated(is_not(" \t"), multispace1),
+            terminated(tag("->"), multispace1),
+            separated_list1(multispace1, is_not(" \t")),
+        ),
+    ))(input)
+    .map(|(i, (constraint, (lhs, mut rhs)))| {
+        (
+            i,
+            ConstrainedRule {
+                constraint,
+                rule: Rule::NonLexical {
+                    lhs: SmallString::from(lhs),
+                    rhs: rhs.drain(..).map(SmallString::from).collect(),
+                },
+            },
+        )
+    })
+}
+
+fn parse_constraint(input: &str) -> IResult<&str, RuleConstraint> {
+    alt((
+        value(RuleConstraint::Root, tag("root")),
+        map(
+            preceded(terminated(tag("min-span"), multispace1), parse_u64),
+            |k| RuleConstraint::MinSpan(k as usize),
+        ),
+        value(RuleConstraint::NoPunctuation, tag("no-punctuation")),
+    ))(input)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn constrained_rule_correct() {
+        assert_eq!(
+            ConstrainedRule {
+                constraint: RuleConstraint::Root,
+                rule: Rule::NonLexical {
+                    lhs: SmallString::from("ROOT"),
+                    rhs: vec![SmallString::from("S")],
+                }
+            },
+            ConstrainedRule::from_str("root ROOT -> S").unwrap()
+        );
+
+        assert_eq!(
+            ConstrainedRule {
+                constraint: RuleConstraint::MinSpan(3),
+                rule: Rule::NonLexical {
+                    lhs: SmallString::from("NP"),
+                    rhs: vec![SmallString::from("NP"), SmallString::from("PP")],
+                }
+            },
+            ConstrainedRule::from_str("min-span 3 NP -> NP PP").unwrap()
+        );
+
+        assert!(ConstrainedRule::from_str("no-punctuation NP -> NP NP").is_ok());
+        assert!(ConstrainedRule::from_str("min-span NP -> NP PP").is_err());
+        assert!(ConstrainedRule::from_str("sometimes NP -> NP PP").is_err());
+    }
+
+    #[test]
+    fn constraint_satisfaction() {
+        let sentence = ["a", ",", "b", "c"];
+
+        assert!(RuleConstraint::Root.is_satisfied(0, 4, &sentence));
+        assert!(!RuleConstraint::Root.is_satisfied(1, 3, &sentence));
+        assert!(RuleConstraint::MinSpan(2).is_satisfied(1, 2, &sentence));
+        assert!(!RuleConstraint::MinSpan(3).is_satisfied(1, 2, &sentence));
+        assert!(RuleConstraint::NoPunctuation.is_satisfied(2, 2, &sentence));
+        assert!(!RuleConstraint::NoPunctuation.is_satisfied(0, 2, &sentence));
+    }
+}
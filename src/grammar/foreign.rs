@@ -0,0 +1,345 @@
+//! Readers for grammars in the formats of other toolkits. They convert the rules into the
+//! native format, `S -> NP VP 0.5` and `NN dog 0.25`, so that they are read like every other
+//! grammar file.
+
+use std::io::{self, BufRead};
+
+use nom::branch::alt;
+use nom::bytes::complete::tag;
+use nom::character::complete::{char, multispace0};
+use nom::combinator::{all_consuming, map, value};
+use nom::error::{Error as NError, ErrorKind};
+use nom::multi::separated_list0;
+use nom::number::complete::double;
+use nom::sequence::{delimited, preceded, separated_pair};
+use nom::{Finish, IResult};
+
+use super::metadata::{GrammarMetadata, HEADER_PREFIX};
+
+/// Separates a tag from the number of its latent substate in Berkeley grammars, as in `NN_1`.
+pub const BERKELEY_SUBSTATE_SEPARATOR: char = '_';
+
+/// Converts a grammar in the text format of the Berkeley parser. Its binary and unary rules
+/// are written as in the native format. Lexical rules have a weight for every latent substate
+/// of their tag, `NN dog [0.25, 0.5]`, and become a rule of `NN_0`, `NN_1`, …, the names of the
+/// substates in the other rules. A single weight keeps the tag as it is. Substates with
+/// weight 0 are left out.
+pub fn berkeley_to_native<R: BufRead>(reader: R) -> io::Result<String> {
+    let mut native = String::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let weights = line
+            .trim_end()
+            .strip_suffix(']')
+            .and_then(|l| l.rsplit_once('['));
+        let (rule, weights) = match weights {
+            Some((rule, weights)) if !line.starts_with(HEADER_PREFIX) => (rule.trim(), weights),
+            _ => {
+                native.push_str(&line);
+                native.push('\n');
+                continue;
+            }
+        };
+        let (lhs, word) = rule.split_once(char::is_whitespace).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Berkeley lexical rule without word: {}", line),
+            )
+        })?;
+        let weights = weights
+            .split(',')
+            .map(|w| w.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid weight in Berkeley lexical rule {}: {}", line, e),
+                )
+            })?;
+
+        if let [weight] = weights[..] {
+            native.push_str(&format!("{} {} {}\n", lhs, word.trim(), weight));
+            continue;
+        }
+        for (substate, weight) in weights.into_iter().enumerate() {
+            if weight > 0.0 {
+                native.push_str(&format!(
+                    "{}{}{} {} {}\n",
+                    lhs,
+                    BERKELEY_SUBSTATE_SEPARATOR,
+                    substate,
+                    word.trim(),
+                    weight
+                ));
+            }
+        }
+    }
+
+    Ok(native)
+}
+
+/// Converts a grammar in JSON, an object with the lists `rules` and `lexicon` and optionally an
+/// object `metadata` with the entries of the header:
+///
+/// ```json
+/// {
+///   "metadata": {"creator": "toolkit"},
+///   "rules": [{"lhs": "S", "rhs": ["NP", "VP"], "weight": 0.5}],
+///   "lexicon": [{"lhs": "NN", "rhs": "dog", "weight": 0.25}]
+/// }
+/// ```
+///
+/// A rule is lexical if its RHS is a single string, no matter which list it is in. A file
+/// may also consist of only a list of rules.
+pub fn json_to_native(text: &str) -> io::Result<(GrammarMetadata, String)> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    let json = match all_consuming(json_value)(text).finish() {
+        Ok((_, json)) => json,
+        Err(NError { input, .. }) => {
+            let line = text[..text.len() - input.len()].lines().count().max(1);
+            return Err(invalid(format!("invalid JSON in line {}", line)));
+        }
+    };
+
+    let mut metadata = GrammarMetadata::default();
+    let mut rules = vec![];
+    match json {
+        Json::Array(list) => rules.extend(list),
+        Json::Object(members) => {
+            for (key, member) in members {
+                match (key.as_str(), member) {
+                    ("rules", Json::Array(list)) | ("lexicon", Json::Array(list)) => {
+                        rules.extend(list)
+                    }
+                    ("metadata", Json::Object(entries)) => {
+                        for (key, entry) in entries {
+                            metadata = match entry {
+                                Json::String(s) => metadata.with(&key, s),
+                                other => metadata.with(&key, other),
+                            };
+                        }
+                    }
+                    (key, _) => {
+                        return Err(invalid(format!("unexpected member \"{}\" of grammar", key)))
+                    }
+                }
+            }
+        }
+        _ => {
+            return Err(invalid(String::from(
+                "a grammar has to be an object or a list",
+            )))
+        }
+    }
+
+    let mut native = String::new();
+    for (i, rule) in rules.into_iter().enumerate() {
+        let rule = JsonRule::try_from(rule)
+            .map_err(|e| invalid(format!("rule {} of the grammar {}", i + 1, e)))?;
+        native.push_str(&rule.lhs);
+        match rule.rhs {
+            JsonRhs::Word(word) => native.push_str(&format!(" {}", word)),
+            JsonRhs::Nonterminals(rhs) => {
+                native.push_str(" ->");
+                for n in rhs {
+                    native.push_str(&format!(" {}", n));
+                }
+            }
+        }
+        native.push_str(&format!(" {}\n", rule.weight));
+    }
+
+    Ok((metadata, native))
+}
+
+/// Value of a JSON document. Objects keep the order of their members.
+#[derive(Clone, Debug, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl std::fmt::Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(x) => write!(f, "{}", x),
+            Json::String(s) => write!(f, "{}", s),
+            Json::Array(_) | Json::Object(_) => write!(f, "…"),
+        }
+    }
+}
+
+enum JsonRhs {
+    Word(String),
+    Nonterminals(Vec<String>),
+}
+
+struct JsonRule {
+    lhs: String,
+    rhs: JsonRhs,
+    weight: f64,
+}
+
+impl TryFrom<Json> for JsonRule {
+    type Error = String;
+
+    fn try_from(json: Json) -> Result<Self, Self::Error> {
+        let members = match json {
+            Json::Object(members) => members,
+            _ => return Err(String::from("is not an object")),
+        };
+        let (mut lhs, mut rhs, mut weight) = (None, None, None);
+        for (key, member) in members {
+            match (key.as_str(), member) {
+                ("lhs", Json::String(s)) => lhs = Some(symbol(s)?),
+                ("rhs", Json::String(s)) => rhs = Some(JsonRhs::Word(symbol(s)?)),
+                ("rhs", Json::Array(list)) if !list.is_empty() => {
+                    let rhs_list = list
+                        .into_iter()
+                        .map(|n| match n {
+                            Json::String(s) => symbol(s),
+                            _ => Err(String::from("has a RHS with something else than strings")),
+                        })
+                        .collect::<Result<_, _>>()?;
+                    rhs = Some(JsonRhs::Nonterminals(rhs_list));
+                }
+                ("weight", Json::Number(w)) => weight = Some(w),
+                (key, _) => return Err(format!("has an invalid member \"{}\"", key)),
+            }
+        }
+
+        match (lhs, rhs, weight) {
+            (Some(lhs), Some(rhs), Some(weight)) => Ok(JsonRule { lhs, rhs, weight }),
+            _ => Err(String::from("needs an lhs, an rhs and a weight")),
+        }
+    }
+}
+
+/// Symbols are written separated by spaces, so they can't contain any.
+fn symbol(s: String) -> Result<String, String> {
+    if s.is_empty() || s.contains(char::is_whitespace) {
+        Err(format!(
+            "has the symbol \"{}\", which is empty or contains spaces",
+            s
+        ))
+    } else {
+        Ok(s)
+    }
+}
+
+fn json_value(input: &str) -> IResult<&str, Json> {
+    delimited(
+        multispace0,
+        alt((
+            value(Json::Null, tag("null")),
+            value(Json::Bool(true), tag("true")),
+            value(Json::Bool(false), tag("false")),
+            map(json_string, Json::String),
+            map(
+                delimited(
+                    char('['),
+                    separated_list0(char(','), json_value),
+                    preceded(multispace0, char(']')),
+                ),
+                Json::Array,
+            ),
+            map(
+                delimited(
+                    char('{'),
+                    separated_list0(char(','), json_member),
+                    preceded(multispace0, char('}')),
+                ),
+                Json::Object,
+            ),
+            map(double, Json::Number),
+        )),
+        multispace0,
+    )(input)
+}
+
+fn json_member(input: &str) -> IResult<&str, (String, Json)> {
+    separated_pair(
+        preceded(multispace0, json_string),
+        preceded(multispace0, char(':')),
+        json_value,
+    )(input)
+}
+
+fn json_string(input: &str) -> IResult<&str, String> {
+    let error = |input| nom::Err::Error(NError::new(input, ErrorKind::Escaped));
+    let rest = input.strip_prefix('"').ok_or_else(|| error(input))?;
+
+    let mut s = String::new();
+    let mut chars = rest.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((&rest[i + 1..], s)),
+            '\\' => match chars.next() {
+                Some((_, '"')) => s.push('"'),
+                Some((_, '\\')) => s.push('\\'),
+                Some((_, '/')) => s.push('/'),
+                Some((_, 'b')) => s.push('\u{8}'),
+                Some((_, 'f')) => s.push('\u{c}'),
+                Some((_, 'n')) => s.push('\n'),
+                Some((_, 'r')) => s.push('\r'),
+                Some((_, 't')) => s.push('\t'),
+                Some((j, 'u')) => {
+                    let code = rest
+                        .get(j + 1..j + 5)
+                        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                        .ok_or_else(|| error(&rest[j..]))?;
+                    // Surrogate pairs are not combined.
+                    s.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                    chars.nth(3);
+                }
+                _ => return Err(error(&rest[i..])),
+            },
+            c => s.push(c),
+        }
+    }
+
+    Err(error(input))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn foreign_grammars() {
+        let berkeley = "#! creator: berkeley\n\
+                        S_0 -> NP_0 VP_1 1.0\n\
+                        NN dog [0.25]\n\
+                        VB bark [0.5, 0.0, 0.125]\n";
+        assert_eq!(
+            "#! creator: berkeley\nS_0 -> NP_0 VP_1 1.0\nNN dog 0.25\nVB_0 bark 0.5\n\
+             VB_2 bark 0.125\n",
+            berkeley_to_native(berkeley.as_bytes()).unwrap()
+        );
+        assert!(berkeley_to_native("NN [0.5]\n".as_bytes()).is_err());
+
+        let json = r#"{
+            "metadata": {"creator": "toolkit \"x\"", "version": 2},
+            "rules": [{"lhs": "S", "rhs": ["NP", "VP"], "weight": 0.5}],
+            "lexicon": [{"lhs": "NN", "rhs": "dög", "weight": 2.5e-1}]
+        }"#;
+        let (metadata, native) = json_to_native(json).unwrap();
+        assert_eq!("S -> NP VP 0.5\nNN dög 0.25\n", native);
+        assert_eq!(Some("toolkit \"x\""), metadata.get("creator"));
+        assert_eq!(Some("2"), metadata.get("version"));
+
+        let (_, native) = json_to_native(r#"[{"lhs": "NN", "rhs": "dog", "weight": 1}]"#).unwrap();
+        assert_eq!("NN dog 1\n", native);
+        assert!(json_to_native(r#"[{"lhs": "NN", "rhs": "hot dog", "weight": 1}]"#).is_err());
+        assert!(json_to_native(r#"{"rules": [{"lhs": "S", "rhs": []}]}"#).is_err());
+        assert!(json_to_native(r#"{"rules": [}"#).is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use std::fmt;
+
+use super::metadata::HEADER_PREFIX;
+
+/// File formats in which grammars are distributed.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum GrammarFormat {
+    /// Plain text rules as written by the induce subcommand, e.g. `S -> NP VP 0.5`.
+    Native,
+    /// Text format of the Berkeley parser, with bracketed weights like `NN dog [0.5]`.
+    Berkeley,
+    Json,
+    /// Compiled, non-text grammar.
+    Binary,
+}
+
+impl GrammarFormat {
+    /// Guesses the format from the beginning of a grammar file.
+    pub fn detect(sample: &[u8]) -> Self {
+        let text = match std::str::from_utf8(sample) {
+            Ok(text) => text,
+            // The sample might end in the middle of a character.
+            Err(e) if e.error_len().is_none() => {
+                std::str::from_utf8(&sample[..e.valid_up_to()]).unwrap()
+            }
+            Err(_) => return GrammarFormat::Binary,
+        };
+
+        if text.contains('\0') {
+            return GrammarFormat::Binary;
+        }
+
+        match text.trim_start().chars().next() {
+            Some('{') | Some('[') => GrammarFormat::Json,
+            _ if text
+                .lines()
+                .filter(|l| !l.starts_with(HEADER_PREFIX))
+                .filter_map(|l| l.split_whitespace().last())
+                .any(|w| w.starts_with('[') && w.ends_with(']')) =>
+            {
+                GrammarFormat::Berkeley
+            }
+            _ => GrammarFormat::Native,
+        }
+    }
+}
+
+impl fmt::Display for GrammarFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GrammarFormat::Native => write!(f, "pcfg_tool"),
+            GrammarFormat::Berkeley => write!(f, "Berkeley"),
+            GrammarFormat::Json => write!(f, "JSON"),
+            GrammarFormat::Binary => write!(f, "binary"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn format_detection() {
+        assert_eq!(
+            GrammarFormat::Native,
+            GrammarFormat::detect(b"S -> NP VP 0.5\nNN dog 0.25\n")
+        );
+        assert_eq!(
+            GrammarFormat::Native,
+            GrammarFormat::detect(b"#! options: [tagged]\nNN dog 0.25\n")
+        );
+        assert_eq!(
+            GrammarFormat::Berkeley,
+            GrammarFormat::detect(b"NN dog [0.25]\n")
+        );
+        assert_eq!(
+            GrammarFormat::Json,
+            GrammarFormat::detect(b"  {\"rules\": []}")
+        );
+        assert_eq!(
+            GrammarFormat::Binary,
+            GrammarFormat::detect(b"\x00\xff\x12")
+        );
+    }
+}
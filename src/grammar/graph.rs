@@ -0,0 +1,271 @@
+use std::fmt::Display;
+use std::hash::Hash;
+use std::io::{self, Write};
+
+use fxhash::FxHashMap;
+
+use super::rule::Rule;
+use crate::json::escape_json;
+
+/// How the edges of a `GrammarGraph` are weighted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeWeight {
+    /// Sum of the weights of the rules, i.e. their probabilities in a normalised grammar.
+    Probability,
+    /// Number of rules.
+    Rules,
+}
+
+/// Dependencies between the non-terminals of a grammar, with an edge from A to B for the
+/// non-lexical rules with A on the LHS and B on the RHS. Non-terminals that can't be reached
+/// from the initial non-terminal are marked, as they point to broken parts of the grammar.
+pub struct GrammarGraph<N> {
+    nodes: Vec<N>,
+    index: FxHashMap<N, usize>,
+    /// Number of lexical rules of every non-terminal.
+    lexical: Vec<usize>,
+    /// Summed weight and number of the rules of every edge.
+    edges: FxHashMap<(usize, usize), (f64, usize)>,
+}
+
+impl<N: Eq + Hash + Clone + Display> GrammarGraph<N> {
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![],
+            index: FxHashMap::default(),
+            lexical: vec![],
+            edges: FxHashMap::default(),
+        }
+    }
+
+    fn node(&mut self, n: &N) -> usize {
+        if let Some(i) = self.index.get(n) {
+            return *i;
+        }
+        self.nodes.push(n.clone());
+        self.lexical.push(0);
+        self.index.insert(n.clone(), self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    pub fn insert_rule<T: Eq + Hash>(&mut self, rule: &Rule<N, T>, weight: f64) {
+        match rule {
+            Rule::Lexical { lhs, .. } => {
+                let a = self.node(lhs);
+                self.lexical[a] += 1;
+            }
+            Rule::NonLexical { lhs, rhs } => {
+                let a = self.node(lhs);
+                // A symbol that occurs twice on the RHS adds one rule to the edge.
+                let mut children: Vec<_> = rhs.iter().map(|n| self.node(n)).collect();
+                children.sort_unstable();
+                children.dedup();
+                for b in children {
+                    let edge = self.edges.entry((a, b)).or_insert((0.0, 0));
+                    edge.0 += weight;
+                    edge.1 += 1;
+                }
+            }
+        }
+    }
+
+    /// Whether every non-terminal can be reached from one of the non-terminals in `initial`
+    /// by following the edges.
+    pub fn reachable(&self, initial: &[N]) -> Vec<bool> {
+        let mut children = vec![vec![]; self.nodes.len()];
+        for (a, b) in self.edges.keys() {
+            children[*a].push(*b);
+        }
+
+        let mut reachable = vec![false; self.nodes.len()];
+        let mut stack: Vec<usize> = initial
+            .iter()
+            .filter_map(|n| self.index.get(n).copied())
+            .collect();
+        while let Some(a) = stack.pop() {
+            if !reachable[a] {
+                reachable[a] = true;
+                stack.extend(&children[a]);
+            }
+        }
+        reachable
+    }
+
+    /// Edges whose weight is at least `threshold`, ordered by their non-terminals.
+    fn edges(&self, weight: EdgeWeight, threshold: f64) -> Vec<(usize, usize, f64, usize)> {
+        let mut edges: Vec<_> = self
+            .edges
+            .iter()
+            .map(|((a, b), (w, rules))| (*a, *b, *w, *rules))
+            .filter(|(_, _, w, rules)| match weight {
+                EdgeWeight::Probability => *w >= threshold,
+                EdgeWeight::Rules => *rules as f64 >= threshold,
+            })
+            .collect();
+        edges.sort_unstable_by_key(|(a, b, _, _)| (*a, *b));
+        edges
+    }
+
+    /// Writes the graph in the DOT language of Graphviz. The initial non-terminals are drawn as
+    /// boxes and the non-terminals that can't be reached from them in red.
+    pub fn write_dot<W: Write>(
+        &self,
+        buf: &mut W,
+        initial: &[N],
+        weight: EdgeWeight,
+        threshold: f64,
+    ) -> io::Result<()> {
+        let reachable = self.reachable(initial);
+
+        writeln!(buf, "digraph grammar {{")?;
+        for (a, n) in self.nodes.iter().enumerate() {
+            let mut attributes = vec![];
+            if initial.contains(n) {
+                attributes.push("shape=box");
+            }
+            if !reachable[a] {
+                attributes.push("color=red");
+            }
+            if attributes.is_empty() {
+                writeln!(buf, "    \"{}\";", escape(n))?;
+            } else {
+                writeln!(buf, "    \"{}\" [{}];", escape(n), attributes.join(", "))?;
+            }
+        }
+        for (a, b, w, rules) in self.edges(weight, threshold) {
+            let label = match weight {
+                EdgeWeight::Probability => w.to_string(),
+                EdgeWeight::Rules => rules.to_string(),
+            };
+            writeln!(
+                buf,
+                "    \"{}\" -> \"{}\" [label=\"{}\"];",
+                escape(&self.nodes[a]),
+                escape(&self.nodes[b]),
+                label
+            )?;
+        }
+        writeln!(buf, "}}")
+    }
+
+    /// Writes the graph as a JSON object with a list of nodes and a list of edges.
+    pub fn write_json<W: Write>(
+        &self,
+        buf: &mut W,
+        initial: &[N],
+        weight: EdgeWeight,
+        threshold: f64,
+    ) -> io::Result<()> {
+        let reachable = self.reachable(initial);
+
+        writeln!(buf, "{{")?;
+        writeln!(buf, "  \"nodes\": [")?;
+        for (a, n) in self.nodes.iter().enumerate() {
+            writeln!(
+                buf,
+                "    {{\"label\": \"{}\", \"lexical_rules\": {}, \"reachable\": {}}}{}",
+                escape_json(n),
+                self.lexical[a],
+                reachable[a],
+                if a + 1 < self.nodes.len() { "," } else { "" }
+            )?;
+        }
+        writeln!(buf, "  ],")?;
+        writeln!(buf, "  \"edges\": [")?;
+        let edges = self.edges(weight, threshold);
+        for (i, (a, b, w, rules)) in edges.iter().enumerate() {
+            writeln!(
+                buf,
+                "    {{\"from\": \"{}\", \"to\": \"{}\", \"probability\": {}, \"rules\": {}}}{}",
+                escape_json(&self.nodes[*a]),
+                escape_json(&self.nodes[*b]),
+                w,
+                rules,
+                if i + 1 < edges.len() { "," } else { "" }
+            )?;
+        }
+        writeln!(buf, "  ]")?;
+        writeln!(buf, "}}")
+    }
+}
+
+impl<N: Eq + Hash + Clone + Display> Default for GrammarGraph<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Escapes a label for the quoted strings of DOT.
+pub fn escape<N: Display>(n: &N) -> String {
+    n.to_string().replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn graph() -> GrammarGraph<String> {
+        let rule = |lhs: &str, rhs: &[&str]| Rule::<String, String>::NonLexical {
+            lhs: lhs.to_string(),
+            rhs: rhs.iter().map(|n| n.to_string()).collect(),
+        };
+        let mut graph = GrammarGraph::new();
+        graph.insert_rule(&rule("ROOT", &["NP", "NP"]), 0.25);
+        graph.insert_rule(&rule("ROOT", &["NP", "VP"]), 0.75);
+        graph.insert_rule(&rule("X", &["\"Y\""]), 1.0);
+        graph.insert_rule(
+            &Rule::Lexical {
+                lhs: "NP".to_string(),
+                rhs: "dogs".to_string(),
+            },
+            1.0,
+        );
+        graph
+    }
+
+    #[test]
+    fn dot_graph() {
+        let graph = graph();
+        assert_eq!(
+            vec![true, true, true, false, false],
+            graph.reachable(&["ROOT".to_string()])
+        );
+
+        let mut buf = vec![];
+        graph
+            .write_dot(
+                &mut buf,
+                &["ROOT".to_string()],
+                EdgeWeight::Probability,
+                0.5,
+            )
+            .unwrap();
+        assert_eq!(
+            "digraph grammar {\n    \
+             \"ROOT\" [shape=box];\n    \
+             \"NP\";\n    \
+             \"VP\";\n    \
+             \"X\" [color=red];\n    \
+             \"\\\"Y\\\"\" [color=red];\n    \
+             \"ROOT\" -> \"NP\" [label=\"1\"];\n    \
+             \"ROOT\" -> \"VP\" [label=\"0.75\"];\n    \
+             \"X\" -> \"\\\"Y\\\"\" [label=\"1\"];\n\
+             }\n",
+            String::from_utf8(buf).unwrap()
+        );
+    }
+
+    #[test]
+    fn json_graph() {
+        let mut buf = vec![];
+        graph()
+            .write_json(&mut buf, &["ROOT".to_string()], EdgeWeight::Rules, 2.0)
+            .unwrap();
+        let json = String::from_utf8(buf).unwrap();
+        assert!(json.contains("{\"label\": \"NP\", \"lexical_rules\": 1, \"reachable\": true},\n"));
+        assert!(json.ends_with(
+            "\"edges\": [\n    \
+             {\"from\": \"ROOT\", \"to\": \"NP\", \"probability\": 1, \"rules\": 2}\n  ]\n}\n"
+        ));
+    }
+}
@@ -0,0 +1,86 @@
+use std::io::{self, BufRead};
+
+use fxhash::FxHashMap;
+
+/// Coarser label of fine labels, e.g. `NP` for `NP-SBJ`. Parsing can back off from the rules
+/// of a fine label to those of its coarse label, see `GrammarParse::insert_backoff_rules`.
+#[derive(Clone, Default, Debug)]
+pub struct LabelHierarchy {
+    parents: FxHashMap<String, String>,
+    suffixes: bool,
+}
+
+impl LabelHierarchy {
+    /// Hierarchy given by the function tags of the Penn Treebank: the coarse label of a label is
+    /// the label without its last `-` suffix, e.g. `NP-SBJ-1` → `NP-SBJ` → `NP`. Labels starting
+    /// with `-` (e.g. `-NONE-`) and labels of binarisation (e.g. `NP|<DT,NN>`) have none.
+    pub fn from_suffixes() -> Self {
+        Self {
+            parents: FxHashMap::default(),
+            suffixes: true,
+        }
+    }
+
+    /// Reads a hierarchy with one fine label and its coarse label per line, separated by
+    /// whitespace. Empty lines are skipped.
+    pub fn read<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut hierarchy = Self::default();
+        for line in reader.lines() {
+            let line = line?;
+            let labels: Vec<&str> = line.split_whitespace().collect();
+            match labels.as_slice() {
+                [] => {}
+                [fine, coarse] => {
+                    hierarchy
+                        .parents
+                        .insert(fine.to_string(), coarse.to_string());
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("expected a fine and a coarse label: {}", line),
+                    ))
+                }
+            }
+        }
+        Ok(hierarchy)
+    }
+
+    pub fn parent<'a>(&'a self, label: &'a str) -> Option<&'a str> {
+        if self.suffixes {
+            suffix_parent(label)
+        } else {
+            self.parents.get(label).map(String::as_str)
+        }
+    }
+}
+
+fn suffix_parent(label: &str) -> Option<&str> {
+    if label.starts_with('-') || label.contains(['|', '<', '>', '^', ',']) {
+        return None;
+    }
+    match label.rsplit_once('-') {
+        Some((coarse, suffix)) if !coarse.is_empty() && !suffix.is_empty() => Some(coarse),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parents() {
+        let suffixes = LabelHierarchy::from_suffixes();
+        assert_eq!(Some("NP-SBJ"), suffixes.parent("NP-SBJ-1"));
+        assert_eq!(Some("NP"), suffixes.parent("NP-SBJ"));
+        assert_eq!(None, suffixes.parent("NP"));
+        assert_eq!(None, suffixes.parent("-NONE-"));
+        assert_eq!(None, suffixes.parent("NP|<NP-SBJ,VP>"));
+
+        let file = LabelHierarchy::read(&b"NP-SBJ NP\n\nPP-LOC  PP\n"[..]).unwrap();
+        assert_eq!(Some("NP"), file.parent("NP-SBJ"));
+        assert_eq!(None, file.parent("NP-TMP"));
+        assert!(LabelHierarchy::read(&b"NP-SBJ\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,677 @@
+use std::fmt::Display;
+use std::hash::Hash;
+
+use fxhash::FxHashMap;
+
+use super::bare::GrammarBare;
+use super::rule::Rule;
+use crate::rng::XorShift;
+use crate::tree::{NodeType, Tree};
+
+/// Separates a label from the number of its latent subcategory, as in `NP@3`.
+pub const SUBCATEGORY_SEPARATOR: char = '@';
+
+/// Relative amount of random noise added to the rule weights when subcategories are split,
+/// so that EM can tell the two halves apart.
+const SPLIT_NOISE: f64 = 0.01;
+
+/// PCFG whose non-terminals are split into latent subcategories, trained on a treebank
+/// with split-merge cycles (Petrov et al., 2006). Every rule of the treebank has a weight
+/// for every combination of subcategories of its non-terminals, stored row-major with
+/// the subcategory of the LHS first. The initial non-terminals are never split.
+pub struct LatentGrammar<A: Eq + Hash> {
+    roots: Vec<A>,
+    subcategories: FxHashMap<A, usize>,
+    rules: FxHashMap<Rule<A, A>, Vec<f64>>,
+}
+
+/// Weights of the subcategories of a node, divided by `exp(log_scale)`,
+/// so that they don't underflow in large trees.
+#[derive(Default)]
+struct Scaled {
+    weights: Vec<f64>,
+    log_scale: f64,
+}
+
+impl Scaled {
+    fn new(mut weights: Vec<f64>, mut log_scale: f64) -> Self {
+        let max = weights.iter().copied().fold(0.0, f64::max);
+        if max > 0.0 {
+            weights.iter_mut().for_each(|w| *w /= max);
+            log_scale += max.ln();
+        }
+        Scaled { weights, log_scale }
+    }
+}
+
+/// Expected counts of the subcategories of all rules and labels of a treebank.
+struct Expectations<A: Eq + Hash> {
+    rules: FxHashMap<Rule<A, A>, Vec<f64>>,
+    labels: FxHashMap<A, Vec<f64>>,
+    log_likelihood: f64,
+}
+
+impl<A: Eq + Hash + Clone + Display> LatentGrammar<A> {
+    /// Reads off the rules of the treebank with their relative frequencies.
+    /// All trees have to be binarised, see `is_trainable`.
+    pub fn from_treebank(trees: &[Tree<A>], roots: Vec<A>) -> Self {
+        let mut counts: FxHashMap<Rule<A, A>, f64> = FxHashMap::default();
+        let mut subcategories = FxHashMap::default();
+        for root in &roots {
+            subcategories.insert(root.clone(), 1);
+        }
+
+        fn collect<A: Eq + Hash + Clone>(
+            tree: &Tree<A>,
+            counts: &mut FxHashMap<Rule<A, A>, f64>,
+            subcategories: &mut FxHashMap<A, usize>,
+        ) {
+            if tree.is_leaf() {
+                return;
+            }
+            subcategories.insert(tree.root.clone(), 1);
+            *counts.entry(node_rule(tree)).or_insert(0.0) += 1.0;
+            for child in &tree.children {
+                collect(child, counts, subcategories);
+            }
+        }
+        for tree in trees {
+            collect(tree, &mut counts, &mut subcategories);
+        }
+
+        let mut grammar = LatentGrammar {
+            roots,
+            subcategories,
+            rules: counts.keys().map(|r| (r.clone(), vec![0.0])).collect(),
+        };
+        grammar.maximize(counts.into_iter().map(|(r, c)| (r, vec![c])).collect());
+        grammar
+    }
+
+    /// Number of subcategories of `label`.
+    pub fn subcategories(&self, label: &A) -> usize {
+        self.subcategories.get(label).copied().unwrap_or(1)
+    }
+
+    /// Splits every subcategory except the ones of the initial non-terminals in two.
+    /// Subcategory `k` becomes `2k` and `2k + 1`, both with the weights of `k`
+    /// and some random noise.
+    pub fn split(&mut self, rng: &mut XorShift) {
+        let old = self.subcategories.clone();
+        for (label, n) in self.subcategories.iter_mut() {
+            if !self.roots.contains(label) {
+                *n *= 2;
+            }
+        }
+
+        let mut rules = FxHashMap::default();
+        for (rule, weights) in self.rules.drain() {
+            let old_dims = dims(&old, &rule);
+            let new_dims = dims(&self.subcategories, &rule);
+            // Splitting the children divides the weight among their halves.
+            let children_factor: usize = old_dims[1..]
+                .iter()
+                .zip(&new_dims[1..])
+                .map(|(o, n)| n / o)
+                .product();
+
+            let len = new_dims.iter().product();
+            let mut new_weights = Vec::with_capacity(len);
+            for idx in 0..len {
+                let old_idx = remap(idx, &new_dims, &old_dims, |k, i| {
+                    i * old_dims[k] / new_dims[k]
+                });
+                let noise = 1.0 + SPLIT_NOISE * (2.0 * rng.weight() - 1.0);
+                new_weights.push(weights[old_idx] / children_factor as f64 * noise);
+            }
+            rules.insert(rule, new_weights);
+        }
+        self.rules = rules;
+    }
+
+    /// Runs one iteration of expectation maximization over the treebank and returns
+    /// the log-likelihood of the treebank before it.
+    pub fn em_step(&mut self, trees: &[Tree<A>]) -> f64 {
+        let expectations = self.expectations(trees);
+        self.maximize(expectations.rules);
+        expectations.log_likelihood
+    }
+
+    /// Merges the pairs of subcategories created by the last split whose merge loses the
+    /// least likelihood of the treebank, `fraction` of all pairs. The loss of merging a pair
+    /// is approximated from the inside and outside weights at the nodes of the treebank.
+    pub fn merge(&mut self, trees: &[Tree<A>], fraction: f64) {
+        let expectations = self.expectations(trees);
+        // Share of each subcategory within its pair.
+        let shares: FxHashMap<&A, Vec<f64>> = expectations
+            .labels
+            .iter()
+            .map(|(label, counts)| {
+                let shares = counts
+                    .chunks(2)
+                    .flat_map(|pair| {
+                        let total: f64 = pair.iter().sum();
+                        pair.iter()
+                            .map(move |c| if total > 0.0 { c / total } else { 0.5 })
+                    })
+                    .collect();
+                (label, shares)
+            })
+            .collect();
+
+        let mut losses: FxHashMap<(&A, usize), f64> = FxHashMap::default();
+        for tree in trees.iter().filter(|t| is_trainable(t)) {
+            let inside = self.inside(tree);
+            let outside = self.outside(tree, &inside, self.root_outside(tree));
+            self.merge_losses(tree, &inside, &outside, &shares, &mut losses);
+        }
+
+        let mut candidates: Vec<_> = losses.into_iter().collect();
+        candidates.sort_by(|(l1, x), (l2, y)| {
+            x.partial_cmp(y)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| (l1.0.to_string(), l1.1).cmp(&(l2.0.to_string(), l2.1)))
+        });
+        let merged = (candidates.len() as f64 * fraction) as usize;
+
+        // New subcategory of every old one, and its share in the new one.
+        let mut mapping: FxHashMap<A, Vec<(usize, f64)>> = self
+            .subcategories
+            .iter()
+            .map(|(label, n)| (label.clone(), (0..*n).map(|i| (i, 1.0)).collect()))
+            .collect();
+        for ((label, pair), _) in &candidates[..merged] {
+            let m = mapping.get_mut(*label).unwrap();
+            m[2 * pair + 1].0 = m[2 * pair].0;
+            m[2 * pair].1 = shares[label][2 * pair];
+            m[2 * pair + 1].1 = shares[label][2 * pair + 1];
+        }
+        // Renumber the remaining subcategories consecutively.
+        for (label, m) in mapping.iter_mut() {
+            let mut numbers: Vec<usize> = m.iter().map(|(i, _)| *i).collect();
+            numbers.dedup();
+            for (i, _) in m.iter_mut() {
+                *i = numbers.binary_search(i).unwrap();
+            }
+            self.subcategories.insert(label.clone(), numbers.len());
+        }
+
+        let old: FxHashMap<A, usize> = mapping.iter().map(|(l, m)| (l.clone(), m.len())).collect();
+        let mut rules = FxHashMap::default();
+        for (rule, weights) in self.rules.drain() {
+            let old_dims = dims(&old, &rule);
+            let new_dims = dims(&self.subcategories, &rule);
+            let labels = rule_labels(&rule);
+
+            let mut new_weights = vec![0.0; new_dims.iter().product()];
+            for (idx, w) in weights.iter().enumerate() {
+                let new_idx = remap(idx, &old_dims, &new_dims, |k, i| mapping[labels[k]][i].0);
+                // Weights of the LHS are averaged, the ones of the children add up.
+                let lhs_sub = idx / (weights.len() / old_dims[0]);
+                new_weights[new_idx] += w * mapping[labels[0]][lhs_sub].1;
+            }
+            rules.insert(rule, new_weights);
+        }
+        self.rules = rules;
+    }
+
+    /// The grammar over the split labels, named like `NP@3`.
+    /// Labels with only one subcategory keep their name.
+    pub fn to_grammar(&self) -> GrammarBare<String, String, f64> {
+        let name = |label: &A, sub: usize| {
+            if self.subcategories(label) == 1 {
+                label.to_string()
+            } else {
+                format!("{}{}{}", label, SUBCATEGORY_SEPARATOR, sub)
+            }
+        };
+
+        let mut grammar = GrammarBare::new();
+        for (rule, weights) in &self.rules {
+            let dims = dims(&self.subcategories, rule);
+            for (idx, w) in weights.iter().enumerate().filter(|(_, w)| **w > 0.0) {
+                let subs = decompose(idx, &dims);
+                let split_rule = match rule {
+                    Rule::Lexical { lhs, rhs } => Rule::Lexical {
+                        lhs: name(lhs, subs[0]),
+                        rhs: rhs.to_string(),
+                    },
+                    Rule::NonLexical { lhs, rhs } => Rule::NonLexical {
+                        lhs: name(lhs, subs[0]),
+                        rhs: rhs
+                            .iter()
+                            .zip(&subs[1..])
+                            .map(|(n, sub)| name(n, *sub))
+                            .collect(),
+                    },
+                };
+                grammar.rules.insert(split_rule, *w);
+            }
+        }
+        grammar.normalised()
+    }
+
+    fn expectations(&self, trees: &[Tree<A>]) -> Expectations<A> {
+        let mut expectations = Expectations {
+            rules: self
+                .rules
+                .iter()
+                .map(|(r, w)| (r.clone(), vec![0.0; w.len()]))
+                .collect(),
+            labels: self
+                .subcategories
+                .iter()
+                .map(|(l, n)| (l.clone(), vec![0.0; *n]))
+                .collect(),
+            log_likelihood: 0.0,
+        };
+
+        for tree in trees.iter().filter(|t| is_trainable(t)) {
+            let inside = self.inside(tree);
+            let outside = self.outside(tree, &inside, self.root_outside(tree));
+            let likelihood: f64 = inside
+                .root
+                .weights
+                .iter()
+                .zip(&outside.root.weights)
+                .map(|(i, o)| i * o)
+                .sum();
+            if likelihood <= 0.0 {
+                continue;
+            }
+            let log_likelihood = likelihood.ln() + inside.root.log_scale + outside.root.log_scale;
+            expectations.log_likelihood += log_likelihood;
+            self.count(tree, &inside, &outside, log_likelihood, &mut expectations);
+        }
+
+        expectations
+    }
+
+    /// Sets the weights to the relative frequencies of the counts among all rules with the
+    /// same LHS subcategory. Subcategories without counts keep their weights.
+    fn maximize(&mut self, counts: FxHashMap<Rule<A, A>, Vec<f64>>) {
+        let mut totals: FxHashMap<&A, Vec<f64>> = FxHashMap::default();
+        for (rule, c) in &counts {
+            let lhs = rule_labels(rule)[0];
+            let n = self.subcategories(lhs);
+            let total = totals.entry(lhs).or_insert_with(|| vec![0.0; n]);
+            for (idx, count) in c.iter().enumerate() {
+                total[idx / (c.len() / n)] += count;
+            }
+        }
+
+        for (rule, c) in &counts {
+            let lhs = rule_labels(rule)[0];
+            let n = self.subcategories(lhs);
+            let weights = self.rules.get_mut(rule).unwrap();
+            for (idx, count) in c.iter().enumerate() {
+                let total = totals[lhs][idx / (c.len() / n)];
+                if total > 0.0 {
+                    weights[idx] = count / total;
+                }
+            }
+        }
+    }
+
+    fn inside(&self, tree: &Tree<A>) -> Tree<Scaled> {
+        if tree.is_leaf() {
+            return Tree {
+                root: Scaled::default(),
+                children: vec![],
+            };
+        }
+
+        let rule = node_rule(tree);
+        let w = &self.rules[&rule];
+        let n = self.subcategories(&tree.root);
+        let children: Vec<_> = tree.children.iter().map(|c| self.inside(c)).collect();
+
+        let root = match (&rule, children.as_slice()) {
+            (Rule::Lexical { .. }, _) => Scaled::new(w.clone(), 0.0),
+            (_, [b]) => {
+                let nb = b.root.weights.len();
+                let weights = (0..n)
+                    .map(|x| (0..nb).map(|y| w[x * nb + y] * b.root.weights[y]).sum())
+                    .collect();
+                Scaled::new(weights, b.root.log_scale)
+            }
+            (_, [b, c]) => {
+                let (nb, nc) = (b.root.weights.len(), c.root.weights.len());
+                let mut weights = vec![0.0; n];
+                for (x, weight) in weights.iter_mut().enumerate() {
+                    for y in 0..nb {
+                        for z in 0..nc {
+                            *weight +=
+                                w[(x * nb + y) * nc + z] * b.root.weights[y] * c.root.weights[z];
+                        }
+                    }
+                }
+                Scaled::new(weights, b.root.log_scale + c.root.log_scale)
+            }
+            _ => unreachable!("Only binarised trees are trained on!"),
+        };
+
+        Tree { root, children }
+    }
+
+    fn root_outside(&self, tree: &Tree<A>) -> Scaled {
+        Scaled::new(vec![1.0; self.subcategories(&tree.root)], 0.0)
+    }
+
+    fn outside(&self, tree: &Tree<A>, inside: &Tree<Scaled>, out: Scaled) -> Tree<Scaled> {
+        let rule = node_rule(tree);
+        let children = match (&rule, inside.children.as_slice()) {
+            (Rule::Lexical { .. }, _) => vec![Tree {
+                root: Scaled::default(),
+                children: vec![],
+            }],
+            (_, [b]) => {
+                let nb = b.root.weights.len();
+                let w = &self.rules[&rule];
+                let weights = (0..nb)
+                    .map(|y| {
+                        (0..out.weights.len())
+                            .map(|x| out.weights[x] * w[x * nb + y])
+                            .sum()
+                    })
+                    .collect();
+                let b_out = Scaled::new(weights, out.log_scale);
+                vec![self.outside(&tree.children[0], b, b_out)]
+            }
+            (_, [b, c]) => {
+                let (nb, nc) = (b.root.weights.len(), c.root.weights.len());
+                let w = &self.rules[&rule];
+                let mut b_weights = vec![0.0; nb];
+                let mut c_weights = vec![0.0; nc];
+                for (x, o) in out.weights.iter().enumerate() {
+                    for y in 0..nb {
+                        for z in 0..nc {
+                            let ow = o * w[(x * nb + y) * nc + z];
+                            b_weights[y] += ow * c.root.weights[z];
+                            c_weights[z] += ow * b.root.weights[y];
+                        }
+                    }
+                }
+                let b_out = Scaled::new(b_weights, out.log_scale + c.root.log_scale);
+                let c_out = Scaled::new(c_weights, out.log_scale + b.root.log_scale);
+                vec![
+                    self.outside(&tree.children[0], b, b_out),
+                    self.outside(&tree.children[1], c, c_out),
+                ]
+            }
+            _ => unreachable!("Only binarised trees are trained on!"),
+        };
+
+        Tree {
+            root: out,
+            children,
+        }
+    }
+
+    /// Adds the expected counts of the rules and labels of `tree` to `expectations`.
+    fn count(
+        &self,
+        tree: &Tree<A>,
+        inside: &Tree<Scaled>,
+        outside: &Tree<Scaled>,
+        log_likelihood: f64,
+        expectations: &mut Expectations<A>,
+    ) {
+        if tree.is_leaf() {
+            return;
+        }
+
+        let (i, o) = (&inside.root, &outside.root);
+        let posterior = (i.log_scale + o.log_scale - log_likelihood).exp();
+        for (x, count) in expectations
+            .labels
+            .get_mut(&tree.root)
+            .unwrap()
+            .iter_mut()
+            .enumerate()
+        {
+            *count += posterior * i.weights[x] * o.weights[x];
+        }
+
+        let rule = node_rule(tree);
+        let w = &self.rules[&rule];
+        let counts = expectations.rules.get_mut(&rule).unwrap();
+        match (&rule, inside.children.as_slice()) {
+            (Rule::Lexical { .. }, _) => {
+                let factor = (o.log_scale - log_likelihood).exp();
+                for (x, count) in counts.iter_mut().enumerate() {
+                    *count += factor * o.weights[x] * w[x];
+                }
+            }
+            (_, [b]) => {
+                let nb = b.root.weights.len();
+                let factor = (o.log_scale + b.root.log_scale - log_likelihood).exp();
+                for (idx, count) in counts.iter_mut().enumerate() {
+                    let (x, y) = (idx / nb, idx % nb);
+                    *count += factor * o.weights[x] * w[idx] * b.root.weights[y];
+                }
+            }
+            (_, [b, c]) => {
+                let (nb, nc) = (b.root.weights.len(), c.root.weights.len());
+                let factor =
+                    (o.log_scale + b.root.log_scale + c.root.log_scale - log_likelihood).exp();
+                for (idx, count) in counts.iter_mut().enumerate() {
+                    let (x, y, z) = (idx / (nb * nc), idx / nc % nb, idx % nc);
+                    *count +=
+                        factor * o.weights[x] * w[idx] * b.root.weights[y] * c.root.weights[z];
+                }
+            }
+            _ => unreachable!("Only binarised trees are trained on!"),
+        }
+
+        for ((child, child_inside), child_outside) in tree
+            .children
+            .iter()
+            .zip(&inside.children)
+            .zip(&outside.children)
+        {
+            self.count(
+                child,
+                child_inside,
+                child_outside,
+                log_likelihood,
+                expectations,
+            );
+        }
+    }
+
+    /// Adds the loss of log-likelihood of merging every pair of subcategories
+    /// at the nodes of `tree` to `losses`.
+    fn merge_losses<'a>(
+        &'a self,
+        tree: &Tree<A>,
+        inside: &Tree<Scaled>,
+        outside: &Tree<Scaled>,
+        shares: &FxHashMap<&'a A, Vec<f64>>,
+        losses: &mut FxHashMap<(&'a A, usize), f64>,
+    ) {
+        if tree.is_leaf() {
+            return;
+        }
+
+        let (label, n) = self.subcategories.get_key_value(&tree.root).unwrap();
+        if !self.roots.contains(label) {
+            let (i, o) = (&inside.root.weights, &outside.root.weights);
+            let total: f64 = i.iter().zip(o).map(|(i, o)| i * o).sum();
+            for pair in 0..n / 2 {
+                let (a, b) = (2 * pair, 2 * pair + 1);
+                let merged_inside = shares[label][a] * i[a] + shares[label][b] * i[b];
+                let merged = total - i[a] * o[a] - i[b] * o[b] + merged_inside * (o[a] + o[b]);
+                if total > 0.0 && merged > 0.0 {
+                    *losses.entry((label, pair)).or_insert(0.0) += total.ln() - merged.ln();
+                }
+            }
+        }
+
+        for ((child, child_inside), child_outside) in tree
+            .children
+            .iter()
+            .zip(&inside.children)
+            .zip(&outside.children)
+        {
+            self.merge_losses(child, child_inside, child_outside, shares, losses);
+        }
+    }
+}
+
+/// Checks that every inner node of `tree` either has a single word as child
+/// or one or two inner nodes, as needed for training.
+pub fn is_trainable<A>(tree: &Tree<A>) -> bool {
+    match tree.children.as_slice() {
+        [] => true,
+        [c] if c.is_leaf() => true,
+        children => children.len() <= 2 && children.iter().all(|c| !c.is_leaf() && is_trainable(c)),
+    }
+}
+
+/// Strips the subcategory from a split label, e.g. `NP@3` becomes `NP`.
+pub fn project_label(label: &str) -> &str {
+    match label.rsplit_once(SUBCATEGORY_SEPARATOR) {
+        Some((base, sub)) if !sub.is_empty() && sub.chars().all(|c| c.is_ascii_digit()) => base,
+        _ => label,
+    }
+}
+
+/// Replaces the split labels of all non-terminals of `tree` by their original labels.
+pub fn project_tree<N, T>(tree: &mut Tree<NodeType<N, T>>)
+where
+    N: AsRef<str> + for<'a> From<&'a str>,
+{
+    if let NodeType::NonTerminal(label) = &tree.root {
+        let projected = N::from(project_label(label.as_ref()));
+        tree.root = NodeType::NonTerminal(projected);
+    }
+    tree.children.iter_mut().for_each(project_tree);
+}
+
+/// The rule applied at an inner node of a trainable tree.
+fn node_rule<A: Eq + Hash + Clone>(tree: &Tree<A>) -> Rule<A, A> {
+    match tree.children.as_slice() {
+        [c] if c.is_leaf() => Rule::Lexical {
+            lhs: tree.root.clone(),
+            rhs: c.root.clone(),
+        },
+        children => Rule::NonLexical {
+            lhs: tree.root.clone(),
+            rhs: children.iter().map(|c| c.root.clone()).collect(),
+        },
+    }
+}
+
+/// The non-terminals of a rule, starting with the LHS.
+fn rule_labels<A: Eq + Hash>(rule: &Rule<A, A>) -> Vec<&A> {
+    match rule {
+        Rule::Lexical { lhs, .. } => vec![lhs],
+        Rule::NonLexical { lhs, rhs } => std::iter::once(lhs).chain(rhs).collect(),
+    }
+}
+
+/// Numbers of subcategories of the non-terminals of a rule, starting with the LHS.
+fn dims<A: Eq + Hash>(subcategories: &FxHashMap<A, usize>, rule: &Rule<A, A>) -> Vec<usize> {
+    rule_labels(rule)
+        .into_iter()
+        .map(|l| subcategories.get(l).copied().unwrap_or(1))
+        .collect()
+}
+
+/// Splits a row-major index into the subcategories of the non-terminals.
+fn decompose(mut idx: usize, dims: &[usize]) -> Vec<usize> {
+    let mut subs = vec![0; dims.len()];
+    for (sub, dim) in subs.iter_mut().zip(dims).rev() {
+        *sub = idx % dim;
+        idx /= dim;
+    }
+    subs
+}
+
+/// Maps a row-major index over `from` to one over `to`, mapping the subcategory `i`
+/// of the `k`-th non-terminal with `f(k, i)`.
+fn remap<F: Fn(usize, usize) -> usize>(idx: usize, from: &[usize], to: &[usize], f: F) -> usize {
+    decompose(idx, from)
+        .into_iter()
+        .enumerate()
+        .zip(to)
+        .fold(0, |acc, ((k, i), dim)| acc * dim + f(k, i))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::SExp;
+    use smallstr::SmallString;
+    use std::str::FromStr;
+
+    type Label = SmallString<[u8; 8]>;
+
+    fn treebank() -> Vec<Tree<Label>> {
+        [
+            "(ROOT (S (NP (D the) (N dog)) (VP (V barks))))",
+            "(ROOT (S (NP (N dogs)) (VP (V chase) (NP (D the) (N cat)))))",
+            "(ROOT (S (NP (D a) (N cat)) (VP (V sees) (NP (N dogs)))))",
+        ]
+        .iter()
+        .map(|s| Tree::try_from(SExp::from_str(s).unwrap()).unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn split_merge() {
+        let trees = treebank();
+        assert!(trees.iter().all(is_trainable));
+        let mut grammar = LatentGrammar::from_treebank(&trees, vec![Label::from("ROOT")]);
+        let unsplit = grammar.em_step(&trees);
+
+        grammar.split(&mut XorShift::new(1));
+        assert_eq!(grammar.subcategories(&Label::from("NP")), 2);
+        assert_eq!(grammar.subcategories(&Label::from("ROOT")), 1);
+
+        // EM never lowers the likelihood, which the split barely changes.
+        let mut previous = grammar.em_step(&trees);
+        assert!(previous > unsplit - 0.1);
+        for _ in 0..5 {
+            let current = grammar.em_step(&trees);
+            assert!(current >= previous - 1e-9);
+            previous = current;
+        }
+
+        let split = grammar.to_grammar();
+        assert!(split.rules.keys().any(|r| matches!(r,
+            Rule::Lexical { lhs, .. } if lhs == "N@1")));
+
+        grammar.merge(&trees, 1.0);
+        assert_eq!(grammar.subcategories(&Label::from("NP")), 1);
+        let merged = grammar.to_grammar();
+        let weight = |lhs: &str, rhs: &str| {
+            merged.rules[&Rule::Lexical {
+                lhs: lhs.to_string(),
+                rhs: rhs.to_string(),
+            }]
+        };
+        // Close to the relative frequencies, since EM has almost converged.
+        assert!((weight("N", "dogs") - 0.4).abs() < 1e-2);
+        assert!((weight("D", "the") - 2.0 / 3.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn projection() {
+        assert_eq!(project_label("NP@12"), "NP");
+        assert_eq!(project_label("NP"), "NP");
+        assert_eq!(project_label("@"), "@");
+        assert_eq!(project_label("a@b"), "a@b");
+
+        let mut tree = Tree {
+            root: NodeType::NonTerminal("S@1".to_string()),
+            children: vec![Tree {
+                root: NodeType::Terminal("x@1".to_string()),
+                children: vec![],
+            }],
+        };
+        project_tree(&mut tree);
+        assert_eq!(tree.to_string(), "(S x@1)");
+    }
+}
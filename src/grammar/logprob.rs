@@ -0,0 +1,148 @@
+use std::cmp::Ordering;
+use std::ops::{Add, AddAssign, Div, Mul};
+
+use float_ord::FloatOrd;
+
+/// Floating point type in which the logarithms are stored. With the `compact-weights`
+/// feature, weights take half the memory, but lose precision.
+#[cfg(not(feature = "compact-weights"))]
+pub type Float = f64;
+#[cfg(feature = "compact-weights")]
+pub type Float = f32;
+
+/// Relative error up to which probabilities that went through `LogProb` are considered equal,
+/// e.g. in tests. It depends on the precision of `Float`.
+pub const TOLERANCE: f64 = 1e4 * Float::EPSILON as f64;
+
+/// Whether `a` and `b` are equal up to `TOLERANCE`, relative to the larger of them or 1.
+pub fn approx_eq(a: f64, b: f64) -> bool {
+    (a - b).abs() <= TOLERANCE * a.abs().max(b.abs()).max(1.0)
+}
+
+/// Probability stored as its logarithm. Parsing multiplies many probabilities, which
+/// underflows to 0 for long sentences, while adding their logarithms doesn't.
+/// `*` and `/` multiply and divide the probabilities, `+` adds them.
+/// The default is the probability 0.
+#[derive(Copy, Clone, Debug)]
+pub struct LogProb(pub Float);
+
+impl LogProb {
+    pub const ZERO: Self = LogProb(Float::NEG_INFINITY);
+    pub const ONE: Self = LogProb(0.0);
+
+    pub fn from_prob(p: f64) -> Self {
+        Self::from_ln(p.ln())
+    }
+
+    pub fn from_ln(ln: f64) -> Self {
+        LogProb(ln as Float)
+    }
+
+    pub fn prob(self) -> f64 {
+        self.ln().exp()
+    }
+
+    /// The logarithm of the probability.
+    // The conversion is only needed with `compact-weights`.
+    #[allow(clippy::useless_conversion)]
+    pub fn ln(self) -> f64 {
+        f64::from(self.0)
+    }
+
+    /// Raises the probability to the power of `e`.
+    pub fn powf(self, e: f64) -> Self {
+        Self::from_ln(self.ln() * e)
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == Float::NEG_INFINITY
+    }
+}
+
+impl Default for LogProb {
+    fn default() -> Self {
+        Self::ZERO
+    }
+}
+
+impl PartialEq for LogProb {
+    fn eq(&self, other: &Self) -> bool {
+        FloatOrd(self.0) == FloatOrd(other.0)
+    }
+}
+
+impl Eq for LogProb {}
+
+impl PartialOrd for LogProb {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for LogProb {
+    fn cmp(&self, other: &Self) -> Ordering {
+        FloatOrd(self.0).cmp(&FloatOrd(other.0))
+    }
+}
+
+// Multiplying probabilities adds their logarithms.
+#[allow(clippy::suspicious_arithmetic_impl)]
+impl Mul for LogProb {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        LogProb(self.0 + rhs.0)
+    }
+}
+
+#[allow(clippy::suspicious_arithmetic_impl)]
+impl Div for LogProb {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self {
+        LogProb(self.0 - rhs.0)
+    }
+}
+
+impl Add for LogProb {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        let (hi, lo) = if self > rhs { (self, rhs) } else { (rhs, self) };
+        if hi.is_zero() {
+            return Self::ZERO;
+        }
+        LogProb(hi.0 + (lo.0 - hi.0).exp().ln_1p())
+    }
+}
+
+impl AddAssign for LogProb {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn log_arithmetic() {
+        let p = LogProb::from_prob(0.5);
+        let q = LogProb::from_prob(0.25);
+
+        assert!(approx_eq((p * q).prob(), 0.125));
+        assert!(approx_eq((q / p).prob(), 0.5));
+        assert!(approx_eq((p + q).prob(), 0.75));
+        assert_eq!(p + LogProb::ZERO, p);
+        assert!((LogProb::ZERO + LogProb::ZERO).is_zero());
+        assert!((p * LogProb::ZERO).is_zero());
+        assert!(LogProb::ZERO < q && q < p && p < LogProb::ONE);
+        assert!(approx_eq(p.powf(2.0).prob(), 0.25));
+
+        // 0.5^2000 underflows as f64, but not as logarithm.
+        let small = (0..2000).fold(LogProb::ONE, |acc, _| acc * p);
+        assert!(!small.is_zero());
+        assert!(small > small * p);
+    }
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+/// Prefix of the lines that make up the metadata header at the start of a grammar file.
+/// A single `#` can't be used, because it is a tag of the Penn Treebank.
+pub const HEADER_PREFIX: &str = "#!";
+
+/// Key-value pairs describing how a grammar was created, e.g. the creator, corpus, options
+/// and a hash of the corpus. Written as `#! key: value` lines before the rules.
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct GrammarMetadata {
+    entries: Vec<(String, String)>,
+}
+
+impl GrammarMetadata {
+    pub fn with(mut self, key: &str, value: impl fmt::Display) -> Self {
+        self.entries.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[(String, String)] {
+        &self.entries
+    }
+
+    /// Reads the header from the start of a grammar file and leaves the reader at the first rule.
+    /// Files without header result in empty metadata.
+    pub fn read<R: BufRead>(reader: &mut R) -> io::Result<Self> {
+        let mut metadata = Self::default();
+        let mut line = String::new();
+
+        while reader.fill_buf()?.starts_with(HEADER_PREFIX.as_bytes()) {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let entry = line[HEADER_PREFIX.len()..].trim();
+            // Lines without key are treated as comments.
+            if let Some((key, value)) = entry.split_once(':') {
+                metadata
+                    .entries
+                    .push((key.trim().to_string(), value.trim().to_string()));
+            }
+        }
+
+        Ok(metadata)
+    }
+
+    pub fn write<W: Write>(&self, buf: &mut W) -> io::Result<()> {
+        for (key, value) in &self.entries {
+            writeln!(buf, "{} {}: {}", HEADER_PREFIX, key, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for GrammarMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in &self.entries {
+            writeln!(f, "{}: {}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn header_roundtrip() {
+        let metadata = GrammarMetadata::default()
+            .with("creator", "pcfg_tool 0.1.0")
+            .with("hash", 42);
+        let mut file = vec![];
+        metadata.write(&mut file).unwrap();
+        file.extend_from_slice(b"# # 1.0\n");
+
+        let mut reader = &file[..];
+        assert_eq!(metadata, GrammarMetadata::read(&mut reader).unwrap());
+        assert_eq!(Some("42"), metadata.get("hash"));
+        // A PTB tag `#` is not mistaken for the header.
+        assert_eq!(b"# # 1.0\n", reader);
+        assert!(GrammarMetadata::read(&mut reader).unwrap().is_empty());
+    }
+}
@@ -1,4 +1,22 @@
 pub mod bare;
+pub mod binary;
 pub mod chart;
+pub mod cnf;
+pub mod constraint;
+pub mod foreign;
+pub mod format;
+pub mod graph;
+pub mod hierarchy;
+pub mod latent;
+pub mod logprob;
+pub mod metadata;
+pub mod outside;
 pub mod parse;
+pub mod provenance;
+pub mod prune;
 pub mod rule;
+pub mod sample;
+pub mod score;
+pub mod spill;
+#[cfg(feature = "chart-trace")]
+pub mod trace;
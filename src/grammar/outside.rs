@@ -0,0 +1,279 @@
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+
+use float_ord::FloatOrd;
+use fxhash::FxHashMap;
+use nom::branch::alt;
+use nom::bytes::complete::is_not;
+use nom::character::complete::{multispace0, multispace1, u64 as parse_u64};
+use nom::combinator::all_consuming;
+use nom::error::Error as NError;
+use nom::number::complete::double;
+use nom::sequence::{terminated, tuple};
+use nom::{Finish, IResult};
+use smallstr::SmallString;
+
+use super::rule::{Rule, WeightedRule};
+
+/// Estimate of the Viterbi outside weight of a non-terminal, i.e. of the best derivation
+/// from the initial non-terminal with a gap for it. With `context`, the estimate only holds
+/// for items with the given numbers of words to their left and right (SX estimate).
+/// For A* parsing the estimates must not be lower than the actual outside weights.
+#[derive(PartialEq, Debug)]
+pub struct OutsideEstimate<N> {
+    pub label: N,
+    pub context: Option<(usize, usize)>,
+    pub weight: f64,
+}
+
+type ParsedOutsideEstimate = OutsideEstimate<SmallString<[u8; 8]>>;
+
+impl<N: fmt::Display> fmt::Display for OutsideEstimate<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.context {
+            Some((left, right)) => write!(f, "{} {} {} {}", self.label, left, right, self.weight),
+            None => write!(f, "{} {}", self.label, self.weight),
+        }
+    }
+}
+
+/// Parses lines of the form `NP 0.25`, or `NP 2 3 0.25` for an estimate that only
+/// holds with 2 words to the left and 3 words to the right.
+impl FromStr for ParsedOutsideEstimate {
+    type Err = NError<String>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match all_consuming(alt((parse_contextual, parse_general)))(s.trim()).finish() {
+            Ok((_, estimate)) => Ok(estimate),
+            Err(NError { input, code }) => Err(NError {
+                input: input.to_string(),
+                code,
+            }),
+        }
+    }
+}
+
+fn parse_contextual(input: &str) -> IResult<&str, ParsedOutsideEstimate> {
+    tuple((
+        terminated(is_not(" \t"), multispace1),
+        terminated(parse_u64, multispace1),
+        terminated(parse_u64, multispace1),
+        terminated(double, multispace0),
+    ))(input)
+    .map(|(i, (label, left, right, weight))| {
+        (
+            i,
+            OutsideEstimate {
+                label: SmallString::from(label),
+                context: Some((left as usize, right as usize)),
+                weight,
+            },
+        )
+    })
+}
+
+fn parse_general(input: &str) -> IResult<&str, ParsedOutsideEstimate> {
+    tuple((
+        terminated(is_not(" \t"), multispace1),
+        terminated(double, multispace0),
+    ))(input)
+    .map(|(i, (label, weight))| {
+        (
+            i,
+            OutsideEstimate {
+                label: SmallString::from(label),
+                context: None,
+                weight,
+            },
+        )
+    })
+}
+
+/// Computes the Viterbi outside weight of every non-terminal of the grammar over all
+/// contexts, i.e. the weight of the best derivation from one of the non-terminals in `initial`
+/// with a gap for it,
+/// in which all other non-terminals derive their best yield. The results are sorted by label.
+/// Non-terminals that don't occur in any derivation get the weight 0.
+pub fn viterbi_outside<N, T>(
+    rules: &[WeightedRule<N, T, FloatOrd<f64>>],
+    initial: &[N],
+) -> Vec<OutsideEstimate<N>>
+where
+    N: Eq + Hash + Ord + Clone,
+    T: Eq + Hash,
+{
+    let mut nonterminals: Vec<&N> = initial.iter().collect();
+    for weighted_rule in rules {
+        match &weighted_rule.rule {
+            Rule::Lexical { lhs, .. } => nonterminals.push(lhs),
+            Rule::NonLexical { lhs, rhs } => {
+                nonterminals.push(lhs);
+                nonterminals.extend(rhs.iter());
+            }
+        }
+    }
+    nonterminals.sort();
+    nonterminals.dedup();
+
+    // The best derivations don't repeat a non-terminal on any path, so their weights
+    // are found after as many rounds as there are non-terminals.
+    let rounds = nonterminals.len() + 1;
+
+    let mut inside: FxHashMap<&N, f64> = FxHashMap::default();
+    for weighted_rule in rules {
+        if let Rule::Lexical { lhs, .. } = &weighted_rule.rule {
+            improve(&mut inside, lhs, weighted_rule.weight.0);
+        }
+    }
+    for _ in 0..rounds {
+        let mut changed = false;
+        for weighted_rule in rules {
+            if let Rule::NonLexical { lhs, rhs } = &weighted_rule.rule {
+                let children: Option<f64> = rhs.iter().map(|n| inside.get(n).copied()).product();
+                if let Some(children) = children {
+                    changed |= improve(&mut inside, lhs, weighted_rule.weight.0 * children);
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    let mut outside: FxHashMap<&N, f64> = FxHashMap::default();
+    for n in initial {
+        outside.insert(n, 1.0);
+    }
+    for _ in 0..rounds {
+        let mut changed = false;
+        for weighted_rule in rules {
+            if let Rule::NonLexical { lhs, rhs } = &weighted_rule.rule {
+                let parent = match outside.get(lhs) {
+                    Some(parent) => *parent,
+                    None => continue,
+                };
+                for (i, n) in rhs.iter().enumerate() {
+                    let siblings: Option<f64> = rhs
+                        .iter()
+                        .enumerate()
+                        .filter(|(j, _)| *j != i)
+                        .map(|(_, m)| inside.get(m).copied())
+                        .product();
+                    if let Some(siblings) = siblings {
+                        changed |=
+                            improve(&mut outside, n, parent * weighted_rule.weight.0 * siblings);
+                    }
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    nonterminals
+        .into_iter()
+        .map(|n| OutsideEstimate {
+            label: n.clone(),
+            context: None,
+            weight: outside.get(n).copied().unwrap_or(0.0),
+        })
+        .collect()
+}
+
+/// Raises the weight of `n` to `weight`. Returns whether it was lower before.
+fn improve<'a, N: Eq + Hash>(weights: &mut FxHashMap<&'a N, f64>, n: &'a N, weight: f64) -> bool {
+    let entry = weights.entry(n).or_insert(0.0);
+    if weight > *entry {
+        *entry = weight;
+        true
+    } else {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rule(lhs: &str, rhs: &[&str], weight: f64) -> WeightedRule<String, String, FloatOrd<f64>> {
+        WeightedRule {
+            rule: Rule::NonLexical {
+                lhs: lhs.to_string(),
+                rhs: rhs.iter().map(|n| n.to_string()).collect(),
+            },
+            weight: FloatOrd(weight),
+        }
+    }
+
+    #[test]
+    fn outside_weights() {
+        let lexical = |lhs: &str, rhs: &str, weight| WeightedRule {
+            rule: Rule::Lexical {
+                lhs: lhs.to_string(),
+                rhs: rhs.to_string(),
+            },
+            weight: FloatOrd(weight),
+        };
+        let rules = vec![
+            rule("ROOT", &["S"], 1.0),
+            rule("S", &["NP", "VP"], 1.0),
+            rule("NP", &["D", "N"], 0.4),
+            lexical("NP", "she", 0.6),
+            rule("VP", &["V", "NP"], 1.0),
+            lexical("D", "the", 1.0),
+            lexical("N", "dog", 0.5),
+            lexical("V", "sees", 1.0),
+            rule("X", &["NP"], 1.0),
+        ];
+
+        let weights: Vec<_> = viterbi_outside(&rules, &["ROOT".to_string()])
+            .into_iter()
+            .map(|e| (e.label, e.weight))
+            .collect();
+        let expected = [
+            ("D", 0.6 * 0.4 * 0.5),
+            ("N", 0.6 * 0.4),
+            ("NP", 0.6),
+            ("ROOT", 1.0),
+            ("S", 1.0),
+            ("V", 0.6 * 0.6),
+            ("VP", 0.6),
+            ("X", 0.0),
+        ];
+
+        assert_eq!(weights.len(), expected.len());
+        for ((label, weight), (expected_label, expected_weight)) in weights.iter().zip(expected) {
+            assert_eq!(label, expected_label);
+            assert!((weight - expected_weight).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn parse_estimates() {
+        assert_eq!(
+            OutsideEstimate::from_str("NP 0.25").unwrap(),
+            OutsideEstimate {
+                label: SmallString::from("NP"),
+                context: None,
+                weight: 0.25,
+            }
+        );
+        assert_eq!(
+            OutsideEstimate::from_str("NP 2 3 0.25").unwrap(),
+            OutsideEstimate {
+                label: SmallString::from("NP"),
+                context: Some((2, 3)),
+                weight: 0.25,
+            }
+        );
+        assert_eq!(
+            OutsideEstimate::from_str("NP 2 3 0.25")
+                .unwrap()
+                .to_string(),
+            "NP 2 3 0.25"
+        );
+        assert!(OutsideEstimate::from_str("NP 1 0.5").is_err());
+    }
+}
@@ -1,60 +1,276 @@
 use std::collections::BinaryHeap;
-use std::hash::Hash;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
 
 use float_ord::FloatOrd;
-use fxhash::{FxBuildHasher, FxHashMap};
+use fxhash::{FxBuildHasher, FxHashMap, FxHashSet, FxHasher};
 use multimap::MultiMap;
+use rayon::prelude::*;
 
-use super::chart::Chart;
+use super::binary;
+use super::chart::{cell_start_index, Chart, SpanMask, SurvivorIndex};
+use super::constraint::RuleConstraint;
+use super::logprob::LogProb;
+use super::metadata::GrammarMetadata;
+use super::outside::OutsideEstimate;
+use super::prune::{PruneMode, SpanInfo};
 use super::rule::{Rule, WeightedRule};
+use super::spill::rule_text;
+#[cfg(feature = "chart-trace")]
+use super::trace::{ChartTrace, Derivation, Frame, FrameStage, TraceEntry};
+use crate::rng::XorShift;
 use crate::tree::NodeType;
 use crate::Sentence;
 use crate::Tree;
 
-type ChartEntry = (FloatOrd<f64>, Option<BacktraceInfo>);
+/// Weight of the best derivation of a non-terminal in a cell, and how it was derived.
+/// The default entry is not derivable.
+pub type ChartEntry = (LogProb, Option<BacktraceInfo>);
 type IntNt = u32;
 
+/// Key of the hash of the unary rules in the header of files written by `write_unary_closure`.
+pub const CLOSURE_HASH_KEY: &str = "unary-rules";
+
+/// Iterations after which summing over chains of unary rules is cut off.
+const MAX_UNARY_ITERATIONS: usize = 64;
+/// Contributions below this fraction of an entry end the summation over chains of unary rules.
+const UNARY_EPSILON: f64 = 1e-12;
+/// Log probabilities found with pruning may exceed the base log probability by this much,
+/// to allow for rounding.
+const PRUNING_EPSILON: f64 = 1e-9;
+
 /// Reresents backtrace information used during the execution of the
 /// cyk algorithm to construct the constituent tree.
 /// For `Binary`, the contained  integers refer to the cell in c of that non-terminal.
 /// For `Chain`, it refers to the non-terminal in the same cell in c.
 /// For `Term` it represents the location of the terminal in the input sentence.
+/// For `Closure`, the first integer refers to the non-terminal in the same cell
+/// at the bottom of the chain, the second one to the chain in the unary closure.
+/// The indices are stored as `u32` to keep chart entries small, which limits charts to
+/// 2^32 entries.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
+pub enum BacktraceInfo {
+    Binary(u32, u32),
+    Chain(u32),
+    Term(u32),
+    Closure(u32, u32),
+}
+
+/// Backtrace information of a derivation in the k-best chart.
+/// For `Binary`, the chart indices of the children are followed by the rank of their derivation.
+/// For `Chain`, the non-terminal in the same cell is followed by the rank of its derivation.
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
-enum BacktraceInfo {
-    Binary(usize, usize),
-    Chain(usize),
+enum KBestBacktrace {
+    Binary(usize, usize, usize, usize),
+    Chain(usize, usize),
     Term(usize),
 }
 
-pub struct PruneMode {
-    pub threshold: Option<f64>,
-    pub fixed_size: Option<usize>,
+type KBestEntry = (LogProb, KBestBacktrace);
+// Priority, inside weight, start, span and label, and how the item was derived.
+type AgendaItem = (LogProb, LogProb, (usize, usize, IntNt), BacktraceInfo);
+// Expected number of uses of rules in the derivations of a sentence.
+type ExpectedCounts<N, T> = Vec<(Rule<N, T>, f64)>;
+// Combination of the derivations of two children with a rule and split point.
+type KBestCandidate = (LogProb, KBestBacktrace, (usize, usize));
+
+/// Point during chart construction at which a cell is passed to an observer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum CellStage {
+    /// Before the unary closure.
+    Filled,
+    Closure,
+    Pruned(Beam),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Beam {
+    Threshold,
+    Rank,
+    Coarse,
+    Posterior,
+    Deadline,
+    Tags,
+    Custom,
+}
+
+impl fmt::Display for Beam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Beam::Threshold => write!(f, "threshold beam"),
+            Beam::Rank => write!(f, "rank beam"),
+            Beam::Coarse => write!(f, "coarse grammar"),
+            Beam::Posterior => write!(f, "posterior threshold"),
+            Beam::Deadline => write!(f, "timeout"),
+            Beam::Tags => write!(f, "tag model"),
+            Beam::Custom => write!(f, "custom pruner"),
+        }
+    }
+}
+
+/// Outcome of checking where the gold derivation of a sentence got lost in the chart.
+#[derive(Debug, PartialEq, Eq)]
+pub enum GoldDiagnosis<N> {
+    /// All entries of the gold derivation survived.
+    Intact,
+    /// The gold tree does not fit the sentence.
+    YieldMismatch,
+    /// The gold entry could not be derived with the grammar.
+    NotDerived { start: usize, span: usize, label: N },
+    /// The gold entry was removed by a beam.
+    Pruned {
+        start: usize,
+        span: usize,
+        label: N,
+        beam: Beam,
+    },
+}
+
+impl<N: fmt::Display> fmt::Display for GoldDiagnosis<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stage = |span: usize| {
+            if span == 1 {
+                "lexical".to_string()
+            } else {
+                format!("span length {}", span)
+            }
+        };
+
+        match self {
+            GoldDiagnosis::Intact => write!(f, "gold derivation was not pruned"),
+            GoldDiagnosis::YieldMismatch => write!(f, "gold tree does not match sentence length"),
+            GoldDiagnosis::NotDerived { start, span, label } => write!(
+                f,
+                "gold item {} [{},{}] not derivable at {}",
+                label,
+                start,
+                start + span,
+                stage(*span)
+            ),
+            GoldDiagnosis::Pruned {
+                start,
+                span,
+                label,
+                beam,
+            } => write!(
+                f,
+                "gold item {} [{},{}] pruned at {} by {}",
+                label,
+                start,
+                start + span,
+                stage(*span),
+                beam
+            ),
+        }
+    }
+}
+
+/// A pruning mode that found a more probable derivation than parsing without pruning.
+#[derive(Debug)]
+pub struct PruningDiscrepancy {
+    pub mode: String,
+    pub base: LogProb,
+    pub pruned: LogProb,
+}
+
+impl fmt::Display for PruningDiscrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} found log probability {} above {} without pruning",
+            self.mode,
+            self.pruned.ln(),
+            self.base.ln()
+        )
+    }
+}
+
+/// Chain of unary rules from a non-terminal back to itself, listed from top to bottom,
+/// whose weight is at least 1.
+#[derive(Debug, PartialEq)]
+pub struct UnaryCycle<N> {
+    pub chain: Vec<N>,
+    pub weight: f64,
+}
+
+impl<N: fmt::Display> fmt::Display for UnaryCycle<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unary rules form a cycle ")?;
+        for (i, n) in self.chain.iter().enumerate() {
+            write!(f, "{}{}", if i > 0 { " -> " } else { "" }, n)?;
+        }
+        write!(f, " with weight {}", self.weight)
+    }
+}
+
+/// Why the parser can't use a rule of a file that refers to rules of the grammar.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RuleProblem {
+    /// A non-lexical rule with more than two or no non-terminals on its RHS.
+    NotBinarised,
+    /// A non-terminal of the rule doesn't occur in the grammar.
+    UnknownNonterminal,
+    /// Only non-lexical rules can be constrained.
+    Lexical,
 }
 
-impl PruneMode {
-    fn is_prune(&self) -> bool {
-        self.threshold.is_some() || self.fixed_size.is_some()
+/// Rule of a file that refers to rules of the grammar, e.g. protected rules, that the parser
+/// can't use.
+#[derive(Debug, PartialEq)]
+pub struct InvalidRule<N: Eq + Hash, T: Eq + Hash> {
+    pub rule: Rule<N, T>,
+    pub problem: RuleProblem,
+}
+
+impl<N, T> fmt::Display for InvalidRule<N, T>
+where
+    N: Eq + Hash + fmt::Display,
+    T: Eq + Hash + fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rule {} ", rule_text(&self.rule))?;
+        match self.problem {
+            RuleProblem::NotBinarised => write!(f, "is not binarised"),
+            RuleProblem::UnknownNonterminal => {
+                write!(f, "has a non-terminal that doesn't occur in the grammar")
+            }
+            RuleProblem::Lexical => write!(
+                f,
+                "is lexical, but only non-lexical rules can be constrained"
+            ),
+        }
     }
+}
+
+/// Posterior probabilities of labeled spans of a sentence.
+/// Displayed as one `start end label posterior` line per span, separated by tabs
+/// and followed by an empty line.
+#[derive(Debug, PartialEq)]
+pub struct SpanPosteriors<N>(pub Vec<(usize, usize, N, f64)>);
 
-    fn empty() -> Self {
-        Self {
-            threshold: None,
-            fixed_size: None,
+impl<N: fmt::Display> fmt::Display for SpanPosteriors<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (start, end, label, posterior) in &self.0 {
+            writeln!(f, "{}\t{}\t{}\t{}", start, end, label, posterior)?;
         }
+        Ok(())
     }
 }
 
 #[derive(Debug)]
 /// Grammar built specifically for deriving most
 /// probable constituent trees from sentences with
-/// CYK algorithm.
+/// CYK algorithm. Weights are stored and combined as
+/// logarithms, so that long sentences don't underflow.
 pub struct GrammarParse<N, T, W>
 where
     N: Eq + Hash,
     T: Eq + Hash,
     W: Copy + Default,
 {
-    initial_nonterminal: IntNt,
+    // Non-terminals that may derive a whole sentence.
+    initial_nonterminals: Vec<IntNt>,
     // Lexical rules which we search by terminal on the RHS.
     pub rules_lexical: MultiMap<T, (IntNt, W), FxBuildHasher>,
     // Non-lexical rules with one non-terminals on the RHS.
@@ -63,30 +279,97 @@ where
     // Non-lexical rules with two non-terminals on the RHS.
     // We search by non-terminal on the LHS.
     rules_double: MultiMap<IntNt, (IntNt, IntNt, W), FxBuildHasher>,
+    // The same rules for the CYK parser, as (LHS, weight), grouped by the right child.
+    // We search by the left non-terminal on the RHS, and then by the right one.
+    rules_double_left: MultiMap<IntNt, (IntNt, Vec<(IntNt, W)>), FxBuildHasher>,
+    // Best binary rule for each pair of non-terminals on the RHS,
+    // used by the shift-reduce parser.
+    best_double: FxHashMap<(IntNt, IntNt), (IntNt, W)>,
     // Lookup table for intified non-terminals.
     lookup: Vec<N>,
     lookup_index: FxHashMap<N, IntNt>,
+    // Rules whose chart entries are never pruned.
+    protected_lexical: MultiMap<T, IntNt, FxBuildHasher>,
+    protected_chain: FxHashSet<(IntNt, IntNt)>,
+    protected_double: FxHashSet<(IntNt, IntNt, IntNt)>,
+    // Precomputed unary closure, replacing the per-cell closure when present.
+    // We search by the non-terminal at the bottom of the chain.
+    closure: Option<MultiMap<IntNt, (IntNt, W, usize), FxBuildHasher>>,
+    // Chains of the unary closure from top to bottom.
+    closure_paths: Vec<Vec<IntNt>>,
+    // Restrictions on where non-lexical rules may be applied.
+    constraints_chain: FxHashMap<(IntNt, IntNt), Vec<RuleConstraint>>,
+    constraints_double: FxHashMap<(IntNt, IntNt, IntNt), Vec<RuleConstraint>>,
+    // Outside estimates for A* parsing, for all contexts and for
+    // specific numbers of words to the left and right.
+    outside: FxHashMap<IntNt, W>,
+    outside_context: FxHashMap<(IntNt, usize, usize), W>,
 }
 
-impl<N, T> GrammarParse<N, T, FloatOrd<f64>>
+impl<N, T> GrammarParse<N, T, LogProb>
 where
-    N: Eq + Hash + Clone,
-    T: Eq + Hash + Clone,
+    N: Eq + Hash + Clone + Sync,
+    T: Eq + Hash + Clone + AsRef<str> + Sync,
 {
     pub fn new(initial_nonterminal: N) -> Self {
         let mut result = Self {
-            initial_nonterminal: 0,
+            initial_nonterminals: vec![],
             rules_lexical: MultiMap::default(),
             rules_chain: MultiMap::default(),
             rules_double: MultiMap::default(),
+            rules_double_left: MultiMap::default(),
+            best_double: FxHashMap::default(),
             lookup: vec![],
             lookup_index: FxHashMap::default(),
+            protected_lexical: MultiMap::default(),
+            protected_chain: FxHashSet::default(),
+            protected_double: FxHashSet::default(),
+            closure: None,
+            closure_paths: vec![],
+            constraints_chain: FxHashMap::default(),
+            constraints_double: FxHashMap::default(),
+            outside: FxHashMap::default(),
+            outside_context: FxHashMap::default(),
         };
-        result.initial_nonterminal = result.intify(initial_nonterminal);
+        result.add_initial_nonterminal(initial_nonterminal);
 
         result
     }
 
+    /// Allows `n` to derive whole sentences as well. The parsers pick the most probable of the
+    /// initial non-terminals, the inside-outside computations sum over them.
+    pub fn add_initial_nonterminal(&mut self, n: N) {
+        let n = self.intify(n);
+        if !self.initial_nonterminals.contains(&n) {
+            self.initial_nonterminals.push(n);
+        }
+    }
+
+    /// The initial non-terminal with the most probable entry among the entries `cell`
+    /// of the root cell, the first one if none of them is derivable.
+    fn best_initial<E, F: Fn(&E) -> LogProb>(&self, cell: &[E], weight: F) -> usize {
+        let mut best = self.initial_nonterminals[0] as usize;
+        for a in self
+            .initial_nonterminals
+            .iter()
+            .skip(1)
+            .map(|a| *a as usize)
+        {
+            if weight(&cell[a]) > weight(&cell[best]) {
+                best = a;
+            }
+        }
+        best
+    }
+
+    /// Sum of the inside weights of the initial non-terminals over the whole sentence.
+    fn initial_inside(&self, inside: &Chart<LogProb>, s_len: usize) -> LogProb {
+        let root = inside.cell_start_index(0, s_len);
+        self.initial_nonterminals
+            .iter()
+            .fold(LogProb::ZERO, |total, a| total + inside[root + *a as usize])
+    }
+
     fn intify(&mut self, n: N) -> IntNt {
         self.lookup_index.get(&n).copied().unwrap_or_else(|| {
             let index = self.lookup.len() as IntNt;
@@ -96,331 +379,2352 @@ where
         })
     }
 
-    pub fn insert_rule(&mut self, weighted_rule: WeightedRule<N, T, FloatOrd<f64>>) {
-        match weighted_rule.rule {
+    /// Inserts a rule into the grammar. Parsing is only supported with binarised grammars, so
+    /// non-lexical rules with more than two non-terminals on their RHS are rejected.
+    pub fn insert_rule(
+        &mut self,
+        weighted_rule: WeightedRule<N, T, FloatOrd<f64>>,
+    ) -> Result<(), InvalidRule<N, T>> {
+        self.insert_log_rule(
+            weighted_rule.rule,
+            LogProb::from_prob(weighted_rule.weight.0),
+        )
+    }
+
+    /// Like `insert_rule`, but with the weight already as `LogProb`, e.g. for grammars that store
+    /// log-probabilities too small for an `f64` probability.
+    pub fn insert_log_rule(
+        &mut self,
+        rule: Rule<N, T>,
+        weight: LogProb,
+    ) -> Result<(), InvalidRule<N, T>> {
+        match rule {
             Rule::Lexical { lhs, rhs } => {
                 let lhs = self.intify(lhs);
-                self.rules_lexical.insert(rhs, (lhs, weighted_rule.weight));
+                self.rules_lexical.insert(rhs, (lhs, weight));
+            }
+
+            Rule::NonLexical { lhs, rhs } if rhs.is_empty() || rhs.len() > 2 => {
+                return Err(InvalidRule {
+                    rule: Rule::NonLexical { lhs, rhs },
+                    problem: RuleProblem::NotBinarised,
+                })
             }
 
             Rule::NonLexical { lhs, mut rhs } => {
                 let lhs = self.intify(lhs);
                 let rhs: Vec<_> = rhs.drain(..).map(|n| self.intify(n)).collect();
 
-                match rhs.as_slice() {
-                    [n] => {
-                        self.rules_chain.insert(*n, (lhs, weighted_rule.weight));
-                    }
-                    [n1, n2] => self
-                        .rules_double
-                        .insert(lhs, (*n1, *n2, weighted_rule.weight)),
-                    _ => panic!("Parsing is only supported with binarised grammar rules!"),
+                if let [n1, n2] = rhs[..] {
+                    self.insert_double(lhs, n1, n2, weight);
+                } else {
+                    self.rules_chain.insert(rhs[0], (lhs, weight));
                 }
             }
         };
+        Ok(())
     }
 
-    pub fn cyk(&self, sentence: &Sentence<T>, mode: &PruneMode) -> Option<Tree<NodeType<N, T>>> {
-        let num_nt = self.lookup.len();
-        let s_len = sentence.len();
-        const ZERO: FloatOrd<f64> = FloatOrd(0.0);
+    fn insert_double(&mut self, a: IntNt, b: IntNt, c: IntNt, weight: LogProb) {
+        let best = self.best_double.entry((b, c)).or_insert((a, weight));
+        if weight > best.1 {
+            *best = (a, weight);
+        }
+        self.rules_double.insert(a, (b, c, weight));
+        match self
+            .rules_double_left
+            .get_vec_mut(&b)
+            .and_then(|rights| rights.iter_mut().find(|(right, _)| *right == c))
+        {
+            Some((_, rules)) => rules.push((a, weight)),
+            None => self.rules_double_left.insert(b, (c, vec![(a, weight)])),
+        }
+    }
 
-        let mut chart: Chart<ChartEntry> = Chart::new(s_len, num_nt);
-        self.chart_setup(sentence, &mut chart, mode);
+    /// Weight of a rule of the grammar, `None` if the grammar doesn't contain it.
+    pub fn rule_weight(&self, rule: &Rule<N, T>) -> Option<f64> {
+        match rule {
+            Rule::Lexical { lhs, rhs } => {
+                let a = self.lookup_index.get(lhs)?;
+                self.rules_lexical
+                    .get_vec(rhs)?
+                    .iter()
+                    .find(|(b, _)| b == a)
+                    .map(|(_, w)| w.prob())
+            }
+            Rule::NonLexical { lhs, rhs } => {
+                let a = *self.lookup_index.get(lhs)?;
+                let rhs = rhs
+                    .iter()
+                    .map(|n| self.lookup_index.get(n).copied())
+                    .collect::<Option<Vec<_>>>()?;
 
-        for r in 2..=s_len {
-            for i in 0..=(s_len - r) {
-                let j = i + r;
-                let i_j = chart.cell_start_index(i, r);
-                for a in 0..num_nt {
-                    for m in (i + 1)..j {
-                        let i_m = chart.cell_start_index(i, m - i);
-                        let m_j = chart.cell_start_index(m, j - m);
+                match rhs.as_slice() {
+                    [b] => self
+                        .rules_chain
+                        .get_vec(b)?
+                        .iter()
+                        .find(|(x, _)| *x == a)
+                        .map(|(_, w)| w.prob()),
+                    [b, c] => self
+                        .rules_double
+                        .get_vec(&a)?
+                        .iter()
+                        .find(|(x, y, _)| x == b && y == c)
+                        .map(|(_, _, w)| w.prob()),
+                    _ => None,
+                }
+            }
+        }
+    }
 
-                        if let Some(binary_rules) = self.rules_double.get_vec(&(a as IntNt)) {
-                            let binary_rules_iter = binary_rules
-                                .iter()
-                                .map(|(b, c, w)| (*b as usize, *c as usize, w))
-                                .filter(|(b, c, _)| {
-                                    // Manually filter out zero factors for pruning.
-                                    // This provides a significant speedup.
-                                    if mode.is_prune() {
-                                        chart[i_m + *b].0 > ZERO && chart[m_j + *c].0 > ZERO
-                                    } else {
-                                        true
-                                    }
-                                });
-
-                            chart[i_j + a] = chart[i_j + a].max(
-                                binary_rules_iter
-                                    .map(|(b, c, weight)| {
-                                        (
-                                            FloatOrd(
-                                                weight.0
-                                                    * chart[i_m + b].0 .0
-                                                    * chart[m_j + c].0 .0,
-                                            ),
-                                            Some(BacktraceInfo::Binary(i_m + b, m_j + c)),
-                                        )
-                                    })
-                                    .max()
-                                    .unwrap_or_default(),
-                            );
-                        }
+    /// Gives every non-terminal the rules of its coarser labels that it lacks, e.g. `NP-SBJ -> DT
+    /// NN` for `NP -> DT NN`, with their weight multiplied by `penalty` for every step up the
+    /// hierarchy. `parent` returns the coarse label of a label. Has to be called before a unary
+    /// closure is inserted; a precomputed one, e.g. of a compiled grammar, is discarded if unary
+    /// rules are added. Returns the number of added rules.
+    pub fn insert_backoff_rules<F: Fn(&N) -> Option<N>>(
+        &mut self,
+        parent: F,
+        penalty: f64,
+    ) -> usize {
+        // Rules by their LHS.
+        let mut lexical: FxHashMap<IntNt, Vec<(T, LogProb)>> = FxHashMap::default();
+        for (t, rules) in self.rules_lexical.iter_all() {
+            for (a, w) in rules {
+                lexical.entry(*a).or_default().push((t.clone(), *w));
+            }
+        }
+        let mut chain: FxHashMap<IntNt, Vec<(IntNt, LogProb)>> = FxHashMap::default();
+        for (b, rules) in self.rules_chain.iter_all() {
+            for (a, w) in rules {
+                chain.entry(*a).or_default().push((*b, *w));
+            }
+        }
+        let double: FxHashMap<IntNt, Vec<(IntNt, IntNt, LogProb)>> = self
+            .rules_double
+            .iter_all()
+            .map(|(a, rules)| (*a, rules.clone()))
+            .collect();
+
+        let mut added = 0;
+        for a in 0..self.lookup.len() as IntNt {
+            let mut factor = LogProb::ONE;
+            let mut label = self.lookup[a as usize].clone();
+            let mut visited = FxHashSet::default();
+            while let Some(coarse) = parent(&label) {
+                factor = factor * LogProb::from_prob(penalty);
+                if !visited.insert(coarse.clone()) {
+                    break;
+                }
+                label = coarse;
+                let c = match self.lookup_index.get(&label) {
+                    Some(c) => *c,
+                    None => continue,
+                };
+
+                for (t, w) in lexical.get(&c).into_iter().flatten() {
+                    let rules = self.rules_lexical.get_vec(t);
+                    if !rules.is_some_and(|r| r.iter().any(|(x, _)| *x == a)) {
+                        self.rules_lexical.insert(t.clone(), (a, *w * factor));
+                        added += 1;
                     }
                 }
-                self.unary_closure(chart.get_cell_mut(i_j));
-                if let Some(threshold) = mode.threshold {
-                    self.prune_threshold(chart.get_cell_mut(i_j), threshold);
+
+                for (b, w) in chain.get(&c).into_iter().flatten() {
+                    let rules = self.rules_chain.get_vec(b);
+                    if *b != a && !rules.is_some_and(|r| r.iter().any(|(x, _)| *x == a)) {
+                        self.rules_chain.insert(*b, (a, *w * factor));
+                        self.closure = None;
+                        self.closure_paths.clear();
+                        added += 1;
+                    }
                 }
-                if let Some(fixed_size) = mode.fixed_size {
-                    self.prune_fixed_size(chart.get_cell_mut(i_j), fixed_size);
+
+                for &(b1, b2, w) in double.get(&c).into_iter().flatten() {
+                    let rules = self.rules_double.get_vec(&a);
+                    if !rules.is_some_and(|r| r.iter().any(|(x, y, _)| (*x, *y) == (b1, b2))) {
+                        self.insert_double(a, b1, b2, w * factor);
+                        added += 1;
+                    }
                 }
             }
         }
 
-        let root_cell = chart.cell_start_index(0, s_len) + (self.initial_nonterminal as usize);
-        Self::construct_best_tree(chart.data(), root_cell, sentence, &self.lookup)
+        added
     }
 
-    fn chart_setup(&self, sentence: &Sentence<T>, chart: &mut Chart<ChartEntry>, mode: &PruneMode) {
-        let num_nt = chart.num_nt();
+    /// The non-terminals of a non-lexical rule of the grammar, with the second one on the RHS if
+    /// the rule is binary.
+    fn existing_rule(
+        &self,
+        lhs: &N,
+        rhs: &[N],
+    ) -> Result<(IntNt, IntNt, Option<IntNt>), RuleProblem> {
+        let known = |n: &N| {
+            self.lookup_index
+                .get(n)
+                .copied()
+                .ok_or(RuleProblem::UnknownNonterminal)
+        };
+        match rhs {
+            [b] => Ok((known(lhs)?, known(b)?, None)),
+            [b, c] => Ok((known(lhs)?, known(b)?, Some(known(c)?))),
+            _ => Err(RuleProblem::NotBinarised),
+        }
+    }
 
-        for (i, word) in sentence.iter().enumerate() {
-            if let Some(lexicals) = self.rules_lexical.get_vec(word) {
-                for (nt, weight) in lexicals {
-                    let nt = *nt as usize;
-                    chart[(i * num_nt) + nt] = (*weight, Some(BacktraceInfo::Term(i)));
+    /// Marks a rule, so that chart entries derived with it are exempt from pruning. The rule has
+    /// to be binarised and its non-terminals have to occur in the grammar.
+    pub fn protect_rule(&mut self, rule: Rule<N, T>) -> Result<(), InvalidRule<N, T>> {
+        let problem = match &rule {
+            Rule::Lexical { lhs, rhs } => match self.lookup_index.get(lhs) {
+                Some(&a) => {
+                    self.protected_lexical.insert(rhs.clone(), a);
+                    return Ok(());
                 }
+                None => RuleProblem::UnknownNonterminal,
+            },
+            Rule::NonLexical { lhs, rhs } => match self.existing_rule(lhs, rhs) {
+                Ok((a, b, None)) => {
+                    self.protected_chain.insert((a, b));
+                    return Ok(());
+                }
+                Ok((a, b, Some(c))) => {
+                    self.protected_double.insert((a, b, c));
+                    return Ok(());
+                }
+                Err(problem) => problem,
+            },
+        };
+        Err(InvalidRule { rule, problem })
+    }
+
+    /// Restricts where a non-lexical rule may be applied during parsing. The rule has to be
+    /// binarised and its non-terminals have to occur in the grammar.
+    pub fn constrain_rule(
+        &mut self,
+        rule: Rule<N, T>,
+        constraint: RuleConstraint,
+    ) -> Result<(), InvalidRule<N, T>> {
+        let problem = match &rule {
+            Rule::Lexical { .. } => RuleProblem::Lexical,
+            Rule::NonLexical { lhs, rhs } => match self.existing_rule(lhs, rhs) {
+                Ok((a, b, None)) => {
+                    self.constraints_chain
+                        .entry((a, b))
+                        .or_default()
+                        .push(constraint);
+                    return Ok(());
+                }
+                Ok((a, b, Some(c))) => {
+                    self.constraints_double
+                        .entry((a, b, c))
+                        .or_default()
+                        .push(constraint);
+                    return Ok(());
+                }
+                Err(problem) => problem,
+            },
+        };
+        Err(InvalidRule { rule, problem })
+    }
+
+    /// Checks the constraints of the chain rule `a -> b` for the given cell.
+    fn chain_allowed(
+        &self,
+        a: IntNt,
+        b: IntNt,
+        start: usize,
+        span: usize,
+        sentence: &Sentence<T>,
+    ) -> bool {
+        match self.constraints_chain.get(&(a, b)) {
+            Some(constraints) => constraints
+                .iter()
+                .all(|c| c.is_satisfied(start, span, &sentence.0)),
+            None => true,
+        }
+    }
+
+    /// Checks the constraints of the binary rule `a -> b c` for the given cell.
+    fn double_allowed(
+        &self,
+        (a, b, c): (IntNt, IntNt, IntNt),
+        start: usize,
+        span: usize,
+        sentence: &Sentence<T>,
+    ) -> bool {
+        match self.constraints_double.get(&(a, b, c)) {
+            Some(constraints) => constraints
+                .iter()
+                .all(|con| con.is_satisfied(start, span, &sentence.0)),
+            None => true,
+        }
+    }
+
+    /// Inserts an estimate of the outside weight of a non-terminal for A* parsing.
+    pub fn insert_outside_estimate(&mut self, estimate: OutsideEstimate<N>) {
+        let label = self.intify(estimate.label);
+        match estimate.context {
+            Some((left, right)) => {
+                self.outside_context
+                    .insert((label, left, right), LogProb::from_prob(estimate.weight));
             }
-            self.unary_closure(chart.get_cell_mut(i * num_nt));
-            if let Some(threshold) = mode.threshold {
-                self.prune_threshold(chart.get_cell_mut(i * num_nt), threshold);
-            }
-            if let Some(fixed_size) = mode.fixed_size {
-                self.prune_fixed_size(chart.get_cell_mut(i * num_nt), fixed_size);
+            None => {
+                self.outside
+                    .insert(label, LogProb::from_prob(estimate.weight));
             }
         }
     }
 
-    fn unary_closure(&self, c: &mut [ChartEntry]) {
-        // Use max heap so we can easily extract the element with
-        // the greatest weight.
-        let mut queue = BinaryHeap::with_capacity(c.len());
-
-        // Fill the queue with elements from c.
-        // We invert tuple order of non-terminal and weight in the
-        // queue, so that the queue is sorted by weight.
-        for ele in c
-            .iter()
+    /// Outside estimate of `a` with `left` words to its left and `right` words to its right.
+    /// Without an estimate, 1 is used, which never underestimates.
+    fn outside_estimate(&self, a: IntNt, left: usize, right: usize) -> LogProb {
+        self.outside_context
+            .get(&(a, left, right))
+            .or_else(|| self.outside.get(&a))
             .copied()
-            .enumerate()
-            .filter(|(_, (w, _))| w.0 != 0.0)
-            .map(|(i, w)| (w, i))
-        {
-            queue.push(ele);
+            .unwrap_or(LogProb::ONE)
+    }
+
+    /// Inserts a chain of the precomputed unary closure, listed from top to bottom.
+    /// Once a chain has been inserted, the parser uses the precomputed closure
+    /// instead of computing it for every cell.
+    pub fn insert_unary_chain(&mut self, chain: Vec<N>, weight: FloatOrd<f64>) {
+        let chain: Vec<_> = chain.into_iter().map(|n| self.intify(n)).collect();
+        if chain.len() < 2 {
+            panic!("Unary chains need at least two non-terminals!");
         }
-        for weight in c.iter_mut() {
-            *weight = Default::default();
+        self.insert_closure_path(chain, LogProb::from_prob(weight.0));
+    }
+
+    fn insert_closure_path(&mut self, mut chain: Vec<IntNt>, weight: LogProb) {
+        let (top, bottom) = (chain[0], chain[chain.len() - 1]);
+
+        // Chains looping back onto themselves never improve a derivation.
+        if top == bottom {
+            return;
         }
 
-        while let Some(((q, backtrace), b)) = queue.pop() {
-            if q > c[b].0 {
-                c[b] = (q, backtrace);
-                if let Some(chain_rules) = self.rules_chain.get_vec(&(b as IntNt)) {
-                    for (a, chain_weight) in chain_rules {
-                        queue.push((
-                            (
-                                FloatOrd(chain_weight.0 * q.0),
-                                Some(BacktraceInfo::Chain(b)),
-                            ),
-                            *a as usize,
-                        ));
+        chain.shrink_to_fit();
+        self.closure_paths.push(chain);
+        self.closure
+            .get_or_insert_with(MultiMap::default)
+            .insert(bottom, (top, weight, self.closure_paths.len() - 1));
+    }
+
+    /// Computes the best chain of unary rules between all pairs of non-terminals.
+    /// Chains are listed from top to bottom.
+    pub fn unary_chains(&self) -> Vec<(Vec<N>, FloatOrd<f64>)> {
+        self.best_unary_chains()
+            .into_iter()
+            .map(|(chain, weight)| {
+                (
+                    chain
+                        .into_iter()
+                        .map(|n| self.lookup[n as usize].clone())
+                        .collect(),
+                    FloatOrd(weight.prob()),
+                )
+            })
+            .collect()
+    }
+
+    fn best_unary_chains(&self) -> Vec<(Vec<IntNt>, LogProb)> {
+        let num_nt = self.lookup.len();
+        let mut chains = vec![];
+
+        for b in 0..num_nt {
+            let mut best = vec![LogProb::ZERO; num_nt];
+            // Next non-terminal on the best chain towards `b`.
+            let mut next: Vec<Option<usize>> = vec![None; num_nt];
+            let mut queue = BinaryHeap::new();
+            queue.push((LogProb::ONE, b, None));
+
+            while let Some((q, a, n)) = queue.pop() {
+                if q > best[a] {
+                    best[a] = q;
+                    next[a] = n;
+                    if let Some(chain_rules) = self.rules_chain.get_vec(&(a as IntNt)) {
+                        for (parent, chain_weight) in chain_rules {
+                            queue.push((*chain_weight * q, *parent as usize, Some(a)));
+                        }
                     }
                 }
             }
+
+            for a in (0..num_nt).filter(|a| *a != b && !best[*a].is_zero()) {
+                let mut chain = vec![a as IntNt];
+                let mut current = a;
+                while let Some(n) = next[current] {
+                    chain.push(n as IntNt);
+                    current = n;
+                }
+                chains.push((chain, best[a]));
+            }
         }
+
+        chains
     }
 
-    /// Zeroes all entries that are smaller than best probability in the given cell
-    /// multiplied by `threshold`;
-    fn prune_threshold(&self, c: &mut [ChartEntry], threshold: f64) {
-        let m = c.iter().max().unwrap().0;
-        let cutoff = FloatOrd(m.0 * threshold);
+    /// Finds a chain of unary rules from a non-terminal back to itself with a weight of
+    /// at least 1. Repeating such a cycle never makes a derivation worse, so the best chains
+    /// of unary rules don't exist and computing them wouldn't end.
+    pub fn unary_cycle(&self) -> Option<UnaryCycle<N>> {
+        let num_nt = self.lookup.len();
+
+        for b in 0..num_nt {
+            let mut best = vec![LogProb::ZERO; num_nt];
+            let mut next: Vec<Option<usize>> = vec![None; num_nt];
+            let mut queue = BinaryHeap::new();
+            queue.push((LogProb::ONE, b, None, 0));
+
+            while let Some((q, a, n, len)) = queue.pop() {
+                // Longer chains contain a cycle that doesn't go through `b`. It is found
+                // when starting from one of its non-terminals.
+                if q <= best[a] || len > num_nt {
+                    continue;
+                }
+                best[a] = q;
+                next[a] = n;
 
-        for chart_ele in c {
-            if chart_ele.0 < cutoff {
-                *chart_ele = Default::default();
+                for (parent, chain_weight) in self
+                    .rules_chain
+                    .get_vec(&(a as IntNt))
+                    .into_iter()
+                    .flatten()
+                {
+                    let weight = *chain_weight * q;
+                    if *parent as usize == b && weight >= LogProb::ONE {
+                        let mut chain = vec![self.lookup[b].clone(), self.lookup[a].clone()];
+                        let mut current = a;
+                        while let Some(n) = next[current] {
+                            chain.push(self.lookup[n].clone());
+                            current = n;
+                        }
+                        return Some(UnaryCycle {
+                            chain,
+                            weight: weight.prob(),
+                        });
+                    }
+                    queue.push((weight, *parent as usize, Some(a), len + 1));
+                }
             }
         }
+
+        None
     }
 
-    /// Zeroes all entries that are smaller than the n-best entry in the cell.
-    /// If `n` is less than the cell size, we use the last entry.
-    fn prune_fixed_size(&self, c: &mut [ChartEntry], n: usize) {
-        let num_nt = c.len();
+    /// Precomputes the unary closure once, so that the parser applies the best chain between
+    /// two non-terminals directly instead of searching the chains in every cell. Has to be
+    /// called after all non-lexical rules are inserted. The closure is still computed per cell
+    /// if one was inserted with `insert_unary_chain` or if unary rules are constrained, as a
+    /// cell may then need a worse chain. Returns whether the closure was precomputed.
+    pub fn precompute_unary_closure(&mut self) -> Result<bool, UnaryCycle<N>> {
+        if let Some(cycle) = self.unary_cycle() {
+            return Err(cycle);
+        }
+        if self.closure.is_some() || !self.constraints_chain.is_empty() {
+            return Ok(false);
+        }
 
-        let n_best = {
-            let mut sorted = vec![Default::default(); c.len()];
-            sorted.copy_from_slice(c);
-            sorted.sort_unstable();
-            sorted.reverse();
+        for (chain, weight) in self.best_unary_chains() {
+            self.insert_closure_path(chain, weight);
+        }
+        Ok(true)
+    }
 
-            if n > num_nt {
-                *sorted.last().unwrap()
-            } else {
-                sorted[n - 1]
+    /// Hash of the unary rules and their weights, from which the unary closure is computed.
+    /// It doesn't depend on the order of the rules. The weights are hashed with single
+    /// precision, so that they may differ in their last digits.
+    pub fn unary_rules_hash(&self) -> u64 {
+        let mut hash: u64 = 0;
+        for (b, rules) in self.rules_chain.iter_all() {
+            for (a, w) in rules {
+                let mut hasher = FxHasher::default();
+                self.lookup[*a as usize].hash(&mut hasher);
+                self.lookup[*b as usize].hash(&mut hasher);
+                hasher.write_u32((w.ln() as f32).to_bits());
+                hash = hash.wrapping_add(hasher.finish());
             }
-        };
+        }
+        hash
+    }
 
-        for chart_ele in c {
-            if *chart_ele < n_best {
-                *chart_ele = Default::default();
+    /// Writes the unary closure with one chain per line, followed by its weight. The header
+    /// records the hash of the unary rules, see `unary_rules_hash`.
+    pub fn write_unary_closure<Wr: Write>(&self, buf: &mut Wr) -> io::Result<()>
+    where
+        N: fmt::Display,
+    {
+        GrammarMetadata::default()
+            .with(
+                CLOSURE_HASH_KEY,
+                format!("{:016x}", self.unary_rules_hash()),
+            )
+            .write(buf)?;
+        for (chain, weight) in self.unary_chains() {
+            for n in chain {
+                write!(buf, "{} ", n)?;
             }
+            writeln!(buf, "{}", weight.0)?;
         }
+
+        Ok(())
     }
 
-    fn construct_best_tree(
-        c: &[ChartEntry],
-        c_idx: usize,
-        sentence: &Sentence<T>,
-        lookup: &[N],
-    ) -> Option<Tree<NodeType<N, T>>> {
-        let num_nt = lookup.len();
+    /// Writes the non-terminals and rules in a binary format, which is read much faster than
+    /// the grammar files, because the non-terminals are already intified. The unary closure is
+    /// written as well if it was precomputed. Protected rules, constraints and outside
+    /// estimates are not written.
+    pub fn write_compiled<Wr: Write>(&self, buf: &mut Wr) -> io::Result<()>
+    where
+        N: AsRef<str>,
+    {
+        binary::write_header(buf)?;
 
-        match c[c_idx].1 {
-            None => None,
-            Some(BacktraceInfo::Term(t)) => Some(Tree {
-                root: NodeType::NonTerminal(lookup[c_idx % num_nt].clone()),
-                children: vec![Tree {
-                    root: NodeType::Terminal(sentence.0[t].clone()),
-                    children: vec![],
-                }],
-            }),
-            Some(BacktraceInfo::Chain(i)) => {
-                let nt = c_idx % num_nt;
-                Self::construct_best_tree(c, c_idx - nt + i, sentence, lookup).map(|tree| Tree {
-                    root: NodeType::NonTerminal(lookup[nt].clone()),
-                    children: vec![tree],
-                })
+        binary::write_u32(buf, self.lookup.len() as IntNt)?;
+        for n in &self.lookup {
+            binary::write_str(buf, n.as_ref())?;
+        }
+
+        binary::write_u64(
+            buf,
+            self.rules_lexical
+                .iter_all()
+                .map(|(_, r)| r.len())
+                .sum::<usize>() as u64,
+        )?;
+        for (t, rules) in self.rules_lexical.iter_all() {
+            for (a, w) in rules {
+                binary::write_str(buf, t.as_ref())?;
+                binary::write_u32(buf, *a)?;
+                binary::write_f64(buf, w.ln())?;
             }
-            Some(BacktraceInfo::Binary(i, j)) => {
-                if let (Some(tree_i), Some(tree_j)) = (
-                    Self::construct_best_tree(c, i, sentence, lookup),
-                    Self::construct_best_tree(c, j, sentence, lookup),
-                ) {
-                    let nt = c_idx % num_nt;
-                    Some(Tree {
-                        root: NodeType::NonTerminal(lookup[nt].clone()),
-                        children: vec![tree_i, tree_j],
-                    })
-                } else {
-                    None
-                }
+        }
+
+        binary::write_u64(
+            buf,
+            self.rules_chain
+                .iter_all()
+                .map(|(_, r)| r.len())
+                .sum::<usize>() as u64,
+        )?;
+        for (b, rules) in self.rules_chain.iter_all() {
+            for (a, w) in rules {
+                binary::write_u32(buf, *b)?;
+                binary::write_u32(buf, *a)?;
+                binary::write_f64(buf, w.ln())?;
             }
         }
+
+        binary::write_u64(
+            buf,
+            self.rules_double
+                .iter_all()
+                .map(|(_, r)| r.len())
+                .sum::<usize>() as u64,
+        )?;
+        for (a, rules) in self.rules_double.iter_all() {
+            for (b, c, w) in rules {
+                binary::write_u32(buf, *a)?;
+                binary::write_u32(buf, *b)?;
+                binary::write_u32(buf, *c)?;
+                binary::write_f64(buf, w.ln())?;
+            }
+        }
+
+        // Chains of the closure in the order they were inserted.
+        let mut chains: Vec<_> = self
+            .closure
+            .iter()
+            .flat_map(|closure| closure.iter_all())
+            .flat_map(|(_, chains)| chains)
+            .map(|(_, w, path)| (*path, *w))
+            .collect();
+        chains.sort_unstable_by_key(|(path, _)| *path);
+        binary::write_u64(buf, chains.len() as u64)?;
+        for (path, w) in chains {
+            let chain = &self.closure_paths[path];
+            binary::write_u32(buf, chain.len() as u32)?;
+            for n in chain {
+                binary::write_u32(buf, *n)?;
+            }
+            binary::write_f64(buf, w.ln())?;
+        }
+
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    /// Reads a grammar written by `write_compiled`.
+    pub fn read_compiled<R: Read>(reader: &mut R, initial_nonterminal: N) -> io::Result<Self>
+    where
+        N: for<'a> From<&'a str>,
+        T: for<'a> From<&'a str>,
+    {
+        binary::read_header(reader)?;
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
 
-    #[test]
-    fn cyk_base_correct() {
-        let mut grammar = GrammarParse::new("S".to_string());
+        let mut grammar = Self::new(initial_nonterminal.clone());
+        grammar.lookup.clear();
+        grammar.lookup_index.clear();
+        for i in 0..binary::read_u32(reader)? {
+            let n = N::from(binary::read_string(reader)?.as_str());
+            if grammar.intify(n) != i {
+                return Err(invalid("non-terminal occurs twice in compiled grammar"));
+            }
+        }
+        // The initial non-terminal might not occur in the compiled grammar.
+        grammar.initial_nonterminals.clear();
+        grammar.add_initial_nonterminal(initial_nonterminal);
+        let num_nt = grammar.lookup.len() as IntNt;
+        let nt = |reader: &mut R| {
+            let n = binary::read_u32(reader)?;
+            if n < num_nt {
+                Ok(n)
+            } else {
+                Err(invalid("unknown non-terminal in compiled grammar"))
+            }
+        };
 
-        grammar.insert_rule(WeightedRule {
-            rule: Rule::NonLexical {
-                lhs: "S".to_string(),
-                rhs: vec!["NP".to_string(), "VP".to_string()],
-            },
-            weight: FloatOrd(1.0),
-        });
-        grammar.insert_rule(WeightedRule {
-            rule: Rule::NonLexical {
-                lhs: "VP".to_string(),
-                rhs: vec!["VP".to_string(), "PP".to_string()],
-            },
-            weight: FloatOrd(1.0),
-        });
-        grammar.insert_rule(WeightedRule {
-            rule: Rule::NonLexical {
-                lhs: "VP".to_string(),
-                rhs: vec!["V".to_string(), "NP".to_string()],
-            },
-            weight: FloatOrd(1.0),
-        });
-        grammar.insert_rule(WeightedRule {
-            rule: Rule::NonLexical {
-                lhs: "PP".to_string(),
-                rhs: vec!["P".to_string(), "NP".to_string()],
-            },
-            weight: FloatOrd(1.0),
-        });
-        grammar.insert_rule(WeightedRule {
-            rule: Rule::NonLexical {
-                lhs: "NP".to_string(),
-                rhs: vec!["Det".to_string(), "N".to_string()],
-            },
-            weight: FloatOrd(1.0),
-        });
-        grammar.insert_rule(WeightedRule {
-            rule: Rule::NonLexical {
-                lhs: "NP".to_string(),
-                rhs: vec!["PN".to_string()],
-            },
-            weight: FloatOrd(1.0),
-        });
-        grammar.insert_rule(WeightedRule {
-            rule: Rule::Lexical {
-                lhs: "VP".to_string(),
-                rhs: "eats".to_string(),
-            },
-            weight: FloatOrd(1.0),
-        });
-        grammar.insert_rule(WeightedRule {
-            rule: Rule::Lexical {
-                lhs: "PN".to_string(),
-                rhs: "she".to_string(),
-            },
-            weight: FloatOrd(1.0),
-        });
-        grammar.insert_rule(WeightedRule {
-            rule: Rule::Lexical {
-                lhs: "V".to_string(),
-                rhs: "eats".to_string(),
-            },
-            weight: FloatOrd(1.0),
-        });
-        grammar.insert_rule(WeightedRule {
-            rule: Rule::Lexical {
-                lhs: "P".to_string(),
-                rhs: "with".to_string(),
-            },
-            weight: FloatOrd(1.0),
-        });
-        grammar.insert_rule(WeightedRule {
-            rule: Rule::Lexical {
-                lhs: "N".to_string(),
-                rhs: "fish".to_string(),
-            },
-            weight: FloatOrd(1.0),
+        for _ in 0..binary::read_u64(reader)? {
+            let t = T::from(binary::read_string(reader)?.as_str());
+            let a = nt(reader)?;
+            let w = LogProb::from_ln(binary::read_f64(reader)?);
+            grammar.rules_lexical.insert(t, (a, w));
+        }
+
+        for _ in 0..binary::read_u64(reader)? {
+            let b = nt(reader)?;
+            let a = nt(reader)?;
+            let w = LogProb::from_ln(binary::read_f64(reader)?);
+            grammar.rules_chain.insert(b, (a, w));
+        }
+
+        for _ in 0..binary::read_u64(reader)? {
+            let a = nt(reader)?;
+            let b = nt(reader)?;
+            let c = nt(reader)?;
+            let w = LogProb::from_ln(binary::read_f64(reader)?);
+            grammar.insert_double(a, b, c, w);
+        }
+
+        for _ in 0..binary::read_u64(reader)? {
+            let len = binary::read_u32(reader)?;
+            if len < 2 {
+                return Err(invalid("unary chain with less than two non-terminals"));
+            }
+            let chain = (0..len).map(|_| nt(reader)).collect::<io::Result<_>>()?;
+            let w = LogProb::from_ln(binary::read_f64(reader)?);
+            grammar.insert_closure_path(chain, w);
+        }
+
+        Ok(grammar)
+    }
+
+    /// Finds the most probable tree with the CYK algorithm. Every cell is pruned with the
+    /// pruners of `mode`, which may include custom implementations of `Pruner`.
+    pub fn cyk(
+        &self,
+        sentence: &Sentence<T>,
+        mode: &PruneMode<N, T>,
+    ) -> Option<Tree<NodeType<N, T>>> {
+        let chart = self.fill_chart(sentence, mode, |_, _, _, _| {});
+
+        let root_cell = chart.cell_start_index(0, sentence.len());
+        let data = chart.data();
+        let best = self.best_initial(&data[root_cell..], |e| e.0);
+        self.construct_best_tree(data, root_cell + best, sentence)
+    }
+
+    /// Finds the most probable tree like `cyk` and records every state of every cell
+    /// during chart construction.
+    #[cfg(feature = "chart-trace")]
+    pub fn cyk_traced(
+        &self,
+        sentence: &Sentence<T>,
+        mode: &PruneMode<N, T>,
+    ) -> (Option<Tree<NodeType<N, T>>>, ChartTrace<N>)
+    where
+        T: fmt::Display,
+    {
+        let num_nt = self.lookup.len();
+        let s_len = sentence.len();
+        // Start and end of the cell at every index at which a cell starts.
+        let cells: FxHashMap<usize, (usize, usize)> = (1..=s_len)
+            .flat_map(|span| (0..=(s_len - span)).map(move |start| (start, span)))
+            .map(|(start, span)| {
+                (
+                    cell_start_index(s_len, num_nt, start, span),
+                    (start, start + span),
+                )
+            })
+            .collect();
+        let child = |idx: usize| {
+            let (start, end) = cells[&(idx - idx % num_nt)];
+            (self.lookup[idx % num_nt].clone(), start, end)
+        };
+
+        let mut frames = vec![];
+        let chart = self.fill_chart(sentence, mode, |start, span, stage, cell| {
+            let entries = cell
+                .iter()
+                .enumerate()
+                .filter_map(|(a, (weight, backtrace))| {
+                    let derivation = match (*backtrace)? {
+                        BacktraceInfo::Term(position) => Derivation::Word(position as usize),
+                        BacktraceInfo::Binary(i, j) => {
+                            Derivation::Binary(child(i as usize), child(j as usize))
+                        }
+                        BacktraceInfo::Chain(b) => Derivation::Chain(vec![
+                            self.lookup[a].clone(),
+                            self.lookup[b as usize].clone(),
+                        ]),
+                        BacktraceInfo::Closure(_, path) => Derivation::Chain(
+                            self.closure_paths[path as usize]
+                                .iter()
+                                .map(|n| self.lookup[*n as usize].clone())
+                                .collect(),
+                        ),
+                    };
+                    Some(TraceEntry {
+                        label: self.lookup[a].clone(),
+                        log_prob: weight.ln(),
+                        derivation,
+                    })
+                })
+                .collect();
+            frames.push(Frame {
+                step: frames.len(),
+                start,
+                end: start + span,
+                stage: match stage {
+                    CellStage::Filled => FrameStage::Filled,
+                    CellStage::Closure => FrameStage::Closure,
+                    CellStage::Pruned(beam) => FrameStage::Pruned(beam),
+                },
+                entries,
+            });
         });
-        grammar.insert_rule(WeightedRule {
-            rule: Rule::Lexical {
-                lhs: "N".to_string(),
-                rhs: "fork".to_string(),
-            },
-            weight: FloatOrd(1.0),
+
+        let root_cell = chart.cell_start_index(0, s_len);
+        let data = chart.data();
+        let best = self.best_initial(&data[root_cell..], |e| e.0);
+        let trace = ChartTrace {
+            sentence: sentence.iter().map(|w| w.to_string()).collect(),
+            frames,
+        };
+        (
+            self.construct_best_tree(data, root_cell + best, sentence),
+            trace,
+        )
+    }
+
+    /// Runs the CYK algorithm with `gold` as reference and reports the first
+    /// gold chart entry that is lost during chart construction.
+    pub fn diagnose_pruning(
+        &self,
+        sentence: &Sentence<T>,
+        mode: &PruneMode<N, T>,
+        gold: &Tree<N>,
+    ) -> GoldDiagnosis<N> {
+        let mut items = vec![];
+        if gold_items(gold, 0, &mut items) != sentence.len() {
+            return GoldDiagnosis::YieldMismatch;
+        }
+
+        let mut gold_cells = Vec::with_capacity(items.len());
+        for (start, span, label) in items {
+            match self.lookup_index.get(label) {
+                Some(a) => gold_cells.push((start, span, *a as usize, label)),
+                None => {
+                    return GoldDiagnosis::NotDerived {
+                        start,
+                        span,
+                        label: label.clone(),
+                    }
+                }
+            }
+        }
+
+        let mut result = None;
+        self.fill_chart(sentence, mode, |start, span, stage, cell| {
+            if result.is_some() || stage == CellStage::Filled {
+                return;
+            }
+
+            for (_, _, a, label) in gold_cells
+                .iter()
+                .filter(|(s, l, _, _)| *s == start && *l == span)
+            {
+                if cell[*a].0.is_zero() {
+                    let label = (*label).clone();
+                    result = Some(match stage {
+                        CellStage::Filled | CellStage::Closure => {
+                            GoldDiagnosis::NotDerived { start, span, label }
+                        }
+                        CellStage::Pruned(beam) => GoldDiagnosis::Pruned {
+                            start,
+                            span,
+                            label,
+                            beam,
+                        },
+                    });
+                    return;
+                }
+            }
         });
-        grammar.insert_rule(WeightedRule {
-            rule: Rule::Lexical {
-                lhs: "Det".to_string(),
-                rhs: "a".to_string(),
+
+        result.unwrap_or(GoldDiagnosis::Intact)
+    }
+
+    /// Parses `sentence` without pruning and with every one of `modes`, which are named for
+    /// the report. Pruning only removes derivations, so the best derivation found with pruning
+    /// can't be more probable than the one found without. Returns the modes for which it is.
+    pub fn check_pruning(
+        &self,
+        sentence: &Sentence<T>,
+        modes: &[(String, PruneMode<N, T>)],
+    ) -> Vec<PruningDiscrepancy> {
+        let root_probability = |mode| {
+            let chart = self.fill_chart(sentence, mode, |_, _, _, _| {});
+            let cell = &chart.data()[chart.cell_start_index(0, sentence.len())..];
+            cell[self.best_initial(cell, |e| e.0)].0
+        };
+
+        let base_mode = PruneMode::empty();
+        let base = root_probability(&base_mode);
+        modes
+            .iter()
+            .filter_map(|(name, mode)| {
+                let pruned = root_probability(mode);
+                (pruned.ln() > base.ln() + PRUNING_EPSILON * base.ln().abs().max(1.0)).then(|| {
+                    PruningDiscrepancy {
+                        mode: name.clone(),
+                        base,
+                        pruned,
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the `k` most probable trees for `sentence` with their probabilities, best first.
+    /// Every chart item keeps its `k` best derivations. For binary rules they are enumerated
+    /// lazily from the best combinations of the derivations of the children, similar to
+    /// Huang & Chiang (2005). Pruning and the precomputed unary closure are not used.
+    pub fn cyk_kbest(&self, sentence: &Sentence<T>, k: usize) -> Vec<(Tree<NodeType<N, T>>, f64)> {
+        let num_nt = self.lookup.len();
+        let s_len = sentence.len();
+        if s_len == 0 || k == 0 {
+            return vec![];
+        }
+
+        let mut chart: Chart<Vec<KBestEntry>> = Chart::new(s_len, num_nt);
+
+        for (i, word) in sentence.iter().enumerate() {
+            let mut queue = BinaryHeap::new();
+            if let Some(lexicals) = self.rules_lexical.get_vec(word) {
+                for (a, weight) in lexicals {
+                    queue.push((*weight, *a as usize, KBestBacktrace::Term(i)));
+                }
+            }
+            let i_j = chart.cell_start_index(i, 1);
+            self.kbest_unary(&mut chart, i_j, queue, k, i, 1, sentence);
+        }
+
+        for r in 2..=s_len {
+            for i in 0..=(s_len - r) {
+                let i_j = chart.cell_start_index(i, r);
+                let mut queue = BinaryHeap::new();
+
+                for (a, rules) in self.rules_double.iter_all() {
+                    // Frontier of combinations (rule, split, rank of left, rank of right).
+                    let mut frontier = BinaryHeap::new();
+                    let mut visited = FxHashSet::default();
+                    let mut push = |frontier: &mut BinaryHeap<KBestCandidate>,
+                                    rule: usize,
+                                    m: usize,
+                                    x: usize,
+                                    y: usize| {
+                        let (b, c, w): &(IntNt, IntNt, LogProb) = &rules[rule];
+                        let left = chart.cell_start_index(i, m) + *b as usize;
+                        let right = chart.cell_start_index(i + m, r - m) + *c as usize;
+                        if let (Some(l), Some(r)) = (chart[left].get(x), chart[right].get(y)) {
+                            if visited.insert((rule, m, x, y)) {
+                                frontier.push((
+                                    *w * l.0 * r.0,
+                                    KBestBacktrace::Binary(left, x, right, y),
+                                    (rule, m),
+                                ));
+                            }
+                        }
+                    };
+
+                    for (rule, (b, c, _)) in rules.iter().enumerate() {
+                        if !self.constraints_double.is_empty()
+                            && !self.double_allowed((*a, *b, *c), i, r, sentence)
+                        {
+                            continue;
+                        }
+                        for m in 1..r {
+                            push(&mut frontier, rule, m, 0, 0);
+                        }
+                    }
+
+                    for _ in 0..k {
+                        match frontier.pop() {
+                            Some((w, backtrace, (rule, m))) => {
+                                if let KBestBacktrace::Binary(_, x, _, y) = backtrace {
+                                    push(&mut frontier, rule, m, x + 1, y);
+                                    push(&mut frontier, rule, m, x, y + 1);
+                                }
+                                queue.push((w, *a as usize, backtrace));
+                            }
+                            None => break,
+                        }
+                    }
+                }
+
+                self.kbest_unary(&mut chart, i_j, queue, k, i, r, sentence);
+            }
+        }
+
+        // The k best derivations of all initial non-terminals, best first.
+        let root_cell = chart.cell_start_index(0, s_len);
+        let mut roots: Vec<(LogProb, usize, usize)> = self
+            .initial_nonterminals
+            .iter()
+            .flat_map(|a| {
+                let root = root_cell + *a as usize;
+                chart[root]
+                    .iter()
+                    .enumerate()
+                    .map(move |(rank, (w, _))| (*w, root, rank))
+            })
+            .collect();
+        roots.sort_by(|(w1, _, _), (w2, _, _)| w2.cmp(w1));
+        roots.truncate(k);
+
+        roots
+            .into_iter()
+            .filter_map(|(w, root, rank)| {
+                self.construct_kth_tree(&chart, root, rank, sentence)
+                    .map(|tree| (tree, w.prob()))
+            })
+            .collect()
+    }
+
+    /// Distributes the derivations in `queue` onto the items of a cell, best first,
+    /// and adds derivations with chain rules until every item has `k` derivations
+    /// or no more derivations are left.
+    #[allow(clippy::too_many_arguments)]
+    fn kbest_unary(
+        &self,
+        chart: &mut Chart<Vec<KBestEntry>>,
+        cell: usize,
+        mut queue: BinaryHeap<(LogProb, usize, KBestBacktrace)>,
+        k: usize,
+        start: usize,
+        span: usize,
+        sentence: &Sentence<T>,
+    ) {
+        while let Some((w, b, backtrace)) = queue.pop() {
+            if chart[cell + b].len() >= k {
+                continue;
+            }
+            chart[cell + b].push((w, backtrace));
+            let rank = chart[cell + b].len() - 1;
+
+            if let Some(chain_rules) = self.rules_chain.get_vec(&(b as IntNt)) {
+                for (a, chain_weight) in chain_rules
+                    .iter()
+                    .filter(|(a, _)| self.chain_allowed(*a, b as IntNt, start, span, sentence))
+                {
+                    queue.push((
+                        *chain_weight * w,
+                        *a as usize,
+                        KBestBacktrace::Chain(b, rank),
+                    ));
+                }
+            }
+        }
+    }
+
+    fn construct_kth_tree(
+        &self,
+        chart: &Chart<Vec<KBestEntry>>,
+        idx: usize,
+        rank: usize,
+        sentence: &Sentence<T>,
+    ) -> Option<Tree<NodeType<N, T>>> {
+        let num_nt = self.lookup.len();
+        let root = NodeType::NonTerminal(self.lookup[idx % num_nt].clone());
+
+        let children = match chart[idx].get(rank)?.1 {
+            KBestBacktrace::Term(t) => vec![Tree {
+                root: NodeType::Terminal(sentence.0[t].clone()),
+                children: vec![],
+            }],
+            KBestBacktrace::Chain(b, rank) => {
+                vec![self.construct_kth_tree(chart, idx - idx % num_nt + b, rank, sentence)?]
+            }
+            KBestBacktrace::Binary(left, x, right, y) => vec![
+                self.construct_kth_tree(chart, left, x, sentence)?,
+                self.construct_kth_tree(chart, right, y, sentence)?,
+            ],
+        };
+
+        Some(Tree { root, children })
+    }
+
+    /// Agenda-based A* parsing. Items are finished in the order of their inside weight
+    /// multiplied by the outside estimate of `insert_outside_estimate`. As long as the
+    /// estimates never underestimate the outside weights, the first finished item for the
+    /// whole sentence is the best one, while items with bad estimates are never finished.
+    /// Pruning and the precomputed unary closure are not used.
+    pub fn astar(&self, sentence: &Sentence<T>) -> Option<Tree<NodeType<N, T>>> {
+        let num_nt = self.lookup.len();
+        let s_len = sentence.len();
+        if s_len == 0 {
+            return None;
+        }
+
+        let mut c: Chart<ChartEntry> = Chart::new(s_len, num_nt);
+        let mut agenda: BinaryHeap<AgendaItem> = BinaryHeap::new();
+        let push = |agenda: &mut BinaryHeap<AgendaItem>,
+                    (start, span, a): (usize, usize, IntNt),
+                    inside: LogProb,
+                    backtrace: BacktraceInfo| {
+            let estimate = self.outside_estimate(a, start, s_len - start - span);
+            agenda.push((inside * estimate, inside, (start, span, a), backtrace));
+        };
+
+        // Binary rules, searched by their left and right child.
+        let mut by_left: MultiMap<IntNt, (IntNt, IntNt, LogProb), FxBuildHasher> =
+            MultiMap::default();
+        let mut by_right: MultiMap<IntNt, (IntNt, IntNt, LogProb), FxBuildHasher> =
+            MultiMap::default();
+        for (a, rules) in self.rules_double.iter_all() {
+            for (b, d, w) in rules {
+                by_left.insert(*b, (*a, *d, *w));
+                by_right.insert(*d, (*a, *b, *w));
+            }
+        }
+
+        for (i, word) in sentence.iter().enumerate() {
+            if let Some(rules) = self.rules_lexical.get_vec(word) {
+                for (a, w) in rules {
+                    push(&mut agenda, (i, 1, *a), *w, BacktraceInfo::Term(i as u32));
+                }
+            }
+        }
+
+        while let Some((_, inside, (start, span, a), backtrace)) = agenda.pop() {
+            let idx = c.cell_start_index(start, span) + a as usize;
+            if c[idx].1.is_some() {
+                continue;
+            }
+            c[idx] = (inside, Some(backtrace));
+
+            if start == 0 && span == s_len && self.initial_nonterminals.contains(&a) {
+                return self.construct_best_tree(c.data(), idx, sentence);
+            }
+
+            if let Some(rules) = self.rules_chain.get_vec(&a) {
+                for (b, w) in rules {
+                    if self.chain_allowed(*b, a, start, span, sentence) {
+                        push(
+                            &mut agenda,
+                            (start, span, *b),
+                            inside * *w,
+                            BacktraceInfo::Chain(a),
+                        );
+                    }
+                }
+            }
+
+            if let Some(rules) = by_left.get_vec(&a) {
+                for (x, d, w) in rules {
+                    for right_span in 1..=s_len - start - span {
+                        let right = c.cell_start_index(start + span, right_span) + *d as usize;
+                        let total = span + right_span;
+                        if c[right].1.is_some()
+                            && self.double_allowed((*x, a, *d), start, total, sentence)
+                        {
+                            push(
+                                &mut agenda,
+                                (start, total, *x),
+                                *w * inside * c[right].0,
+                                BacktraceInfo::Binary(idx as u32, right as u32),
+                            );
+                        }
+                    }
+                }
+            }
+
+            if let Some(rules) = by_right.get_vec(&a) {
+                for (x, b, w) in rules {
+                    for left_start in 0..start {
+                        let left = c.cell_start_index(left_start, start - left_start) + *b as usize;
+                        let total = start + span - left_start;
+                        if c[left].1.is_some()
+                            && self.double_allowed((*x, *b, a), left_start, total, sentence)
+                        {
+                            push(
+                                &mut agenda,
+                                (left_start, total, *x),
+                                *w * c[left].0 * inside,
+                                BacktraceInfo::Binary(left as u32, idx as u32),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Greedy shift-reduce parsing without a chart, in time linear in the length of the
+    /// sentence. Every word is shifted with one of its tags in the lexicon. `tags` restricts
+    /// the tags of every position, e.g. to those of a tag dictionary, where `None`, a missing
+    /// position or a set without any tag of the word allows all of them. Of the allowed tags,
+    /// the one is shifted whose item can be reduced with the top of the stack by the most
+    /// probable rules, or else the most probable one.
+    ///
+    /// After every shift, the two topmost items are reduced with the most probable binary rule
+    /// as long as there is one, where either item may first be reduced with a unary rule.
+    /// Every item gets at most one unary rule this way. At the end, the best chain of unary
+    /// rules to an initial non-terminal is put on top of the last item. Doesn't necessarily
+    /// find the best tree, or any tree at all. Constraints on rules are not applied.
+    pub fn shift_reduce(
+        &self,
+        sentence: &Sentence<T>,
+        tags: &[Option<FxHashSet<N>>],
+    ) -> Option<Tree<NodeType<N, T>>> {
+        // Items with their label and whether a unary rule was applied to them.
+        let mut stack: Vec<(IntNt, Tree<NodeType<N, T>>, bool)> =
+            Vec::with_capacity(sentence.len());
+
+        for (i, word) in sentence.iter().enumerate() {
+            let lexical = self.rules_lexical.get_vec(word)?;
+            let allowed: Vec<_> = match tags.get(i) {
+                Some(Some(allowed)) => lexical
+                    .iter()
+                    .filter(|(tag, _)| allowed.contains(&self.lookup[*tag as usize]))
+                    .collect(),
+                _ => vec![],
+            };
+            let candidates = if allowed.is_empty() {
+                lexical.iter().collect()
+            } else {
+                allowed
+            };
+
+            // Shift
+            let top = stack.last().map(|(a, _, unary)| (*a, *unary));
+            let (tag, _) = candidates.into_iter().max_by_key(|(tag, w)| {
+                match top.and_then(|top| self.best_reduction(top, (*tag, false))) {
+                    Some((.., reduction)) => (true, reduction * *w),
+                    None => (false, *w),
+                }
+            })?;
+            stack.push((
+                *tag,
+                Tree {
+                    root: NodeType::NonTerminal(self.lookup[*tag as usize].clone()),
+                    children: vec![Tree {
+                        root: NodeType::Terminal(word.clone()),
+                        children: vec![],
+                    }],
+                },
+                false,
+            ));
+
+            // Reduce
+            while stack.len() >= 2 {
+                let (b, c) = (&stack[stack.len() - 2], &stack[stack.len() - 1]);
+                let (a, unary_b, unary_c, _) = match self.best_reduction((b.0, b.2), (c.0, c.2)) {
+                    Some(reduction) => reduction,
+                    None => break,
+                };
+
+                let (_, right, _) = stack.pop().unwrap();
+                let (_, left, _) = stack.pop().unwrap();
+                stack.push((
+                    a,
+                    Tree {
+                        root: NodeType::NonTerminal(self.lookup[a as usize].clone()),
+                        children: vec![
+                            self.reduce_unary(left, unary_b),
+                            self.reduce_unary(right, unary_c),
+                        ],
+                    },
+                    false,
+                ));
+            }
+        }
+
+        match stack.pop() {
+            Some((a, tree, _)) if stack.is_empty() => self.chain_to_initial(a, tree, sentence),
+            _ => None,
+        }
+    }
+
+    /// The most probable reduction of the adjacent items labeled `b` and `c` by a binary rule,
+    /// where either item that has no unary rule yet may first get one. Returns the LHS of the
+    /// binary rule, the LHSs of the unary rules and the weight of all rules.
+    fn best_reduction(
+        &self,
+        b: (IntNt, bool),
+        c: (IntNt, bool),
+    ) -> Option<(IntNt, Option<IntNt>, Option<IntNt>, LogProb)> {
+        let unary_options = |(a, unary): (IntNt, bool)| {
+            let mut options = vec![(None, LogProb::ONE)];
+            if let (false, Some(rules)) = (unary, self.rules_chain.get_vec(&a)) {
+                options.extend(rules.iter().map(|(x, w)| (Some(*x), *w)));
+            }
+            options
+        };
+
+        let mut best: Option<(IntNt, Option<IntNt>, Option<IntNt>, LogProb)> = None;
+        for (unary_b, w_b) in unary_options(b) {
+            for (unary_c, w_c) in unary_options(c) {
+                let children = (unary_b.unwrap_or(b.0), unary_c.unwrap_or(c.0));
+                if let Some((a, w)) = self.best_double.get(&children) {
+                    let w = *w * w_b * w_c;
+                    if best.map_or(true, |(.., best_w)| w > best_w) {
+                        best = Some((*a, unary_b, unary_c, w));
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    /// Puts the LHS of a unary rule on top of the tree of an item, if there is one.
+    fn reduce_unary(
+        &self,
+        tree: Tree<NodeType<N, T>>,
+        unary: Option<IntNt>,
+    ) -> Tree<NodeType<N, T>> {
+        match unary {
+            Some(a) => Tree {
+                root: NodeType::NonTerminal(self.lookup[a as usize].clone()),
+                children: vec![tree],
             },
-            weight: FloatOrd(1.0),
-        });
+            None => tree,
+        }
+    }
+
+    /// Puts the best chain of unary rules from the initial non-terminal down to `a`
+    /// on top of `tree`.
+    fn chain_to_initial(
+        &self,
+        a: IntNt,
+        tree: Tree<NodeType<N, T>>,
+        sentence: &Sentence<T>,
+    ) -> Option<Tree<NodeType<N, T>>> {
+        let mut c = vec![Default::default(); self.lookup.len()];
+        c[a as usize] = (LogProb::ONE, Some(BacktraceInfo::Term(0)));
+        self.unary_closure(&mut c, 0, sentence.len(), sentence);
+
+        let mut tree = tree;
+        let mut current = self.best_initial(&c, |e| e.0);
+        let mut chain = vec![];
+        while let BacktraceInfo::Chain(b) = c[current].1? {
+            chain.push(current);
+            current = b as usize;
+        }
+
+        for n in chain.iter().rev() {
+            tree = Tree {
+                root: NodeType::NonTerminal(self.lookup[*n].clone()),
+                children: vec![tree],
+            };
+        }
+        Some(tree)
+    }
+
+    /// Computes the posterior probability of every labeled span of `sentence`
+    /// with the inside-outside algorithm. Spans with zero posterior are left out,
+    /// so the result is empty if the sentence can't be derived.
+    pub fn span_posteriors(&self, sentence: &Sentence<T>) -> SpanPosteriors<N> {
+        let num_nt = self.lookup.len();
+        let s_len = sentence.len();
+        let inside = self.inside(sentence, 1.0);
+        let total = self.initial_inside(&inside, s_len);
+
+        if s_len == 0 || total.is_zero() {
+            return SpanPosteriors(vec![]);
+        }
+
+        let outside = self.outside(sentence, &inside);
+
+        let mut result = vec![];
+        for r in 1..=s_len {
+            for i in 0..=(s_len - r) {
+                let i_j = outside.cell_start_index(i, r);
+                for a in 0..num_nt {
+                    let posterior = (inside[i_j + a] * outside[i_j + a] / total).prob();
+                    if posterior > 0.0 {
+                        result.push((i, i + r, self.lookup[a].clone(), posterior));
+                    }
+                }
+            }
+        }
+
+        result.sort_by(|(s1, e1, _, _), (s2, e2, _, _)| (s1, e1).cmp(&(s2, e2)));
+        SpanPosteriors(result)
+    }
+
+    /// Natural logarithm of the inside probability of `sentence`, i.e. of the summed probability
+    /// of all its derivations, or `None` if it can't be derived.
+    pub fn sentence_log_prob(&self, sentence: &Sentence<T>) -> Option<f64> {
+        let s_len = sentence.len();
+        if s_len == 0 {
+            return None;
+        }
+
+        let inside = self.inside(sentence, 1.0);
+        let total = self.initial_inside(&inside, s_len);
+        if total.is_zero() {
+            None
+        } else {
+            Some(total.ln())
+        }
+    }
+
+    /// Marks the labeled spans of `sentence` whose posterior probability is at least `threshold`,
+    /// computed with the inside-outside algorithm. Returns `None` if the sentence can't be
+    /// derived.
+    pub fn posterior_mask(&self, sentence: &Sentence<T>, threshold: f64) -> Option<SpanMask> {
+        let num_nt = self.lookup.len();
+        let s_len = sentence.len();
+        let inside = self.inside(sentence, 1.0);
+        let total = self.initial_inside(&inside, s_len);
+
+        if s_len == 0 || total.is_zero() {
+            return None;
+        }
+
+        let outside = self.outside(sentence, &inside);
+        let threshold = LogProb::from_prob(threshold);
+
+        let mut mask = SpanMask::new(s_len, num_nt);
+        for r in 1..=s_len {
+            for i in 0..=(s_len - r) {
+                let i_j = outside.cell_start_index(i, r);
+                for a in 0..num_nt {
+                    let posterior = inside[i_j + a] * outside[i_j + a] / total;
+                    if !posterior.is_zero() && posterior >= threshold {
+                        mask.allow(i, r, a);
+                    }
+                }
+            }
+        }
+
+        Some(mask)
+    }
+
+    /// Marks the labeled spans whose posterior probability is at least `threshold`, e.g. those
+    /// of a forest read with `Forest::from_reader`. Labels the grammar doesn't have are ignored.
+    pub fn span_mask(
+        &self,
+        sentence_len: usize,
+        spans: &SpanPosteriors<N>,
+        threshold: f64,
+    ) -> SpanMask {
+        let mut mask = SpanMask::new(sentence_len, self.lookup.len());
+        for (start, end, label, posterior) in &spans.0 {
+            if *posterior < threshold || start >= end || *end > sentence_len {
+                continue;
+            }
+            if let Some(a) = self.lookup_index.get(label) {
+                mask.allow(*start, end - start, *a as usize);
+            }
+        }
+        mask
+    }
+
+    /// Maps every non-terminal of this grammar to the non-terminal of `coarse` that `project`
+    /// turns it into, or to `None` if `coarse` doesn't have it.
+    pub fn label_projection<F: Fn(&N) -> N>(
+        &self,
+        coarse: &GrammarParse<N, T, LogProb>,
+        project: F,
+    ) -> Vec<Option<usize>> {
+        self.lookup
+            .iter()
+            .map(|n| coarse.lookup_index.get(&project(n)).map(|a| *a as usize))
+            .collect()
+    }
+
+    /// Computes how often every rule is expected to be used in a derivation of `sentence`,
+    /// with the inside-outside algorithm, as needed for expectation maximization.
+    /// Returns the expected counts together with the log-likelihood of the sentence,
+    /// or `None` if the sentence can't be derived.
+    pub fn expected_counts(&self, sentence: &Sentence<T>) -> Option<(ExpectedCounts<N, T>, f64)> {
+        let s_len = sentence.len();
+        if s_len == 0 {
+            return None;
+        }
+
+        let inside = self.inside(sentence, 1.0);
+        let total = self.initial_inside(&inside, s_len);
+        if total.is_zero() {
+            return None;
+        }
+        let outside = self.outside(sentence, &inside);
+
+        let mut lexical: FxHashMap<(IntNt, &T), f64> = FxHashMap::default();
+        let mut chain: FxHashMap<(IntNt, IntNt), f64> = FxHashMap::default();
+        let mut double: FxHashMap<(IntNt, IntNt, IntNt), f64> = FxHashMap::default();
+
+        for (i, word) in sentence.iter().enumerate() {
+            let i_j = outside.cell_start_index(i, 1);
+            if let Some(rules) = self.rules_lexical.get_vec(word) {
+                for (a, w) in rules {
+                    let count = (*w * outside[i_j + *a as usize] / total).prob();
+                    *lexical.entry((*a, word)).or_insert(0.0) += count;
+                }
+            }
+        }
+
+        for r in 1..=s_len {
+            for i in 0..=(s_len - r) {
+                let i_j = outside.cell_start_index(i, r);
+                for (b, rules) in self.rules_chain.iter_all() {
+                    for (a, w) in rules
+                        .iter()
+                        .filter(|(a, _)| self.chain_allowed(*a, *b, i, r, sentence))
+                    {
+                        let count = (*w * outside[i_j + *a as usize] * inside[i_j + *b as usize]
+                            / total)
+                            .prob();
+                        *chain.entry((*a, *b)).or_insert(0.0) += count;
+                    }
+                }
+
+                for (a, rules) in self.rules_double.iter_all() {
+                    let out_a = outside[i_j + *a as usize];
+                    if out_a.is_zero() {
+                        continue;
+                    }
+                    for (b, c, w) in rules.iter().filter(|(b, c, _)| {
+                        self.constraints_double.is_empty()
+                            || self.double_allowed((*a, *b, *c), i, r, sentence)
+                    }) {
+                        let mut sum = LogProb::ZERO;
+                        for m in 1..r {
+                            let i_m = inside.cell_start_index(i, m) + *b as usize;
+                            let m_j = inside.cell_start_index(i + m, r - m) + *c as usize;
+                            sum += inside[i_m] * inside[m_j];
+                        }
+                        let count = (*w * out_a * sum / total).prob();
+                        *double.entry((*a, *b, *c)).or_insert(0.0) += count;
+                    }
+                }
+            }
+        }
+
+        let nt = |a: IntNt| self.lookup[a as usize].clone();
+        let counts = lexical
+            .into_iter()
+            .map(|((a, word), count)| {
+                let rule = Rule::Lexical {
+                    lhs: nt(a),
+                    rhs: word.clone(),
+                };
+                (rule, count)
+            })
+            .chain(chain.into_iter().map(|((a, b), count)| {
+                let rule = Rule::NonLexical {
+                    lhs: nt(a),
+                    rhs: vec![nt(b)],
+                };
+                (rule, count)
+            }))
+            .chain(double.into_iter().map(|((a, b, c), count)| {
+                let rule = Rule::NonLexical {
+                    lhs: nt(a),
+                    rhs: vec![nt(b), nt(c)],
+                };
+                (rule, count)
+            }))
+            .filter(|(_, count)| *count > 0.0)
+            .collect();
+
+        Some((counts, total.ln()))
+    }
+
+    /// Fills a chart with the outside probabilities for the given inside chart,
+    /// summing over all derivations.
+    fn outside(&self, sentence: &Sentence<T>, inside: &Chart<LogProb>) -> Chart<LogProb> {
+        let num_nt = self.lookup.len();
+        let s_len = sentence.len();
+        let root = inside.cell_start_index(0, s_len);
+
+        let mut outside: Chart<LogProb> = Chart::new(s_len, num_nt);
+        for a in &self.initial_nonterminals {
+            outside[root + *a as usize] = LogProb::ONE;
+        }
+
+        for r in (1..=s_len).rev() {
+            for i in 0..=(s_len - r) {
+                let i_j = outside.cell_start_index(i, r);
+                self.unary_sum(outside.get_cell_mut(i_j), i, r, sentence, true, 1.0);
+
+                for (a, rules) in self.rules_double.iter_all() {
+                    let out_a = outside[i_j + *a as usize];
+                    if out_a.is_zero() {
+                        continue;
+                    }
+                    for (b, c, w) in rules.iter().filter(|(b, c, _)| {
+                        self.constraints_double.is_empty()
+                            || self.double_allowed((*a, *b, *c), i, r, sentence)
+                    }) {
+                        for m in 1..r {
+                            let i_m = outside.cell_start_index(i, m) + *b as usize;
+                            let m_j = outside.cell_start_index(i + m, r - m) + *c as usize;
+                            outside[i_m] += *w * out_a * inside[m_j];
+                            outside[m_j] += *w * out_a * inside[i_m];
+                        }
+                    }
+                }
+            }
+        }
+
+        outside
+    }
+
+    /// Samples `n` derivations of `sentence` from the inside chart, with probabilities
+    /// proportional to their weight raised to the power of `1 / temperature`. Higher
+    /// temperatures flatten the distribution. Every derivation is returned with its
+    /// probability under this distribution. Pruning and the precomputed unary closure
+    /// are not used. The result is empty if the sentence can't be derived.
+    pub fn sample(
+        &self,
+        sentence: &Sentence<T>,
+        n: usize,
+        temperature: f64,
+        rng: &mut XorShift,
+    ) -> Vec<(Tree<NodeType<N, T>>, f64)> {
+        let s_len = sentence.len();
+        if s_len == 0 {
+            return vec![];
+        }
+
+        let inside = self.inside(sentence, temperature);
+        let total = self.initial_inside(&inside, s_len);
+        if total.is_zero() {
+            return vec![];
+        }
+
+        let root_cell = inside.cell_start_index(0, s_len);
+        (0..n)
+            .filter_map(|_| {
+                // The initial non-terminal is chosen proportionally to its inside weight.
+                let mut threshold = total.prob() * rng.weight();
+                let mut root = self.initial_nonterminals[0] as usize;
+                for a in self.initial_nonterminals.iter().map(|a| *a as usize) {
+                    let w = inside[root_cell + a].prob();
+                    if w > 0.0 {
+                        root = a;
+                        if threshold <= w {
+                            break;
+                        }
+                        threshold -= w;
+                    }
+                }
+                self.sample_derivation(&inside, 0, s_len, root, 0, temperature, sentence, rng)
+            })
+            .map(|(tree, weight)| (tree, (weight / total).prob()))
+            .collect()
+    }
+
+    /// Samples a derivation of non-terminal `a` over the given span, choosing every rule
+    /// with probability proportional to its tempered weight times the inside weights of its
+    /// children. After `MAX_UNARY_ITERATIONS` unary rules in a row, only other rules are chosen.
+    /// Returns the derivation with the product of its tempered rule weights.
+    #[allow(clippy::too_many_arguments)]
+    fn sample_derivation(
+        &self,
+        inside: &Chart<LogProb>,
+        start: usize,
+        span: usize,
+        a: usize,
+        unary_depth: usize,
+        temperature: f64,
+        sentence: &Sentence<T>,
+        rng: &mut XorShift,
+    ) -> Option<(Tree<NodeType<N, T>>, LogProb)> {
+        enum Choice {
+            Term(LogProb),
+            Chain(usize, LogProb),
+            Binary(usize, usize, usize, LogProb),
+        }
+
+        let cell = inside.cell_start_index(start, span);
+        let mut choices = vec![];
+
+        if span == 1 {
+            if let Some(rules) = self.rules_lexical.get_vec(&sentence.0[start]) {
+                for (_, w) in rules.iter().filter(|(x, _)| *x as usize == a) {
+                    let w = w.powf(1.0 / temperature);
+                    choices.push((w, Choice::Term(w)));
+                }
+            }
+        }
+
+        if unary_depth < MAX_UNARY_ITERATIONS {
+            for (b, rules) in self.rules_chain.iter_all() {
+                for (_, w) in rules.iter().filter(|(x, _)| {
+                    *x as usize == a && self.chain_allowed(*x, *b, start, span, sentence)
+                }) {
+                    let w = w.powf(1.0 / temperature);
+                    choices.push((
+                        w * inside[cell + *b as usize],
+                        Choice::Chain(*b as usize, w),
+                    ));
+                }
+            }
+        }
+
+        if let Some(rules) = self.rules_double.get_vec(&(a as IntNt)) {
+            for (b, c, w) in rules.iter().filter(|(b, c, _)| {
+                self.constraints_double.is_empty()
+                    || self.double_allowed((a as IntNt, *b, *c), start, span, sentence)
+            }) {
+                let w = w.powf(1.0 / temperature);
+                for m in 1..span {
+                    let left = inside[inside.cell_start_index(start, m) + *b as usize];
+                    let right = inside[inside.cell_start_index(start + m, span - m) + *c as usize];
+                    choices.push((
+                        w * left * right,
+                        Choice::Binary(*b as usize, *c as usize, m, w),
+                    ));
+                }
+            }
+        }
+
+        let total = choices
+            .iter()
+            .fold(LogProb::ZERO, |total, (w, _)| total + *w);
+        if total.is_zero() {
+            return None;
+        }
+
+        // Walk through the choices until their cumulative probability exceeds the draw.
+        let mut draw = rng.weight();
+        let mut chosen = None;
+        for (w, choice) in choices.into_iter().filter(|(w, _)| !w.is_zero()) {
+            chosen = Some(choice);
+            draw -= (w / total).prob();
+            if draw <= 0.0 {
+                break;
+            }
+        }
+
+        let label = NodeType::NonTerminal(self.lookup[a].clone());
+        match chosen? {
+            Choice::Term(w) => Some((
+                Tree {
+                    root: label,
+                    children: vec![Tree {
+                        root: NodeType::Terminal(sentence.0[start].clone()),
+                        children: vec![],
+                    }],
+                },
+                w,
+            )),
+            Choice::Chain(b, w) => {
+                let (child, child_weight) = self.sample_derivation(
+                    inside,
+                    start,
+                    span,
+                    b,
+                    unary_depth + 1,
+                    temperature,
+                    sentence,
+                    rng,
+                )?;
+                Some((
+                    Tree {
+                        root: label,
+                        children: vec![child],
+                    },
+                    w * child_weight,
+                ))
+            }
+            Choice::Binary(b, c, m, w) => {
+                let (left, left_weight) =
+                    self.sample_derivation(inside, start, m, b, 0, temperature, sentence, rng)?;
+                let (right, right_weight) = self.sample_derivation(
+                    inside,
+                    start + m,
+                    span - m,
+                    c,
+                    0,
+                    temperature,
+                    sentence,
+                    rng,
+                )?;
+                Some((
+                    Tree {
+                        root: label,
+                        children: vec![left, right],
+                    },
+                    w * left_weight * right_weight,
+                ))
+            }
+        }
+    }
+
+    /// Fills a chart with the inside probabilities, summing over all derivations.
+    /// The rule weights are raised to the power of `1 / temperature`.
+    fn inside(&self, sentence: &Sentence<T>, temperature: f64) -> Chart<LogProb> {
+        let num_nt = self.lookup.len();
+        let s_len = sentence.len();
+        let mut chart: Chart<LogProb> = Chart::new(s_len, num_nt);
+
+        for (i, word) in sentence.iter().enumerate() {
+            let i_j = chart.cell_start_index(i, 1);
+            if let Some(rules) = self.rules_lexical.get_vec(word) {
+                for (a, w) in rules {
+                    chart[i_j + *a as usize] += w.powf(1.0 / temperature);
+                }
+            }
+            self.unary_sum(chart.get_cell_mut(i_j), i, 1, sentence, false, temperature);
+        }
+
+        for r in 2..=s_len {
+            for i in 0..=(s_len - r) {
+                let i_j = chart.cell_start_index(i, r);
+                for (a, rules) in self.rules_double.iter_all() {
+                    let mut sum = LogProb::ZERO;
+                    for (b, c, w) in rules.iter().filter(|(b, c, _)| {
+                        self.constraints_double.is_empty()
+                            || self.double_allowed((*a, *b, *c), i, r, sentence)
+                    }) {
+                        for m in 1..r {
+                            let i_m = chart.cell_start_index(i, m) + *b as usize;
+                            let m_j = chart.cell_start_index(i + m, r - m) + *c as usize;
+                            sum += w.powf(1.0 / temperature) * chart[i_m] * chart[m_j];
+                        }
+                    }
+                    chart[i_j + *a as usize] += sum;
+                }
+                self.unary_sum(chart.get_cell_mut(i_j), i, r, sentence, false, temperature);
+            }
+        }
+
+        chart
+    }
+
+    /// Adds the contributions of all chains of unary rules to a cell. Going upwards,
+    /// as needed for inside probabilities, the weight of `a -> b` is added from `b` to `a`.
+    /// Going downwards for outside probabilities, it is added from `a` to `b`.
+    fn unary_sum(
+        &self,
+        c: &mut [LogProb],
+        start: usize,
+        span: usize,
+        sentence: &Sentence<T>,
+        downwards: bool,
+        temperature: f64,
+    ) {
+        let mut delta = c.to_vec();
+
+        for _ in 0..MAX_UNARY_ITERATIONS {
+            let mut next = vec![LogProb::ZERO; c.len()];
+            for (b, rules) in self.rules_chain.iter_all() {
+                for (a, w) in rules
+                    .iter()
+                    .filter(|(a, _)| self.chain_allowed(*a, *b, start, span, sentence))
+                {
+                    let (from, to) = if downwards { (*a, *b) } else { (*b, *a) };
+                    next[to as usize] += w.powf(1.0 / temperature) * delta[from as usize];
+                }
+            }
+
+            let mut changed = false;
+            for (entry, n) in c.iter_mut().zip(&next) {
+                changed |= n.ln() - entry.ln() > UNARY_EPSILON.ln();
+                *entry += *n;
+            }
+            if !changed {
+                break;
+            }
+            delta = next;
+        }
+    }
+
+    /// Fills the chart for `sentence`. After the unary closure and after each
+    /// pruning step, `observe` is called with start position, span length and the cell.
+    /// The binary rules are applied to the cells of a span in parallel, the unary closure and
+    /// pruning run in order of the start position. When pruning, the surviving entries of every
+    /// cell are recorded, so that the binary rules only visit those. Once a pruner aborts
+    /// parsing, the remaining cells are left empty.
+    fn fill_chart<F>(
+        &self,
+        sentence: &Sentence<T>,
+        mode: &PruneMode<N, T>,
+        mut observe: F,
+    ) -> Chart<ChartEntry>
+    where
+        F: FnMut(usize, usize, CellStage, &[ChartEntry]),
+    {
+        let num_nt = self.lookup.len();
+        let s_len = sentence.len();
+
+        let mut chart: Chart<ChartEntry> = Chart::new(s_len, num_nt);
+        self.chart_setup(sentence, &mut chart, mode, &mut observe);
+
+        let mut survivors = mode.is_prune().then(|| SurvivorIndex::new(s_len, num_nt));
+        let is_set = |entry: &ChartEntry| !entry.0.is_zero();
+        if let Some(survivors) = &mut survivors {
+            for i in 0..s_len {
+                survivors.insert_cell(i * num_nt, chart.get_cell_mut(i * num_nt), is_set);
+            }
+        }
+
+        for r in 2..=s_len {
+            if mode.aborts() {
+                break;
+            }
+            // The cells of one span only depend on shorter spans and are filled in parallel.
+            let (shorter, cells) = chart.split_span_mut(r);
+            cells
+                .par_chunks_mut(num_nt)
+                .enumerate()
+                .for_each(|(i, cell)| {
+                    self.combine_binary(shorter, survivors.as_ref(), cell, i, r, mode, sentence)
+                });
+
+            for i in 0..=(s_len - r) {
+                let i_j = chart.cell_start_index(i, r);
+                let cell = chart.get_cell_mut(i_j);
+                self.close_and_prune(cell, i, r, mode, sentence, &mut observe);
+                if let Some(survivors) = &mut survivors {
+                    survivors.insert_cell(i_j, cell, is_set);
+                }
+            }
+        }
+
+        chart
+    }
+
+    /// Fills `cell`, the cell of span `r` starting at `i`, with the best derivations by a binary
+    /// rule from the cells in `chart`, which contains at least all cells of shorter spans.
+    /// If given, the left children are taken from the entries recorded in `survivors`. Stops
+    /// between two split points once a pruner of `mode` aborts parsing.
+    #[allow(clippy::too_many_arguments)]
+    fn combine_binary(
+        &self,
+        chart: &[ChartEntry],
+        survivors: Option<&SurvivorIndex>,
+        cell: &mut [ChartEntry],
+        i: usize,
+        r: usize,
+        mode: &PruneMode<N, T>,
+        sentence: &Sentence<T>,
+    ) {
+        let num_nt = cell.len();
+        let cell_index = |start, span| cell_start_index(sentence.len(), num_nt, start, span);
+        let j = i + r;
+        for m in (i + 1)..j {
+            if mode.aborts() {
+                return;
+            }
+            let i_m = cell_index(i, m - i);
+            let m_j = cell_index(m, j - m);
+
+            let mut dense = 0..num_nt as IntNt;
+            let mut surviving;
+            let lefts: &mut dyn Iterator<Item = IntNt> = match survivors {
+                Some(survivors) => {
+                    surviving = survivors.entries(i_m).iter().copied();
+                    &mut surviving
+                }
+                None => &mut dense,
+            };
+
+            // Only rules whose children both have entries are applied.
+            for b in lefts {
+                let b = b as usize;
+                let left = &chart[i_m + b];
+                if left.0.is_zero() {
+                    continue;
+                }
+                let rights = match self.rules_double_left.get_vec(&(b as IntNt)) {
+                    Some(rights) => rights,
+                    None => continue,
+                };
+
+                for (c, rules) in rights {
+                    let c = *c as usize;
+                    let right = chart[m_j + c].0;
+                    if right.is_zero() {
+                        continue;
+                    }
+
+                    for (a, weight) in rules {
+                        let a = *a as usize;
+                        if !(self.constraints_double.is_empty()
+                            || self.double_allowed(
+                                (a as IntNt, b as IntNt, c as IntNt),
+                                i,
+                                r,
+                                sentence,
+                            ))
+                        {
+                            continue;
+                        }
+
+                        let candidate = (
+                            *weight * left.0 * right,
+                            Some(BacktraceInfo::Binary((i_m + b) as u32, (m_j + c) as u32)),
+                        );
+                        if candidate > cell[a] {
+                            cell[a] = candidate;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn chart_setup<F>(
+        &self,
+        sentence: &Sentence<T>,
+        chart: &mut Chart<ChartEntry>,
+        mode: &PruneMode<N, T>,
+        observe: &mut F,
+    ) where
+        F: FnMut(usize, usize, CellStage, &[ChartEntry]),
+    {
+        let num_nt = chart.num_nt();
+
+        for (i, word) in sentence.iter().enumerate() {
+            if let Some(lexicals) = self.rules_lexical.get_vec(word) {
+                for (nt, weight) in lexicals {
+                    let nt = *nt as usize;
+                    chart[(i * num_nt) + nt] = (*weight, Some(BacktraceInfo::Term(i as u32)));
+                }
+            }
+            self.close_and_prune(
+                chart.get_cell_mut(i * num_nt),
+                i,
+                1,
+                mode,
+                sentence,
+                observe,
+            );
+        }
+    }
+
+    fn close_and_prune<F>(
+        &self,
+        c: &mut [ChartEntry],
+        start: usize,
+        span: usize,
+        mode: &PruneMode<N, T>,
+        sentence: &Sentence<T>,
+        observe: &mut F,
+    ) where
+        F: FnMut(usize, usize, CellStage, &[ChartEntry]),
+    {
+        observe(start, span, CellStage::Filled, c);
+        let span_info = SpanInfo {
+            start,
+            span,
+            sentence,
+            labels: &self.lookup,
+        };
+        if span == 1 {
+            for pruner in mode.pruners() {
+                let pruned = self.prune_unprotected(c, mode, sentence, |c| {
+                    pruner.prune_preterminals(c, &span_info)
+                });
+                if pruned {
+                    observe(start, span, CellStage::Pruned(pruner.beam()), c);
+                }
+            }
+        }
+
+        match &self.closure {
+            Some(closure) => self.apply_closure(closure, c, start, span, sentence),
+            None => self.unary_closure(c, start, span, sentence),
+        }
+        observe(start, span, CellStage::Closure, c);
+
+        for pruner in mode.pruners() {
+            self.prune_unprotected(c, mode, sentence, |c| {
+                pruner.prune_cell(c, &span_info);
+                true
+            });
+            observe(start, span, CellStage::Pruned(pruner.beam()), c);
+        }
+    }
+
+    /// Applies `prune` to `c` without the entries derived with protected rules, so that they
+    /// are neither removed nor counted, and puts them back unless `mode` aborts parsing.
+    /// Returns the result of `prune`.
+    fn prune_unprotected<F>(
+        &self,
+        c: &mut [ChartEntry],
+        mode: &PruneMode<N, T>,
+        sentence: &Sentence<T>,
+        prune: F,
+    ) -> bool
+    where
+        F: FnOnce(&mut [ChartEntry]) -> bool,
+    {
+        if !self.has_protected_rules() {
+            return prune(c);
+        }
+
+        let mut protected = vec![];
+        for (a, entry) in c.iter_mut().enumerate() {
+            if self.is_protected(a, entry, sentence) {
+                protected.push((a, *entry));
+                *entry = Default::default();
+            }
+        }
+        let pruned = prune(c);
+        if !mode.aborts() {
+            for (a, entry) in protected {
+                c[a] = entry;
+            }
+        }
+        pruned
+    }
+
+    fn unary_closure(
+        &self,
+        c: &mut [ChartEntry],
+        start: usize,
+        span: usize,
+        sentence: &Sentence<T>,
+    ) {
+        // Use max heap so we can easily extract the element with
+        // the greatest weight.
+        let mut queue = BinaryHeap::with_capacity(c.len());
+
+        // Fill the queue with elements from c.
+        // We invert tuple order of non-terminal and weight in the
+        // queue, so that the queue is sorted by weight.
+        for ele in c
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, (w, _))| !w.is_zero())
+            .map(|(i, w)| (w, i))
+        {
+            queue.push(ele);
+        }
+        for weight in c.iter_mut() {
+            *weight = Default::default();
+        }
+
+        while let Some(((q, backtrace), b)) = queue.pop() {
+            if q > c[b].0 {
+                c[b] = (q, backtrace);
+                if let Some(chain_rules) = self.rules_chain.get_vec(&(b as IntNt)) {
+                    for (a, chain_weight) in chain_rules
+                        .iter()
+                        .filter(|(a, _)| self.chain_allowed(*a, b as IntNt, start, span, sentence))
+                    {
+                        queue.push((
+                            (*chain_weight * q, Some(BacktraceInfo::Chain(b as u32))),
+                            *a as usize,
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Chains containing a rule that is not allowed for the cell are skipped,
+    /// even if a worse chain without it would be allowed.
+    fn apply_closure(
+        &self,
+        closure: &MultiMap<IntNt, (IntNt, LogProb, usize), FxBuildHasher>,
+        c: &mut [ChartEntry],
+        start: usize,
+        span: usize,
+        sentence: &Sentence<T>,
+    ) {
+        // The closure is transitive, so chains only have to be applied
+        // to the entries before the closure.
+        let entries = c.to_vec();
+
+        for (b, (w, _)) in entries
+            .iter()
+            .enumerate()
+            .filter(|(_, (w, _))| !w.is_zero())
+        {
+            if let Some(chains) = closure.get_vec(&(b as IntNt)) {
+                for (a, chain_weight, path) in chains {
+                    if !self.constraints_chain.is_empty()
+                        && !self.closure_paths[*path]
+                            .windows(2)
+                            .all(|w| self.chain_allowed(w[0], w[1], start, span, sentence))
+                    {
+                        continue;
+                    }
+
+                    let a = *a as usize;
+                    let weight = *chain_weight * *w;
+                    if weight > c[a].0 {
+                        c[a] = (weight, Some(BacktraceInfo::Closure(b as u32, *path as u32)));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Checks whether the rule used for the best derivation of the chart entry
+    /// for non-terminal `a` is protected from pruning.
+    fn is_protected(&self, a: usize, entry: &ChartEntry, sentence: &Sentence<T>) -> bool {
+        let num_nt = self.lookup.len();
+        let a = a as IntNt;
+
+        match entry.1 {
+            None => false,
+            Some(BacktraceInfo::Term(t)) => self
+                .protected_lexical
+                .get_vec(&sentence.0[t as usize])
+                .is_some_and(|nts| nts.contains(&a)),
+            Some(BacktraceInfo::Chain(b)) => self.protected_chain.contains(&(a, b)),
+            Some(BacktraceInfo::Closure(_, path)) => {
+                let path = &self.closure_paths[path as usize];
+                self.protected_chain.contains(&(path[0], path[1]))
+            }
+            Some(BacktraceInfo::Binary(i, j)) => {
+                self.protected_double
+                    .contains(&(a, i % num_nt as IntNt, j % num_nt as IntNt))
+            }
+        }
+    }
+
+    fn has_protected_rules(&self) -> bool {
+        !self.protected_lexical.is_empty()
+            || !self.protected_chain.is_empty()
+            || !self.protected_double.is_empty()
+    }
+
+    fn construct_best_tree(
+        &self,
+        c: &[ChartEntry],
+        c_idx: usize,
+        sentence: &Sentence<T>,
+    ) -> Option<Tree<NodeType<N, T>>> {
+        let lookup = &self.lookup;
+        let num_nt = lookup.len();
+
+        match c[c_idx].1 {
+            None => None,
+            Some(BacktraceInfo::Term(t)) => Some(Tree {
+                root: NodeType::NonTerminal(lookup[c_idx % num_nt].clone()),
+                children: vec![Tree {
+                    root: NodeType::Terminal(sentence.0[t as usize].clone()),
+                    children: vec![],
+                }],
+            }),
+            Some(BacktraceInfo::Chain(i)) => {
+                let nt = c_idx % num_nt;
+                self.construct_best_tree(c, c_idx - nt + i as usize, sentence)
+                    .map(|tree| Tree {
+                        root: NodeType::NonTerminal(lookup[nt].clone()),
+                        children: vec![tree],
+                    })
+            }
+            Some(BacktraceInfo::Closure(i, path)) => {
+                let nt = c_idx % num_nt;
+                let path = &self.closure_paths[path as usize];
+                self.construct_best_tree(c, c_idx - nt + i as usize, sentence)
+                    .map(|tree| {
+                        path[..path.len() - 1]
+                            .iter()
+                            .rev()
+                            .fold(tree, |tree, n| Tree {
+                                root: NodeType::NonTerminal(lookup[*n as usize].clone()),
+                                children: vec![tree],
+                            })
+                    })
+            }
+            Some(BacktraceInfo::Binary(i, j)) => {
+                if let (Some(tree_i), Some(tree_j)) = (
+                    self.construct_best_tree(c, i as usize, sentence),
+                    self.construct_best_tree(c, j as usize, sentence),
+                ) {
+                    let nt = c_idx % num_nt;
+                    Some(Tree {
+                        root: NodeType::NonTerminal(lookup[nt].clone()),
+                        children: vec![tree_i, tree_j],
+                    })
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Collects start position, span length and label of all inner nodes of `tree`.
+/// Returns the span length of `tree`.
+fn gold_items<'a, N>(
+    tree: &'a Tree<N>,
+    start: usize,
+    items: &mut Vec<(usize, usize, &'a N)>,
+) -> usize {
+    if tree.is_leaf() {
+        return 1;
+    }
+
+    let mut span = 0;
+    for child in &tree.children {
+        span += gold_items(child, start + span, items);
+    }
+    items.push((start, span, &tree.root));
+
+    span
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::grammar::hierarchy::LabelHierarchy;
+    use crate::grammar::logprob::approx_eq;
+    use crate::grammar::prune::DeadlinePruner;
+
+    #[test]
+    fn cyk_base_correct() {
+        let mut grammar = GrammarParse::new("S".to_string());
+
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::NonLexical {
+                    lhs: "S".to_string(),
+                    rhs: vec!["NP".to_string(), "VP".to_string()],
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::NonLexical {
+                    lhs: "VP".to_string(),
+                    rhs: vec!["VP".to_string(), "PP".to_string()],
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::NonLexical {
+                    lhs: "VP".to_string(),
+                    rhs: vec!["V".to_string(), "NP".to_string()],
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::NonLexical {
+                    lhs: "PP".to_string(),
+                    rhs: vec!["P".to_string(), "NP".to_string()],
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::NonLexical {
+                    lhs: "NP".to_string(),
+                    rhs: vec!["Det".to_string(), "N".to_string()],
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::NonLexical {
+                    lhs: "NP".to_string(),
+                    rhs: vec!["PN".to_string()],
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "VP".to_string(),
+                    rhs: "eats".to_string(),
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "PN".to_string(),
+                    rhs: "she".to_string(),
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "V".to_string(),
+                    rhs: "eats".to_string(),
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "P".to_string(),
+                    rhs: "with".to_string(),
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "N".to_string(),
+                    rhs: "fish".to_string(),
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "N".to_string(),
+                    rhs: "fork".to_string(),
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "Det".to_string(),
+                    rhs: "a".to_string(),
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
 
         let tree = Tree {
             root: NodeType::NonTerminal("S".to_string()),
@@ -515,6 +2819,13 @@ mod test {
             "fork".to_string(),
         ]);
         assert_eq!(tree, grammar.cyk(&sentence, &PruneMode::empty()).unwrap());
+        // Pruning nothing only changes the cells the binary rules visit, not the result.
+        assert_eq!(
+            tree,
+            grammar
+                .cyk(&sentence, &PruneMode::empty().with_threshold(0.0))
+                .unwrap()
+        );
 
         let sentence = Sentence(vec![
             "a".to_string(),
@@ -527,4 +2838,1002 @@ mod test {
         ]);
         assert!(grammar.cyk(&sentence, &PruneMode::empty()).is_none());
     }
+
+    #[test]
+    fn compiled_roundtrip() {
+        let mut grammar = GrammarParse::new("NP".to_string());
+        for (rule, weight) in [
+            (
+                Rule::NonLexical {
+                    lhs: "S".to_string(),
+                    rhs: vec!["NP".to_string(), "VP".to_string()],
+                },
+                1.0,
+            ),
+            (
+                Rule::NonLexical {
+                    lhs: "VP".to_string(),
+                    rhs: vec!["V".to_string()],
+                },
+                0.5,
+            ),
+            (
+                Rule::Lexical {
+                    lhs: "NP".to_string(),
+                    rhs: "dogs".to_string(),
+                },
+                1.0,
+            ),
+            (
+                Rule::Lexical {
+                    lhs: "V".to_string(),
+                    rhs: "bark".to_string(),
+                },
+                1.0,
+            ),
+        ] {
+            grammar
+                .insert_rule(WeightedRule {
+                    rule,
+                    weight: FloatOrd(weight),
+                })
+                .unwrap();
+        }
+
+        let mut buf = vec![];
+        grammar.write_compiled(&mut buf).unwrap();
+        let compiled: GrammarParse<String, String, LogProb> =
+            GrammarParse::read_compiled(&mut &buf[..], "S".to_string()).unwrap();
+        assert!(compiled.closure.is_none());
+
+        // The precomputed closure is read back instead of computed again.
+        assert_eq!(Ok(true), grammar.precompute_unary_closure());
+        let mut closed = vec![];
+        grammar.write_compiled(&mut closed).unwrap();
+        let mut compiled_closed: GrammarParse<String, String, LogProb> =
+            GrammarParse::read_compiled(&mut &closed[..], "S".to_string()).unwrap();
+        assert_eq!(grammar.closure_paths, compiled_closed.closure_paths);
+        assert_eq!(Ok(false), compiled_closed.precompute_unary_closure());
+
+        let rule = Rule::NonLexical {
+            lhs: "VP".to_string(),
+            rhs: vec!["V".to_string()],
+        };
+        assert_eq!(grammar.rule_weight(&rule), compiled.rule_weight(&rule));
+        let sentence = Sentence(vec!["dogs".to_string(), "bark".to_string()]);
+        for compiled in [&compiled, &compiled_closed] {
+            assert_eq!(
+                "(S (NP dogs) (VP (V bark)))",
+                compiled
+                    .cyk(&sentence, &PruneMode::empty())
+                    .unwrap()
+                    .to_string()
+            );
+        }
+        assert!(GrammarParse::<String, String, LogProb>::read_compiled(
+            &mut &buf[..20],
+            "S".to_string()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn coarse_pass_mask() {
+        let mut grammar = GrammarParse::new("S".to_string());
+        for (rule, weight) in [
+            (
+                Rule::NonLexical {
+                    lhs: "S".to_string(),
+                    rhs: vec!["NP".to_string(), "VP".to_string()],
+                },
+                1.0,
+            ),
+            (
+                Rule::Lexical {
+                    lhs: "NP".to_string(),
+                    rhs: "dogs".to_string(),
+                },
+                0.9,
+            ),
+            (
+                Rule::Lexical {
+                    lhs: "VP".to_string(),
+                    rhs: "dogs".to_string(),
+                },
+                0.1,
+            ),
+            (
+                Rule::Lexical {
+                    lhs: "VP".to_string(),
+                    rhs: "bark".to_string(),
+                },
+                1.0,
+            ),
+        ] {
+            grammar
+                .insert_rule(WeightedRule {
+                    rule,
+                    weight: FloatOrd(weight),
+                })
+                .unwrap();
+        }
+
+        let sentence = Sentence(vec!["dogs".to_string(), "bark".to_string()]);
+        let mask = grammar.posterior_mask(&sentence, 0.5).unwrap();
+        let projection = grammar.label_projection(&grammar, |n| n.clone());
+        let [s, np, vp] = ["S", "NP", "VP"].map(|n| {
+            let a = grammar.lookup_index[&n.to_string()] as usize;
+            assert_eq!(Some(a), projection[a]);
+            a
+        });
+
+        assert!(mask.is_allowed(0, 2, s) && mask.is_allowed(0, 1, np) && mask.is_allowed(1, 1, vp));
+        // VP over "dogs" has a posterior of 0, because it can't be completed to a parse.
+        assert!(!mask.is_allowed(0, 1, vp));
+
+        let mask = grammar.span_mask(2, &grammar.span_posteriors(&sentence), 0.5);
+        assert!(mask.is_allowed(0, 2, s) && mask.is_allowed(0, 1, np) && mask.is_allowed(1, 1, vp));
+        assert!(!mask.is_allowed(0, 1, vp));
+        assert!(grammar
+            .posterior_mask(&Sentence(vec!["bark".to_string()]), 0.5)
+            .is_none());
+    }
+
+    #[test]
+    fn label_backoff() {
+        let rule = |lhs: &str, rhs: &[&str]| Rule::NonLexical {
+            lhs: lhs.to_string(),
+            rhs: rhs.iter().map(|n| n.to_string()).collect(),
+        };
+        let mut grammar = GrammarParse::new("S".to_string());
+        for (rule, weight) in [
+            (rule("S", &["NP-SBJ", "VP"]), 1.0),
+            (rule("NP", &["DT", "NN"]), 0.5),
+            (rule("NP", &["NP-SBJ"]), 0.5),
+            (
+                Rule::Lexical {
+                    lhs: "DT".to_string(),
+                    rhs: "the".to_string(),
+                },
+                1.0,
+            ),
+            (
+                Rule::Lexical {
+                    lhs: "NN".to_string(),
+                    rhs: "dog".to_string(),
+                },
+                1.0,
+            ),
+            (
+                Rule::Lexical {
+                    lhs: "VP".to_string(),
+                    rhs: "barks".to_string(),
+                },
+                1.0,
+            ),
+        ] {
+            grammar
+                .insert_rule(WeightedRule {
+                    rule,
+                    weight: FloatOrd(weight),
+                })
+                .unwrap();
+        }
+
+        let sentence = Sentence(vec![
+            "the".to_string(),
+            "dog".to_string(),
+            "barks".to_string(),
+        ]);
+        assert!(grammar.cyk(&sentence, &PruneMode::empty()).is_none());
+
+        let hierarchy = LabelHierarchy::from_suffixes();
+        let added = grammar.insert_backoff_rules(|n| hierarchy.parent(n).map(String::from), 0.1);
+        // The unary rule would derive NP-SBJ from itself.
+        assert_eq!(1, added);
+        let weight = grammar.rule_weight(&rule("NP-SBJ", &["DT", "NN"])).unwrap();
+        assert!(approx_eq(weight, 0.05));
+        assert_eq!(
+            "(S (NP-SBJ (DT the) (NN dog)) (VP barks))",
+            grammar
+                .cyk(&sentence, &PruneMode::empty())
+                .unwrap()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn protected_rules_survive_pruning() {
+        let mut grammar = GrammarParse::new("S".to_string());
+
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::NonLexical {
+                    lhs: "S".to_string(),
+                    rhs: vec!["A".to_string(), "B".to_string()],
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "A".to_string(),
+                    rhs: "a".to_string(),
+                },
+                weight: FloatOrd(0.1),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "X".to_string(),
+                    rhs: "a".to_string(),
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "B".to_string(),
+                    rhs: "b".to_string(),
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+
+        let sentence = Sentence(vec!["a".to_string(), "b".to_string()]);
+        let mode = PruneMode::empty().with_threshold(0.5);
+
+        // "A" is pruned in favour of "X", so no parse is found.
+        assert!(grammar.cyk(&sentence, &mode).is_none());
+
+        grammar
+            .protect_rule(Rule::Lexical {
+                lhs: "A".to_string(),
+                rhs: "a".to_string(),
+            })
+            .unwrap();
+        assert!(grammar.cyk(&sentence, &mode).is_some());
+        assert_eq!(
+            Some(RuleProblem::NotBinarised),
+            grammar
+                .protect_rule(Rule::NonLexical {
+                    lhs: "S".to_string(),
+                    rhs: vec!["A".to_string(), "B".to_string(), "B".to_string()],
+                })
+                .err()
+                .map(|e| e.problem)
+        );
+        assert_eq!(
+            Some(RuleProblem::UnknownNonterminal),
+            grammar
+                .protect_rule(Rule::NonLexical {
+                    lhs: "S".to_string(),
+                    rhs: vec!["A".to_string(), "Y".to_string()],
+                })
+                .err()
+                .map(|e| e.problem)
+        );
+
+        let modes = vec![
+            (
+                "threshold".to_string(),
+                PruneMode::empty().with_threshold(0.5),
+            ),
+            ("rank".to_string(), PruneMode::empty().with_fixed_size(1)),
+        ];
+        assert!(grammar.check_pruning(&sentence, &modes).is_empty());
+
+        // Protected rules don't outlast a deadline.
+        let expired = PruneMode::empty()
+            .with_threshold(0.5)
+            .with_pruner(DeadlinePruner(Instant::now()));
+        assert!(grammar.cyk(&sentence, &expired).is_none());
+    }
+
+    #[test]
+    fn gold_diagnosis() {
+        let mut grammar = GrammarParse::new("S".to_string());
+
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::NonLexical {
+                    lhs: "S".to_string(),
+                    rhs: vec!["A".to_string(), "B".to_string()],
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "A".to_string(),
+                    rhs: "a".to_string(),
+                },
+                weight: FloatOrd(0.1),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "X".to_string(),
+                    rhs: "a".to_string(),
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "B".to_string(),
+                    rhs: "b".to_string(),
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+
+        let sentence = Sentence(vec!["a".to_string(), "b".to_string()]);
+        let gold = Tree {
+            root: "S".to_string(),
+            children: vec![
+                Tree {
+                    root: "A".to_string(),
+                    children: vec![Tree {
+                        root: "a".to_string(),
+                        children: vec![],
+                    }],
+                },
+                Tree {
+                    root: "B".to_string(),
+                    children: vec![Tree {
+                        root: "b".to_string(),
+                        children: vec![],
+                    }],
+                },
+            ],
+        };
+
+        assert_eq!(
+            GoldDiagnosis::Intact,
+            grammar.diagnose_pruning(&sentence, &PruneMode::empty(), &gold)
+        );
+
+        let mode = PruneMode::empty().with_threshold(0.5);
+        assert_eq!(
+            GoldDiagnosis::Pruned {
+                start: 0,
+                span: 1,
+                label: "A".to_string(),
+                beam: Beam::Threshold,
+            },
+            grammar.diagnose_pruning(&sentence, &mode, &gold)
+        );
+
+        let sentence = Sentence(vec!["b".to_string(), "b".to_string()]);
+        assert_eq!(
+            GoldDiagnosis::NotDerived {
+                start: 0,
+                span: 1,
+                label: "A".to_string(),
+            },
+            grammar.diagnose_pruning(&sentence, &PruneMode::empty(), &gold)
+        );
+    }
+
+    #[test]
+    fn precomputed_closure() {
+        let mut grammar = GrammarParse::new("S".to_string());
+
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::NonLexical {
+                    lhs: "S".to_string(),
+                    rhs: vec!["A".to_string()],
+                },
+                weight: FloatOrd(0.5),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::NonLexical {
+                    lhs: "S".to_string(),
+                    rhs: vec!["B".to_string()],
+                },
+                weight: FloatOrd(0.25),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::NonLexical {
+                    lhs: "A".to_string(),
+                    rhs: vec!["B".to_string()],
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "B".to_string(),
+                    rhs: "b".to_string(),
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+
+        let mut chains = grammar.unary_chains();
+        chains.sort();
+        let expected = [
+            (vec!["A", "B"], 1.0),
+            (vec!["S", "A"], 0.5),
+            (vec!["S", "A", "B"], 0.5),
+        ];
+        assert_eq!(expected.len(), chains.len());
+        for ((chain, weight), (expected_chain, expected_weight)) in chains.iter().zip(expected) {
+            assert_eq!(expected_chain, *chain);
+            assert!(approx_eq(weight.0, expected_weight));
+        }
+
+        let mut file = vec![];
+        grammar.write_unary_closure(&mut file).unwrap();
+        let hash = format!("{:016x}", grammar.unary_rules_hash());
+        let metadata = GrammarMetadata::read(&mut &file[..]).unwrap();
+        assert_eq!(Some(hash.as_str()), metadata.get(CLOSURE_HASH_KEY));
+
+        let sentence = Sentence(vec!["b".to_string()]);
+        let tree = grammar.cyk(&sentence, &PruneMode::empty());
+
+        for (chain, weight) in chains {
+            grammar.insert_unary_chain(chain, weight);
+        }
+        assert_eq!(tree, grammar.cyk(&sentence, &PruneMode::empty()));
+        assert_eq!(Ok(false), grammar.precompute_unary_closure());
+
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::NonLexical {
+                    lhs: "A".to_string(),
+                    rhs: vec!["S".to_string()],
+                },
+                weight: FloatOrd(0.5),
+            })
+            .unwrap();
+        assert_ne!(hash, format!("{:016x}", grammar.unary_rules_hash()));
+    }
+
+    #[test]
+    fn unary_cycles() {
+        let unary = |lhs: &str, rhs: &str, weight| WeightedRule {
+            rule: Rule::NonLexical {
+                lhs: lhs.to_string(),
+                rhs: vec![rhs.to_string()],
+            },
+            weight: FloatOrd(weight),
+        };
+        let mut grammar = GrammarParse::new("S".to_string());
+        for rule in [
+            unary("S", "A", 0.5),
+            unary("A", "B", 0.5),
+            unary("B", "A", 0.5),
+            unary("A", "C", 0.5),
+        ] {
+            grammar.insert_rule(rule).unwrap();
+        }
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "C".to_string(),
+                    rhs: "c".to_string(),
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+        assert_eq!(None, grammar.unary_cycle());
+
+        let sentence = Sentence(vec!["c".to_string()]);
+        let tree = grammar.cyk(&sentence, &PruneMode::empty());
+        assert_eq!(Ok(true), grammar.precompute_unary_closure());
+        assert_eq!(tree, grammar.cyk(&sentence, &PruneMode::empty()));
+        assert_eq!("(S (A (C c)))", tree.unwrap().to_string());
+
+        // A -> B -> A has a weight of 1 with the second rule B -> A.
+        grammar.insert_rule(unary("B", "A", 2.0)).unwrap();
+        let cycle = grammar.unary_cycle().unwrap();
+        assert_eq!(vec!["A", "B", "A"], cycle.chain);
+        assert!(approx_eq(cycle.weight, 1.0));
+
+        let mut grammar = GrammarParse::new("S".to_string());
+        for rule in [
+            unary("S", "A", 1.0),
+            unary("A", "B", 1.0),
+            unary("B", "D", 2.0),
+            unary("D", "B", 0.75),
+        ] {
+            grammar.insert_rule(rule).unwrap();
+        }
+        let cycle = grammar.precompute_unary_closure().unwrap_err();
+        assert_eq!(vec!["B", "D", "B"], cycle.chain);
+        assert!(approx_eq(cycle.weight, 1.5));
+        assert!(cycle
+            .to_string()
+            .starts_with("unary rules form a cycle B -> D -> B with weight 1."));
+    }
+
+    #[test]
+    fn constrained_rules() {
+        let mut grammar = GrammarParse::new("S".to_string());
+
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::NonLexical {
+                    lhs: "S".to_string(),
+                    rhs: vec!["A".to_string(), "A".to_string()],
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::NonLexical {
+                    lhs: "A".to_string(),
+                    rhs: vec!["A".to_string(), "A".to_string()],
+                },
+                weight: FloatOrd(0.5),
+            })
+            .unwrap();
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "A".to_string(),
+                    rhs: "a".to_string(),
+                },
+                weight: FloatOrd(0.5),
+            })
+            .unwrap();
+
+        let sentence = Sentence(vec!["a".to_string(), "a".to_string(), "a".to_string()]);
+        assert!(grammar.cyk(&sentence, &PruneMode::empty()).is_some());
+
+        grammar
+            .constrain_rule(
+                Rule::NonLexical {
+                    lhs: "A".to_string(),
+                    rhs: vec!["A".to_string(), "A".to_string()],
+                },
+                RuleConstraint::MinSpan(3),
+            )
+            .unwrap();
+        assert!(grammar.cyk(&sentence, &PruneMode::empty()).is_none());
+
+        let invalid = grammar
+            .constrain_rule(
+                Rule::NonLexical {
+                    lhs: "S".to_string(),
+                    rhs: vec!["A".to_string(), "A".to_string(), "A".to_string()],
+                },
+                RuleConstraint::Root,
+            )
+            .unwrap_err();
+        assert_eq!(RuleProblem::NotBinarised, invalid.problem);
+        assert_eq!("rule S -> A A A is not binarised", invalid.to_string());
+
+        let invalid = grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::NonLexical {
+                    lhs: "S".to_string(),
+                    rhs: vec!["A".to_string(), "A".to_string(), "A".to_string()],
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap_err();
+        assert_eq!(RuleProblem::NotBinarised, invalid.problem);
+    }
+
+    #[test]
+    fn span_posteriors() {
+        let mut grammar = GrammarParse::new("R".to_string());
+        for (lhs, rhs, weight) in [
+            ("R", vec!["S"], 1.0),
+            ("S", vec!["X", "A"], 0.5),
+            ("S", vec!["A", "X"], 0.5),
+            ("X", vec!["A", "A"], 1.0),
+        ] {
+            grammar
+                .insert_rule(WeightedRule {
+                    rule: Rule::NonLexical {
+                        lhs: lhs.to_string(),
+                        rhs: rhs.iter().map(|n| n.to_string()).collect(),
+                    },
+                    weight: FloatOrd(weight),
+                })
+                .unwrap();
+        }
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "A".to_string(),
+                    rhs: "a".to_string(),
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+
+        let sentence = Sentence(vec!["a".to_string(), "a".to_string(), "a".to_string()]);
+        let mut posteriors = grammar.span_posteriors(&sentence).0;
+        posteriors.sort_by(|x, y| (x.0, x.1, &x.2).cmp(&(y.0, y.1, &y.2)));
+
+        let expected = vec![
+            (0, 1, "A", 1.0),
+            (0, 2, "X", 0.5),
+            (0, 3, "R", 1.0),
+            (0, 3, "S", 1.0),
+            (1, 2, "A", 1.0),
+            (1, 3, "X", 0.5),
+            (2, 3, "A", 1.0),
+        ];
+        assert_eq!(posteriors.len(), expected.len());
+        for ((s, e, label, p), (s_exp, e_exp, label_exp, p_exp)) in posteriors.iter().zip(expected)
+        {
+            assert_eq!((*s, *e, label.as_str()), (s_exp, e_exp, label_exp));
+            assert!(approx_eq(*p, p_exp));
+        }
+
+        let unparsable = Sentence(vec!["a".to_string(), "a".to_string()]);
+        assert!(grammar.span_posteriors(&unparsable).0.is_empty());
+    }
+
+    #[test]
+    fn expected_counts() {
+        let mut grammar = GrammarParse::new("R".to_string());
+        for (lhs, rhs, weight) in [
+            ("R", vec!["S"], 1.0),
+            ("S", vec!["X", "A"], 0.5),
+            ("S", vec!["A", "X"], 0.5),
+            ("X", vec!["A", "A"], 1.0),
+        ] {
+            grammar
+                .insert_rule(WeightedRule {
+                    rule: Rule::NonLexical {
+                        lhs: lhs.to_string(),
+                        rhs: rhs.iter().map(|n| n.to_string()).collect(),
+                    },
+                    weight: FloatOrd(weight),
+                })
+                .unwrap();
+        }
+        for (lhs, rhs, weight) in [("A", "a", 0.5), ("A", "b", 0.5)] {
+            grammar
+                .insert_rule(WeightedRule {
+                    rule: Rule::Lexical {
+                        lhs: lhs.to_string(),
+                        rhs: rhs.to_string(),
+                    },
+                    weight: FloatOrd(weight),
+                })
+                .unwrap();
+        }
+
+        let sentence = Sentence(vec!["a".to_string(), "a".to_string(), "b".to_string()]);
+        let (counts, log_likelihood) = grammar.expected_counts(&sentence).unwrap();
+        assert!(approx_eq(log_likelihood, 0.125f64.ln()));
+
+        let count = |lhs: &str, rhs: &[&str]| {
+            let rule = match rhs {
+                [t] if t.starts_with(char::is_lowercase) => Rule::Lexical {
+                    lhs: lhs.to_string(),
+                    rhs: t.to_string(),
+                },
+                _ => Rule::NonLexical {
+                    lhs: lhs.to_string(),
+                    rhs: rhs.iter().map(|n| n.to_string()).collect(),
+                },
+            };
+            counts
+                .iter()
+                .find(|(r, _)| *r == rule)
+                .map(|(_, c)| *c)
+                .unwrap_or(0.0)
+        };
+        for (lhs, rhs, expected) in [
+            ("R", vec!["S"], 1.0),
+            ("S", vec!["X", "A"], 0.5),
+            ("S", vec!["A", "X"], 0.5),
+            ("X", vec!["A", "A"], 1.0),
+            ("A", vec!["a"], 2.0),
+            ("A", vec!["b"], 1.0),
+        ] {
+            assert!(approx_eq(count(lhs, &rhs), expected));
+        }
+        assert_eq!(counts.len(), 6);
+
+        let unparsable = Sentence(vec!["a".to_string(), "a".to_string()]);
+        assert!(grammar.expected_counts(&unparsable).is_none());
+    }
+
+    #[test]
+    fn sample_derivations() {
+        let mut grammar = GrammarParse::new("R".to_string());
+        for (lhs, rhs, weight) in [
+            ("R", vec!["S"], 1.0),
+            ("S", vec!["X", "A"], 0.8),
+            ("S", vec!["A", "X"], 0.2),
+            ("X", vec!["A", "A"], 1.0),
+        ] {
+            grammar
+                .insert_rule(WeightedRule {
+                    rule: Rule::NonLexical {
+                        lhs: lhs.to_string(),
+                        rhs: rhs.iter().map(|n| n.to_string()).collect(),
+                    },
+                    weight: FloatOrd(weight),
+                })
+                .unwrap();
+        }
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "A".to_string(),
+                    rhs: "a".to_string(),
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+
+        let sentence = Sentence(vec!["a".to_string(), "a".to_string(), "a".to_string()]);
+        let left = "(R (S (X (A a) (A a)) (A a)))";
+        let mut rng = XorShift::new(3);
+
+        let samples = grammar.sample(&sentence, 200, 1.0, &mut rng);
+        assert_eq!(samples.len(), 200);
+        let mut left_count = 0;
+        for (tree, p) in &samples {
+            if tree.to_string() == left {
+                left_count += 1;
+                assert!(approx_eq(*p, 0.8));
+            } else {
+                assert_eq!(tree.to_string(), "(R (S (A a) (X (A a) (A a))))");
+                assert!(approx_eq(*p, 0.2));
+            }
+        }
+        assert!((130..190).contains(&left_count));
+
+        // A higher temperature flattens the distribution to 2/3 and 1/3.
+        for (tree, p) in grammar.sample(&sentence, 10, 2.0, &mut rng) {
+            let expected = if tree.to_string() == left { 2.0 } else { 1.0 } / 3.0;
+            assert!(approx_eq(p, expected));
+        }
+
+        let unparsable = Sentence(vec!["a".to_string(), "a".to_string()]);
+        assert!(grammar.sample(&unparsable, 5, 1.0, &mut rng).is_empty());
+
+        // Both derivations add up to the probability of the sentence.
+        let log_prob = grammar.sentence_log_prob(&sentence).unwrap();
+        assert!(approx_eq(log_prob, 0.0));
+        assert_eq!(None, grammar.sentence_log_prob(&unparsable));
+    }
+
+    #[test]
+    fn shift_reduce_greedy() {
+        let mut grammar = GrammarParse::new("ROOT".to_string());
+        for (lhs, rhs, weight) in [
+            ("ROOT", vec!["S"], 1.0),
+            ("S", vec!["NP", "VP"], 1.0),
+            ("NP", vec!["D", "N"], 1.0),
+            ("VP", vec!["V"], 1.0),
+        ] {
+            grammar
+                .insert_rule(WeightedRule {
+                    rule: Rule::NonLexical {
+                        lhs: lhs.to_string(),
+                        rhs: rhs.iter().map(|n| n.to_string()).collect(),
+                    },
+                    weight: FloatOrd(weight),
+                })
+                .unwrap();
+        }
+        for (lhs, rhs, weight) in [
+            ("D", "the", 1.0),
+            ("N", "dog", 0.2),
+            ("V", "dog", 0.8),
+            ("V", "barks", 0.2),
+        ] {
+            grammar
+                .insert_rule(WeightedRule {
+                    rule: Rule::Lexical {
+                        lhs: lhs.to_string(),
+                        rhs: rhs.to_string(),
+                    },
+                    weight: FloatOrd(weight),
+                })
+                .unwrap();
+        }
+
+        let sentence = Sentence(vec![
+            "the".to_string(),
+            "dog".to_string(),
+            "barks".to_string(),
+        ]);
+        // "dog" is shifted as N, as only N can be reduced with D, and "barks" gets the unary
+        // rule VP -> V to be reduced with NP.
+        assert_eq!(
+            "(ROOT (S (NP (D the) (N dog)) (VP (V barks))))".to_string(),
+            format!("{}", grammar.shift_reduce(&sentence, &[]).unwrap())
+        );
+
+        let tags = |tag: &str| Some([tag.to_string()].into_iter().collect());
+        assert!(grammar
+            .shift_reduce(&sentence, &[None, tags("V")])
+            .is_none());
+        // Tags that the word doesn't have are ignored.
+        assert!(grammar
+            .shift_reduce(&sentence, &[None, tags("D")])
+            .is_some());
+
+        let unparsable = Sentence(vec!["dog".to_string(), "the".to_string()]);
+        assert!(grammar.shift_reduce(&unparsable, &[]).is_none());
+    }
+
+    #[test]
+    fn kbest() {
+        let mut grammar = GrammarParse::new("R".to_string());
+        for (lhs, rhs, weight) in [
+            ("R", vec!["S"], 1.0),
+            ("S", vec!["X", "A"], 0.6),
+            ("S", vec!["A", "X"], 0.4),
+            ("X", vec!["A", "A"], 1.0),
+        ] {
+            grammar
+                .insert_rule(WeightedRule {
+                    rule: Rule::NonLexical {
+                        lhs: lhs.to_string(),
+                        rhs: rhs.iter().map(|n| n.to_string()).collect(),
+                    },
+                    weight: FloatOrd(weight),
+                })
+                .unwrap();
+        }
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "A".to_string(),
+                    rhs: "a".to_string(),
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+
+        let sentence = Sentence(vec!["a".to_string(), "a".to_string(), "a".to_string()]);
+        let trees = grammar.cyk_kbest(&sentence, 3);
+        let expected = [
+            ("(R (S (X (A a) (A a)) (A a)))", 0.6),
+            ("(R (S (A a) (X (A a) (A a))))", 0.4),
+        ];
+        assert_eq!(expected.len(), trees.len());
+        for ((tree, weight), (expected_tree, expected_weight)) in trees.iter().zip(expected) {
+            assert_eq!(expected_tree, tree.to_string());
+            assert!(approx_eq(*weight, expected_weight));
+        }
+        assert_eq!(
+            format!("{}", grammar.cyk_kbest(&sentence, 1)[0].0),
+            format!("{}", grammar.cyk(&sentence, &PruneMode::empty()).unwrap())
+        );
+    }
+
+    #[test]
+    fn multiple_initial_nonterminals() {
+        let mut grammar = GrammarParse::new("R".to_string());
+        for (lhs, rhs, weight) in [
+            ("R", vec!["A", "X"], 0.4),
+            ("T", vec!["X", "A"], 0.7),
+            ("X", vec!["A", "A"], 1.0),
+        ] {
+            grammar
+                .insert_rule(WeightedRule {
+                    rule: Rule::NonLexical {
+                        lhs: lhs.to_string(),
+                        rhs: rhs.iter().map(|n| n.to_string()).collect(),
+                    },
+                    weight: FloatOrd(weight),
+                })
+                .unwrap();
+        }
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "A".to_string(),
+                    rhs: "a".to_string(),
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+
+        let sentence = Sentence(vec!["a".to_string(), "a".to_string(), "a".to_string()]);
+        assert_eq!(
+            "(R (A a) (X (A a) (A a)))",
+            format!("{}", grammar.cyk(&sentence, &PruneMode::empty()).unwrap())
+        );
+        assert!(grammar.shift_reduce(&sentence, &[]).is_none());
+
+        grammar.add_initial_nonterminal("T".to_string());
+        assert_eq!(
+            "(T (X (A a) (A a)) (A a))",
+            format!("{}", grammar.cyk(&sentence, &PruneMode::empty()).unwrap())
+        );
+        assert_eq!(
+            "(T (X (A a) (A a)) (A a))",
+            format!("{}", grammar.shift_reduce(&sentence, &[]).unwrap())
+        );
+        let trees = grammar.cyk_kbest(&sentence, 3);
+        assert_eq!(
+            vec!["(T (X (A a) (A a)) (A a))", "(R (A a) (X (A a) (A a)))"],
+            trees
+                .iter()
+                .map(|(t, _)| format!("{}", t))
+                .collect::<Vec<_>>()
+        );
+        assert!(approx_eq(trees[0].1, 0.7) && approx_eq(trees[1].1, 0.4));
+
+        let posteriors = grammar.span_posteriors(&sentence).0;
+        let root = |label: &str| {
+            posteriors
+                .iter()
+                .find(|(s, e, l, _)| (*s, *e, l.as_str()) == (0, 3, label))
+                .unwrap()
+                .3
+        };
+        assert!(approx_eq(root("T"), 0.7 / 1.1));
+        assert!(approx_eq(root("R"), 0.4 / 1.1));
+    }
+
+    #[test]
+    fn astar() {
+        let mut grammar = GrammarParse::new("R".to_string());
+        for (lhs, rhs, weight) in [
+            ("R", vec!["S"], 1.0),
+            ("S", vec!["X", "A"], 0.6),
+            ("S", vec!["A", "X"], 0.4),
+            ("X", vec!["A", "A"], 1.0),
+        ] {
+            grammar
+                .insert_rule(WeightedRule {
+                    rule: Rule::NonLexical {
+                        lhs: lhs.to_string(),
+                        rhs: rhs.iter().map(|n| n.to_string()).collect(),
+                    },
+                    weight: FloatOrd(weight),
+                })
+                .unwrap();
+        }
+        grammar
+            .insert_rule(WeightedRule {
+                rule: Rule::Lexical {
+                    lhs: "A".to_string(),
+                    rhs: "a".to_string(),
+                },
+                weight: FloatOrd(1.0),
+            })
+            .unwrap();
+
+        let sentence = Sentence(vec!["a".to_string(), "a".to_string(), "a".to_string()]);
+        let best = format!("{}", grammar.cyk(&sentence, &PruneMode::empty()).unwrap());
+
+        // Without estimates, A* is uniform cost search.
+        assert_eq!(format!("{}", grammar.astar(&sentence).unwrap()), best);
+
+        for (label, context, weight) in
+            [("X", None, 0.6), ("A", None, 0.6), ("X", Some((1, 0)), 0.4)]
+        {
+            grammar.insert_outside_estimate(OutsideEstimate {
+                label: label.to_string(),
+                context,
+                weight,
+            });
+        }
+        assert_eq!(format!("{}", grammar.astar(&sentence).unwrap()), best);
+
+        let unparsable = Sentence(vec!["a".to_string(), "a".to_string()]);
+        assert!(grammar.astar(&unparsable).is_none());
+    }
 }
@@ -0,0 +1,221 @@
+use std::fmt::{self, Display};
+use std::hash::Hash;
+
+use fxhash::FxHashMap;
+
+use super::rule::Rule;
+use crate::json::escape_json;
+use crate::tree::{NodeType, Tree};
+
+/// Inner node of a parse tree, covering the words from `start` to before `end`, with the
+/// grammar rule that it was derived with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Constituent {
+    pub label: String,
+    pub start: usize,
+    pub end: usize,
+    /// The rule in the grammar file format, without weight.
+    pub rule: String,
+    /// Index of the rule in the grammar, `None` if the grammar doesn't contain it,
+    /// e.g. for the NOPARSE node of a sentence that couldn't be parsed.
+    pub index: Option<usize>,
+}
+
+/// Constituents of a parse tree in pre-order. Written as a line of JSON.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Provenance(pub Vec<Constituent>);
+
+/// Index of every rule of a grammar in the order in which the rules were read.
+pub struct RuleIndex<N: Eq + Hash, T: Eq + Hash> {
+    indices: FxHashMap<Rule<N, T>, usize>,
+    len: usize,
+}
+
+impl<N, T> RuleIndex<N, T>
+where
+    N: Eq + Hash + Clone + Display,
+    T: Eq + Hash + Clone + Display,
+{
+    pub fn new() -> Self {
+        Self {
+            indices: FxHashMap::default(),
+            len: 0,
+        }
+    }
+
+    /// Gives the rule the next index. A rule that occurs again keeps its first index,
+    /// but the repetition is counted.
+    pub fn insert(&mut self, rule: Rule<N, T>) {
+        self.indices.entry(rule).or_insert(self.len);
+        self.len += 1;
+    }
+
+    pub fn get(&self, rule: &Rule<N, T>) -> Option<usize> {
+        self.indices.get(rule).copied()
+    }
+
+    /// Finds the rule of every inner node of a parse tree.
+    pub fn provenance(&self, tree: &Tree<NodeType<N, T>>) -> Provenance {
+        let mut constituents = vec![];
+        self.collect(tree, &mut 0, &mut constituents);
+        Provenance(constituents)
+    }
+
+    fn collect(
+        &self,
+        tree: &Tree<NodeType<N, T>>,
+        position: &mut usize,
+        constituents: &mut Vec<Constituent>,
+    ) {
+        if tree.is_leaf() {
+            *position += 1;
+            return;
+        }
+
+        let rule = match (&tree.root, tree.children.as_slice()) {
+            (NodeType::NonTerminal(lhs), [child]) if child.is_leaf() => match &child.root {
+                NodeType::Terminal(rhs) => Some(Rule::Lexical {
+                    lhs: lhs.clone(),
+                    rhs: rhs.clone(),
+                }),
+                NodeType::NonTerminal(_) => None,
+            },
+            (NodeType::NonTerminal(lhs), children) => children
+                .iter()
+                .map(|c| match &c.root {
+                    NodeType::NonTerminal(n) => Some(n.clone()),
+                    NodeType::Terminal(_) => None,
+                })
+                .collect::<Option<Vec<_>>>()
+                .map(|rhs| Rule::NonLexical {
+                    lhs: lhs.clone(),
+                    rhs,
+                }),
+            (NodeType::Terminal(_), _) => None,
+        };
+        let children: Vec<_> = tree.children.iter().map(|c| c.root.to_string()).collect();
+        let text = if tree.children.len() == 1 && tree.children[0].is_leaf() {
+            format!("{} {}", tree.root, children[0])
+        } else {
+            format!("{} -> {}", tree.root, children.join(" "))
+        };
+
+        // The constituent is added before its descendants, its end is set afterwards.
+        let idx = constituents.len();
+        constituents.push(Constituent {
+            label: tree.root.to_string(),
+            start: *position,
+            end: *position,
+            rule: text,
+            index: rule.and_then(|r| self.get(&r)),
+        });
+        for child in &tree.children {
+            self.collect(child, position, constituents);
+        }
+        constituents[idx].end = *position;
+    }
+}
+
+impl<N, T> Default for RuleIndex<N, T>
+where
+    N: Eq + Hash + Clone + Display,
+    T: Eq + Hash + Clone + Display,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{\"constituents\": [")?;
+        for (i, c) in self.0.iter().enumerate() {
+            write!(
+                f,
+                "{}{{\"start\": {}, \"end\": {}, \"label\": \"{}\", \"rule\": \"{}\", \"index\": ",
+                if i > 0 { ", " } else { "" },
+                c.start,
+                c.end,
+                escape_json(&c.label),
+                escape_json(&c.rule)
+            )?;
+            match c.index {
+                Some(index) => write!(f, "{}}}", index)?,
+                None => write!(f, "null}}")?,
+            }
+        }
+        write!(f, "]}}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use smallstr::SmallString;
+
+    use super::*;
+    use crate::grammar::rule::ParsedWeightedRule;
+    use crate::Sentence;
+
+    #[test]
+    fn rule_provenance() {
+        let mut index = RuleIndex::new();
+        for rule in [
+            "S -> NP VP 1",
+            "NP -> DT NN 1",
+            "VP -> V 1",
+            "DT the 1",
+            "NN dog 1",
+        ] {
+            index.insert(ParsedWeightedRule::from_str(rule).unwrap().rule);
+        }
+
+        let leaf = |w: &str| Tree {
+            root: NodeType::Terminal(SmallString::from(w)),
+            children: vec![],
+        };
+        let node = |n: &str, children| Tree {
+            root: NodeType::NonTerminal(SmallString::from(n)),
+            children,
+        };
+        let tree = node(
+            "S",
+            vec![
+                node(
+                    "NP",
+                    vec![node("DT", vec![leaf("the")]), node("NN", vec![leaf("dog")])],
+                ),
+                node("VP", vec![node("V", vec![leaf("barks")])]),
+            ],
+        );
+
+        let provenance = index.provenance(&tree);
+        assert_eq!(
+            vec![
+                (0, 3, Some(0)),
+                (0, 2, Some(1)),
+                (0, 1, Some(3)),
+                (1, 2, Some(4))
+            ],
+            provenance.0[..4]
+                .iter()
+                .map(|c| (c.start, c.end, c.index))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            "{\"start\": 2, \"end\": 3, \"label\": \"V\", \"rule\": \"V barks\", \"index\": null}",
+            Provenance(provenance.0[5..].to_vec())
+                .to_string()
+                .trim_start_matches("{\"constituents\": [")
+                .trim_end_matches("]}")
+        );
+
+        let noparse = Sentence::from_str("the dog").unwrap().into_noparse();
+        assert_eq!(
+            "{\"constituents\": [{\"start\": 0, \"end\": 2, \"label\": \"NOPARSE\", \
+             \"rule\": \"NOPARSE -> the dog\", \"index\": null}]}",
+            index.provenance(&noparse).to_string()
+        );
+    }
+}
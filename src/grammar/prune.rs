@@ -0,0 +1,311 @@
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Instant;
+
+use fxhash::FxHashSet;
+
+use super::chart::SpanMask;
+use super::logprob::LogProb;
+use super::parse::{Beam, ChartEntry};
+use crate::Sentence;
+
+/// Position of a chart cell that is about to be pruned.
+pub struct SpanInfo<'a, N, T> {
+    pub start: usize,
+    pub span: usize,
+    pub sentence: &'a Sentence<T>,
+    /// Non-terminal of every entry of the cell, by index.
+    pub labels: &'a [N],
+}
+
+/// Strategy for removing unpromising entries from a chart cell after its unary closure.
+/// Entries are removed by resetting them to `ChartEntry::default()`. Entries derived with
+/// protected rules are taken out of the cell before and put back by the parser afterwards, so
+/// that pruners neither remove nor count them.
+pub trait Pruner<N, T>: Send + Sync {
+    fn prune_cell(&self, cell: &mut [ChartEntry], span_info: &SpanInfo<N, T>);
+
+    /// Removes entries from the cell of a single word before its unary closure, so that only
+    /// the preterminals of the word are seen. Returns whether the pruner prunes at this point.
+    fn prune_preterminals(&self, _cell: &mut [ChartEntry], _span_info: &SpanInfo<N, T>) -> bool {
+        false
+    }
+
+    /// The beam reported when a gold item is lost to this pruner.
+    fn beam(&self) -> Beam {
+        Beam::Custom
+    }
+
+    /// Whether parsing the sentence is given up, e.g. after a deadline. The parser then stops
+    /// filling the chart and doesn't keep the entries of protected rules either.
+    fn aborts(&self) -> bool {
+        false
+    }
+}
+
+/// Removes all entries that are smaller than the best probability
+/// in the cell multiplied by the threshold.
+pub struct ThresholdPruner(pub f64);
+
+impl<N, T> Pruner<N, T> for ThresholdPruner {
+    fn prune_cell(&self, cell: &mut [ChartEntry], _: &SpanInfo<N, T>) {
+        let m = cell.iter().max().unwrap().0;
+        let cutoff = m * LogProb::from_prob(self.0);
+
+        for chart_ele in cell.iter_mut() {
+            if chart_ele.0 < cutoff {
+                *chart_ele = Default::default();
+            }
+        }
+    }
+
+    fn beam(&self) -> Beam {
+        Beam::Threshold
+    }
+}
+
+/// Removes all entries that are smaller than the n-best entry in the cell.
+/// If n is larger than the cell, the last entry is used.
+pub struct RankPruner(pub usize);
+
+impl<N, T> Pruner<N, T> for RankPruner {
+    fn prune_cell(&self, cell: &mut [ChartEntry], _: &SpanInfo<N, T>) {
+        let n_best = {
+            let mut sorted = cell.to_vec();
+            sorted.sort_unstable();
+            sorted.reverse();
+
+            if self.0 > sorted.len() {
+                *sorted.last().unwrap()
+            } else {
+                sorted[self.0 - 1]
+            }
+        };
+
+        for chart_ele in cell.iter_mut() {
+            if *chart_ele < n_best {
+                *chart_ele = Default::default();
+            }
+        }
+    }
+
+    fn beam(&self) -> Beam {
+        Beam::Rank
+    }
+}
+
+/// Removes all entries whose non-terminal is projected onto an entry of a coarse grammar that
+/// the mask of the coarse pass doesn't allow. `projection` maps every non-terminal to its coarse
+/// non-terminal, as returned by `GrammarParse::label_projection`. Entries without coarse
+/// non-terminal are kept.
+#[derive(Clone)]
+pub struct CoarsePruner {
+    pub mask: SpanMask,
+    pub projection: Arc<Vec<Option<usize>>>,
+}
+
+impl<N, T> Pruner<N, T> for CoarsePruner {
+    fn prune_cell(&self, cell: &mut [ChartEntry], span_info: &SpanInfo<N, T>) {
+        for (a, chart_ele) in cell.iter_mut().enumerate() {
+            if let Some(Some(coarse)) = self.projection.get(a) {
+                if !self
+                    .mask
+                    .is_allowed(span_info.start, span_info.span, *coarse)
+                {
+                    *chart_ele = Default::default();
+                }
+            }
+        }
+    }
+
+    fn beam(&self) -> Beam {
+        Beam::Coarse
+    }
+}
+
+/// Removes all entries that the mask doesn't allow, e.g. those whose posterior probability is
+/// below a threshold, as computed by `GrammarParse::posterior_mask`.
+#[derive(Clone)]
+pub struct PosteriorPruner(pub SpanMask);
+
+impl<N, T> Pruner<N, T> for PosteriorPruner {
+    fn prune_cell(&self, cell: &mut [ChartEntry], span_info: &SpanInfo<N, T>) {
+        for (a, chart_ele) in cell.iter_mut().enumerate() {
+            if !self.0.is_allowed(span_info.start, span_info.span, a) {
+                *chart_ele = Default::default();
+            }
+        }
+    }
+
+    fn beam(&self) -> Beam {
+        Beam::Posterior
+    }
+}
+
+/// Removes all entries of the cells that are pruned after the given point in time, so that
+/// parsing a sentence is aborted without result once it takes too long.
+pub struct DeadlinePruner(pub Instant);
+
+impl<N, T> Pruner<N, T> for DeadlinePruner {
+    fn prune_cell(&self, cell: &mut [ChartEntry], _: &SpanInfo<N, T>) {
+        if Instant::now() >= self.0 {
+            cell.fill(Default::default());
+        }
+    }
+
+    fn beam(&self) -> Beam {
+        Beam::Deadline
+    }
+
+    fn aborts(&self) -> bool {
+        Instant::now() >= self.0
+    }
+}
+
+/// Removes the preterminals of every word that aren't among its allowed tags, e.g. those of
+/// `TagBigramModel::allowed_tags`, before the unary closure. Words without allowed tags and
+/// words whose preterminals would all be removed keep their preterminals. Cells after the
+/// closure are kept as they are.
+#[derive(Clone)]
+pub struct TagPruner<N>(pub Vec<Option<FxHashSet<N>>>);
+
+impl<N: Eq + Hash + Send + Sync, T> Pruner<N, T> for TagPruner<N> {
+    fn prune_cell(&self, _: &mut [ChartEntry], _: &SpanInfo<N, T>) {}
+
+    fn prune_preterminals(&self, cell: &mut [ChartEntry], span_info: &SpanInfo<N, T>) -> bool {
+        let allowed = match self.0.get(span_info.start) {
+            Some(Some(allowed)) => allowed,
+            _ => return true,
+        };
+        let is_pruned = |a: usize, entry: &ChartEntry| {
+            !entry.0.is_zero() && !allowed.contains(&span_info.labels[a])
+        };
+
+        if cell
+            .iter()
+            .enumerate()
+            .any(|(a, entry)| !entry.0.is_zero() && !is_pruned(a, entry))
+        {
+            for (a, chart_ele) in cell.iter_mut().enumerate() {
+                if is_pruned(a, chart_ele) {
+                    *chart_ele = Default::default();
+                }
+            }
+        }
+        true
+    }
+
+    fn beam(&self) -> Beam {
+        Beam::Tags
+    }
+}
+
+/// Pruning strategies applied to every cell of the chart, in the order they were added.
+pub struct PruneMode<N, T> {
+    pruners: Vec<Box<dyn Pruner<N, T>>>,
+}
+
+impl<N, T> PruneMode<N, T> {
+    pub fn empty() -> Self {
+        Self { pruners: vec![] }
+    }
+
+    pub fn is_prune(&self) -> bool {
+        !self.pruners.is_empty()
+    }
+
+    pub fn pruners(&self) -> &[Box<dyn Pruner<N, T>>] {
+        &self.pruners
+    }
+
+    /// Whether one of the pruners gives up parsing the sentence.
+    pub fn aborts(&self) -> bool {
+        self.pruners.iter().any(|p| p.aborts())
+    }
+
+    pub fn with_threshold(self, threshold: f64) -> Self {
+        self.with_pruner(ThresholdPruner(threshold))
+    }
+
+    pub fn with_fixed_size(self, size: usize) -> Self {
+        self.with_pruner(RankPruner(size))
+    }
+
+    pub fn with_pruner<P: Pruner<N, T> + 'static>(mut self, pruner: P) -> Self {
+        self.pruners.push(Box::new(pruner));
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn builtin_pruners() {
+        let sentence = Sentence(vec!["a".to_string()]);
+        let labels = ["A", "B", "C", "D"];
+        let info = SpanInfo {
+            start: 0,
+            span: 1,
+            sentence: &sentence,
+            labels: &labels,
+        };
+        let cell = [0.5, 0.2, 0.0, 0.05].map(|p| (LogProb::from_prob(p), None));
+
+        let mut c = cell;
+        ThresholdPruner(0.5).prune_cell(&mut c, &info);
+        assert!(!c[0].0.is_zero() && c[1..].iter().all(|e| e.0.is_zero()));
+
+        let mut c = cell;
+        RankPruner(2).prune_cell(&mut c, &info);
+        assert!(!c[0].0.is_zero() && !c[1].0.is_zero());
+        assert!(c[2].0.is_zero() && c[3].0.is_zero());
+
+        let mut c = cell;
+        RankPruner(10).prune_cell(&mut c, &info);
+        assert_eq!(c, cell);
+
+        let mut mask = SpanMask::new(1, 2);
+        mask.allow(0, 1, 1);
+        let coarse = CoarsePruner {
+            mask,
+            projection: Arc::new(vec![Some(0), Some(1), None, Some(0)]),
+        };
+        let mut c = cell;
+        coarse.prune_cell(&mut c, &info);
+        assert!(c[0].0.is_zero() && !c[1].0.is_zero() && c[3].0.is_zero());
+
+        let mut mask = SpanMask::new(1, 4);
+        mask.allow(0, 1, 3);
+        let mut c = cell;
+        PosteriorPruner(mask).prune_cell(&mut c, &info);
+        assert!(c[..3].iter().all(|e| e.0.is_zero()) && !c[3].0.is_zero());
+
+        let tags = TagPruner(vec![Some(FxHashSet::from_iter(["B"]))]);
+        let mut c = cell;
+        Pruner::<_, String>::prune_cell(&tags, &mut c, &info);
+        assert_eq!(c, cell);
+        assert!(tags.prune_preterminals(&mut c, &info));
+        assert!(c[0].0.is_zero() && !c[1].0.is_zero() && c[3].0.is_zero());
+        // Words keep their preterminals if none is allowed.
+        let mut c = cell;
+        TagPruner(vec![Some(FxHashSet::from_iter(["C"]))]).prune_preterminals(&mut c, &info);
+        assert_eq!(c, cell);
+
+        let mut c = cell;
+        DeadlinePruner(Instant::now() + Duration::from_secs(60)).prune_cell(&mut c, &info);
+        assert_eq!(c, cell);
+        DeadlinePruner(Instant::now()).prune_cell(&mut c, &info);
+        assert!(c.iter().all(|e| e.0.is_zero()));
+        assert!(PruneMode::<&str, String>::empty()
+            .with_threshold(0.5)
+            .with_pruner(DeadlinePruner(Instant::now()))
+            .aborts());
+        assert!(!PruneMode::<&str, String>::empty()
+            .with_pruner(DeadlinePruner(Instant::now() + Duration::from_secs(60)))
+            .aborts());
+    }
+}
@@ -1,3 +1,4 @@
+use std::fmt;
 use std::hash::Hash;
 use std::str::FromStr;
 
@@ -5,7 +6,7 @@ use float_ord::FloatOrd;
 use nom::branch::alt;
 use nom::bytes::complete::{is_not, tag};
 use nom::character::complete::{multispace0, multispace1};
-use nom::combinator::all_consuming;
+use nom::combinator::{all_consuming, verify};
 use nom::error::Error as NError;
 use nom::multi::many_till;
 use nom::number::complete::double;
@@ -29,8 +30,136 @@ pub struct WeightedRule<N: Eq + Hash, T: Eq + Hash, W> {
     pub weight: W,
 }
 
-type ParsedWeightedRule = WeightedRule<SmallString<[u8; 8]>, SmallString<[u8; 8]>, FloatOrd<f64>>;
+/// Chain of unary rules, listed from the top non-terminal to the bottom one.
+#[derive(PartialEq, Debug)]
+pub struct WeightedChain<N, W> {
+    pub chain: Vec<N>,
+    pub weight: W,
+}
+
+/// Scale of the weights in grammar files. Other toolkits often store the logarithms of the
+/// probabilities.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum WeightScale {
+    #[default]
+    Probability,
+    /// Natural logarithm of the probability.
+    Ln,
+    Log10,
+}
+
+impl WeightScale {
+    /// Weight of the probability `p` on this scale. A probability of 0 is `-inf` on the
+    /// logarithmic scales.
+    pub fn encode(self, p: f64) -> f64 {
+        match self {
+            WeightScale::Probability => p,
+            WeightScale::Ln => p.ln(),
+            WeightScale::Log10 => p.log10(),
+        }
+    }
+
+    /// Probability of the weight `w` on this scale.
+    pub fn decode(self, w: f64) -> f64 {
+        match self {
+            WeightScale::Probability => w,
+            WeightScale::Ln => w.exp(),
+            WeightScale::Log10 => 10f64.powf(w),
+        }
+    }
+
+    /// Natural logarithm of the probability of the weight `w` on this scale. Logarithmic
+    /// weights are only rescaled, so that log-probabilities below about -745, whose
+    /// probability underflows to 0, keep their value.
+    pub fn ln(self, w: f64) -> f64 {
+        match self {
+            WeightScale::Probability => w.ln(),
+            WeightScale::Ln => w,
+            WeightScale::Log10 => w * std::f64::consts::LN_10,
+        }
+    }
+}
+
+/// Corrections of degenerate rule weights, e.g. of hand-edited grammars, while a grammar is
+/// loaded. Counts the corrected weights, so that they can be reported.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct WeightCorrection {
+    /// Weights below this value are raised to it, including negative weights and NaN.
+    /// Weights of zero are kept.
+    pub floor: Option<FloatOrd<f64>>,
+    /// Weights above 1 are lowered to 1.
+    pub clamp: bool,
+    pub floored: usize,
+    pub clamped: usize,
+}
+
+impl WeightCorrection {
+    pub fn new(floor: Option<f64>, clamp: bool) -> Self {
+        Self {
+            floor: floor.map(FloatOrd),
+            clamp,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_corrected(&self) -> bool {
+        self.floored + self.clamped > 0
+    }
+
+    pub fn correct<N: Eq + Hash, T: Eq + Hash>(
+        &mut self,
+        weighted_rule: &mut WeightedRule<N, T, FloatOrd<f64>>,
+    ) {
+        let weight = weighted_rule.weight.0;
+        if let Some(FloatOrd(floor)) = self.floor {
+            if weight != 0.0 && (weight < floor || weight.is_nan()) {
+                weighted_rule.weight = FloatOrd(floor);
+                self.floored += 1;
+                return;
+            }
+        }
+        if self.clamp && weight > 1.0 {
+            weighted_rule.weight = FloatOrd(1.0);
+            self.clamped += 1;
+        }
+    }
+
+    /// Like `correct`, but for the natural logarithm `ln` of a weight. Returns the corrected
+    /// logarithm.
+    pub fn correct_ln(&mut self, ln: f64) -> f64 {
+        if let Some(FloatOrd(floor)) = self.floor {
+            if ln != f64::NEG_INFINITY && (ln < floor.ln() || ln.is_nan()) {
+                self.floored += 1;
+                return floor.ln();
+            }
+        }
+        if self.clamp && ln > 0.0 {
+            self.clamped += 1;
+            return 0.0;
+        }
+        ln
+    }
+}
+
+impl fmt::Display for WeightCorrection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.floor {
+            Some(FloatOrd(floor)) => write!(f, "{} weights raised to {}", self.floored, floor)?,
+            None => write!(f, "no weights raised")?,
+        }
+        if self.clamp {
+            write!(f, ", {} weights above 1 lowered to 1", self.clamped)
+        } else {
+            write!(f, ", no weights lowered")
+        }
+    }
+}
+
+pub type ParsedRule = Rule<SmallString<[u8; 8]>, SmallString<[u8; 8]>>;
+pub type ParsedWeightedRule =
+    WeightedRule<SmallString<[u8; 8]>, SmallString<[u8; 8]>, FloatOrd<f64>>;
 type NonLexicalRhs = (Vec<SmallString<[u8; 8]>>, FloatOrd<f64>);
+type ParsedWeightedChain = WeightedChain<SmallString<[u8; 8]>, FloatOrd<f64>>;
 
 impl FromStr for ParsedWeightedRule {
     type Err = NError<String>;
@@ -46,6 +175,27 @@ impl FromStr for ParsedWeightedRule {
     }
 }
 
+impl FromStr for ParsedWeightedChain {
+    type Err = NError<String>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match all_consuming(parse_chain)(s).finish() {
+            Ok((_, chain)) => Ok(chain),
+            Err(NError { input, code }) => Err(NError {
+                input: input.to_string(),
+                code,
+            }),
+        }
+    }
+}
+
+fn parse_chain(input: &str) -> IResult<&str, ParsedWeightedChain> {
+    verify(parse_rhs_nonlexical_rule, |(chain, _): &NonLexicalRhs| {
+        chain.len() >= 2
+    })(input.trim())
+    .map(|(i, (chain, weight))| (i, WeightedChain { chain, weight }))
+}
+
 fn parse_rule(input: &str) -> IResult<&str, ParsedWeightedRule> {
     alt((parse_lexical_rule, parse_nonlexical_rule))(input.trim())
 }
@@ -139,4 +289,95 @@ mod test {
         assert!(WeightedRule::from_str("-> JJ JJ 0.14285714285714285").is_err());
         assert!(WeightedRule::from_str("ADJP EXTRA -> JJ JJ 0.14285714285714285").is_err());
     }
+
+    #[test]
+    fn weight_correction() {
+        let mut correction = WeightCorrection::new(Some(1e-6), true);
+        let corrected: Vec<_> = [1e-9, 0.0, -0.5, f64::NAN, 0.5, 1.5, f64::INFINITY]
+            .into_iter()
+            .map(|w| {
+                let mut rule = WeightedRule {
+                    rule: Rule::Lexical { lhs: "A", rhs: "a" },
+                    weight: FloatOrd(w),
+                };
+                correction.correct(&mut rule);
+                rule.weight.0
+            })
+            .collect();
+        assert_eq!(vec![1e-6, 0.0, 1e-6, 1e-6, 0.5, 1.0, 1.0], corrected);
+        assert_eq!((3, 2), (correction.floored, correction.clamped));
+        assert_eq!(
+            "3 weights raised to 0.000001, 2 weights above 1 lowered to 1",
+            correction.to_string()
+        );
+
+        let mut correction = WeightCorrection::new(None, false);
+        let mut rule = WeightedRule {
+            rule: Rule::Lexical { lhs: "A", rhs: "a" },
+            weight: FloatOrd(2.0),
+        };
+        correction.correct(&mut rule);
+        assert_eq!(FloatOrd(2.0), rule.weight);
+        assert!(!correction.is_corrected());
+
+        let mut correction = WeightCorrection::new(Some(1e-6), true);
+        let corrected: Vec<_> = [
+            (1e-9f64).ln(),
+            f64::NEG_INFINITY,
+            f64::NAN,
+            0.5f64.ln(),
+            0.5,
+        ]
+        .into_iter()
+        .map(|ln| correction.correct_ln(ln))
+        .collect();
+        assert_eq!(
+            vec![
+                (1e-6f64).ln(),
+                f64::NEG_INFINITY,
+                (1e-6f64).ln(),
+                0.5f64.ln(),
+                0.0
+            ],
+            corrected
+        );
+        assert_eq!((2, 1), (correction.floored, correction.clamped));
+    }
+
+    #[test]
+    fn weight_scales() {
+        for scale in [
+            WeightScale::Probability,
+            WeightScale::Ln,
+            WeightScale::Log10,
+        ] {
+            assert!((scale.decode(scale.encode(0.25)) - 0.25).abs() < 1e-12);
+        }
+        assert!((WeightScale::Log10.encode(0.01) + 2.0).abs() < 1e-12);
+        assert_eq!(0.0, WeightScale::Ln.decode(f64::NEG_INFINITY));
+
+        for scale in [WeightScale::Ln, WeightScale::Log10] {
+            assert!((scale.ln(scale.encode(0.25)) - 0.25f64.ln()).abs() < 1e-12);
+        }
+        // Far below the smallest f64 probability.
+        assert_eq!(-2000.0, WeightScale::Ln.ln(-2000.0));
+        assert!((WeightScale::Log10.ln(-1000.0) + 1000.0 * std::f64::consts::LN_10).abs() < 1e-9);
+    }
+
+    #[test]
+    fn chain_correct() {
+        let parsed = WeightedChain::from_str("S VP V 0.25").unwrap();
+        let chain = WeightedChain {
+            chain: vec![
+                SmallString::from("S"),
+                SmallString::from("VP"),
+                SmallString::from("V"),
+            ],
+            weight: FloatOrd(0.25),
+        };
+        assert_eq!(chain, parsed);
+
+        // chains need a top and a bottom
+        assert!(WeightedChain::from_str("S 0.25").is_err());
+    }
 }
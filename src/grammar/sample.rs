@@ -0,0 +1,156 @@
+use std::fmt;
+use std::hash::Hash;
+
+use fxhash::FxHashMap;
+
+use super::rule::Rule;
+use crate::rng::XorShift;
+use crate::tree::Tree;
+
+/// Reason why no derivation was sampled.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SampleError<A> {
+    /// The derivation got deeper than the limit. Derivations of grammars that aren't tight
+    /// may never end.
+    TooDeep,
+    /// A non-terminal of the derivation has no rules with a positive weight.
+    NoRules(A),
+}
+
+impl<A: fmt::Display> fmt::Display for SampleError<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SampleError::TooDeep => write!(f, "derivation exceeds the maximal depth"),
+            SampleError::NoRules(n) => write!(f, "non-terminal {} has no rules", n),
+        }
+    }
+}
+
+/// Samples derivations of a PCFG top-down, choosing the rule of every non-terminal with the
+/// probability given by its weight.
+pub struct GrammarSampler<A: Eq + Hash> {
+    rules: FxHashMap<A, Vec<(Rule<A, A>, f64)>>,
+}
+
+impl<A: Eq + Hash> Default for GrammarSampler<A> {
+    fn default() -> Self {
+        Self {
+            rules: FxHashMap::default(),
+        }
+    }
+}
+
+impl<A: Eq + Hash + Clone> GrammarSampler<A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rules without a positive weight are never chosen.
+    pub fn insert_rule(&mut self, rule: Rule<A, A>, weight: f64) {
+        if weight.is_nan() || weight <= 0.0 {
+            return;
+        }
+        let lhs = match &rule {
+            Rule::Lexical { lhs, .. } | Rule::NonLexical { lhs, .. } => lhs.clone(),
+        };
+        self.rules.entry(lhs).or_default().push((rule, weight));
+    }
+
+    /// Samples a derivation of `initial`, with the words as leaves. A rule is chosen with its
+    /// share of the weights of all rules of its LHS, so the grammar needn't be normalised.
+    /// Derivations with more than `max_depth` levels of non-terminals are abandoned.
+    pub fn sample(
+        &self,
+        initial: &A,
+        rng: &mut XorShift,
+        max_depth: usize,
+    ) -> Result<Tree<A>, SampleError<A>> {
+        if max_depth == 0 {
+            return Err(SampleError::TooDeep);
+        }
+        let rules = self
+            .rules
+            .get(initial)
+            .ok_or_else(|| SampleError::NoRules(initial.clone()))?;
+
+        let total: f64 = rules.iter().map(|(_, w)| w).sum();
+        let mut remaining = rng.weight() * total;
+        let mut chosen = &rules[rules.len() - 1].0;
+        for (rule, weight) in rules {
+            remaining -= weight;
+            if remaining <= 0.0 {
+                chosen = rule;
+                break;
+            }
+        }
+
+        let children = match chosen {
+            Rule::Lexical { rhs, .. } => vec![Tree {
+                root: rhs.clone(),
+                children: vec![],
+            }],
+            Rule::NonLexical { rhs, .. } => rhs
+                .iter()
+                .map(|n| self.sample(n, rng, max_depth - 1))
+                .collect::<Result<_, _>>()?,
+        };
+        Ok(Tree {
+            root: initial.clone(),
+            children,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn nonlexical(lhs: &str, rhs: &[&str]) -> Rule<String, String> {
+        Rule::NonLexical {
+            lhs: lhs.to_string(),
+            rhs: rhs.iter().map(|n| n.to_string()).collect(),
+        }
+    }
+
+    fn lexical(lhs: &str, rhs: &str) -> Rule<String, String> {
+        Rule::Lexical {
+            lhs: lhs.to_string(),
+            rhs: rhs.to_string(),
+        }
+    }
+
+    #[test]
+    fn sampled_derivations() {
+        let mut sampler = GrammarSampler::new();
+        sampler.insert_rule(nonlexical("S", &["A", "B"]), 1.0);
+        sampler.insert_rule(lexical("A", "a"), 1.0);
+        sampler.insert_rule(lexical("B", "b"), 0.5);
+        sampler.insert_rule(lexical("B", "c"), 0.5);
+        sampler.insert_rule(lexical("B", "d"), 0.0);
+
+        let mut rng = XorShift::new(7);
+        let mut yields: Vec<_> = (0..50)
+            .map(|_| {
+                let tree = sampler.sample(&String::from("S"), &mut rng, 2).unwrap();
+                tree.leaves()
+                    .into_iter()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+        yields.sort();
+        yields.dedup();
+        assert_eq!(vec!["a b", "a c"], yields);
+
+        assert_eq!(
+            Err(SampleError::TooDeep),
+            sampler.sample(&String::from("S"), &mut rng, 1)
+        );
+        sampler.insert_rule(nonlexical("S", &["C"]), 1.0);
+        assert!(
+            (0..50).any(|_| sampler.sample(&String::from("S"), &mut rng, 3)
+                == Err(SampleError::NoRules(String::from("C"))))
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use std::hash::Hash;
+
+use super::logprob::LogProb;
+use super::parse::GrammarParse;
+use super::rule::Rule;
+use crate::tree::NodeType;
+use crate::Tree;
+
+type Grammar<N, T> = GrammarParse<N, T, LogProb>;
+
+/// Product of the weights of all rules in `tree`.
+/// Returns `None` if the tree uses a rule that is not in the grammar.
+pub fn tree_inside_score<N, T>(tree: &Tree<NodeType<N, T>>, grammar: &Grammar<N, T>) -> Option<f64>
+where
+    N: Eq + Hash + Clone + Sync,
+    T: Eq + Hash + Clone + AsRef<str> + Sync,
+{
+    tree_inside_scores(tree, grammar).map(|scores| scores.root)
+}
+
+/// Sum of the logarithms of the weights of all rules in `tree`, the logarithm of
+/// `tree_inside_score` without its underflow for long sentences.
+/// Returns `None` if the tree uses a rule that is not in the grammar.
+pub fn tree_log_score<N, T>(tree: &Tree<NodeType<N, T>>, grammar: &Grammar<N, T>) -> Option<f64>
+where
+    N: Eq + Hash + Clone + Sync,
+    T: Eq + Hash + Clone + AsRef<str> + Sync,
+{
+    if let NodeType::Terminal(_) = tree.root {
+        return Some(0.0);
+    }
+
+    let weight = grammar.rule_weight(&node_rule(tree)?)?;
+    tree.children
+        .iter()
+        .map(|c| tree_log_score(c, grammar))
+        .sum::<Option<f64>>()
+        .map(|children| children + weight.ln())
+}
+
+/// Inside score of every node of `tree`, i.e. the product of the weights of the rules
+/// in its subtree. Terminals have the score 1.
+/// Returns `None` if the tree uses a rule that is not in the grammar.
+pub fn tree_inside_scores<N, T>(
+    tree: &Tree<NodeType<N, T>>,
+    grammar: &Grammar<N, T>,
+) -> Option<Tree<f64>>
+where
+    N: Eq + Hash + Clone + Sync,
+    T: Eq + Hash + Clone + AsRef<str> + Sync,
+{
+    if let NodeType::Terminal(_) = tree.root {
+        return Some(Tree {
+            root: 1.0,
+            children: vec![],
+        });
+    }
+
+    let weight = grammar.rule_weight(&node_rule(tree)?)?;
+    let children = tree
+        .children
+        .iter()
+        .map(|c| tree_inside_scores(c, grammar))
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(Tree {
+        root: children.iter().map(|c| c.root).product::<f64>() * weight,
+        children,
+    })
+}
+
+/// Outside score of every node of `tree`, i.e. the product of the weights of all rules
+/// of the tree that are not in the subtree of the node. The root has the score 1.
+/// Returns `None` if the tree uses a rule that is not in the grammar.
+pub fn tree_outside_scores<N, T>(
+    tree: &Tree<NodeType<N, T>>,
+    grammar: &Grammar<N, T>,
+) -> Option<Tree<f64>>
+where
+    N: Eq + Hash + Clone + Sync,
+    T: Eq + Hash + Clone + AsRef<str> + Sync,
+{
+    let inside = tree_inside_scores(tree, grammar)?;
+    outside_scores(tree, &inside, 1.0, grammar)
+}
+
+fn outside_scores<N, T>(
+    tree: &Tree<NodeType<N, T>>,
+    inside: &Tree<f64>,
+    outside: f64,
+    grammar: &Grammar<N, T>,
+) -> Option<Tree<f64>>
+where
+    N: Eq + Hash + Clone + Sync,
+    T: Eq + Hash + Clone + AsRef<str> + Sync,
+{
+    if tree.is_leaf() {
+        return Some(Tree {
+            root: outside,
+            children: vec![],
+        });
+    }
+
+    let weight = grammar.rule_weight(&node_rule(tree)?)?;
+
+    // The siblings are multiplied instead of dividing the inside score of the parent,
+    // which would fail for weights of 0.
+    let children = tree
+        .children
+        .iter()
+        .zip(&inside.children)
+        .enumerate()
+        .map(|(i, (child, child_inside))| {
+            let siblings: f64 = inside
+                .children
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, s)| s.root)
+                .product();
+            outside_scores(child, child_inside, outside * weight * siblings, grammar)
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(Tree {
+        root: outside,
+        children,
+    })
+}
+
+/// The rule applied at the root of `tree`.
+fn node_rule<N: Eq + Hash + Clone, T: Eq + Hash + Clone>(
+    tree: &Tree<NodeType<N, T>>,
+) -> Option<Rule<N, T>> {
+    let lhs = match &tree.root {
+        NodeType::NonTerminal(n) => n.clone(),
+        NodeType::Terminal(_) => return None,
+    };
+
+    match tree.children.as_slice() {
+        [Tree {
+            root: NodeType::Terminal(t),
+            ..
+        }] => Some(Rule::Lexical {
+            lhs,
+            rhs: t.clone(),
+        }),
+        children => {
+            let rhs = children
+                .iter()
+                .map(|c| match &c.root {
+                    NodeType::NonTerminal(n) => Some(n.clone()),
+                    NodeType::Terminal(_) => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            Some(Rule::NonLexical { lhs, rhs })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::grammar::logprob::approx_eq;
+    use crate::grammar::rule::WeightedRule;
+    use float_ord::FloatOrd;
+
+    #[test]
+    fn tree_scores() {
+        let mut grammar = GrammarParse::new("S".to_string());
+        for (lhs, rhs, weight) in [("S", vec!["NP", "V"], 0.5), ("NP", vec!["N"], 0.8)] {
+            grammar
+                .insert_rule(WeightedRule {
+                    rule: Rule::NonLexical {
+                        lhs: lhs.to_string(),
+                        rhs: rhs.iter().map(|n| n.to_string()).collect(),
+                    },
+                    weight: FloatOrd(weight),
+                })
+                .unwrap();
+        }
+        for (lhs, rhs, weight) in [("N", "dogs", 0.25), ("V", "bark", 1.0)] {
+            grammar
+                .insert_rule(WeightedRule {
+                    rule: Rule::Lexical {
+                        lhs: lhs.to_string(),
+                        rhs: rhs.to_string(),
+                    },
+                    weight: FloatOrd(weight),
+                })
+                .unwrap();
+        }
+
+        let nt = |n: &str, children| Tree {
+            root: NodeType::NonTerminal(n.to_string()),
+            children,
+        };
+        let t = |t: &str| Tree {
+            root: NodeType::Terminal(t.to_string()),
+            children: vec![],
+        };
+        let tree = nt(
+            "S",
+            vec![
+                nt("NP", vec![nt("N", vec![t("dogs")])]),
+                nt("V", vec![t("bark")]),
+            ],
+        );
+
+        let inside = tree_inside_score(&tree, &grammar).unwrap();
+        assert!(approx_eq(inside, 0.5 * 0.8 * 0.25));
+
+        let outside = tree_outside_scores(&tree, &grammar).unwrap();
+        assert!(approx_eq(outside.root, 1.0));
+        // NP: S -> NP V with V -> bark.
+        assert!(approx_eq(outside.children[0].root, 0.5));
+        // N: additionally NP -> N.
+        assert!(approx_eq(outside.children[0].children[0].root, 0.5 * 0.8));
+        // V: S -> NP V with the whole NP.
+        assert!(approx_eq(outside.children[1].root, 0.5 * 0.8 * 0.25));
+
+        let unknown = nt(
+            "S",
+            vec![nt("V", vec![t("bark")]), nt("V", vec![t("bark")])],
+        );
+        assert_eq!(tree_inside_score(&unknown, &grammar), None);
+        assert_eq!(tree_log_score(&unknown, &grammar), None);
+        let log_score = tree_log_score(&tree, &grammar).unwrap();
+        assert!(approx_eq(log_score, (0.5f64 * 0.8 * 0.25).ln()));
+    }
+}
@@ -0,0 +1,222 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::hash::Hash;
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use super::bare::GrammarBare;
+use super::rule::{ParsedRule, ParsedWeightedRule, Rule};
+
+/// Rule counts that are written to a sorted run file on disk whenever more than `max_rules`
+/// distinct rules are held in memory. The runs are merged when the normalised rules are read,
+/// so that only the rules of one LHS have to be held in memory at a time.
+///
+/// Every line of a run is the LHS of a rule, followed by the rule in the grammar file format,
+/// with its count as weight, separated by a tab. The lines are sorted by LHS and rule.
+pub struct SpillingCounts<A: Eq + Hash> {
+    counts: GrammarBare<A, A, u32>,
+    max_rules: usize,
+    dir: PathBuf,
+    runs: Vec<PathBuf>,
+}
+
+impl<A: Eq + Hash + Display> SpillingCounts<A> {
+    /// The runs are written into `dir`, which has to exist.
+    pub fn new(dir: PathBuf, max_rules: usize) -> Self {
+        Self {
+            counts: GrammarBare::new(),
+            max_rules,
+            dir,
+            runs: vec![],
+        }
+    }
+
+    /// Number of runs written so far.
+    pub fn runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn absorb(&mut self, other: GrammarBare<A, A, u32>) -> io::Result<()> {
+        self.counts.absorb(other);
+        if self.counts.len() > self.max_rules {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        let mut entries: Vec<RunEntry> = self
+            .counts
+            .rules
+            .drain()
+            .map(|(rule, count)| {
+                let lhs = match &rule {
+                    Rule::Lexical { lhs, .. } | Rule::NonLexical { lhs, .. } => lhs.to_string(),
+                };
+                (lhs, rule_text(&rule), count as u64)
+            })
+            .collect();
+        entries.sort_unstable();
+
+        let path = self.dir.join(format!(
+            "pcfg_tool-{}-{}.run",
+            std::process::id(),
+            self.runs.len()
+        ));
+        let mut file = BufWriter::new(File::create(&path)?);
+        for (lhs, text, count) in entries {
+            writeln!(file, "{}\t{} {}", lhs, text, count)?;
+        }
+        file.flush()?;
+        self.runs.push(path);
+        Ok(())
+    }
+
+    /// Merges the runs and calls `f` with every rule and its count divided by the counts of all
+    /// rules with the same LHS. The rules are grouped by LHS.
+    pub fn for_each_normalised<F>(&mut self, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(&ParsedRule, f64) -> io::Result<()>,
+    {
+        if !self.counts.is_empty() {
+            self.spill()?;
+        }
+
+        let mut runs = self
+            .runs
+            .iter()
+            .map(|path| Ok(BufReader::new(File::open(path)?).lines()))
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut heap = BinaryHeap::new();
+        for (i, run) in runs.iter_mut().enumerate() {
+            if let Some(entry) = next_entry(run)? {
+                heap.push(Reverse((entry, i)));
+            }
+        }
+
+        // Counts of the rules of the current LHS, with the last rule still being summed up.
+        let mut bucket: Vec<(String, u64)> = vec![];
+        let mut bucket_lhs = String::new();
+        while let Some(Reverse(((lhs, text, count), i))) = heap.pop() {
+            if let Some(entry) = next_entry(&mut runs[i])? {
+                heap.push(Reverse((entry, i)));
+            }
+
+            if lhs != bucket_lhs {
+                write_bucket(&mut bucket, &mut f)?;
+                bucket_lhs = lhs;
+            }
+            match bucket.last_mut() {
+                Some((last, c)) if *last == text => *c += count,
+                _ => bucket.push((text, count)),
+            }
+        }
+        write_bucket(&mut bucket, &mut f)
+    }
+}
+
+impl<A: Eq + Hash> Drop for SpillingCounts<A> {
+    fn drop(&mut self) {
+        for path in &self.runs {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+type RunEntry = (String, String, u64);
+
+/// Reads the LHS, the rule without weight and the count of the next line of a run.
+fn next_entry<R: BufRead>(run: &mut Lines<R>) -> io::Result<Option<RunEntry>> {
+    let line = match run.next() {
+        Some(line) => line?,
+        None => return Ok(None),
+    };
+    let entry = line.split_once('\t').and_then(|(lhs, rule)| {
+        let (text, count) = rule.rsplit_once(' ')?;
+        Some((lhs.to_string(), text.to_string(), count.parse().ok()?))
+    });
+    match entry {
+        Some(entry) => Ok(Some(entry)),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid line in run file: {}", line),
+        )),
+    }
+}
+
+fn write_bucket<F>(bucket: &mut Vec<(String, u64)>, f: &mut F) -> io::Result<()>
+where
+    F: FnMut(&ParsedRule, f64) -> io::Result<()>,
+{
+    let total = bucket.iter().map(|(_, c)| c).sum::<u64>() as f64;
+    for (text, count) in bucket.drain(..) {
+        let rule = ParsedWeightedRule::from_str(&format!("{} {}", text, count)).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid rule in run file: {:?}", e),
+            )
+        })?;
+        f(&rule.rule, count as f64 / total)?;
+    }
+    Ok(())
+}
+
+/// A rule as written in the grammar files, without its weight.
+pub fn rule_text<N: Eq + Hash + Display, T: Eq + Hash + Display>(rule: &Rule<N, T>) -> String {
+    match rule {
+        Rule::Lexical { lhs, rhs } => format!("{} {}", lhs, rhs),
+        Rule::NonLexical { lhs, rhs } => {
+            let rhs: Vec<_> = rhs.iter().map(|n| n.to_string()).collect();
+            format!("{} -> {}", lhs, rhs.join(" "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use smallstr::SmallString;
+
+    use super::*;
+    use crate::tree::Tree;
+    use crate::SExp;
+
+    #[test]
+    fn spilled_counts() {
+        let tree = |s: &str| {
+            GrammarBare::<SmallString<[u8; 8]>, _, _>::from(
+                Tree::try_from(SExp::from_str(s).unwrap()).unwrap(),
+            )
+        };
+        let dir = std::env::temp_dir();
+        let mut counts = SpillingCounts::new(dir.clone(), 2);
+        counts.absorb(tree("(S (NP dogs) (VP bark))")).unwrap();
+        counts.absorb(tree("(S (NP cats) (VP bark))")).unwrap();
+        counts.absorb(tree("(S (NP dogs))")).unwrap();
+        assert_eq!(2, counts.runs());
+
+        let mut rules = vec![];
+        counts
+            .for_each_normalised(|rule, p| {
+                rules.push(format!("{} {}", rule_text(rule), p));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            vec![
+                "NP cats 0.3333333333333333",
+                "NP dogs 0.6666666666666666",
+                "S -> NP 0.3333333333333333",
+                "S -> NP VP 0.6666666666666666",
+                "VP bark 1",
+            ],
+            rules
+        );
+
+        let runs = counts.runs.clone();
+        drop(counts);
+        assert!(runs.iter().all(|path| !path.exists()));
+    }
+}
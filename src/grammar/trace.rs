@@ -0,0 +1,233 @@
+use std::fmt::{self, Display};
+
+use super::parse::Beam;
+use crate::json::escape_json;
+
+/// Point in the construction of a cell at which a `Frame` was recorded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrameStage {
+    /// After the lexical rules for cells of single words, otherwise after the binary rules.
+    Filled,
+    /// After the unary closure.
+    Closure,
+    /// After pruning with the given beam.
+    Pruned(Beam),
+}
+
+/// How an entry of a cell was derived.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Derivation<N> {
+    /// Lexical rule for the word at the given position.
+    Word(usize),
+    /// Binary rule, with the label, start and end of both children.
+    Binary((N, usize, usize), (N, usize, usize)),
+    /// Chain of unary rules from top to bottom. The last non-terminal is the entry of the same
+    /// cell that the chain was applied to.
+    Chain(Vec<N>),
+}
+
+/// Entry of a cell, with the logarithm of the probability of its best derivation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceEntry<N> {
+    pub label: N,
+    pub log_prob: f64,
+    pub derivation: Derivation<N>,
+}
+
+/// State of the cell covering the words from `start` to before `end` at one point of the
+/// chart construction. Only entries with a derivation are listed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame<N> {
+    pub step: usize,
+    pub start: usize,
+    pub end: usize,
+    pub stage: FrameStage,
+    pub entries: Vec<TraceEntry<N>>,
+}
+
+/// Every state of the cells of a chart in the order in which the CYK parser produced them,
+/// to show the parser step by step, e.g. in teaching tools.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChartTrace<N> {
+    pub sentence: Vec<String>,
+    pub frames: Vec<Frame<N>>,
+}
+
+impl<N: Display> ChartTrace<N> {
+    /// The frames as JSON objects, in the order in which they were recorded.
+    pub fn replay(&self) -> impl Iterator<Item = String> + '_ {
+        self.frames.iter().map(|f| f.to_string())
+    }
+
+    /// The chart after `step`: the last frame of every cell recorded up to then, ordered by
+    /// span length and start.
+    pub fn chart_at(&self, step: usize) -> Vec<&Frame<N>> {
+        let mut cells: Vec<&Frame<N>> = vec![];
+        for frame in self.frames.iter().take_while(|f| f.step <= step) {
+            match cells
+                .iter_mut()
+                .find(|c| c.start == frame.start && c.end == frame.end)
+            {
+                Some(cell) => *cell = frame,
+                None => cells.push(frame),
+            }
+        }
+        cells.sort_by_key(|c| (c.end - c.start, c.start));
+        cells
+    }
+}
+
+impl fmt::Display for FrameStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameStage::Filled => write!(f, "filled"),
+            FrameStage::Closure => write!(f, "closure"),
+            FrameStage::Pruned(beam) => write!(f, "pruned by {}", beam),
+        }
+    }
+}
+
+impl<N: Display> fmt::Display for Frame<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{{\"step\": {}, \"start\": {}, \"end\": {}, \"stage\": \"{}\", \"entries\": [",
+            self.step, self.start, self.end, self.stage
+        )?;
+        for (i, entry) in self.entries.iter().enumerate() {
+            write!(
+                f,
+                "{}{{\"label\": \"{}\", \"log_prob\": {}, ",
+                if i > 0 { ", " } else { "" },
+                escape_json(&entry.label),
+                entry.log_prob
+            )?;
+            match &entry.derivation {
+                Derivation::Word(position) => write!(f, "\"word\": {}}}", position)?,
+                Derivation::Binary(left, right) => {
+                    write!(f, "\"children\": [")?;
+                    for (j, (label, start, end)) in [left, right].into_iter().enumerate() {
+                        write!(
+                            f,
+                            "{}{{\"label\": \"{}\", \"start\": {}, \"end\": {}}}",
+                            if j > 0 { ", " } else { "" },
+                            escape_json(label),
+                            start,
+                            end
+                        )?;
+                    }
+                    write!(f, "]}}")?;
+                }
+                Derivation::Chain(chain) => {
+                    let chain: Vec<_> = chain
+                        .iter()
+                        .map(|n| format!("\"{}\"", escape_json(n)))
+                        .collect();
+                    write!(f, "\"chain\": [{}]}}", chain.join(", "))?;
+                }
+            }
+        }
+        write!(f, "]}}")
+    }
+}
+
+impl<N: Display> fmt::Display for ChartTrace<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let words: Vec<_> = self
+            .sentence
+            .iter()
+            .map(|w| format!("\"{}\"", escape_json(w)))
+            .collect();
+        write!(f, "{{\"sentence\": [{}], \"frames\": [", words.join(", "))?;
+        for (i, frame) in self.replay().enumerate() {
+            write!(f, "{}{}", if i > 0 { ", " } else { "" }, frame)?;
+        }
+        write!(f, "]}}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use float_ord::FloatOrd;
+
+    use super::*;
+    use crate::grammar::parse::GrammarParse;
+    use crate::grammar::prune::PruneMode;
+    use crate::grammar::rule::{Rule, WeightedRule};
+    use crate::Sentence;
+
+    #[test]
+    fn chart_trace() {
+        let mut grammar = GrammarParse::new("S".to_string());
+        for (lhs, rhs, weight) in [
+            ("S", vec!["NP", "VP"], 1.0),
+            ("NP", vec!["DT", "NN"], 1.0),
+            ("VP", vec!["V"], 0.5),
+        ] {
+            grammar
+                .insert_rule(WeightedRule {
+                    rule: Rule::NonLexical {
+                        lhs: lhs.to_string(),
+                        rhs: rhs.into_iter().map(String::from).collect(),
+                    },
+                    weight: FloatOrd(weight),
+                })
+                .unwrap();
+        }
+        for (lhs, rhs) in [("DT", "the"), ("NN", "dog"), ("V", "barks")] {
+            grammar
+                .insert_rule(WeightedRule {
+                    rule: Rule::Lexical {
+                        lhs: lhs.to_string(),
+                        rhs: rhs.to_string(),
+                    },
+                    weight: FloatOrd(1.0),
+                })
+                .unwrap();
+        }
+
+        let sentence = Sentence(vec![
+            "the".to_string(),
+            "dog".to_string(),
+            "barks".to_string(),
+        ]);
+        let (tree, trace) = grammar.cyk_traced(&sentence, &PruneMode::empty());
+        assert_eq!(grammar.cyk(&sentence, &PruneMode::empty()), tree);
+
+        // Every cell is recorded before and after the unary closure.
+        assert_eq!(12, trace.frames.len());
+        assert_eq!(
+            (2, 3, FrameStage::Filled),
+            (
+                trace.frames[4].start,
+                trace.frames[4].end,
+                trace.frames[4].stage
+            )
+        );
+        assert_eq!(
+            "{\"step\": 5, \"start\": 2, \"end\": 3, \"stage\": \"closure\", \"entries\": [\
+             {\"label\": \"VP\", \"log_prob\": -0.6931471805599453, \"chain\": [\"VP\", \"V\"]}, \
+             {\"label\": \"V\", \"log_prob\": 0, \"word\": 2}]}",
+            trace.replay().nth(5).unwrap()
+        );
+
+        let chart = trace.chart_at(trace.frames.len() - 1);
+        assert_eq!(6, chart.len());
+        let root = chart.last().unwrap();
+        assert_eq!(
+            (0, 3, FrameStage::Closure),
+            (root.start, root.end, root.stage)
+        );
+        assert_eq!(
+            vec![TraceEntry {
+                label: "S".to_string(),
+                log_prob: -std::f64::consts::LN_2,
+                derivation: Derivation::Binary(("NP".to_string(), 0, 2), ("VP".to_string(), 2, 3)),
+            }],
+            root.entries
+        );
+        assert!(trace.to_string().starts_with(
+            "{\"sentence\": [\"the\", \"dog\", \"barks\"], \"frames\": [{\"step\": 0,"
+        ));
+    }
+}
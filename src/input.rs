@@ -0,0 +1,123 @@
+//! Input files that are read one after the other like a single stream, e.g. the sections of a
+//! treebank.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Read};
+use std::path::PathBuf;
+
+use crate::compress;
+
+/// Reads the files in order, each decompressed if it is compressed. A file that doesn't end with
+/// a newline gets one, so that its last line isn't joined with the first line of the next file.
+/// Files are only opened when they are reached; errors name the file.
+pub struct InputFiles {
+    paths: VecDeque<PathBuf>,
+    current: Option<Box<dyn BufRead>>,
+    /// Whether everything read from the current file so far ends with a newline.
+    ends_line: bool,
+    /// The newline after a file without one is read next.
+    pending_newline: bool,
+}
+
+impl InputFiles {
+    pub fn new<I: IntoIterator<Item = PathBuf>>(paths: I) -> Self {
+        Self {
+            paths: paths.into_iter().collect(),
+            current: None,
+            ends_line: true,
+            pending_newline: false,
+        }
+    }
+}
+
+impl Read for InputFiles {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for InputFiles {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while !self.pending_newline {
+            let current = match &mut self.current {
+                Some(current) => current,
+                None => {
+                    let path = match self.paths.pop_front() {
+                        Some(path) => path,
+                        None => return Ok(&[]),
+                    };
+                    let file = compress::open(&path).map_err(|e| {
+                        io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+                    })?;
+                    self.current = Some(file);
+                    self.ends_line = true;
+                    continue;
+                }
+            };
+            if !current.fill_buf()?.is_empty() {
+                break;
+            }
+            self.current = None;
+            self.pending_newline = !self.ends_line;
+        }
+
+        if self.pending_newline {
+            return Ok(b"\n");
+        }
+        match &mut self.current {
+            Some(current) => current.fill_buf(),
+            None => Ok(&[]),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if self.pending_newline {
+            self.pending_newline = amt == 0;
+            return;
+        }
+        if let Some(current) = &mut self.current {
+            if amt > 0 {
+                // The buffer is already filled, so this can't fail.
+                if let Ok(buf) = current.fill_buf() {
+                    self.ends_line = buf.get(amt - 1) == Some(&b'\n');
+                }
+            }
+            current.consume(amt);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn files_in_order() {
+        let dir = std::env::temp_dir().join(format!("pcfg_tool_input_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let paths = [dir.join("a.mrg"), dir.join("b.mrg"), dir.join("c.mrg")];
+        fs::write(&paths[0], "(S (A a))\n(S (B b))").unwrap();
+        fs::write(&paths[1], "").unwrap();
+        fs::write(&paths[2], "(S (C c))\n").unwrap();
+
+        let lines: Vec<_> = InputFiles::new(paths.clone())
+            .lines()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(vec!["(S (A a))", "(S (B b))", "(S (C c))"], lines);
+
+        let missing = dir.join("missing.mrg");
+        let error = InputFiles::new([missing.clone()]).lines().next().unwrap();
+        assert!(error
+            .unwrap_err()
+            .to_string()
+            .contains(&missing.display().to_string()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,37 @@
+//! Strings of the JSON that is printed, e.g. by `parse --format json` and into the warnings file.
+
+use std::fmt::{Display, Write};
+
+/// Escapes a label or word for a quoted JSON string. Control characters, which can't be part
+/// of a JSON string, are written as `\uXXXX`, except for the ones with a short escape.
+pub fn escape_json<N: Display>(n: &N) -> String {
+    let s = n.to_string();
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c < '\u{20}' => {
+                write!(escaped, "\\u{:04x}", c as u32).unwrap();
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn escaped_strings() {
+        assert_eq!("dog", escape_json(&"dog"));
+        assert_eq!("\\\"a\\\\b\\\"", escape_json(&"\"a\\b\""));
+        assert_eq!("a\\tb\\r\\n", escape_json(&"a\tb\r\n"));
+        assert_eq!("\\u0000\\u001f\u{7f}é", escape_json(&"\u{0}\u{1f}\u{7f}é"));
+    }
+}
@@ -0,0 +1,121 @@
+use std::fmt;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+
+use smallstr::SmallString;
+
+use crate::tree::Tree;
+use crate::SExp;
+
+/// The most probable trees of a sentence, as printed by `parse --kbest --probabilities`.
+/// Written as one `TREE PROBABILITY` line per tree, separated by a tab and in order of
+/// decreasing probability, followed by an empty line. The probability and the tab may be
+/// left out. Sentences that couldn't be parsed have a single NOPARSE tree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KBestList<A> {
+    pub trees: Vec<(Tree<A>, Option<f64>)>,
+}
+
+impl KBestList<SmallString<[u8; 8]>> {
+    /// Reads the next list and leaves the reader at the start of the following one.
+    /// Returns `None` at the end of the reader. If a line is invalid, the rest of the list
+    /// is skipped and an error is returned.
+    pub fn from_reader<R: BufRead>(reader: &mut R) -> io::Result<Option<Self>> {
+        let mut trees = vec![];
+        let mut error = None;
+        let mut started = false;
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.trim().is_empty() {
+                // Empty lines before a list are skipped.
+                if started {
+                    break;
+                }
+                continue;
+            }
+
+            started = true;
+            match parse_entry(line) {
+                Ok(entry) => trees.push(entry),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok(started.then_some(Self { trees })),
+        }
+    }
+}
+
+type Entry = (Tree<SmallString<[u8; 8]>>, Option<f64>);
+
+fn parse_entry(line: &str) -> io::Result<Entry> {
+    let (tree, probability) = match line.split_once('\t') {
+        Some((tree, probability)) => (tree, Some(probability)),
+        None => (line, None),
+    };
+    let tree = SExp::from_str(tree).map_err(|e| invalid_data(format!("invalid tree: {:?}", e)))?;
+    let probability = probability
+        .map(|p| {
+            p.trim()
+                .parse()
+                .map_err(|_| invalid_data(format!("invalid probability: {}", p)))
+        })
+        .transpose()?;
+    let tree = Tree::try_from(tree).map_err(|e| invalid_data(format!("invalid tree: {}", e)))?;
+    Ok((tree, probability))
+}
+
+impl<A: fmt::Display> fmt::Display for KBestList<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (tree, probability) in &self.trees {
+            match probability {
+                Some(p) => writeln!(f, "{}\t{}", tree, p)?,
+                None => writeln!(f, "{}", tree)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn kbest_roundtrip() {
+        let input = "\n(S (A a) (B b))\t0.75\n(S (B a) (A b))\t0.25\n\n(NOPARSE c)\n\n";
+        let mut reader = input.as_bytes();
+
+        let first = KBestList::from_reader(&mut reader).unwrap().unwrap();
+        assert_eq!(2, first.trees.len());
+        assert_eq!(Some(0.25), first.trees[1].1);
+        assert_eq!(
+            "(S (A a) (B b))\t0.75\n(S (B a) (A b))\t0.25\n",
+            first.to_string()
+        );
+
+        let second = KBestList::from_reader(&mut reader).unwrap().unwrap();
+        assert_eq!("(NOPARSE c)\n", second.to_string());
+        assert!(KBestList::from_reader(&mut reader).unwrap().is_none());
+
+        // The invalid list is skipped as a whole.
+        let mut reader = &b"(S (A a)\t1.0\n(S (A a))\t1.0\n\n(S (A a))\tone\n"[..];
+        assert!(KBestList::from_reader(&mut reader).is_err());
+        assert!(KBestList::from_reader(&mut reader).is_err());
+        assert!(KBestList::from_reader(&mut reader).unwrap().is_none());
+    }
+}
@@ -0,0 +1,41 @@
+//! Library behind the `pcfg_tool` binary. Besides the grammar and parsing types,
+//! it exposes the tree formats used on the command line, e.g. [`SExp`] for
+//! constituent trees and [`Binarized`] for the node labels of binarised treebanks.
+
+pub mod alignment;
+pub mod annotation;
+pub mod binarized;
+pub mod cache;
+pub mod chaos;
+pub mod charmodel;
+pub mod chunk;
+pub mod compress;
+pub mod deps;
+pub mod error;
+pub mod escape;
+pub mod eval;
+pub mod forest;
+pub mod fuzz;
+pub mod grammar;
+pub mod input;
+pub mod json;
+pub mod kbest;
+pub mod pipeline;
+pub mod preprocess;
+pub mod render;
+pub mod rng;
+pub mod sentence;
+pub mod sexp;
+pub mod signature;
+pub mod suffix;
+pub mod tagdict;
+pub mod tagmodel;
+pub mod tree;
+pub mod treebank;
+pub mod unk;
+pub mod warning;
+
+pub use binarized::node::{Binarized, MarkovizedNode};
+pub use sentence::Sentence;
+pub use sexp::SExp;
+pub use tree::Tree;
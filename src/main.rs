@@ -1,51 +1,297 @@
-pub mod binarized;
-pub mod grammar;
-pub mod sentence;
-pub mod sexp;
-pub mod signature;
-pub mod tree;
-pub mod unk;
-
-use std::fs::File;
-use std::io::{self, BufRead, BufReader};
-use std::path::PathBuf;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use clap::{ArgEnum, Parser, Subcommand};
-use fxhash::FxHashMap;
+use float_ord::FloatOrd;
+use fxhash::{FxHashMap, FxHashSet, FxHasher};
 use rayon::prelude::*;
+use smallstr::SmallString;
 
-use grammar::bare::GrammarBare;
-use grammar::parse::{GrammarParse, PruneMode};
-use grammar::rule::{Rule, WeightedRule};
-use sentence::Sentence;
-use sexp::SExp;
-use tree::Tree;
+use pcfg_tool::alignment::check_alignment_with;
+use pcfg_tool::binarized::markovize::{self, Direction, MarkovParams};
+use pcfg_tool::binarized::node::LabelEquivalence;
+use pcfg_tool::cache::{grammar_hash, ParseCache};
+use pcfg_tool::chaos::{is_injected, ChaosReader, ChaosStats};
+use pcfg_tool::charmodel::CharModel;
+use pcfg_tool::chunk::{chunks, iob_tags, ChunkEvaluation, CHUNK_LABELS};
+use pcfg_tool::compress::{self, Compression};
+use pcfg_tool::deps::{dependencies, write_conll, ConllFormat, HeadRules};
+use pcfg_tool::error::Error;
+use pcfg_tool::escape::{escape_sentence, escape_tree, escape_word, unescape_word};
+use pcfg_tool::eval::{labeled_spans, tags, EvalConfig, Evaluation, NOPARSE};
+use pcfg_tool::forest::Forest;
+use pcfg_tool::fuzz::{FuzzCase, FuzzConfig};
+use pcfg_tool::grammar::bare::GrammarBare;
+use pcfg_tool::grammar::cnf::{cnf_violations, to_cnf, to_strict_cnf, CnfMapping};
+use pcfg_tool::grammar::constraint::ConstrainedRule;
+use pcfg_tool::grammar::foreign::{berkeley_to_native, json_to_native};
+use pcfg_tool::grammar::format::GrammarFormat;
+use pcfg_tool::grammar::graph::{EdgeWeight, GrammarGraph};
+use pcfg_tool::grammar::hierarchy::LabelHierarchy;
+use pcfg_tool::grammar::latent::{is_trainable, project_tree, LatentGrammar};
+use pcfg_tool::grammar::logprob::LogProb;
+use pcfg_tool::grammar::metadata::GrammarMetadata;
+use pcfg_tool::grammar::outside::{viterbi_outside, OutsideEstimate};
+use pcfg_tool::grammar::parse::{GrammarParse, SpanPosteriors, CLOSURE_HASH_KEY};
+use pcfg_tool::grammar::provenance::RuleIndex;
+use pcfg_tool::grammar::prune::{
+    CoarsePruner, DeadlinePruner, PosteriorPruner, PruneMode, TagPruner,
+};
+use pcfg_tool::grammar::rule::{
+    ParsedRule, ParsedWeightedRule, Rule, WeightCorrection, WeightScale, WeightedChain,
+    WeightedRule,
+};
+use pcfg_tool::grammar::sample::{GrammarSampler, SampleError};
+use pcfg_tool::grammar::score::{tree_inside_score, tree_log_score};
+use pcfg_tool::grammar::spill::{rule_text, SpillingCounts};
+use pcfg_tool::input::InputFiles;
+use pcfg_tool::json::escape_json;
+use pcfg_tool::kbest::KBestList;
+use pcfg_tool::pipeline::{Pipeline, SentenceUnking, Stage};
+use pcfg_tool::preprocess::preprocess;
+use pcfg_tool::render::{write_dot, write_latex};
+use pcfg_tool::rng::XorShift;
+use pcfg_tool::signature::{SignatureFeatures, SignatureScheme, UnkModel, UNK};
+use pcfg_tool::suffix::SuffixModel;
+use pcfg_tool::tagdict::TagDictionary;
+use pcfg_tool::tagmodel::TagBigramModel;
+use pcfg_tool::treebank::{
+    strip_outer_brackets, strip_outer_brackets_line, write_export, write_ptb, BracketedTrees,
+    Discontinuity, ExportError, ExportSentences, TigerXmlSentences,
+};
+use pcfg_tool::warning::WarningLog;
+use pcfg_tool::{unk, Binarized, SExp, Sentence, Tree};
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 #[clap(propagate_version = true)]
+#[clap(after_help = "EXIT CODES:\n    \
+    1     a check found problems\n    \
+    2     invalid or incompatible options\n    \
+    3     input in the wrong format\n    \
+    4     reading or writing failed\n    \
+    22    the option is not supported")]
 struct Cli {
     #[clap(subcommand)]
     command: Commands,
+    /// Seed for all randomised subcommands, so that their results can be reproduced.
+    #[clap(long, global = true, default_value_t = 0)]
+    seed: u64,
+    /// Read the input from the given files in order instead of STDIN, e.g. the sections of a
+    /// treebank. Can be given several times. Errors when opening a file name the file.
+    #[clap(long, global = true)]
+    input: Vec<PathBuf>,
+    /// Write everything that would be printed to STDOUT into the given file instead. Files
+    /// ending with `.gz` or `.zst` are compressed with gzip or zstd. Compressed input on STDIN
+    /// is always decompressed.
+    #[clap(long, global = true)]
+    output: Option<PathBuf>,
+    /// Developer check: every line read from STDIN is damaged with the given probability, or
+    /// preceded by a read error, to exercise the error handling. The damage is reported to
+    /// STDERR at the end, and the run panics if an injected error ends it.
+    #[clap(long, global = true, hide = true)]
+    chaos: Option<f64>,
+    /// Read constituent trees that are spread over several lines, as in the files of the Penn
+    /// Treebank, instead of one tree per line. Unlabeled brackets around the trees are removed.
+    #[clap(long, global = true)]
+    multiline_trees: bool,
+    /// Format of the constituent trees that are read. Sentences of export and TIGER-XML files
+    /// become trees below a node labeled `VROOT`, without edge labels and morphology. Not used
+    /// by convert-trees, which has --from.
+    #[clap(long, global = true, default_value_t = TreebankFormat::Sexp, arg_enum)]
+    treebank: TreebankFormat,
+    /// How the discontinuous constituents of export and TIGER-XML sentences are turned into
+    /// trees.
+    #[clap(long, global = true, default_value_t = DiscontinuityStrategy::Raise, arg_enum)]
+    discontinuity: DiscontinuityStrategy,
+    /// Also write the warnings about single lines of the input, e.g. trees that can't be read,
+    /// into this file, as one line of JSON per warning with its category, the number of the
+    /// line if it is known, and the message printed to STDERR as payload.
+    #[clap(long, global = true)]
+    warnings: Option<PathBuf>,
+    /// Escape words that are brackets as in the Penn Treebank, e.g. `(` as `-LRB-`, in the
+    /// trees and sentences that are read, and unescape them in the words printed by deps and
+    /// to-chunks. Round brackets are taken as words of trees where they are the only child of a
+    /// node, as in `(-LRB- ()`. Printed trees keep the escapes.
+    #[clap(long, global = true)]
+    escape_brackets: bool,
+    /// Word that replaces unknown words with unking, e.g. `<unk>` for grammars of other
+    /// toolkits.
+    #[clap(long, global = true, default_value = UNK)]
+    unk_token: String,
+    /// Start of the signatures that replace unknown words with smoothing, followed by the
+    /// features of the word, e.g. `UNK-LC-ing` for `UNK`.
+    #[clap(long, global = true, default_value = UNK)]
+    signature_prefix: String,
+}
+
+impl Cli {
+    /// Name of the input in reports and metadata, the files of --input or STDIN.
+    fn input_name(&self) -> String {
+        if self.input.is_empty() {
+            String::from("STDIN")
+        } else {
+            self.input
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+    }
+
+    fn tree_reading(&self) -> TreeReading {
+        TreeReading {
+            multiline: self.multiline_trees,
+            escape_brackets: self.escape_brackets,
+            treebank: self.treebank,
+            discontinuity: match self.discontinuity {
+                DiscontinuityStrategy::Raise => Discontinuity::Raise,
+                DiscontinuityStrategy::Split => Discontinuity::Split,
+            },
+        }
+    }
+}
+
+/// How constituent trees are read, see --multiline-trees, --escape-brackets, --treebank and
+/// --discontinuity.
+#[derive(Clone, Copy, Default)]
+struct TreeReading {
+    multiline: bool,
+    escape_brackets: bool,
+    treebank: TreebankFormat,
+    discontinuity: Discontinuity,
 }
 
 #[derive(Subcommand)]
+// Only constructed once, so the size of the parse options doesn't matter.
+#[allow(clippy::large_enum_variant)]
 enum Commands {
     /// Reads a sequence of constituent trees from STDIN and prints the induced PCFG to STDOUT.
     /// If the optional argument [GRAMMAR] is present, it is written into the files
-    /// GRAMMAR.rules, GRAMMAR.lexicon and GRAMMAR.words.
-    Induce { grammar: Option<String> },
+    /// GRAMMAR.rules, GRAMMAR.lexicon and GRAMMAR.words. The rules and the lexicon start with a
+    /// header of `#!` lines recording the creator, corpus, options and a hash of the corpus.
+    Induce {
+        grammar: Option<String>,
+        /// Words that have siblings or whose parent is also a phrase, e.g. `(NP dog)` when NP
+        /// dominates other phrases in the corpus, get a synthetic preterminal above them, labeled
+        /// with the label of their parent followed by this suffix (e.g. `-POS`). Can't be
+        /// combined with --spill-rules.
+        #[clap(long)]
+        preterminal_suffix: Option<String>,
+        /// Read sentences of `word/TAG` tokens instead of constituent trees and induce only
+        /// the lexicon. With [GRAMMAR], only GRAMMAR.lexicon and GRAMMAR.words are written.
+        #[clap(long)]
+        tagged: bool,
+        /// With --tagged, also induce the rules of a bigram model of the tags with the given
+        /// initial non-terminal (e.g. `ROOT`), so that the lexicon can be used for parsing on its
+        /// own. `~NN` derives the rest of a sentence starting with an NN, with rules like
+        /// `ROOT -> ~NN`, `~NN -> NN ~VBZ`, and `~NN -> NN` at the end of the sentence.
+        #[clap(long)]
+        tag_bigrams: Option<String>,
+        /// With --tagged, interpolate the induced lexicon with an existing lexicon, given as
+        /// `PATH:WEIGHT` with a weight below 1, and the induced one getting the rest. Tags that
+        /// only occur in one of them keep their weights. The weights of the lexicon are read in
+        /// the scale of --weights.
+        #[clap(long)]
+        merge_lexicon: Option<WeightedCorpus>,
+        /// Read the trees from the given file instead of STDIN, as `PATH:WEIGHT`, decompressed if
+        /// it is compressed with gzip or zstd. Can be given several times to interpolate the
+        /// grammars of several corpora with the given weights, which are normalised to add up
+        /// to 1. With [GRAMMAR], the corpora and their normalised weights are recorded in
+        /// GRAMMAR.mixture, otherwise they are printed to STDERR.
+        #[clap(long)]
+        corpus: Vec<WeightedCorpus>,
+        /// How trees are counted that occur several times in a corpus, e.g. in crawled
+        /// treebanks. The share of duplicates of every corpus is reported to STDERR.
+        #[clap(long, default_value_t = DuplicateTrees::Count, arg_enum)]
+        duplicates: DuplicateTrees,
+        /// Write the rule counts to a sorted file whenever more than this many different rules
+        /// are counted, and merge the files at the end, so that corpora with more rules than fit
+        /// into memory can be read. Can't be combined with --corpus.
+        #[clap(long)]
+        spill_rules: Option<usize>,
+        /// Directory for the files written with --spill-rules. Defaults to the temporary
+        /// directory of the system.
+        #[clap(long)]
+        spill_dir: Option<PathBuf>,
+        /// Estimate the tags of unknown words from the suffixes of the words that occur at most
+        /// this many times, as in TnT, and write the model into GRAMMAR.suffixes for parse with
+        /// --suffix-model. Needs [GRAMMAR] and can't be combined with --spill-rules.
+        #[clap(long)]
+        suffix_model: Option<usize>,
+        /// Add the counts of the tags of the words that occur at most this many times to the
+        /// lexical rules of the signatures of the words, so that the lexicon can parse unknown
+        /// words with --unking or --smoothing without smoothing the trees first. The words are
+        /// kept as well. Can't be combined with --spill-rules.
+        #[clap(long)]
+        unk_threshold: Option<u32>,
+        /// Signatures of the rare words with --unk-threshold. As the position of the words is
+        /// lost, they are treated as not sentence-initial.
+        #[clap(long, default_value_t = SignatureModel::Trivial, arg_enum)]
+        unk_model: SignatureModel,
+        /// Features of the signatures with --unk-threshold, separated by commas.
+        #[clap(long, default_value_t = SignatureFeatures::NAMES.join(","))]
+        signature_features: String,
+        /// Write how often every word occurs into the words file, as `WORD COUNT` separated by a
+        /// tab, with the most frequent words first. The words of all corpora of --corpus are
+        /// counted without their weights. Can't be combined with --spill-rules.
+        #[clap(long)]
+        word_counts: bool,
+        /// Like --word-counts, with the tags of every word in a third column, separated by
+        /// spaces and sorted by decreasing count.
+        #[clap(long)]
+        word_tags: bool,
+        /// Read one tree or tagged sentence per line, preceded by its weight and a tab, e.g.
+        /// `0.25<TAB>(S ...)`, and count its rules with that weight, e.g. with the expected counts
+        /// of EM or to weight domains. Can't be combined with --multiline-trees, --spill-rules,
+        /// --suffix-model, --unk-threshold or --word-counts.
+        #[clap(long)]
+        weighted: bool,
+        /// Binarise the trees as the binarise subcommand does before their rules are counted,
+        /// with --horizontal, --vertical, --direction and --markov-params.
+        #[clap(long)]
+        binarise: bool,
+        /// Horizontal markovisation parameter of --binarise.
+        #[clap(long, default_value_t = 999)]
+        horizontal: usize,
+        /// Vertical markovisation parameter of --binarise.
+        #[clap(long, default_value_t = 1)]
+        vertical: usize,
+        /// Direction of --binarise.
+        #[clap(long, default_value_t = BinarisationDirection::Right, arg_enum)]
+        direction: BinarisationDirection,
+        /// File with the markovisation parameters of label categories for --binarise, as for
+        /// the binarise subcommand.
+        #[clap(long)]
+        markov_params: Option<PathBuf>,
+        /// Scale of the weights that are written, e.g. for toolkits that store logarithms.
+        #[clap(long, default_value_t = WeightFormat::Prob, arg_enum)]
+        weights: WeightFormat,
+    },
     /// Reads a sequence of sentences from STDIN and returns the best derived parse trees to STDOUT.
-    /// RULES and LEXICON are the files that make up the used PCFG.
+    /// RULES and LEXICON are the files that make up the used PCFG. Instead, RULES can be a PCFG
+    /// compiled by the compile subcommand, which is loaded faster. LEXICON is then left out.
+    /// Grammars in the text format of the Berkeley parser or in JSON are recognised and read as
+    /// well. A JSON grammar with rules and lexicon in one file can be given as both.
     Parse {
         rules: String,
-        lexicon: String,
+        lexicon: Option<String>,
         /// Choose the parsing paradigm.
         #[clap(short, long, default_value_t=ParsingParadigma::Cyk, arg_enum)]
         paradigma: ParsingParadigma,
-        /// Set custom initial non-terminal for the given PCFG.        /// Not implemented.
-
+        /// Initial non-terminal of the PCFG, or several separated by commas (e.g. `ROOT,TOP,S`).
+        /// Every sentence is derived from the one with the most probable derivation.
         #[clap(short, long, default_value_t = String::from("ROOT"))]
         initial_nonterminal: String,
         /// Do trivial unking on supplied sentences before parsing.
@@ -54,6 +300,14 @@ enum Commands {
         /// Do smoothing on supplied sentences before parsing.
         #[clap(short, long)]
         smoothing: bool,
+        /// Signatures of the unknown words with --smoothing. The lexicon has to be induced from
+        /// trees smoothed with the same model and features.
+        #[clap(long, default_value_t = SignatureModel::Berkeley4, arg_enum)]
+        unk_model: SignatureModel,
+        /// Features of the signatures, separated by commas. Features that aren't listed are left
+        /// out, e.g. `case` for languages that capitalise all nouns.
+        #[clap(long, default_value_t = SignatureFeatures::NAMES.join(","))]
+        signature_features: String,
         /// Prune parsing data with the given threshold. Rules are only kept if their probability
         /// is not lower than the best derivation multiplied by the threshold.
         #[clap(short, long)]
@@ -62,12 +316,230 @@ enum Commands {
         /// is not lower than the n-best derivation.
         #[clap(short, long)]
         rank_beam: Option<usize>,
-        /// Not implemented.
+        /// Print the n most probable trees for every sentence, one per line and followed by an
+        /// empty line. With --probabilities, these are the k-best lists read by the rescore
+        /// subcommand.
         #[clap(short, long)]
         kbest: Option<u32>,
-        /// Not implemented.
+        /// Parse with A* instead of CYK, using the outside estimates in the given file, as
+        /// written by the outside subcommand. Pruning options are ignored.
         #[clap(short, long)]
         astar: Option<PathBuf>,
+        /// Write the parse trees into numbered chunk files in the given directory instead of
+        /// STDOUT. Chunks that already exist are skipped, so an interrupted run can be resumed.
+        #[clap(long)]
+        output_chunked: Option<PathBuf>,
+        /// Number of sentences per chunk.
+        #[clap(long, default_value_t = LINES_READ)]
+        chunk_size: usize,
+        /// File with rules in the grammar file format that are never pruned, except by
+        /// --timeout-ms. The weights are ignored. The rules have to be binarised and may only
+        /// use non-terminals of the grammar.
+        #[clap(long)]
+        protected_rules: Option<PathBuf>,
+        /// File with rules in the grammar file format that are left out of the grammar.
+        /// The weights are ignored.
+        #[clap(long)]
+        ignored_rules: Option<PathBuf>,
+        /// File with one gold tree per input sentence. For every sentence, the stage of the chart
+        /// construction at which the gold derivation was lost is reported to STDERR.
+        #[clap(long)]
+        diagnose_gold: Option<PathBuf>,
+        /// File with the precomputed unary closure of the grammar, as written by the closure
+        /// subcommand. It is used instead of computing the closure when the grammar is loaded.
+        /// Closures of other unary rules than those of the grammar, e.g. after changing RULES or
+        /// with --ignored-rules or --label-backoff, are rejected. Compiled grammars contain their
+        /// closure already.
+        #[clap(long)]
+        unary_closure: Option<PathBuf>,
+        /// Words may carry an annotation behind this separator (e.g. `word#lemma`). It is ignored
+        /// during parsing and re-attached to the terminals of the parse tree.
+        #[clap(long)]
+        annotation_separator: Option<char>,
+        /// File restricting where non-lexical rules may be applied, with one rule per line
+        /// preceded by `root`, `min-span K` or `no-punctuation`, e.g. `root ROOT -> S`. The rules
+        /// have to be binarised and may only use non-terminals of the grammar.
+        #[clap(long)]
+        constraints: Option<PathBuf>,
+        /// Print the posterior probabilities of all labeled spans instead of the best parse tree,
+        /// as `start end label posterior` lines separated by tabs. Sentences are separated by
+        /// an empty line. Pruning options are ignored.
+        #[clap(long)]
+        span_posteriors: bool,
+        /// Like --span-posteriors, but the spans of every sentence are preceded by a line with its
+        /// words. These are the forests read by the rescore subcommand.
+        #[clap(long)]
+        forest: bool,
+        /// Read all of STDIN before loading the grammar and only load lexicon entries for words
+        /// that occur in the input, plus all UNK signatures. Speeds up parsing few sentences
+        /// with a large lexicon. The model of --char-fallback still learns from all entries.
+        #[clap(long)]
+        lazy_lexicon: bool,
+        /// Read all of STDIN before parsing and report the rate of words unknown to the lexicon
+        /// for every sentence and overall to STDERR.
+        #[clap(long)]
+        oov_report: bool,
+        /// Refuse to parse without unking or smoothing if the overall rate of unknown words
+        /// is higher than this value. Reads all of STDIN before parsing.
+        #[clap(long)]
+        max_oov_rate: Option<f64>,
+        /// File with a suffix model written by induce with --suffix-model. Words that are neither
+        /// in the lexicon nor replaced by unking or smoothing get lexical rules for the tags of
+        /// the rare words of the treebank with the same suffixes.
+        #[clap(long)]
+        suffix_model: Option<PathBuf>,
+        /// Words that are neither in the lexicon nor replaced by unking or smoothing get lexical
+        /// rules for their most likely tags from a character-level model of the lexicon.
+        /// With --suffix-model, only words without any suffix in the model.
+        #[clap(long)]
+        char_fallback: bool,
+        /// Print the probability of every tree behind it, separated by a tab.
+        #[clap(long)]
+        probabilities: bool,
+        /// Output format of the parses. `json` prints one object per line with the words, the
+        /// tree, the natural logarithm of its probability and whether it is a NOPARSE. `spans`
+        /// prints the labeled spans of the phrases of every tree on one line.
+        #[clap(long, default_value_t = ParseFormat::Trees, arg_enum)]
+        format: ParseFormat,
+        /// Flush STDOUT after every this many sentences. By default, the output is only
+        /// flushed when the buffer is full and at the end.
+        #[clap(long)]
+        flush_interval: Option<usize>,
+        /// Parse every sentence as soon as it is read and flush the output right after it,
+        /// for interactive use. Ignored with --output-chunked.
+        #[clap(long)]
+        line_buffered: bool,
+        /// Directory with cached parse results. Sentences that were already parsed with the same
+        /// grammar files and options are looked up instead of parsed again.
+        /// Not used with --span-posteriors, --forest and --diagnose-gold.
+        #[clap(long)]
+        cache: Option<PathBuf>,
+        /// Print n derivations for every sentence, sampled with probabilities proportional to
+        /// their weight, one per line with their probability. Pruning options are ignored.
+        #[clap(long)]
+        sample: Option<usize>,
+        /// Sampled derivations have probabilities proportional to their weight raised to the
+        /// power of 1/TEMPERATURE, which has to be greater than 0. Higher temperatures give more
+        /// diverse samples.
+        #[clap(long, default_value_t = 1.0)]
+        temperature: f64,
+        /// Watch the given directory instead of reading STDIN. Every file NAME that appears in it
+        /// is parsed and the results are written into NAME.trees. Files should be moved into the
+        /// directory once they are complete. Hidden files and files that already have results
+        /// are skipped, so the service can be restarted. Runs until it is interrupted.
+        #[clap(long)]
+        watch: Option<PathBuf>,
+        /// Milliseconds between looking for new files in the watched directory.
+        #[clap(long, default_value_t = 1000)]
+        watch_interval: u64,
+        /// Print the non-terminals of a grammar trained by the split-merge subcommand with their
+        /// original labels, e.g. `NP` instead of `NP@3`.
+        #[clap(long)]
+        project_latent: bool,
+        /// Developer check: the given share of the sentences, chosen at random, is also parsed
+        /// without pruning and with every given beam, --tag-model, --coarse-rules and
+        /// --posterior-threshold on its own. Sentences for which pruning finds a more probable
+        /// derivation are reported to STDERR and make the run fail.
+        #[clap(long)]
+        self_check: Option<f64>,
+        /// Non-lexical rules of a coarse grammar, e.g. one induced from trees with less
+        /// markovisation. Every sentence is parsed with it first, and the CYK parser only keeps
+        /// entries whose coarse label has a posterior probability of at least --coarse-threshold
+        /// in the coarse pass. Requires --coarse-lexicon.
+        #[clap(long)]
+        coarse_rules: Option<PathBuf>,
+        #[clap(long)]
+        coarse_lexicon: Option<PathBuf>,
+        /// Horizontal markovisation of the coarse grammar, used to map labels onto it.
+        #[clap(long, default_value_t = 0)]
+        coarse_horizontal: usize,
+        /// Vertical markovisation of the coarse grammar, used to map labels onto it.
+        #[clap(long, default_value_t = 1)]
+        coarse_vertical: usize,
+        #[clap(long, default_value_t = 1e-4)]
+        coarse_threshold: f64,
+        /// Before parsing with CYK, compute the posterior probability of every chart entry with
+        /// the inside-outside algorithm and prune the entries below the given threshold. Safer
+        /// for accuracy than the beams, which only compare the Viterbi weights within a cell.
+        #[clap(long)]
+        posterior_threshold: Option<f64>,
+        /// Treebank from which a bigram model of the tag sequences is induced. Before parsing
+        /// with CYK, it prunes the preterminals of every word that are unlikely in the context
+        /// of the sentence. Should be the treebank of the grammar, so that its tags are the
+        /// preterminals of the grammar. Words that aren't in the treebank keep all preterminals.
+        /// With shift-reduce, it is the tag dictionary from which the tags of the words are
+        /// chosen.
+        #[clap(long)]
+        tag_model: Option<PathBuf>,
+        /// Preterminals are pruned if their posterior probability under --tag-model is below
+        /// the best one of the word multiplied by this threshold.
+        #[clap(long, default_value_t = 1e-3)]
+        tag_threshold: f64,
+        /// Sentences with more words are not parsed and printed as NOPARSE.
+        #[clap(long)]
+        max_length: Option<usize>,
+        /// CYK parsing of a sentence is aborted after this many milliseconds and it is printed
+        /// as NOPARSE. Parsing with --kbest, --sample, --astar or shift-reduce isn't aborted.
+        #[clap(long)]
+        timeout_ms: Option<u64>,
+        /// Non-terminals get the rules of their coarse label that they lack, e.g. `NP-SBJ -> DT
+        /// NN` for `NP -> DT NN`, with the weight multiplied by this penalty. Coarse labels are
+        /// found by removing `-` suffixes unless --label-hierarchy is given.
+        #[clap(long)]
+        label_backoff: Option<f64>,
+        /// File with the label hierarchy for --label-backoff, with one fine label and its coarse
+        /// label per line, separated by whitespace.
+        #[clap(long)]
+        label_hierarchy: Option<PathBuf>,
+        /// Raise the weights of rules below this value to it when the grammar is loaded,
+        /// including negative weights and NaN, but not weights of zero. The number of changed
+        /// weights is reported to STDERR.
+        #[clap(long)]
+        floor_weights: Option<f64>,
+        /// Lower the weights of rules above 1 to 1 when the grammar is loaded, as they appear in
+        /// hand-edited grammars. The number of changed weights is reported to STDERR.
+        #[clap(long)]
+        clamp_weights: bool,
+        /// Scale of the weights in RULES and LEXICON and in the files of --coarse-rules and
+        /// --coarse-lexicon. They are turned into probabilities before --floor-weights and
+        /// --clamp-weights.
+        #[clap(long, default_value_t = WeightFormat::Prob, arg_enum)]
+        weights: WeightFormat,
+        /// Write the grammar rule of every inner node of the printed trees into this file, as one
+        /// line of JSON per tree with the span, label and rule of every node, and the index of the
+        /// rule in RULES followed by LEXICON, counting from 0. Requires the grammar files and
+        /// can't be combined with --watch, --output-chunked, --cache, --lazy-lexicon,
+        /// --span-posteriors or --forest.
+        #[clap(long)]
+        rule_provenance: Option<PathBuf>,
+        /// Write every sentence that is printed as NOPARSE into this file, once, no matter how
+        /// often it occurs in the input.
+        #[clap(long)]
+        noparse_file: Option<PathBuf>,
+        /// Parse the sentences printed as NOPARSE again after all other sentences, without
+        /// pruning, time limit and beams, and with smoothing unless --unking is given. Every
+        /// sentence is only parsed once more. The output is held back until then, so that the
+        /// new trees are printed in the order of the input. Can't be combined with --watch,
+        /// --output-chunked, --span-posteriors, --forest, --kbest, --sample or --rule-provenance.
+        #[clap(long)]
+        retry_noparse: bool,
+    },
+    /// Reads a PCFG from RULES and LEXICON and writes it in a binary format into the file
+    /// GRAMMAR.bin, which the parse subcommand loads much faster than the text files. The unary
+    /// closure is computed and stored with it. The binary format may change between versions of
+    /// pcfg_tool.
+    Compile {
+        rules: String,
+        lexicon: String,
+        grammar: String,
+    },
+    /// Reads the non-lexical rules of a PCFG from RULES and prints the best chains of unary rules
+    /// between all non-terminals to STDOUT. If the optional argument [GRAMMAR] is present,
+    /// they are written into the file GRAMMAR.closure. The chains follow a header with a hash of
+    /// the unary rules, with which parse checks that they belong to the grammar.
+    Closure {
+        rules: String,
+        grammar: Option<String>,
     },
     /// Reads constituent trees from STDIN and returns their binarised counterparts to STDOUT.
     Binarise {
@@ -77,11 +549,30 @@ enum Commands {
         /// Set vertical markovisation parameter.
         #[clap(short, long, default_value_t = 1)]
         vertical: usize,
+        /// Direction of the binarisation, which changes which siblings the labels of the
+        /// introduced nodes are markovised with.
+        #[clap(long, default_value_t = BinarisationDirection::Right, arg_enum)]
+        direction: BinarisationDirection,
         #[clap(long)]
         help: bool,
+        /// Check that every inner node of the output has at most two children and that every
+        /// markovised label, e.g. `NP|<DT,NN>`, can be parsed back. The first invalid tree is
+        /// reported and the run fails.
+        #[clap(long)]
+        verify_binary: bool,
+        /// File with the vertical and horizontal parameters of label categories that differ
+        /// from --vertical and --horizontal, one `LABEL V H` line per category. Labels with
+        /// function tags, e.g. `NP-SBJ`, use the parameters of their category.
+        #[clap(long)]
+        markov_params: Option<PathBuf>,
     },
     /// Reads binarised constituent trees from STDIN and returns them in their original state to STDOUT.
-    Debinarise,
+    Debinarise {
+        /// Annotations of markovisation that are kept in the labels. `exact` keeps the
+        /// ancestors of vertical markovisation, e.g. `NP^<S>`.
+        #[clap(long, default_value_t = LabelComparison::Base, arg_enum)]
+        labels: LabelComparison,
+    },
     /// Reads sequence of constituent trees from STDIN and returns the derived trees via trivial unking.
     Unk {
         /// If a word occurs less often than the threshold it gets unked.
@@ -93,67 +584,883 @@ enum Commands {
         /// If a word occurs less often than the threshold it gets unked with the derived signature.
         #[clap(short, long)]
         threshold: usize,
+        /// Granularity of the signatures.
+        #[clap(long, default_value_t = SignatureModel::Berkeley4, arg_enum)]
+        unk_model: SignatureModel,
+        /// Features of the signatures, separated by commas. Features that aren't listed are left
+        /// out, e.g. `case` for languages that capitalise all nouns.
+        #[clap(long, default_value_t = SignatureFeatures::NAMES.join(","))]
+        signature_features: String,
+    },
+    /// Developer tool: parses random small grammars and sentences with CYK and cross-checks the
+    /// Viterbi scores against an exhaustive reference parser. Failing cases are printed to STDOUT.
+    FuzzGrammar {
+        /// Number of random grammars to check.
+        #[clap(long, default_value_t = 1000)]
+        iterations: usize,
+        #[clap(long, default_value_t = 4)]
+        nonterminals: usize,
+        #[clap(long, default_value_t = 3)]
+        terminals: usize,
+        /// Maximum length of the generated sentences.
+        #[clap(long, default_value_t = 6)]
+        max_length: usize,
+    },
+    /// Reports the rules in RULES that can't be used by the CYK parser, because they have more
+    /// than two symbols, no symbols or terminals on their RHS. Terminals are only recognised
+    /// if LEXICON is given. The metadata header of RULES is printed to STDERR.
+    CheckCnf {
+        rules: String,
+        lexicon: Option<String>,
+        /// Write an equivalent grammar without violations into the files GRAMMAR.rules and
+        /// GRAMMAR.lexicon.
+        #[clap(long)]
+        fix: Option<String>,
+    },
+    /// Reads a PCFG from RULES and LEXICON and writes a grammar in strict CNF, with only binary
+    /// and lexical rules, into the files GRAMMAR.rules and GRAMMAR.lexicon. Rules are binarised,
+    /// and rules with empty RHS and chain rules removed, like with check-cnf --fix. A chain rule
+    /// is folded into the rules below it with the weight of the best chain, so the new grammar
+    /// finds the same most probable trees, but isn't normalised. What undo-cnf needs to restore
+    /// the trees of the original grammar is written into GRAMMAR.cnf.
+    Cnf {
+        rules: String,
+        lexicon: String,
+        grammar: String,
     },
-    /// Not implemented.
+    /// Reads constituent trees parsed with a grammar written by cnf from STDIN and prints the
+    /// trees of the original grammar to STDOUT, with the chains of unary rules and without the
+    /// non-terminals that cnf introduced. MAPPING is the GRAMMAR.cnf file written by cnf.
+    UndoCnf { mapping: PathBuf },
+    /// Reads a sequence of constituent trees from STDIN and prints the tags of every word with
+    /// their counts and relative frequencies to STDOUT. If LEXICON is given, it is used instead
+    /// of STDIN and the rule weights take the place of the counts.
+    TagDict {
+        #[clap(long)]
+        lexicon: Option<PathBuf>,
+    },
+    /// Reads constituent trees in the format FROM from STDIN and prints them in the format TO
+    /// to STDOUT. Trees that can't be read are reported and skipped.
+    ConvertTrees {
+        #[clap(long, default_value_t = TreeFormat::Ptb, arg_enum)]
+        from: TreeFormat,
+        #[clap(long, default_value_t = TreeFormat::Sexp, arg_enum)]
+        to: TreeFormat,
+    },
+    /// Reads a sequence of constituent trees of the Penn Treebank from STDIN and prints them to
+    /// STDOUT without empty elements (`-NONE-`) and the phrases that only covered them, and
+    /// without function tags and indices in their labels, e.g. `NP` for `NP-SBJ-1` or `NP=2`.
+    Preprocess,
+    /// Reads a PCFG from RULES and LEXICON and prints the Viterbi outside weight of every
+    /// non-terminal to STDOUT, to be used as estimates for A* parsing. If the optional argument
+    /// [GRAMMAR] is present, they are written into the file GRAMMAR.outside.
     Outside {
         rules: String,
         lexicon: String,
         grammar: Option<String>,
+        /// Initial non-terminal of the PCFG, or several separated by commas (e.g. `ROOT,TOP`).
+        #[clap(short, long, default_value_t = String::from("ROOT"))]
+        initial_nonterminal: String,
+    },
+    /// Reads binarised constituent trees from STDIN and trains a PCFG whose non-terminals are
+    /// split into latent subcategories, named like `NP@3`. In every cycle, all subcategories are
+    /// split in two and trained with EM, and the splits that help least are merged back.
+    /// The PCFG is printed to STDOUT or, if the optional argument [GRAMMAR] is present, written
+    /// into the files GRAMMAR.rules, GRAMMAR.lexicon and GRAMMAR.words. Trees that are not
+    /// binarised are reported and skipped.
+    SplitMerge {
+        grammar: Option<String>,
+        /// Number of split-merge cycles. Every cycle at most doubles the subcategories.
+        #[clap(long, default_value_t = 4)]
+        cycles: usize,
+        /// Share of the splits of every cycle that is merged back.
+        #[clap(long, default_value_t = 0.5)]
+        merge_fraction: f64,
+        /// Number of iterations of expectation maximization after splitting and after merging.
+        #[clap(long, default_value_t = 20)]
+        em_iterations: usize,
+        /// Initial non-terminal of the PCFG, or several separated by commas (e.g. `ROOT,TOP`).
+        #[clap(short, long, default_value_t = String::from("ROOT"))]
+        initial_nonterminal: String,
+    },
+    /// Re-estimates the PCFG in RULES and LEXICON from the sentences read from STDIN with the
+    /// inside-outside algorithm and prints the resulting PCFG to STDOUT. If the optional argument
+    /// [GRAMMAR] is present, it is written into the files GRAMMAR.rules, GRAMMAR.lexicon and
+    /// GRAMMAR.words. The log-likelihood of the sentences is reported to STDERR after every
+    /// iteration. Non-terminals that occur in no derivation keep their rules.
+    Train {
+        rules: String,
+        lexicon: String,
+        grammar: Option<String>,
+        /// Number of iterations of expectation maximization.
+        #[clap(long, default_value_t = 10)]
+        iterations: usize,
+        /// Initial non-terminal of the PCFG, or several separated by commas (e.g. `ROOT,TOP`).
+        #[clap(short, long, default_value_t = String::from("ROOT"))]
+        initial_nonterminal: String,
+    },
+    /// Reads a PCFG from RULES and LEXICON and adds the rules of the constituent trees in GOLD
+    /// that it lacks, so that every gold tree can be derived. Every missing rule gets the
+    /// smoothing weight times its count in GOLD, and the rules of every non-terminal are
+    /// normalised again. The trees in GOLD have to be in the form of the grammar, e.g. binarised.
+    /// The PCFG is printed to STDOUT or, if the optional argument [GRAMMAR] is present, written
+    /// into the files GRAMMAR.rules, GRAMMAR.lexicon and GRAMMAR.words.
+    Augment {
+        rules: String,
+        lexicon: String,
+        gold: PathBuf,
+        grammar: Option<String>,
+        #[clap(long, default_value_t = 1e-4)]
+        smoothing: f64,
+    },
+    /// Reads the PCFGs in RULES and LEXICON and in OTHER_RULES and OTHER_LEXICON, e.g. of an
+    /// in-domain and an out-of-domain treebank, and interpolates them linearly, so that every
+    /// rule gets the weight `λ·P1 + (1−λ)·P2`. A rule that only one PCFG has counts with weight 0
+    /// in the other one. A non-terminal that only one PCFG has keeps its rules from that PCFG.
+    /// The PCFG is printed to STDOUT or, if the optional argument [GRAMMAR] is present, written
+    /// into the files GRAMMAR.rules, GRAMMAR.lexicon and GRAMMAR.words.
+    Interpolate {
+        rules: String,
+        lexicon: String,
+        other_rules: String,
+        other_lexicon: String,
+        grammar: Option<String>,
+        /// Weight λ of the first PCFG, greater than 0 and less than 1.
+        #[clap(long, default_value_t = 0.5)]
+        lambda: f64,
+    },
+    /// Compares the constituent trees in PREDICTED with the trees in GOLD, one per line, and
+    /// prints labeled precision, recall and F1, the share of exact matches and the tagging
+    /// accuracy to STDOUT. Pairs of trees that can't be read or don't have the same words are
+    /// reported and skipped. Both files have to contain the same number of lines.
+    Eval {
+        predicted: PathBuf,
+        gold: PathBuf,
+        /// Ignore words tagged as punctuation in GOLD, like the COLLINS.prm file of evalb.
+        #[clap(long)]
+        ignore_punctuation: bool,
+        /// Don't score the bracket of the root node.
+        #[clap(long)]
+        ignore_root: bool,
+        /// Annotations of markovisation that are taken into account when labels are compared,
+        /// e.g. to score trees that weren't debinarised. With `base`, `NP|<DT,NN>^<S>`, `NP^<S>`
+        /// and `NP` are the same label.
+        #[clap(long, default_value_t = LabelComparison::Exact, arg_enum)]
+        labels: LabelComparison,
+    },
+    /// Checks that the words of the i-th tree in TREES, e.g. the output of parse, are the words of
+    /// the i-th sentence in SENTENCES. Words replaced by unking or smoothing are accepted. Every
+    /// mismatch is printed to STDOUT and makes the run fail.
+    CheckAlignment { sentences: PathBuf, trees: PathBuf },
+    /// Reads a sequence of constituent trees from STDIN and prints their base phrases as IOB
+    /// tags to STDOUT, one `WORD TAG IOB` line per word, separated by tabs, and an empty line
+    /// after every sentence. With --gold, the chunks are scored against those of the trees in
+    /// the given file instead.
+    ToChunks {
+        /// Labels of the phrases that are chunked, separated by commas.
+        #[clap(long, default_value_t = CHUNK_LABELS.join(","))]
+        labels: String,
+        #[clap(long)]
+        gold: Option<PathBuf>,
+    },
+    /// Reads a sequence of constituent trees from STDIN and prints their dependency trees to
+    /// STDOUT, one row per word and an empty line after every sentence. The head of every phrase
+    /// is chosen with the head rules of Collins. The relation of a word is the label of the
+    /// largest phrase it heads, or `root`.
+    Deps {
+        #[clap(long, default_value_t = DependencyFormat::Conllu, arg_enum)]
+        format: DependencyFormat,
+        /// File with head rules that replace the built-in rules of their labels, one
+        /// `LABEL SEARCH CATEGORY...` line per rule, e.g. `VP left VBD VBZ VP`. SEARCH is `left`
+        /// or `right` to try the categories in turn, or `leftdis` or `rightdis` to take the
+        /// first child of any of them. The rules of a label are tried in order.
+        #[clap(long)]
+        head_rules: Option<PathBuf>,
+    },
+    /// Reads a sequence of constituent trees from STDIN and prints their words to STDOUT, one
+    /// sentence per line, e.g. as input for parse.
+    Yield {
+        /// Print `word/TAG` tokens with the preterminals of the words as tags, as read by induce
+        /// with --tagged. Words without preterminal are printed without tag.
+        #[clap(long)]
+        tags: bool,
+    },
+    /// Reads a sequence of constituent trees from STDIN and renders every tree as a figure, as a
+    /// standalone LaTeX document drawn with tikz-qtree or as a graph in the DOT language of
+    /// Graphviz. The figures are printed to STDOUT one after the other or, with --directory,
+    /// written into one file per tree, named by its position, e.g. `000000.tex`.
+    Render {
+        #[clap(long, default_value_t = RenderFormat::Latex, arg_enum)]
+        format: RenderFormat,
+        #[clap(long)]
+        directory: Option<PathBuf>,
+    },
+    /// Reads a sequence of constituent trees from STDIN and prints them to STDOUT drawn over
+    /// several lines, one line per node with lines to its children below it, and an empty line
+    /// after every tree. Preterminals are printed on the line of their word.
+    Pretty {
+        /// Draw the lines with ASCII characters instead of box-drawing characters.
+        #[clap(long)]
+        ascii: bool,
+    },
+    /// Reads a PCFG from RULES and the optional LEXICON and prints a graph of the dependencies
+    /// between its non-terminals to STDOUT, with an edge from A to B for the rules with A on
+    /// the LHS and B on the RHS. Non-terminals that can't be reached from the initial
+    /// non-terminal are marked. The lexicon only adds the number of lexical rules in JSON.
+    GraphGrammar {
+        rules: String,
+        lexicon: Option<String>,
+        #[clap(long, default_value_t = GraphFormat::Dot, arg_enum)]
+        format: GraphFormat,
+        #[clap(long, default_value_t = GraphWeight::Probability, arg_enum)]
+        weight: GraphWeight,
+        /// Leave out edges with a lower weight.
+        #[clap(long, default_value_t = 0.0)]
+        threshold: f64,
+        /// Initial non-terminal of the PCFG, or several separated by commas (e.g. `ROOT,TOP`).
+        #[clap(short, long, default_value_t = String::from("ROOT"))]
+        initial_nonterminal: String,
+    },
+    /// Reads k-best lists, as printed by parse with --kbest and --probabilities, from STDIN and
+    /// rescores them with the PCFG in RULES and LEXICON. Every list is printed in the same format,
+    /// ordered by the probabilities of the PCFG and without the trees it can't derive. Lists and
+    /// forests that can't be read or derived are reported and printed as NOPARSE, so that there
+    /// is one result for every input.
+    Rescore {
+        rules: String,
+        lexicon: String,
+        /// Replace unknown words with --unk-token before scoring, as parse does.
+        #[clap(short, long)]
+        unking: bool,
+        /// Replace unknown words with their signatures before scoring, as parse does.
+        #[clap(short, long)]
+        smoothing: bool,
+        /// Signatures of the unknown words with --smoothing.
+        #[clap(long, default_value_t = SignatureModel::Berkeley4, arg_enum)]
+        unk_model: SignatureModel,
+        /// Features of the signatures, separated by commas.
+        #[clap(long, default_value_t = SignatureFeatures::NAMES.join(","))]
+        signature_features: String,
+        /// Read forests, as printed by parse with --forest, instead and print the most probable
+        /// tree of the PCFG that only uses the spans of the forest.
+        #[clap(long)]
+        forest: bool,
+        /// Only use the spans of the forests with at least this posterior probability.
+        #[clap(long, default_value_t = 0.0)]
+        threshold: f64,
+        /// Initial non-terminal of the PCFG, or several separated by commas (e.g. `ROOT,TOP`).
         #[clap(short, long, default_value_t = String::from("ROOT"))]
         initial_nonterminal: String,
     },
+    /// Reads a sequence of sentences from STDIN and prints the natural logarithm of the
+    /// probability of every sentence under the PCFG in RULES and LEXICON to STDOUT, summed over
+    /// all its derivations, or `-inf` if it can't be derived. The perplexity per word of the
+    /// sentences that can be derived is printed to STDERR.
+    Perplexity {
+        rules: String,
+        lexicon: String,
+        /// Initial non-terminal of the PCFG, or several separated by commas (e.g. `ROOT,TOP`).
+        #[clap(short, long, default_value_t = String::from("ROOT"))]
+        initial_nonterminal: String,
+        /// Do trivial unking on supplied sentences before scoring.
+        #[clap(short, long)]
+        unking: bool,
+        /// Do smoothing on supplied sentences before scoring.
+        #[clap(short, long)]
+        smoothing: bool,
+        /// Signatures of the unknown words with --smoothing, as in parse.
+        #[clap(long, default_value_t = SignatureModel::Berkeley4, arg_enum)]
+        unk_model: SignatureModel,
+        /// Features of the signatures, separated by commas.
+        #[clap(long, default_value_t = SignatureFeatures::NAMES.join(","))]
+        signature_features: String,
+    },
+    /// Reads a PCFG from RULES and LEXICON and prints derivations sampled from it to STDOUT, one
+    /// tree per line. The rule of every non-terminal is chosen with its probability, starting
+    /// with the initial non-terminal. The derivations only depend on --seed.
+    Generate {
+        rules: String,
+        lexicon: String,
+        /// Number of derivations.
+        #[clap(short, long, default_value_t = 10)]
+        number: usize,
+        /// Print only the words of the derivations, separated by spaces.
+        #[clap(long)]
+        yields: bool,
+        /// Derivations with more levels of non-terminals are discarded and sampled again, as the
+        /// derivations of a grammar that isn't tight may never end.
+        #[clap(long, default_value_t = 100)]
+        max_depth: usize,
+        #[clap(short, long, default_value_t = String::from("ROOT"))]
+        initial_nonterminal: String,
+    },
+    /// Runs several subcommands in one process, e.g.
+    /// `binarise v=2 | induce GRAMMAR` or `unk | parse RULES LEXICON | debinarise | eval GOLD`.
+    /// The trees are handed from one stage to the next without intermediate files. The first
+    /// stage reads constituent trees from STDIN, or sentences if it is parse. The trees of the
+    /// last stage are printed to STDOUT, unless it is induce or eval, which print like the
+    /// subcommands of the same name. unk and smooth take a threshold `t=N` on treebanks; before
+    /// parse they replace the unknown words of the sentences instead. parse takes the initial
+    /// non-terminals as `i=ROOT`. preprocess (or strip-functions) removes empty elements and
+    /// function tags. induce with GRAMMAR also writes GRAMMAR.pipeline, a header recording the
+    /// stages with all their options, so that the grammar can be induced again.
+    Pipeline { spec: String },
+}
+
+/// Number of tags a word gets from the character-level fallback.
+const CHAR_FALLBACK_TAGS: usize = 5;
+
+/// Number of sentences that are read and parsed in one batch.
+const LINES_READ: usize = 128;
+
+/// Number of sentences that are parsed or wait for earlier sentences at the same time when
+/// parsing a stream.
+const SENTENCES_IN_FLIGHT: usize = 1024;
+
+/// Damage done to STDIN with --chaos.
+static CHAOS_STATS: ChaosStats = ChaosStats::new();
+
+/// Warnings about lines of the input, written into the file given with --warnings.
+static WARNINGS: WarningLog = WarningLog::new();
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Eq)]
+enum DuplicateTrees {
+    /// Every occurrence is counted.
+    Count,
+    /// Trees that are identical up to whitespace are only counted once.
+    Once,
+}
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Eq)]
+enum GraphFormat {
+    /// The DOT language of Graphviz.
+    Dot,
+    Json,
+}
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Eq)]
+enum GraphWeight {
+    /// Sum of the weights of the rules of an edge.
+    Probability,
+    /// Number of rules of an edge.
+    Rules,
+}
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Eq)]
+enum TreeFormat {
+    /// Single-line S-expressions, as used by all other subcommands.
+    Sexp,
+    /// Bracketed trees over several lines, possibly with unlabeled brackets around them.
+    Ptb,
+    /// Negra/Tiger export format.
+    Export,
+}
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Eq, Debug)]
+enum ParseFormat {
+    /// S-expressions, one per line.
+    Trees,
+    /// One JSON object per line, e.g. `{"tokens": ["a"], "tree": {"label": "ROOT", "children":
+    /// ["a"]}, "log_prob": -0.69, "noparse": false}`.
+    Json,
+    /// The phrases of every tree as `LABEL START END` spans in preorder, separated by tabs, e.g.
+    /// `S 0 5\tNP 0 2\tVP 2 5`. Words are counted from 0 and preterminals are left out. A
+    /// sentence that can't be parsed is `NOPARSE 0 N` for its N words.
+    Spans,
+}
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Eq)]
+enum RenderFormat {
+    /// A LaTeX document with a `\Tree` of tikz-qtree.
+    Latex,
+    /// The DOT language of Graphviz.
+    Dot,
+}
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Eq, Default)]
+enum TreebankFormat {
+    /// One S-expression per line, or over several lines with --multiline-trees.
+    #[default]
+    Sexp,
+    /// Negra/Tiger export format, versions 3 and 4.
+    Export,
+    /// TIGER-XML, with a tree for every `<s>` element.
+    TigerXml,
+}
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Eq)]
+enum DiscontinuityStrategy {
+    /// Move the children that are not adjacent to the head of a constituent up to its parent.
+    Raise,
+    /// Turn every contiguous part of a constituent into a constituent with the same label.
+    Split,
+}
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Eq, Debug)]
+enum WeightFormat {
+    /// Probabilities.
+    Prob,
+    /// Natural logarithms of the probabilities.
+    Logprob,
+    /// Logarithms of the probabilities to base 10.
+    Log10prob,
+}
+
+impl WeightFormat {
+    fn scale(self) -> WeightScale {
+        match self {
+            WeightFormat::Prob => WeightScale::Probability,
+            WeightFormat::Logprob => WeightScale::Ln,
+            WeightFormat::Log10prob => WeightScale::Log10,
+        }
+    }
+}
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Eq)]
+enum DependencyFormat {
+    /// CoNLL-U, with the tags as language-specific part-of-speech tags.
+    Conllu,
+    /// CoNLL-X, with the tags as coarse and fine part-of-speech tags.
+    Conllx,
+}
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Eq)]
+enum LabelComparison {
+    /// Labels are only equal if all their annotations are.
+    Exact,
+    /// Ancestors of vertical markovisation are ignored.
+    IgnoreAncestors,
+    /// Only the labels without annotations are compared.
+    Base,
+}
+
+impl LabelComparison {
+    fn equivalence(self) -> LabelEquivalence {
+        match self {
+            LabelComparison::Exact => LabelEquivalence::Exact,
+            LabelComparison::IgnoreAncestors => LabelEquivalence::IgnoreAncestors,
+            LabelComparison::Base => LabelEquivalence::Base,
+        }
+    }
+}
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Eq, Debug)]
+enum SignatureModel {
+    /// Every unknown word is `UNK`.
+    Trivial,
+    /// Only the capitalisation and the digits of the word.
+    Basic,
+    /// Capitalisation, digits, dashes, periods, commas, the last letter and the script.
+    Berkeley4,
+    /// Capitalisation, digits, dashes and common English suffixes, e.g. `-ing`.
+    Berkeley5,
+    /// Like berkeley4, with the dashes, full stops and commas of all scripts.
+    Unicode,
+}
+
+impl SignatureModel {
+    fn model(self) -> UnkModel {
+        match self {
+            SignatureModel::Trivial => UnkModel::Trivial,
+            SignatureModel::Basic => UnkModel::Basic,
+            SignatureModel::Berkeley4 => UnkModel::Berkeley4,
+            SignatureModel::Berkeley5 => UnkModel::Berkeley5,
+            SignatureModel::Unicode => UnkModel::Unicode,
+        }
+    }
+
+    /// The model with the features of --signature-features and the prefix of
+    /// --signature-prefix.
+    fn scheme(self, features: &str, prefix: &str) -> Result<SignatureScheme, Error> {
+        Ok(SignatureScheme {
+            model: self.model(),
+            features: features.parse().map_err(Error::Usage)?,
+            prefix: prefix.to_string(),
+        })
+    }
 }
 
 #[derive(ArgEnum, Copy, Clone, PartialEq, Eq)]
+enum BinarisationDirection {
+    /// Splits off the leftmost child and groups the remaining children to the right.
+    Right,
+    /// Splits off the rightmost child and groups the remaining children to the left.
+    Left,
+}
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Eq, Debug)]
 enum ParsingParadigma {
     Cyk,
     Deductive,
+    /// Greedy shift-reduce parsing with unary reductions in linear time, without optimality
+    /// guarantee.
+    ShiftReduce,
 }
 
-fn main() -> io::Result<()> {
+fn main() {
     let cli = Cli::parse();
 
-    match &cli.command {
-        Commands::Induce { grammar } => {
-            let stdin = io::stdin();
-            let handle = stdin.lock();
+    let result = match &cli.warnings {
+        Some(path) => File::create(path).map_err(Error::Io).and_then(|file| {
+            WARNINGS.open(Box::new(BufWriter::new(file)));
+            run(&cli)
+        }),
+        None => run(&cli),
+    };
+    // Warnings of a failed run are written as well.
+    let flushed = WARNINGS.flush().map_err(Error::Io);
+    let result = result.and_then(|()| flushed);
+    if cli.chaos.is_some() {
+        eprintln!(
+            "Chaos: {} of {} lines were corrupted and {} read errors were injected.",
+            CHAOS_STATS.corrupted.load(Ordering::Relaxed),
+            CHAOS_STATS.lines.load(Ordering::Relaxed),
+            CHAOS_STATS.failed.load(Ordering::Relaxed)
+        );
+        // Damaged lines have to be reported and skipped by the subcommands.
+        if let Err(Error::Io(e)) = &result {
+            assert!(!is_injected(e), "an injected read error ended the run");
+        }
+    }
 
-            let grammar_absolute = handle
-                .lines()
-                .filter_map(|l| {
-                    if l.is_err() {
-                        eprintln!("Error when reading line: {:?}", l);
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code())
+    }
+}
+
+/// Runs the subcommand. An error is printed by `main`, which exits with the code of its kind.
+fn run(cli: &Cli) -> Result<(), Error> {
+    match &cli.command {
+        Commands::Induce {
+            grammar,
+            preterminal_suffix,
+            tagged,
+            tag_bigrams,
+            merge_lexicon,
+            corpus,
+            duplicates,
+            spill_rules,
+            spill_dir,
+            suffix_model,
+            unk_threshold,
+            unk_model,
+            signature_features,
+            word_counts,
+            word_tags,
+            weighted,
+            binarise,
+            horizontal,
+            vertical,
+            direction,
+            markov_params,
+            weights,
+            ..
+        } => {
+            if suffix_model.is_some() && (grammar.is_none() || spill_rules.is_some()) {
+                return Err(Error::Usage(String::from(
+                    "--suffix-model needs [GRAMMAR] and can't be combined with --spill-rules",
+                )));
+            }
+            if !corpus.is_empty() && !cli.input.is_empty() {
+                return Err(Error::Usage(String::from(
+                    "--corpus can't be combined with --input, give the files as --corpus PATH:1",
+                )));
+            }
+            if preterminal_suffix.is_some() && spill_rules.is_some() {
+                return Err(Error::Usage(String::from(
+                    "--preterminal-suffix can't be combined with --spill-rules",
+                )));
+            }
+            if unk_threshold.is_some() && spill_rules.is_some() {
+                return Err(Error::Usage(String::from(
+                    "--unk-threshold can't be combined with --spill-rules",
+                )));
+            }
+            let word_counts = *word_counts || *word_tags;
+            if word_counts && spill_rules.is_some() {
+                return Err(Error::Usage(String::from(
+                    "--word-counts and --word-tags can't be combined with --spill-rules",
+                )));
+            }
+            if *weighted
+                && (cli.multiline_trees
+                    || cli.treebank != TreebankFormat::Sexp
+                    || spill_rules.is_some()
+                    || suffix_model.is_some()
+                    || unk_threshold.is_some()
+                    || word_counts)
+            {
+                return Err(Error::Usage(String::from(
+                    "--weighted can't be combined with --multiline-trees, --treebank, \
+                     --spill-rules, --suffix-model, --unk-threshold or --word-counts",
+                )));
+            }
+            if !*tagged && (tag_bigrams.is_some() || merge_lexicon.is_some()) {
+                return Err(Error::Usage(String::from(
+                    "--tag-bigrams and --merge-lexicon need --tagged",
+                )));
+            }
+            if merge_lexicon.is_some() && spill_rules.is_some() {
+                return Err(Error::Usage(String::from(
+                    "--merge-lexicon can't be combined with --spill-rules",
+                )));
+            }
+            if merge_lexicon.as_ref().map_or(false, |m| m.weight >= 1.0) {
+                return Err(Error::Usage(String::from(
+                    "The weight of --merge-lexicon has to be below 1",
+                )));
+            }
+            if *binarise && *tagged {
+                return Err(Error::Usage(String::from(
+                    "--binarise needs trees and can't be combined with --tagged",
+                )));
+            }
+            let binarisation = if *binarise {
+                Some(read_markov_params(
+                    *horizontal,
+                    *vertical,
+                    *direction,
+                    markov_params.as_deref(),
+                )?)
+            } else {
+                None
+            };
+            let signatures = unk_model.scheme(signature_features, &cli.signature_prefix)?;
+            let add_unknown_words = |counts: &mut Counts| {
+                if let Some(threshold) = unk_threshold {
+                    counts.add_unknown_words(*threshold, |word| {
+                        SmallString::from(signatures.signature(word, 1).as_str())
+                    });
+                }
+            };
+            let mut hasher = FxHasher::default();
+            let mut spilled = None;
+            let mut dictionary = TagDictionary::new();
+            let grammar_normalised: GrammarBare<_, _, f64> = if let Some(max_rules) = spill_rules {
+                if !corpus.is_empty() {
+                    return Err(Error::Usage(String::from(
+                        "--spill-rules can't be combined with --corpus, as the grammars are \
+                         interpolated in memory",
+                    )));
+                }
+                let dir = spill_dir.clone().unwrap_or_else(std::env::temp_dir);
+                let mut counts = SpillingCounts::new(dir, *max_rules);
+                let handle = input_handle(cli);
+                let mut filter = DuplicateFilter::new(*duplicates);
+                induce_counts(
+                    handle,
+                    *tagged,
+                    tag_bigrams.as_deref(),
+                    false,
+                    cli.tree_reading(),
+                    preterminal_suffix.as_deref(),
+                    binarisation.as_ref(),
+                    &mut hasher,
+                    &mut filter,
+                    |g, _| counts.absorb(g),
+                )?;
+                filter.report(&cli.input_name());
+                spilled = Some(counts);
+                GrammarBare::default()
+            } else if corpus.is_empty() {
+                let handle = input_handle(cli);
+                let mut filter = DuplicateFilter::new(*duplicates);
+                let mut counts = GrammarBare::default();
+                let mut fractional = GrammarBare::default();
+                induce_counts(
+                    handle,
+                    *tagged,
+                    tag_bigrams.as_deref(),
+                    *weighted,
+                    cli.tree_reading(),
+                    preterminal_suffix.as_deref(),
+                    binarisation.as_ref(),
+                    &mut hasher,
+                    &mut filter,
+                    |g, weight| {
+                        if *weighted {
+                            fractional.absorb_weighted(g, weight);
+                        } else {
+                            counts.absorb(g);
+                        }
+                        Ok(())
+                    },
+                )?;
+                filter.report(&cli.input_name());
+                if let Some(suffix) = preterminal_suffix {
+                    counts.split_preterminals(suffix);
+                    fractional.split_preterminals(suffix);
+                }
+                if *weighted {
+                    fractional.normalised()
+                } else {
+                    if suffix_model.is_some() || word_counts {
+                        insert_lexical_counts(&mut dictionary, &counts);
                     }
-                    l.ok()
-                })
-                .map(|l| SExp::from_str(&l))
-                .filter_map(|s| {
-                    if s.is_err() {
-                        eprintln!("Error when parsing SExp: {:?}", s);
+                    add_unknown_words(&mut counts);
+                    GrammarBare::from(counts)
+                }
+            } else {
+                let total: f64 = corpus.iter().map(|c| c.weight).sum();
+                let mut grammars = vec![];
+                for c in corpus {
+                    let reader = compress::open(&c.path)?;
+                    let mut filter = DuplicateFilter::new(*duplicates);
+                    let mut counts = GrammarBare::default();
+                    let mut fractional = GrammarBare::default();
+                    induce_counts(
+                        reader,
+                        *tagged,
+                        tag_bigrams.as_deref(),
+                        *weighted,
+                        cli.tree_reading(),
+                        preterminal_suffix.as_deref(),
+                        binarisation.as_ref(),
+                        &mut hasher,
+                        &mut filter,
+                        |g, weight| {
+                            if *weighted {
+                                fractional.absorb_weighted(g, weight);
+                            } else {
+                                counts.absorb(g);
+                            }
+                            Ok(())
+                        },
+                    )?;
+                    filter.report(&c.path.display().to_string());
+                    if let Some(suffix) = preterminal_suffix {
+                        counts.split_preterminals(suffix);
+                        fractional.split_preterminals(suffix);
                     }
-                    s.ok()
-                })
-                .map(Tree::from)
-                .map(GrammarBare::from)
-                .fold(GrammarBare::default(), |acc, x| acc.merge(x));
+                    let grammar = if *weighted {
+                        fractional.normalised()
+                    } else {
+                        if suffix_model.is_some() || word_counts {
+                            insert_lexical_counts(&mut dictionary, &counts);
+                        }
+                        add_unknown_words(&mut counts);
+                        GrammarBare::from(counts)
+                    };
+                    grammars.push((grammar, c.weight / total));
+                }
+                GrammarBare::interpolate(grammars)
+            };
+            let grammar_normalised = match merge_lexicon {
+                Some(lexicon) => {
+                    let mut existing = GrammarBare::new();
+                    for r in read_weighted_rules(&lexicon.path, true, |_| true)? {
+                        existing
+                            .rules
+                            .insert(r.rule, weights.scale().decode(r.weight.0));
+                    }
+                    GrammarBare::interpolate([
+                        (grammar_normalised, 1.0 - lexicon.weight),
+                        (existing, lexicon.weight),
+                    ])
+                }
+                None => grammar_normalised,
+            };
+            // Without the rules of a bigram model, only the lexicon is written.
+            let lexicon_only = *tagged && tag_bigrams.is_none();
 
-            let grammar_normalised: GrammarBare<_, _, f64> = GrammarBare::from(grammar_absolute);
+            let metadata = GrammarMetadata::default()
+                .with(
+                    "creator",
+                    format!("pcfg_tool {}", env!("CARGO_PKG_VERSION")),
+                )
+                .with(
+                    "corpus",
+                    if corpus.is_empty() {
+                        cli.input_name()
+                    } else {
+                        corpus
+                            .iter()
+                            .map(|c| format!("{}:{}", c.path.display(), c.weight))
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    },
+                )
+                .with(
+                    "options",
+                    format!(
+                        "tagged={} tag-bigrams={} merge-lexicon={} weighted={} preterminal-suffix={} \
+                         binarise={} duplicates={} unk-threshold={} weights={}",
+                        tagged,
+                        tag_bigrams.as_deref().unwrap_or("none"),
+                        merge_lexicon.as_ref().map_or(String::from("none"), |m| format!(
+                            "{}:{}",
+                            m.path.display(),
+                            m.weight
+                        )),
+                        weighted,
+                        preterminal_suffix.as_deref().unwrap_or("none"),
+                        if *binarise {
+                            format!(
+                                "h={},v={},{}{}",
+                                horizontal,
+                                vertical,
+                                match direction {
+                                    BinarisationDirection::Right => "right",
+                                    BinarisationDirection::Left => "left",
+                                },
+                                markov_params
+                                    .as_ref()
+                                    .map_or(String::new(), |p| format!(",{}", p.display()))
+                            )
+                        } else {
+                            String::from("none")
+                        },
+                        match duplicates {
+                            DuplicateTrees::Count => "count",
+                            DuplicateTrees::Once => "once",
+                        },
+                        unk_threshold.map_or(String::from("none"), |t| t.to_string()),
+                        match weights {
+                            WeightFormat::Prob => "prob",
+                            WeightFormat::Logprob => "logprob",
+                            WeightFormat::Log10prob => "log10prob",
+                        }
+                    ),
+                )
+                .with("hash", format!("{:016x}", hasher.finish()));
 
             // Write to files if grammar name was chosen, otherwise print to STDOUT.
-            if let Some(grammar_name) = grammar {
-                let mut rules_file = File::create(format!("{}.rules", grammar_name))?;
-                grammar_normalised.write_non_lexical_rules(&mut rules_file)?;
-                let mut lexicon_file = File::create(format!("{}.lexicon", grammar_name))?;
-                grammar_normalised.write_lexical_rules(&mut lexicon_file)?;
-                let mut words_file = File::create(format!("{}.words", grammar_name))?;
-                grammar_normalised.write_terminals(&mut words_file)?;
+            if let Some(counts) = &mut spilled {
+                write_spilled_grammar(
+                    counts,
+                    grammar.as_deref(),
+                    cli.output.as_deref(),
+                    lexicon_only,
+                    &metadata,
+                    weights.scale(),
+                )?;
             } else {
-                let stdout = io::stdout();
-                let mut out_handle = stdout.lock();
+                write_grammar(
+                    &grammar_normalised.rescaled(weights.scale()),
+                    grammar.as_deref(),
+                    cli.output.as_deref(),
+                    lexicon_only,
+                    &metadata,
+                    word_counts.then_some(&dictionary),
+                    *word_tags,
+                )?;
+
+                match grammar {
+                    Some(grammar_name) if !corpus.is_empty() => {
+                        let mut mixture_file = File::create(format!("{}.mixture", grammar_name))?;
+                        write_mixture(&mut mixture_file, corpus)?;
+                    }
+                    None if !corpus.is_empty() => write_mixture(&mut io::stderr(), corpus)?,
+                    _ => (),
+                }
+            }
 
-                grammar_normalised.write_non_lexical_rules(&mut out_handle)?;
-                grammar_normalised.write_lexical_rules(&mut out_handle)?;
-                grammar_normalised.write_terminals(&mut out_handle)?;
+            if let (Some(rare), Some(grammar_name)) = (suffix_model, grammar) {
+                let mut suffix_file =
+                    BufWriter::new(File::create(format!("{}.suffixes", grammar_name))?);
+                SuffixModel::from_dictionary(&dictionary, *rare).write(&mut suffix_file)?;
+                suffix_file.flush()?;
             }
         }
         Commands::Parse {
@@ -163,269 +1470,3934 @@ fn main() -> io::Result<()> {
             initial_nonterminal,
             unking,
             smoothing,
+            unk_model,
+            signature_features,
             threshold_beam,
             rank_beam,
             kbest,
             astar,
+            output_chunked,
+            chunk_size,
+            protected_rules,
+            ignored_rules,
+            diagnose_gold,
+            unary_closure,
+            annotation_separator,
+            constraints,
+            span_posteriors,
+            forest,
+            lazy_lexicon,
+            oov_report,
+            max_oov_rate,
+            suffix_model,
+            char_fallback,
+            probabilities,
+            format,
+            flush_interval,
+            line_buffered,
+            cache,
+            sample,
+            temperature,
+            watch,
+            watch_interval,
+            project_latent,
+            self_check,
+            coarse_rules,
+            coarse_lexicon,
+            coarse_horizontal,
+            coarse_vertical,
+            coarse_threshold,
+            posterior_threshold,
+            tag_model,
+            tag_threshold,
+            max_length,
+            timeout_ms,
+            label_backoff,
+            label_hierarchy,
+            floor_weights,
+            clamp_weights,
+            weights,
+            rule_provenance,
+            noparse_file,
+            retry_noparse,
         } => {
+            let signatures = &unk_model.scheme(signature_features, &cli.signature_prefix)?;
+            let unk_token = cli.unk_token.as_str();
             // Filter out all unsupported options
-            if kbest.is_some() || astar.is_some() || *paradigma == ParsingParadigma::Deductive {
-                std::process::exit(22)
+            if *paradigma == ParsingParadigma::Deductive {
+                return Err(Error::Unsupported(String::from("Deductive parsing")));
             }
 
             if *unking && *smoothing {
-                panic!("Unking and smoothing are mutually exclusive. Only use one!")
+                return Err(Error::Usage(String::from(
+                    "Unking and smoothing are mutually exclusive. Only use one",
+                )));
             }
 
-            let mode = PruneMode {
-                threshold: *threshold_beam,
-                fixed_size: *rank_beam,
-            };
+            if *chunk_size == 0 {
+                return Err(Error::Usage(String::from(
+                    "--chunk-size has to be at least 1",
+                )));
+            }
 
-            let mut grammar = GrammarParse::new(initial_nonterminal.as_str().into());
+            if temperature.is_nan() || *temperature <= 0.0 {
+                return Err(Error::Usage(String::from(
+                    "--temperature has to be greater than 0",
+                )));
+            }
 
-            let rules_file = File::open(rules)?;
-            let lexicon_file = File::open(lexicon)?;
-            let rules_reader = BufReader::new(rules_file);
-            let lexicon_reader = BufReader::new(lexicon_file);
+            if watch.is_some()
+                && (output_chunked.is_some()
+                    || diagnose_gold.is_some()
+                    || *lazy_lexicon
+                    || *oov_report
+                    || max_oov_rate.is_some())
+            {
+                return Err(Error::Usage(String::from(
+                    "--watch can't be combined with --output-chunked, --diagnose-gold, \
+                     --lazy-lexicon, --oov-report or --max-oov-rate",
+                )));
+            }
 
-            rules_reader
-                .lines()
-                .filter_map(|l| {
-                    if l.is_err() {
-                        eprintln!("Error when reading line: {:?}", l);
+            let with_beams = |mut mode: PruneMode<_, _>| {
+                if let Some(threshold) = threshold_beam {
+                    mode = mode.with_threshold(*threshold);
+                }
+                if let Some(size) = rank_beam {
+                    mode = mode.with_fixed_size(*size);
+                }
+                mode
+            };
+            let mode = with_beams(PruneMode::empty());
+
+            // Every beam on its own and all of them together.
+            let check_modes = || {
+                let mut check_modes = vec![];
+                if let Some(threshold) = threshold_beam {
+                    check_modes.push((
+                        String::from("threshold beam"),
+                        PruneMode::empty().with_threshold(*threshold),
+                    ));
+                }
+                if let Some(size) = rank_beam {
+                    check_modes.push((
+                        String::from("rank beam"),
+                        PruneMode::empty().with_fixed_size(*size),
+                    ));
+                }
+                if let (Some(threshold), Some(size)) = (threshold_beam, rank_beam) {
+                    check_modes.push((
+                        String::from("threshold and rank beam"),
+                        PruneMode::empty()
+                            .with_threshold(*threshold)
+                            .with_fixed_size(*size),
+                    ));
+                }
+                check_modes
+            };
+            let mut checked = 0;
+            let mut discrepancies = 0;
+
+            let initial = initial_nonterminals(initial_nonterminal)?;
+            let compiled = is_compiled_grammar(Path::new(rules))?;
+            if compiled
+                && (ignored_rules.is_some()
+                    || *lazy_lexicon
+                    || *char_fallback
+                    || rule_provenance.is_some()
+                    || floor_weights.is_some()
+                    || *clamp_weights
+                    || *weights != WeightFormat::Prob
+                    || unary_closure.is_some())
+            {
+                return Err(Error::Usage(String::from(
+                    "--ignored-rules, --lazy-lexicon, --char-fallback, --rule-provenance, \
+                     --floor-weights, --clamp-weights, --weights and --unary-closure can't be \
+                     used with a compiled grammar",
+                )));
+            }
+            let mut correction = WeightCorrection::new(*floor_weights, *clamp_weights);
+            let scale = weights.scale();
+            if rule_provenance.is_some()
+                && (watch.is_some()
+                    || output_chunked.is_some()
+                    || cache.is_some()
+                    || *lazy_lexicon
+                    || *span_posteriors
+                    || *forest)
+            {
+                return Err(Error::Usage(String::from(
+                    "--rule-provenance can't be combined with --watch, --output-chunked, --cache, \
+                     --lazy-lexicon, --span-posteriors or --forest",
+                )));
+            }
+            if (noparse_file.is_some() || *retry_noparse)
+                && (watch.is_some()
+                    || output_chunked.is_some()
+                    || *span_posteriors
+                    || *forest
+                    || kbest.is_some()
+                    || sample.is_some())
+            {
+                return Err(Error::Usage(String::from(
+                    "--noparse-file and --retry-noparse can't be combined with --watch, \
+                     --output-chunked, --span-posteriors, --forest, --kbest or --sample",
+                )));
+            }
+            if *retry_noparse && rule_provenance.is_some() {
+                return Err(Error::Usage(String::from(
+                    "--retry-noparse can't be combined with --rule-provenance",
+                )));
+            }
+            let mut rule_index = rule_provenance.as_ref().map(|_| RuleIndex::new());
+            let mut grammar = if compiled {
+                let mut reader = BufReader::new(File::open(rules)?);
+                GrammarParse::read_compiled(&mut reader, initial[0].clone())?
+            } else {
+                GrammarParse::new(initial[0].clone())
+            };
+            for n in &initial[1..] {
+                grammar.add_initial_nonterminal(n.clone());
+            }
+
+            let ignored = ignored_rules
+                .as_deref()
+                .map(read_rule_set)
+                .transpose()?
+                .unwrap_or_default();
+
+            let mut handle = input_handle(cli);
+
+            // The whole input is kept in memory if it has to be inspected before parsing.
+            let input = if *lazy_lexicon || *oov_report || max_oov_rate.is_some() {
+                let mut input = String::new();
+                handle.read_to_string(&mut input)?;
+                if cli.escape_brackets {
+                    input = escape_lines(&input);
+                }
+                Some(input)
+            } else {
+                None
+            };
+            let vocabulary = input
+                .as_deref()
+                .filter(|_| *lazy_lexicon)
+                .map(|input| input_vocabulary(input, *annotation_separator));
+
+            let mut char_model = char_fallback.then(CharModel::new);
+            let suffix_model: Option<SuffixModel<SmallString<[u8; 8]>>> = match suffix_model {
+                Some(path) => Some(SuffixModel::read(BufReader::new(File::open(path)?))?),
+                None => None,
+            };
+
+            if !compiled {
+                let lexicon = match lexicon {
+                    Some(lexicon) => Path::new(lexicon),
+                    None => {
+                        return Err(Error::Usage(String::from(
+                            "LEXICON is required unless RULES is a compiled grammar",
+                        )))
+                    }
+                };
+                let (rules, lexicon) = checked_grammar_files(Path::new(rules), lexicon)?;
+
+                read_weighted_rules(rules, false, |_| true)?
+                    .inspect(|r| {
+                        if let Some(index) = &mut rule_index {
+                            index.insert(r.rule.clone());
+                        }
+                    })
+                    .filter(|r| !ignored.contains(&r.rule))
+                    .try_for_each(|r| {
+                        let weight = correction.correct_ln(scale.ln(r.weight.0));
+                        grammar.insert_log_rule(r.rule, LogProb::from_ln(weight))
+                    })
+                    .map_err(|e| Error::Format(e.to_string()))?;
+                // The character-level model learns from the whole lexicon, so with
+                // --lazy-lexicon the vocabulary only decides which rules the grammar gets.
+                let unk_prefixes = [unk_token, signatures.prefix.as_str()];
+                let full_lexicon = char_model.is_some();
+                read_weighted_rules(lexicon, true, |l| match &vocabulary {
+                    Some(vocabulary) => {
+                        full_lexicon || lexicon_line_needed(l, vocabulary, &unk_prefixes)
+                    }
+                    None => true,
+                })?
+                .inspect(|r| {
+                    if let Some(index) = &mut rule_index {
+                        index.insert(r.rule.clone());
                     }
-                    l.ok()
                 })
-                .map(|l| WeightedRule::from_str(&l))
-                .filter_map(|r| {
-                    if r.is_err() {
-                        eprintln!("Error when parsing non-lexical rule: {:?}", r);
-                    }
-
-                    if let Ok(WeightedRule {
-                        rule: Rule::Lexical { lhs: _, rhs: _ },
-                        weight: _,
-                    }) = r
-                    {
-                        eprintln!(
-                            "Lexical rule parsed when parsing non-lexical rules: {:?}",
-                            r
-                        );
-                        None
-                    } else {
-                        r.ok()
+                .filter(|r| !ignored.contains(&r.rule))
+                .try_for_each(|r| {
+                    let weight = correction.correct_ln(scale.ln(r.weight.0));
+                    if let (Some(model), Rule::Lexical { lhs, rhs }) = (&mut char_model, &r.rule) {
+                        model.insert(lhs.clone(), rhs);
+                        if let Some(vocabulary) = &vocabulary {
+                            if !terminal_needed(rhs, vocabulary, &unk_prefixes) {
+                                return Ok(());
+                            }
+                        }
                     }
+                    grammar.insert_log_rule(r.rule, LogProb::from_ln(weight))
                 })
-                .for_each(|r| grammar.insert_rule(r));
+                .map_err(|e| Error::Format(e.to_string()))?;
+            }
 
-            lexicon_reader
-                .lines()
-                .filter_map(|l| {
-                    if l.is_err() {
-                        eprintln!("Error when reading line: {:?}", l);
+            if let Some(penalty) = label_backoff {
+                let hierarchy = match label_hierarchy {
+                    Some(path) => LabelHierarchy::read(BufReader::new(File::open(path)?))?,
+                    None => LabelHierarchy::from_suffixes(),
+                };
+                grammar
+                    .insert_backoff_rules(|n| hierarchy.parent(n).map(SmallString::from), *penalty);
+            } else if label_hierarchy.is_some() {
+                return Err(Error::Usage(String::from(
+                    "--label-hierarchy requires --label-backoff",
+                )));
+            }
+
+            if let Some(input) = input {
+                if *oov_report || max_oov_rate.is_some() {
+                    let rate = oov_rate(&input, *annotation_separator, *oov_report, |w| {
+                        grammar.rules_lexical.contains_key(&SmallString::from(w))
+                    });
+
+                    if let Some(max_oov_rate) = max_oov_rate {
+                        if rate > *max_oov_rate && !*unking && !*smoothing {
+                            return Err(Error::Format(format!(
+                                "Rate of unknown words {:.4} exceeds {}. Use unking or smoothing",
+                                rate, max_oov_rate
+                            )));
+                        }
                     }
-                    l.ok()
-                })
-                .map(|l| WeightedRule::from_str(&l))
-                .filter_map(|r| {
-                    if r.is_err() {
-                        eprintln!("Error when parsing lexical rule: {:?}", r);
-                    }
-
-                    if let Ok(WeightedRule {
-                        rule: Rule::NonLexical { lhs: _, rhs: _ },
-                        weight: _,
-                    }) = r
-                    {
-                        eprintln!(
-                            "Non-lexical rule parsed when parsing lexical rules: {:?}",
-                            r
-                        );
-                        None
-                    } else {
-                        r.ok()
+                }
+                handle = Box::new(io::Cursor::new(input));
+            }
+
+            if let Some(unary_closure) = unary_closure {
+                let mut reader = BufReader::new(File::open(unary_closure)?);
+                // A closure of other unary rules would derive trees the grammar can't.
+                let hash = format!("{:016x}", grammar.unary_rules_hash());
+                let metadata = GrammarMetadata::read(&mut reader)?;
+                if metadata.get(CLOSURE_HASH_KEY) != Some(hash.as_str()) {
+                    return Err(Error::Format(format!(
+                        "{} wasn't computed from the unary rules of the grammar. Write it again \
+                         with the closure subcommand",
+                        unary_closure.display()
+                    )));
+                }
+
+                // The chains follow the header, which has one line per entry.
+                let header = metadata.entries().len();
+                reader
+                    .lines()
+                    .enumerate()
+                    .map(|(i, l)| (header + i, l))
+                    .filter_map(|(i, l)| {
+                        if l.is_err() {
+                            WARNINGS.warn(
+                                "read",
+                                Some(i + 1),
+                                format_args!("Error when reading line: {:?}", l),
+                            );
+                        }
+                        Some((i, l.ok()?))
+                    })
+                    .map(|(i, l)| (i, WeightedChain::from_str(&l)))
+                    .filter_map(|(i, c)| {
+                        if c.is_err() {
+                            WARNINGS.warn(
+                                "closure",
+                                Some(i + 1),
+                                format_args!("Error when parsing unary chain: {:?}", c),
+                            );
+                        }
+                        c.ok()
+                    })
+                    .for_each(|c| grammar.insert_unary_chain(c.chain, c.weight));
+            }
+
+            if let Some(astar) = astar {
+                let reader = BufReader::new(File::open(astar)?);
+
+                reader
+                    .lines()
+                    .enumerate()
+                    .filter_map(|(i, l)| {
+                        if l.is_err() {
+                            WARNINGS.warn(
+                                "read",
+                                Some(i + 1),
+                                format_args!("Error when reading line: {:?}", l),
+                            );
+                        }
+                        Some((i, l.ok()?))
+                    })
+                    .map(|(i, l)| (i, OutsideEstimate::from_str(&l)))
+                    .filter_map(|(i, e)| {
+                        if e.is_err() {
+                            WARNINGS.warn(
+                                "outside",
+                                Some(i + 1),
+                                format_args!("Error when parsing outside estimate: {:?}", e),
+                            );
+                        }
+                        e.ok()
+                    })
+                    .for_each(|e| grammar.insert_outside_estimate(e));
+            }
+
+            if let Some(constraints) = constraints {
+                let reader = BufReader::new(File::open(constraints)?);
+
+                reader
+                    .lines()
+                    .enumerate()
+                    .filter_map(|(i, l)| {
+                        if l.is_err() {
+                            WARNINGS.warn(
+                                "read",
+                                Some(i + 1),
+                                format_args!("Error when reading line: {:?}", l),
+                            );
+                        }
+                        Some((i, l.ok()?))
+                    })
+                    .map(|(i, l)| (i, ConstrainedRule::from_str(&l)))
+                    .filter_map(|(i, c)| {
+                        if c.is_err() {
+                            WARNINGS.warn(
+                                "constraint",
+                                Some(i + 1),
+                                format_args!("Error when parsing rule constraint: {:?}", c),
+                            );
+                        }
+                        c.ok()
+                    })
+                    .try_for_each(|c| {
+                        grammar
+                            .constrain_rule(c.rule, c.constraint)
+                            .map_err(|e| Error::Format(format!("{}: {}", constraints.display(), e)))
+                    })?;
+            }
+
+            if let Some(protected_rules) = protected_rules {
+                for rule in read_rule_set(protected_rules)? {
+                    grammar.protect_rule(rule).map_err(|e| {
+                        Error::Format(format!("{}: {}", protected_rules.display(), e))
+                    })?;
+                }
+            }
+
+            // The chains of unary rules are searched once instead of in every cell.
+            grammar
+                .precompute_unary_closure()
+                .map_err(|c| Error::Format(c.to_string()))?;
+
+            let coarse = match (coarse_rules, coarse_lexicon) {
+                (Some(coarse_rules), Some(coarse_lexicon)) => {
+                    let mut coarse = GrammarParse::new(initial[0].clone());
+                    for n in &initial[1..] {
+                        coarse.add_initial_nonterminal(n.clone());
                     }
-                })
-                .for_each(|r| grammar.insert_rule(r));
+                    let (coarse_rules, coarse_lexicon) =
+                        checked_grammar_files(coarse_rules, coarse_lexicon)?;
+                    read_weighted_rules(coarse_rules, false, |_| true)?
+                        .chain(read_weighted_rules(coarse_lexicon, true, |_| true)?)
+                        .try_for_each(|r| {
+                            let weight = correction.correct_ln(scale.ln(r.weight.0));
+                            coarse.insert_log_rule(r.rule, LogProb::from_ln(weight))
+                        })
+                        .map_err(|e| Error::Format(e.to_string()))?;
+                    coarse
+                        .precompute_unary_closure()
+                        .map_err(|c| Error::Format(c.to_string()))?;
+
+                    let projection =
+                        grammar.label_projection(&coarse, |n| match Binarized::from_str(n) {
+                            Ok(node) => node
+                                .coarsen(*coarse_horizontal, *coarse_vertical)
+                                .to_string()
+                                .as_str()
+                                .into(),
+                            Err(_) => n.clone(),
+                        });
+                    Some((coarse, Arc::new(projection)))
+                }
+                (None, None) => None,
+                _ => {
+                    return Err(Error::Usage(String::from(
+                        "--coarse-rules and --coarse-lexicon have to be given together",
+                    )))
+                }
+            };
+
+            if correction.is_corrected() {
+                eprintln!("Corrected rule weights: {}", correction);
+            }
+
+            let tag_bigrams = match tag_model {
+                Some(path) => {
+                    let mut model = TagBigramModel::new();
+                    read_trees(path)?
+                        .iter()
+                        .flatten()
+                        .for_each(|t| model.insert_tree(t));
+                    Some(model)
+                }
+                None => None,
+            };
+
+            if let Some(dir) = output_chunked {
+                fs::create_dir_all(dir)?;
+            }
+
+            let gold_trees = diagnose_gold.as_deref().map(read_trees).transpose()?;
+
+            let cache = match cache {
+                Some(dir) => {
+                    // Every option that changes the printed trees is part of the hash.
+                    let files: Vec<&Path> = [
+                        Some(Path::new(rules)),
+                        lexicon.as_deref().map(Path::new),
+                        astar.as_deref(),
+                        protected_rules.as_deref(),
+                        ignored_rules.as_deref(),
+                        unary_closure.as_deref(),
+                        constraints.as_deref(),
+                        coarse_rules.as_deref(),
+                        coarse_lexicon.as_deref(),
+                        label_hierarchy.as_deref(),
+                        tag_model.as_deref(),
+                        suffix_model.as_deref(),
+                    ]
+                    .into_iter()
+                    .flatten()
+                    .collect();
+                    let settings = format!(
+                        "{:?} {} {} {} {:?} {} {:?} {:?} {:?} {:?} {} {} {} {:?} {} {} {} {} {} {} {:?} {:?} {} {:?} {} {:?} {:?} {} {}",
+                        paradigma,
+                        initial_nonterminal,
+                        unking,
+                        smoothing,
+                        unk_model,
+                        signature_features,
+                        threshold_beam,
+                        rank_beam,
+                        kbest,
+                        annotation_separator,
+                        char_fallback,
+                        probabilities,
+                        astar.is_some(),
+                        sample,
+                        temperature,
+                        cli.seed,
+                        project_latent,
+                        coarse_horizontal,
+                        coarse_vertical,
+                        coarse_threshold,
+                        posterior_threshold,
+                        label_backoff,
+                        tag_threshold,
+                        floor_weights,
+                        clamp_weights,
+                        format,
+                        weights,
+                        cli.unk_token,
+                        cli.signature_prefix,
+                    );
+                    Some(ParseCache::new(dir, grammar_hash(&files, &settings)?)?)
+                }
+                None => None,
+            };
 
-            const LINES_READ: usize = 128;
-            let stdin = io::stdin();
-            let mut handle = stdin.lock();
+            let line_buffered = *line_buffered && output_chunked.is_none();
+            // Sentences are parsed as a stream, unless they are needed in batches, e.g. for the
+            // chunk files or the unknown-word models, which change the grammar.
+            let streaming = watch.is_none()
+                && output_chunked.is_none()
+                && char_model.is_none()
+                && suffix_model.is_none()
+                && gold_trees.is_none()
+                && self_check.is_none()
+                && !*span_posteriors
+                && !*forest
+                && rule_provenance.is_none()
+                && noparse_file.is_none()
+                && !*retry_noparse;
+            // The stream is read after the parse function is set up, so no batch is read.
+            let batch_size = if streaming {
+                0
+            } else if line_buffered {
+                1
+            } else {
+                *chunk_size
+            };
+            let mut out = FlushingWriter::new(
+                output_handle(cli.output.as_deref())?,
+                if line_buffered {
+                    Some(1)
+                } else {
+                    *flush_interval
+                },
+            );
+
+            let mut provenance_out = match rule_provenance {
+                Some(path) => Some(BufWriter::new(File::create(path)?)),
+                None => None,
+            };
+
+            let mut noparse_out = match noparse_file {
+                Some(path) => Some(BufWriter::new(File::create(path)?)),
+                None => None,
+            };
+            // Distinct sentences printed as NOPARSE, with the positions of their results.
+            let mut noparses: Vec<(String, Vec<usize>)> = vec![];
+            let mut noparse_idx: FxHashMap<String, usize> = FxHashMap::default();
+            // With --retry-noparse, all results are held back until the retry.
+            let mut held = vec![];
+
+            let worker_errors = AtomicUsize::new(0);
+            let too_long = AtomicUsize::new(0);
+            let timed_out = AtomicUsize::new(0);
             let mut input_buf = String::new();
             let mut done = false;
+            let mut chunk_idx = 0;
+            let mut sentence_idx = 0;
+            let mut watched = None;
+            let mut unreadable = FxHashSet::default();
+            // Words that already went through the fallbacks, whether they got tags or not.
+            let mut fallback_words: FxHashSet<SmallString<[u8; 8]>> = FxHashSet::default();
             while !done {
-                for _ in 0..LINES_READ {
-                    match handle.read_line(&mut input_buf) {
-                        Ok(0) => {
-                            done = true;
-                            break;
+                if let Some(dir) = watch {
+                    let (path, input) = next_watched_file(
+                        dir,
+                        Duration::from_millis(*watch_interval),
+                        &mut unreadable,
+                    )?;
+                    input_buf = input;
+                    watched = Some(path);
+                } else {
+                    for _ in 0..batch_size {
+                        match handle.read_line(&mut input_buf) {
+                            Ok(0) => {
+                                done = true;
+                                break;
+                            }
+                            Ok(_) => {}
+                            Err(x) => WARNINGS.warn(
+                                "read",
+                                Some(sentence_idx + input_buf.lines().count() + 1),
+                                format_args!("Error when reading line: {:?}", x),
+                            ),
                         }
-                        Ok(_) => {}
-                        Err(x) => eprintln!("Error when reading line: {:?}", x),
+                    }
+                }
+                if cli.escape_brackets {
+                    input_buf = escape_lines(&input_buf);
+                }
+
+                let batch_start = sentence_idx;
+                sentence_idx += input_buf.lines().count();
+
+                // Chunks that were completed by a previous run are not parsed again.
+                if let Some(dir) = output_chunked {
+                    if chunk_path(dir, chunk_idx).exists() {
+                        input_buf.clear();
+                        chunk_idx += 1;
+                        continue;
                     }
                 }
 
-                let trees: Vec<_> = if *unking || *smoothing {
-                    input_buf
-                        .par_lines()
-                        .map(Sentence::from_str)
-                        .filter_map(|s| {
-                            if s.is_err() {
-                                eprintln!("Error when parsing sentence: {:?}", s);
+                if char_model.is_some() || suffix_model.is_some() {
+                    for line in input_buf.lines() {
+                        for (i, word) in line.split_whitespace().enumerate() {
+                            let word =
+                                SmallString::from(strip_annotation(word, *annotation_separator));
+                            let covered = grammar.rules_lexical.contains_key(&word)
+                                || (*unking
+                                    && grammar
+                                        .rules_lexical
+                                        .contains_key(&SmallString::from(unk_token)))
+                                || (*smoothing
+                                    && grammar.rules_lexical.contains_key(&SmallString::from(
+                                        signatures.signature(&word, i),
+                                    )));
+                            if covered || !fallback_words.insert(word.clone()) {
+                                continue;
                             }
-                            s.ok()
-                        })
-                        .map(|mut s| {
-                            // Unking and smoothing are effectively the same operation, but
-                            // smoothing is more fine grained.
-                            let wmap = if *unking {
-                                s.unkify(&grammar.rules_lexical)
-                            } else {
-                                // smoothing
-                                s.smooth(&grammar.rules_lexical)
+
+                            let mut tags = match &suffix_model {
+                                Some(model) => model.emissions(&word),
+                                None => vec![],
                             };
-                            (s, wmap)
-                        })
-                        .map(|(s, wmap)| {
-                            (
-                                grammar.cyk(&s, &mode).unwrap_or_else(|| s.into_noparse()),
-                                wmap,
-                            )
-                        })
-                        .map(|(mut t, wmap)| {
-                            if let Some(wmap) = wmap {
-                                t.deunkify(wmap);
+                            if let (true, Some(model)) = (tags.is_empty(), &char_model) {
+                                tags = model.best_tags(&word, CHAR_FALLBACK_TAGS);
+                            }
+                            for (tag, p) in tags {
+                                grammar
+                                    .insert_rule(WeightedRule {
+                                        rule: Rule::Lexical {
+                                            lhs: tag,
+                                            rhs: word.clone(),
+                                        },
+                                        weight: FloatOrd(p),
+                                    })
+                                    .map_err(|e| Error::Format(e.to_string()))?;
+                            }
+                        }
+                    }
+                }
+
+                if let Some(gold_trees) = &gold_trees {
+                    for (i, line) in input_buf.lines().enumerate() {
+                        let idx = batch_start + i;
+                        let mut sentence = match Sentence::from_str(line) {
+                            Ok(s) => s,
+                            Err(_) => continue,
+                        };
+                        if let Some(sep) = annotation_separator {
+                            sentence.split_annotations(*sep);
+                        }
+                        if *unking {
+                            sentence.unkify_with(&grammar.rules_lexical, unk_token);
+                        } else if *smoothing {
+                            sentence.smooth_with(&grammar.rules_lexical, signatures);
+                        }
+
+                        match gold_trees.get(idx) {
+                            Some(Some(gold)) => eprintln!(
+                                "Sentence {}: {}",
+                                idx + 1,
+                                grammar.diagnose_pruning(&sentence, &mode, gold)
+                            ),
+                            Some(None) => {
+                                eprintln!("Sentence {}: gold tree can't be read", idx + 1)
+                            }
+                            None => eprintln!("Sentence {}: no gold tree", idx + 1),
+                        }
+                    }
+                }
+
+                if let Some(rate) = self_check {
+                    for (i, line) in input_buf.lines().enumerate() {
+                        let mut hasher = FxHasher::default();
+                        hasher.write(line.as_bytes());
+                        if XorShift::new(cli.seed ^ hasher.finish()).weight() > *rate {
+                            continue;
+                        }
+                        let mut sentence = match Sentence::from_str(line) {
+                            Ok(s) => s,
+                            Err(_) => continue,
+                        };
+                        if let Some(sep) = annotation_separator {
+                            sentence.split_annotations(*sep);
+                        }
+                        if *unking {
+                            sentence.unkify_with(&grammar.rules_lexical, unk_token);
+                        } else if *smoothing {
+                            sentence.smooth_with(&grammar.rules_lexical, signatures);
+                        }
+
+                        // The pruners that depend on the sentence, on their own and together
+                        // with the beams.
+                        let mut modes = check_modes();
+                        let beams = modes.len();
+                        let mut all = PruneMode::empty();
+                        if let Some(model) = &tag_bigrams {
+                            let pruner = TagPruner(model.allowed_tags(&sentence.0, *tag_threshold));
+                            modes.push((
+                                String::from("tag model"),
+                                PruneMode::empty().with_pruner(pruner.clone()),
+                            ));
+                            all = all.with_pruner(pruner);
+                        }
+                        if let Some((coarse, projection)) = &coarse {
+                            if let Some(mask) = coarse.posterior_mask(&sentence, *coarse_threshold)
+                            {
+                                let pruner = CoarsePruner {
+                                    mask,
+                                    projection: projection.clone(),
+                                };
+                                modes.push((
+                                    String::from("coarse pass"),
+                                    PruneMode::empty().with_pruner(pruner.clone()),
+                                ));
+                                all = all.with_pruner(pruner);
+                            }
+                        }
+                        if let Some(threshold) = posterior_threshold {
+                            if let Some(mask) = grammar.posterior_mask(&sentence, *threshold) {
+                                let pruner = PosteriorPruner(mask);
+                                modes.push((
+                                    String::from("posterior threshold"),
+                                    PruneMode::empty().with_pruner(pruner.clone()),
+                                ));
+                                all = all.with_pruner(pruner);
+                            }
+                        }
+                        if modes.len() > beams {
+                            modes.push((String::from("all pruning"), with_beams(all)));
+                        }
+
+                        checked += 1;
+                        for discrepancy in grammar.check_pruning(&sentence, &modes) {
+                            discrepancies += 1;
+                            eprintln!("Sentence {}: {}", batch_start + i + 1, discrepancy);
+                        }
+                    }
+                }
+
+                if *span_posteriors || *forest {
+                    let posteriors: Vec<_> = input_buf
+                        .lines()
+                        .collect::<Vec<_>>()
+                        .into_par_iter()
+                        .enumerate()
+                        .map(|(i, l)| {
+                            let line_no = batch_start + i + 1;
+                            // Lines that aren't sentences get an empty block, so that the
+                            // blocks stay aligned with the input.
+                            let mut s = match Sentence::from_str(l) {
+                                Ok(s) => s,
+                                Err(e) => {
+                                    WARNINGS.warn(
+                                        "sentence",
+                                        Some(line_no),
+                                        format_args!("Error when parsing sentence: {:?}", e),
+                                    );
+                                    let spans = SpanPosteriors::<String>(vec![]);
+                                    return if *forest {
+                                        Forest {
+                                            sentence: Sentence::<String>(vec![]),
+                                            spans,
+                                        }
+                                        .to_string()
+                                    } else {
+                                        spans.to_string()
+                                    };
+                                }
+                            };
+                            if let Some(sep) = annotation_separator {
+                                s.split_annotations(*sep);
+                            }
+                            // Forests keep the words from before unking.
+                            let words = Sentence(s.0.clone());
+                            if *unking {
+                                s.unkify_with(&grammar.rules_lexical, unk_token);
+                            } else if *smoothing {
+                                s.smooth_with(&grammar.rules_lexical, signatures);
+                            }
+                            let spans =
+                                catch_sentence_panic(&s, Some(line_no), &worker_errors, || {
+                                    grammar.span_posteriors(&s)
+                                })
+                                .unwrap_or(SpanPosteriors(vec![]));
+                            if *forest {
+                                Forest {
+                                    sentence: words,
+                                    spans,
+                                }
+                                .to_string()
+                            } else {
+                                spans.to_string()
                             }
-                            t
                         })
-                        .collect()
-                } else {
-                    input_buf
-                        .par_lines()
-                        .map(Sentence::from_str)
-                        .filter_map(|s| {
-                            if s.is_err() {
-                                eprintln!("Error when parsing sentence: {:?}", s);
+                        .collect();
+
+                    write_output(
+                        &mut out,
+                        output_chunked.as_deref(),
+                        chunk_idx,
+                        watched.as_deref(),
+                        &posteriors,
+                    )?;
+                    input_buf.clear();
+                    chunk_idx += 1;
+                    continue;
+                }
+
+                let noparse_result = |s: Sentence<SmallString<[u8; 8]>>| {
+                    let noparse = s.into_noparse();
+                    let provenance = rule_index
+                        .as_ref()
+                        .map(|i| i.provenance(&noparse).to_string());
+                    let result = match format {
+                        ParseFormat::Trees => noparse.to_string(),
+                        ParseFormat::Json => parse_json(&noparse, None, true),
+                        ParseFormat::Spans => parse_spans(&noparse, true),
+                    };
+                    (result, provenance, true)
+                };
+                let parse_line = |line_no: usize, line: &str| {
+                    if let Some(max_length) = max_length {
+                        if line.split_whitespace().count() > *max_length {
+                            too_long.fetch_add(1, Ordering::Relaxed);
+                            return Sentence::from_str(line).ok().map(noparse_result);
+                        }
+                    }
+
+                    if let Some((result, noparse)) = cache.as_ref().and_then(|c| c.get(line)) {
+                        return Some((result, None, noparse));
+                    }
+
+                    // Lines that aren't sentences, e.g. empty lines, get an empty NOPARSE, so
+                    // that the output stays aligned with the input.
+                    let mut s = match Sentence::from_str(line) {
+                        Ok(s) => s,
+                        Err(e) => {
+                            WARNINGS.warn(
+                                "sentence",
+                                Some(line_no),
+                                format_args!("Error when parsing sentence: {:?}", e),
+                            );
+                            return Some(noparse_result(Sentence(vec![])));
+                        }
+                    };
+
+                    let annotations = annotation_separator.map(|sep| s.split_annotations(sep));
+                    // Unking and smoothing are effectively the same operation, but
+                    // smoothing is more fine grained.
+                    let wmap = if *unking {
+                        s.unkify_with(&grammar.rules_lexical, unk_token)
+                    } else if *smoothing {
+                        s.smooth_with(&grammar.rules_lexical, signatures)
+                    } else {
+                        None
+                    };
+
+                    let deadline = timeout_ms.map(|t| Instant::now() + Duration::from_millis(t));
+                    let trees: Option<Vec<(Tree<_>, Option<f64>)>> =
+                        catch_sentence_panic(&s, Some(line_no), &worker_errors, || {
+                            match (paradigma, kbest) {
+                                _ if sample.is_some() => {
+                                    // Every sentence gets its own generator, so that the samples
+                                    // don't depend on the order in which sentences are parsed.
+                                    let mut hasher = FxHasher::default();
+                                    hasher.write(line.as_bytes());
+                                    let mut rng = XorShift::new(cli.seed ^ hasher.finish());
+                                    grammar
+                                        .sample(&s, sample.unwrap(), *temperature, &mut rng)
+                                        .into_iter()
+                                        .map(|(t, w)| (t, Some(w)))
+                                        .collect()
+                                }
+                                (_, Some(k)) => grammar
+                                    .cyk_kbest(&s, *k as usize)
+                                    .into_iter()
+                                    .map(|(t, w)| (t, Some(w)))
+                                    .collect(),
+                                (ParsingParadigma::Cyk, None) if astar.is_some() => {
+                                    grammar.astar(&s).map(|t| (t, None)).into_iter().collect()
+                                }
+                                (ParsingParadigma::ShiftReduce, None) => grammar
+                                    .shift_reduce(
+                                        &s,
+                                        &tag_bigrams.as_ref().map_or(vec![], |m| {
+                                            m.allowed_tags(&s.0, *tag_threshold)
+                                        }),
+                                    )
+                                    .map(|t| (t, None))
+                                    .into_iter()
+                                    .collect(),
+                                _ => {
+                                    // Pruners that depend on the sentence. Without a coarse
+                                    // parse, the sentence is parsed without the coarse pruner.
+                                    let coarse_pruner =
+                                        coarse.as_ref().and_then(|(coarse, projection)| {
+                                            Some(CoarsePruner {
+                                                mask: coarse
+                                                    .posterior_mask(&s, *coarse_threshold)?,
+                                                projection: projection.clone(),
+                                            })
+                                        });
+                                    let posterior_pruner = posterior_threshold.and_then(|t| {
+                                        Some(PosteriorPruner(grammar.posterior_mask(&s, t)?))
+                                    });
+                                    let tag_pruner = tag_bigrams
+                                        .as_ref()
+                                        .map(|m| TagPruner(m.allowed_tags(&s.0, *tag_threshold)));
+                                    let sentence_mode = (coarse_pruner.is_some()
+                                        || posterior_pruner.is_some()
+                                        || tag_pruner.is_some()
+                                        || deadline.is_some())
+                                    .then(|| {
+                                        let mut mode = PruneMode::empty();
+                                        if let Some(pruner) = tag_pruner {
+                                            mode = mode.with_pruner(pruner);
+                                        }
+                                        if let Some(deadline) = deadline {
+                                            mode = mode.with_pruner(DeadlinePruner(deadline));
+                                        }
+                                        if let Some(pruner) = coarse_pruner {
+                                            mode = mode.with_pruner(pruner);
+                                        }
+                                        if let Some(pruner) = posterior_pruner {
+                                            mode = mode.with_pruner(pruner);
+                                        }
+                                        with_beams(mode)
+                                    });
+                                    grammar
+                                        .cyk(&s, sentence_mode.as_ref().unwrap_or(&mode))
+                                        .map(|t| (t, None))
+                                        .into_iter()
+                                        .collect()
+                                }
+                            }
+                        });
+                    // Without a tree after the deadline, the parse was aborted.
+                    let aborted = trees.as_ref().is_some_and(|t| t.is_empty())
+                        && deadline.is_some_and(|d| Instant::now() >= d);
+                    if aborted {
+                        timed_out.fetch_add(1, Ordering::Relaxed);
+                    }
+                    // Results of internal errors and aborted parses are not cached.
+                    let failed = trees.is_none() || aborted;
+                    let trees = trees.unwrap_or_default();
+
+                    let noparse = trees.is_empty();
+                    let trees = if noparse {
+                        vec![(s.into_noparse(), None)]
+                    } else {
+                        trees
+                    };
+
+                    // The rules are found before the words and labels are changed.
+                    let provenance = rule_index.as_ref().map(|index| {
+                        trees
+                            .iter()
+                            .map(|(t, _)| index.provenance(t).to_string())
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    });
+
+                    let result = trees
+                        .into_iter()
+                        .map(|(mut t, w)| {
+                            // The words are still those of the grammar.
+                            let log_prob = match (format, noparse) {
+                                (ParseFormat::Json, false) => {
+                                    w.map(f64::ln).or_else(|| tree_log_score(&t, &grammar))
+                                }
+                                _ => None,
+                            };
+                            if let Some(wmap) = &wmap {
+                                t.deunkify(wmap.clone());
+                            }
+                            if let (Some(sep), Some(annotations)) =
+                                (annotation_separator, &annotations)
+                            {
+                                t.attach_annotations(annotations.clone(), *sep);
+                            }
+                            if *project_latent {
+                                project_tree(&mut t);
+                            }
+                            match (format, w) {
+                                (ParseFormat::Json, _) => parse_json(&t, log_prob, noparse),
+                                (ParseFormat::Spans, _) => parse_spans(&t, noparse),
+                                (ParseFormat::Trees, Some(w))
+                                    if *probabilities || sample.is_some() =>
+                                {
+                                    format!("{}\t{}", t, w)
+                                }
+                                (ParseFormat::Trees, _) => t.to_string(),
                             }
-                            s.ok()
                         })
-                        .map(|s| grammar.cyk(&s, &mode).unwrap_or_else(|| s.into_noparse()))
-                        .collect()
+                        .collect::<Vec<_>>()
+                        .join("\n");
+
+                    if let (Some(cache), false) = (&cache, failed) {
+                        if let Err(e) = cache.insert(line, &result, noparse) {
+                            WARNINGS.warn(
+                                "cache",
+                                Some(line_no),
+                                format_args!("Error when writing to cache: {:?}", e),
+                            );
+                        }
+                    }
+                    Some((result, provenance, noparse))
                 };
 
-                for tree in trees {
-                    println!("{}", tree);
+                if streaming {
+                    // Every k-best list ends with an empty line.
+                    let window = if line_buffered {
+                        1
+                    } else {
+                        SENTENCES_IN_FLIGHT
+                    };
+                    stream_parse(&mut handle, &mut out, window, |line_no, line| {
+                        let result = match line {
+                            Some(line) if cli.escape_brackets => {
+                                parse_line(line_no, &escape_sentence(line))
+                            }
+                            Some(line) => parse_line(line_no, line),
+                            // Unreadable lines get the empty NOPARSE of unreadable sentences.
+                            None => Some(noparse_result(Sentence(vec![]))),
+                        };
+                        result.map(|(r, _, _)| if kbest.is_some() { r + "\n" } else { r })
+                    })?;
+                    break;
+                }
+
+                // Long sentences are parsed first, so that they don't hold up the end of the
+                // batch. The original order is restored afterwards.
+                let mut lines: Vec<(usize, &str)> = input_buf.lines().enumerate().collect();
+                lines.sort_by_key(|(_, line)| Reverse(line.split_whitespace().count()));
+                let mut trees: Vec<(usize, Option<ParseResult>)> = lines
+                    .par_iter()
+                    .with_max_len(1)
+                    .map(|&(idx, line)| {
+                        // Every k-best list ends with an empty line.
+                        let result = parse_line(batch_start + idx + 1, line);
+                        (
+                            idx,
+                            result.map(|(r, p, noparse)| {
+                                (if kbest.is_some() { r + "\n" } else { r }, p, noparse)
+                            }),
+                        )
+                    })
+                    .collect();
+                trees.sort_unstable_by_key(|(idx, _)| *idx);
+
+                if noparse_out.is_some() || *retry_noparse {
+                    let batch_lines: Vec<&str> = input_buf.lines().collect();
+                    let mut position = held.len();
+                    for (idx, result) in &trees {
+                        let noparse = match result {
+                            Some((_, _, noparse)) => *noparse,
+                            None => continue,
+                        };
+                        if noparse {
+                            let line = batch_lines[*idx];
+                            let i = *noparse_idx.entry(line.to_string()).or_insert_with(|| {
+                                noparses.push((line.to_string(), vec![]));
+                                noparses.len() - 1
+                            });
+                            if noparses[i].1.is_empty() {
+                                if let Some(noparse_out) = &mut noparse_out {
+                                    writeln!(noparse_out, "{}", line)?;
+                                }
+                            }
+                            noparses[i].1.push(position);
+                        }
+                        position += 1;
+                    }
+                }
+
+                let (trees, provenance): (Vec<_>, Vec<_>) = trees
+                    .into_iter()
+                    .filter_map(|(_, t)| t)
+                    .map(|(t, p, _)| (t, p))
+                    .unzip();
+
+                if let Some(provenance_out) = &mut provenance_out {
+                    for p in provenance.into_iter().flatten() {
+                        writeln!(provenance_out, "{}", p)?;
+                    }
+                }
+
+                if *retry_noparse {
+                    held.extend(trees);
+                } else {
+                    write_output(
+                        &mut out,
+                        output_chunked.as_deref(),
+                        chunk_idx,
+                        watched.as_deref(),
+                        &trees,
+                    )?;
                 }
 
                 input_buf.clear();
+                chunk_idx += 1;
+            }
+
+            if let Some(mut noparse_out) = noparse_out {
+                noparse_out.flush()?;
+            }
+            if *retry_noparse {
+                let retried: Vec<Option<String>> = noparses
+                    .par_iter()
+                    .map(|(line, _)| {
+                        let mut s = Sentence::from_str(line).ok()?;
+                        let annotations = annotation_separator.map(|sep| s.split_annotations(sep));
+                        let wmap = if *unking {
+                            s.unkify_with(&grammar.rules_lexical, unk_token)
+                        } else {
+                            s.smooth_with(&grammar.rules_lexical, signatures)
+                        };
+                        // A sentence may be on several lines, so none is reported.
+                        let mut t = catch_sentence_panic(&s, None, &worker_errors, || {
+                            grammar.cyk(&s, &PruneMode::empty())
+                        })??;
+                        let log_prob = tree_log_score(&t, &grammar);
+                        if let Some(wmap) = wmap {
+                            t.deunkify(wmap);
+                        }
+                        if let (Some(sep), Some(annotations)) = (annotation_separator, annotations)
+                        {
+                            t.attach_annotations(annotations, *sep);
+                        }
+                        if *project_latent {
+                            project_tree(&mut t);
+                        }
+                        Some(match format {
+                            ParseFormat::Trees => t.to_string(),
+                            ParseFormat::Json => parse_json(&t, log_prob, false),
+                            ParseFormat::Spans => parse_spans(&t, false),
+                        })
+                    })
+                    .collect();
+
+                let mut recovered = 0;
+                for ((_, positions), tree) in noparses.iter().zip(retried) {
+                    if let Some(tree) = tree {
+                        recovered += 1;
+                        for &p in positions {
+                            held[p] = tree.clone();
+                        }
+                    }
+                }
+                eprintln!(
+                    "Retry: {} of {} distinct NOPARSE sentences were parsed.",
+                    recovered,
+                    noparses.len()
+                );
+                for result in &held {
+                    out.write_result(result)?;
+                }
+            } else if noparse_file.is_some() {
+                eprintln!(
+                    "{} distinct sentences were printed as NOPARSE.",
+                    noparses.len()
+                );
+            }
+            out.flush()?;
+            if let Some(mut provenance_out) = provenance_out {
+                provenance_out.flush()?;
+            }
+
+            let worker_errors = worker_errors.into_inner();
+            if worker_errors > 0 {
+                eprintln!(
+                    "{} sentences were not parsed due to internal errors.",
+                    worker_errors
+                );
+            }
+            let too_long = too_long.into_inner();
+            if too_long > 0 {
+                eprintln!(
+                    "{} sentences were not parsed because they are longer than {} words.",
+                    too_long,
+                    max_length.unwrap()
+                );
+            }
+            let timed_out = timed_out.into_inner();
+            if timed_out > 0 {
+                eprintln!(
+                    "{} sentences were not parsed because they took longer than {} ms.",
+                    timed_out,
+                    timeout_ms.unwrap()
+                );
+            }
+
+            if self_check.is_some() {
+                eprintln!(
+                    "Self-check: {} discrepancies in {} sentences.",
+                    discrepancies, checked
+                );
+                if discrepancies > 0 {
+                    return Err(Error::Check(format!(
+                        "the self-check found {} discrepancies",
+                        discrepancies
+                    )));
+                }
+            }
+        }
+        Commands::Compile {
+            rules,
+            lexicon,
+            grammar,
+        } => {
+            let mut grammar_parse = GrammarParse::new(SmallString::from("ROOT"));
+            let (rules, lexicon) = checked_grammar_files(Path::new(rules), Path::new(lexicon))?;
+            read_weighted_rules(rules, false, |_| true)?
+                .chain(read_weighted_rules(lexicon, true, |_| true)?)
+                .try_for_each(|r| grammar_parse.insert_rule(r))
+                .map_err(|e| Error::Format(e.to_string()))?;
+            grammar_parse
+                .precompute_unary_closure()
+                .map_err(|c| Error::Format(c.to_string()))?;
+
+            let mut out = BufWriter::new(File::create(format!("{}.bin", grammar))?);
+            grammar_parse.write_compiled(&mut out)?;
+            out.flush()?;
+        }
+        Commands::Closure { rules, grammar } => {
+            let mut grammar_parse = GrammarParse::new(SmallString::from("ROOT"));
+            read_weighted_rules(Path::new(rules), false, |_| true)?
+                .try_for_each(|r| grammar_parse.insert_rule(r))
+                .map_err(|e| Error::Format(e.to_string()))?;
+            if let Some(cycle) = grammar_parse.unary_cycle() {
+                return Err(Error::Format(cycle.to_string()));
+            }
+
+            if let Some(grammar_name) = grammar {
+                let mut closure_file = File::create(format!("{}.closure", grammar_name))?;
+                grammar_parse.write_unary_closure(&mut closure_file)?;
+            } else {
+                let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+
+                grammar_parse.write_unary_closure(&mut out_handle)?;
+                out_handle.flush()?;
             }
         }
         Commands::Binarise {
             horizontal,
             vertical,
+            direction,
+            verify_binary,
+            markov_params,
             ..
         } => {
-            let stdin = io::stdin();
-            let handle = stdin.lock();
+            let params =
+                read_markov_params(*horizontal, *vertical, *direction, markov_params.as_deref())?;
+            let handle = input_handle(cli);
+            let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
 
-            handle
-                .lines()
-                .filter_map(|l| {
-                    if l.is_err() {
-                        eprintln!("Error when reading line: {:?}", l);
-                    }
-                    l.ok()
-                })
-                .map(|l| SExp::from_str(&l))
-                .filter_map(|s| {
-                    if s.is_err() {
-                        eprintln!("Error when parsing SExp: {:?}", s);
+            tree_lines(handle, cli.tree_reading())
+                .enumerate()
+                .filter_map(|(i, l)| tree_from_line(i + 1, &l))
+                .map(|t| t.markovize_with(&params, &[]).to_string())
+                .enumerate()
+                .try_for_each(|(idx, t)| {
+                    if *verify_binary {
+                        let verified = SExp::from_str(&t)
+                            .map_err(|e| format!("{:?}", e))
+                            .and_then(|s| Tree::try_from(s).map_err(|e| e.to_string()))
+                            .and_then(|t| markovize::verify_binary(&t).map_err(|v| v.to_string()));
+                        if let Err(e) = verified {
+                            out_handle.flush()?;
+                            return Err(Error::Check(format!(
+                                "Binarised tree {} is invalid, {}: {}",
+                                idx + 1,
+                                e,
+                                t
+                            )));
+                        }
                     }
-                    s.ok()
-                })
-                .map(Tree::from)
-                .map(|t| t.markovize(*vertical, *horizontal, &[]))
-                .for_each(|t| println!("{}", t));
+                    writeln!(out_handle, "{}", t)?;
+                    Ok(())
+                })?;
+            out_handle.flush()?;
         }
-        Commands::Debinarise => {
-            let stdin = io::stdin();
-            let handle = stdin.lock();
+        Commands::Pipeline { spec } => {
+            let pipeline = Pipeline::from_str(spec).map_err(Error::Usage)?;
+            run_pipeline(
+                &pipeline,
+                input_handle(cli),
+                cli.tree_reading(),
+                &cli.unk_token,
+                &SignatureScheme {
+                    prefix: cli.signature_prefix.clone(),
+                    ..SignatureScheme::default()
+                },
+                cli.output.as_deref(),
+            )?;
+        }
+        Commands::Debinarise { labels } => {
+            let handle = input_handle(cli);
+            let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
 
-            handle
-                .lines()
-                .filter_map(|l| {
-                    if l.is_err() {
-                        eprintln!("Error when reading line: {:?}", l);
-                    }
-                    l.ok()
-                })
-                .map(|l| SExp::from_str(&l))
-                .filter_map(|s| {
-                    if s.is_err() {
-                        eprintln!("Error when parsing SExp: {:?}", s);
-                    }
-                    s.ok()
-                })
-                .map(Tree::from)
-                .map(Tree::parse_markovized)
-                .map(Tree::debinarize)
-                .for_each(|t| println!("{}", t));
+            let trees = tree_lines(handle, cli.tree_reading())
+                .enumerate()
+                .filter_map(|(i, l)| tree_from_line(i + 1, &l))
+                .map(Tree::parse_markovized);
+            for t in trees {
+                writeln!(out_handle, "{}", t.debinarize_with(labels.equivalence())?)?;
+            }
+            out_handle.flush()?;
         }
         Commands::Unk { threshold } => {
-            unking(UnkingMode::Trivial, *threshold);
+            let out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+            unking(
+                UnkingMode::Trivial(&cli.unk_token),
+                *threshold,
+                input_handle(cli),
+                cli.tree_reading(),
+                out_handle,
+            )?;
         }
-        Commands::Smooth { threshold } => {
-            unking(UnkingMode::Smoothing, *threshold);
+        Commands::Smooth {
+            threshold,
+            unk_model,
+            signature_features,
+        } => {
+            let out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+            unking(
+                UnkingMode::Smoothing(unk_model.scheme(signature_features, &cli.signature_prefix)?),
+                *threshold,
+                input_handle(cli),
+                cli.tree_reading(),
+                out_handle,
+            )?;
         }
-        _ => std::process::exit(22),
-    }
+        Commands::CheckCnf {
+            rules,
+            lexicon,
+            fix,
+        } => {
+            let (metadata, non_lexical, lexical, terminals) =
+                read_cnf_input(Path::new(rules), lexicon.as_deref().map(Path::new))?;
+            if !metadata.is_empty() {
+                eprint!("{}", metadata);
+            }
 
-    Ok(())
-}
+            let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+            let mut violating = 0;
+            for (line, r) in &non_lexical {
+                let violations = cnf_violations(&r.rule, &terminals);
+                if !violations.is_empty() {
+                    violating += 1;
+                }
+                for violation in violations {
+                    writeln!(out_handle, "{}: {}", violation, line)?;
+                }
+            }
+            out_handle.flush()?;
+            eprintln!("{} of {} rules violate CNF.", violating, non_lexical.len());
 
-enum UnkingMode {
-    Trivial,
-    Smoothing,
+            if let Some(grammar_name) = fix {
+                let rules = non_lexical.into_iter().map(|(_, r)| r).chain(lexical);
+                let grammar_fixed: GrammarBare<_, _, f64> = GrammarBare {
+                    rules: to_cnf(rules.collect(), &terminals)
+                        .into_iter()
+                        .map(|r| (r.rule, r.weight.0))
+                        .collect(),
+                };
+
+                let mut rules_file = File::create(format!("{}.rules", grammar_name))?;
+                grammar_fixed.write_non_lexical_rules(&mut rules_file)?;
+                let mut lexicon_file = File::create(format!("{}.lexicon", grammar_name))?;
+                grammar_fixed.write_lexical_rules(&mut lexicon_file)?;
+            }
+        }
+        Commands::Cnf {
+            rules,
+            lexicon,
+            grammar,
+        } => {
+            let (_, non_lexical, lexical, terminals) =
+                read_cnf_input(Path::new(rules), Some(Path::new(lexicon)))?;
+            let rules = non_lexical.into_iter().map(|(_, r)| r).chain(lexical);
+            let (rules, mapping) = to_strict_cnf(rules.collect(), &terminals)
+                .map_err(|c| Error::Format(c.to_string()))?;
+            let grammar_cnf: GrammarBare<_, _, f64> = GrammarBare {
+                rules: rules.into_iter().map(|r| (r.rule, r.weight.0)).collect(),
+            };
+
+            let mut rules_file = File::create(format!("{}.rules", grammar))?;
+            grammar_cnf.write_non_lexical_rules(&mut rules_file)?;
+            let mut lexicon_file = File::create(format!("{}.lexicon", grammar))?;
+            grammar_cnf.write_lexical_rules(&mut lexicon_file)?;
+            let mut mapping_file = File::create(format!("{}.cnf", grammar))?;
+            write!(mapping_file, "{}", mapping)?;
+        }
+        Commands::UndoCnf { mapping } => {
+            let mapping: CnfMapping<SmallString<[u8; 8]>> =
+                CnfMapping::from_str(&fs::read_to_string(mapping)?).map_err(Error::Format)?;
+            let handle = input_handle(cli);
+            let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+
+            tree_lines(handle, cli.tree_reading())
+                .enumerate()
+                .filter_map(|(i, l)| tree_from_line(i + 1, &l))
+                .try_for_each(|t| writeln!(out_handle, "{}", mapping.restore(t)))?;
+            out_handle.flush()?;
+        }
+        Commands::TagDict { lexicon } => {
+            let mut dict = TagDictionary::new();
+
+            if let Some(lexicon) = lexicon {
+                read_weighted_rules(lexicon, true, |_| true)?.for_each(|r| {
+                    if let Rule::Lexical { lhs, rhs } = r.rule {
+                        dict.insert(rhs, lhs, r.weight.0);
+                    }
+                });
+            } else {
+                let handle = input_handle(cli);
+
+                tree_lines(handle, cli.tree_reading())
+                    .enumerate()
+                    .filter_map(|(i, l)| tree_from_line(i + 1, &l))
+                    .for_each(|t| dict.insert_tree(&t));
+            }
+
+            let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+            dict.write(&mut out_handle)?;
+            out_handle.flush()?;
+        }
+        Commands::ConvertTrees { from, to } => {
+            let lines = input_handle(cli).lines().enumerate().filter_map(|(i, l)| {
+                if l.is_err() {
+                    WARNINGS.warn(
+                        "read",
+                        Some(i + 1),
+                        format_args!("Error when reading line: {:?}", l),
+                    );
+                }
+                l.ok()
+            });
+
+            let trees: Box<dyn Iterator<Item = Tree<_>>> = match from {
+                TreeFormat::Sexp | TreeFormat::Ptb => {
+                    let strip = *from == TreeFormat::Ptb;
+                    let trees: Box<dyn Iterator<Item = String>> = if strip {
+                        Box::new(BracketedTrees::new(lines))
+                    } else {
+                        Box::new(lines)
+                    };
+                    Box::new(
+                        trees
+                            .enumerate()
+                            .filter_map(|(i, l)| {
+                                let s = SExp::from_str(&l);
+                                if s.is_err() {
+                                    WARNINGS.warn(
+                                        "sexp",
+                                        Some(i + 1),
+                                        format_args!("Error when parsing SExp: {:?}", s),
+                                    );
+                                }
+                                Some((i, s.ok()?))
+                            })
+                            .map(move |(i, s)| (i, if strip { strip_outer_brackets(s) } else { s }))
+                            .filter_map(|(i, s)| {
+                                let t = Tree::try_from(s);
+                                if let Err(e) = &t {
+                                    WARNINGS.warn(
+                                        "tree",
+                                        Some(i + 1),
+                                        format_args!("Error when reading tree: {}", e),
+                                    );
+                                }
+                                t.ok()
+                            }),
+                    )
+                }
+                TreeFormat::Export => Box::new(
+                    ExportSentences::new(lines)
+                        .with_discontinuity(cli.tree_reading().discontinuity)
+                        .enumerate()
+                        .filter_map(|(i, t)| {
+                            if let Err(e) = &t {
+                                WARNINGS.warn(
+                                    "export",
+                                    Some(i + 1),
+                                    format_args!("Error when parsing export sentence: {}", e),
+                                );
+                            }
+                            t.ok()
+                        }),
+                ),
+            };
+
+            let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+            for (i, tree) in trees.enumerate() {
+                match to {
+                    TreeFormat::Sexp => writeln!(out_handle, "{}", tree)?,
+                    TreeFormat::Ptb => write_ptb(&mut out_handle, &tree)?,
+                    TreeFormat::Export => write_export(&mut out_handle, i + 1, &tree)?,
+                }
+            }
+            out_handle.flush()?;
+        }
+        Commands::Preprocess => {
+            let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+
+            tree_lines(input_handle(cli), cli.tree_reading())
+                .enumerate()
+                .filter_map(|(i, l)| {
+                    let s = SExp::from_str(&l);
+                    if s.is_err() {
+                        WARNINGS.warn(
+                            "sexp",
+                            Some(i + 1),
+                            format_args!("Error when parsing SExp: {:?}", s),
+                        );
+                    }
+                    let t = Tree::try_from(strip_outer_brackets(s.ok()?));
+                    if let Err(e) = &t {
+                        WARNINGS.warn(
+                            "tree",
+                            Some(i + 1),
+                            format_args!("Error when reading tree: {}", e),
+                        );
+                    }
+                    let preprocessed = preprocess(t.ok()?);
+                    if preprocessed.is_none() {
+                        WARNINGS.warn("tree", Some(i + 1), "Tree only covers empty elements");
+                    }
+                    preprocessed
+                })
+                .try_for_each(|t| writeln!(out_handle, "{}", t))?;
+            out_handle.flush()?;
+        }
+        Commands::Outside {
+            rules,
+            lexicon,
+            grammar,
+            initial_nonterminal,
+        } => {
+            let (rules, lexicon) = checked_grammar_files(Path::new(rules), Path::new(lexicon))?;
+            let weighted_rules: Vec<_> = read_weighted_rules(rules, false, |_| true)?
+                .chain(read_weighted_rules(lexicon, true, |_| true)?)
+                .collect();
+
+            let estimates =
+                viterbi_outside(&weighted_rules, &initial_nonterminals(initial_nonterminal)?);
+
+            if let Some(grammar_name) = grammar {
+                let mut outside_file = File::create(format!("{}.outside", grammar_name))?;
+                for estimate in estimates {
+                    writeln!(outside_file, "{}", estimate)?;
+                }
+            } else {
+                let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+                for estimate in estimates {
+                    writeln!(out_handle, "{}", estimate)?;
+                }
+                out_handle.flush()?;
+            }
+        }
+        Commands::SplitMerge {
+            grammar,
+            cycles,
+            merge_fraction,
+            em_iterations,
+            initial_nonterminal,
+        } => {
+            let trees: Vec<Tree<SmallString<[u8; 8]>>> =
+                tree_lines(input_handle(cli), cli.tree_reading())
+                    .enumerate()
+                    .filter_map(|(i, l)| {
+                        let t = tree_from_line(i + 1, &l)?;
+                        let binarised = is_trainable(&t);
+                        if !binarised {
+                            WARNINGS.warn(
+                                "tree",
+                                Some(i + 1),
+                                format_args!("Tree is not binarised: {}", t),
+                            );
+                        }
+                        binarised.then_some(t)
+                    })
+                    .collect();
+
+            let mut latent =
+                LatentGrammar::from_treebank(&trees, initial_nonterminals(initial_nonterminal)?);
+            let mut rng = XorShift::new(cli.seed);
+            for cycle in 1..=*cycles {
+                latent.split(&mut rng);
+                for _ in 0..*em_iterations {
+                    latent.em_step(&trees);
+                }
+                latent.merge(&trees, *merge_fraction);
+                let mut log_likelihood = 0.0;
+                for _ in 0..*em_iterations {
+                    log_likelihood = latent.em_step(&trees);
+                }
+                eprintln!("Cycle {}: log-likelihood {}", cycle, log_likelihood);
+            }
+
+            let grammar_latent = latent.to_grammar();
+            if let Some(grammar_name) = grammar {
+                let mut rules_file = File::create(format!("{}.rules", grammar_name))?;
+                grammar_latent.write_non_lexical_rules(&mut rules_file)?;
+                let mut lexicon_file = File::create(format!("{}.lexicon", grammar_name))?;
+                grammar_latent.write_lexical_rules(&mut lexicon_file)?;
+                let mut words_file = File::create(format!("{}.words", grammar_name))?;
+                grammar_latent.write_terminals(&mut words_file)?;
+            } else {
+                let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+
+                grammar_latent.write_non_lexical_rules(&mut out_handle)?;
+                grammar_latent.write_lexical_rules(&mut out_handle)?;
+                grammar_latent.write_terminals(&mut out_handle)?;
+                out_handle.flush()?;
+            }
+        }
+        Commands::Train {
+            rules,
+            lexicon,
+            grammar,
+            iterations,
+            initial_nonterminal,
+        } => {
+            let initial = initial_nonterminals(initial_nonterminal)?;
+            let (rules, lexicon) = checked_grammar_files(Path::new(rules), Path::new(lexicon))?;
+            let mut current: GrammarBare<_, _, f64> = GrammarBare::new();
+            for r in read_weighted_rules(rules, false, |_| true)?.chain(read_weighted_rules(
+                lexicon,
+                true,
+                |_| true,
+            )?) {
+                current.rules.insert(r.rule, r.weight.0);
+            }
+
+            let sentences: Vec<_> = input_handle(cli)
+                .lines()
+                .enumerate()
+                .filter_map(|(i, l)| {
+                    if l.is_err() {
+                        WARNINGS.warn(
+                            "read",
+                            Some(i + 1),
+                            format_args!("Error when reading line: {:?}", l),
+                        );
+                    }
+                    Some((i, l.ok()?))
+                })
+                .map(|(i, l)| {
+                    if cli.escape_brackets {
+                        (i, Sentence::from_str(&escape_sentence(&l)))
+                    } else {
+                        (i, Sentence::from_str(&l))
+                    }
+                })
+                .filter_map(|(i, s)| {
+                    if s.is_err() {
+                        WARNINGS.warn(
+                            "sentence",
+                            Some(i + 1),
+                            format_args!("Error when parsing sentence: {:?}", s),
+                        );
+                    }
+                    s.ok()
+                })
+                .collect();
+
+            for iteration in 1..=*iterations {
+                let mut grammar_parse = GrammarParse::new(initial[0].clone());
+                for n in &initial[1..] {
+                    grammar_parse.add_initial_nonterminal(n.clone());
+                }
+                for (rule, weight) in &current.rules {
+                    grammar_parse
+                        .insert_rule(WeightedRule {
+                            rule: rule.clone(),
+                            weight: FloatOrd(*weight),
+                        })
+                        .map_err(|e| Error::Format(e.to_string()))?;
+                }
+
+                let results: Vec<_> = sentences
+                    .par_iter()
+                    .map(|s| grammar_parse.expected_counts(s))
+                    .collect();
+
+                let mut counts: GrammarBare<_, _, f64> = GrammarBare::new();
+                let mut log_likelihood = 0.0;
+                let mut not_derivable = 0;
+                for result in results {
+                    match result {
+                        Some((sentence_counts, sentence_likelihood)) => {
+                            for (rule, count) in sentence_counts {
+                                *counts.rules.entry(rule).or_insert(0.0) += count;
+                            }
+                            log_likelihood += sentence_likelihood;
+                        }
+                        None => not_derivable += 1,
+                    }
+                }
+                eprintln!(
+                    "Iteration {}: log-likelihood {}, {} sentences not derivable",
+                    iteration, log_likelihood, not_derivable
+                );
+
+                let counted: FxHashSet<_> = counts
+                    .rules
+                    .keys()
+                    .map(|rule| match rule {
+                        Rule::Lexical { lhs, .. } | Rule::NonLexical { lhs, .. } => lhs.clone(),
+                    })
+                    .collect();
+                for (rule, weight) in current.rules {
+                    let lhs = match &rule {
+                        Rule::Lexical { lhs, .. } | Rule::NonLexical { lhs, .. } => lhs,
+                    };
+                    if !counted.contains(lhs) {
+                        counts.rules.insert(rule, weight);
+                    }
+                }
+                current = counts.normalised();
+            }
+
+            // Write to files if grammar name was chosen, otherwise print to STDOUT.
+            if let Some(grammar_name) = grammar {
+                let mut rules_file = File::create(format!("{}.rules", grammar_name))?;
+                current.write_non_lexical_rules(&mut rules_file)?;
+                let mut lexicon_file = File::create(format!("{}.lexicon", grammar_name))?;
+                current.write_lexical_rules(&mut lexicon_file)?;
+                let mut words_file = File::create(format!("{}.words", grammar_name))?;
+                current.write_terminals(&mut words_file)?;
+            } else {
+                let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+
+                current.write_non_lexical_rules(&mut out_handle)?;
+                current.write_lexical_rules(&mut out_handle)?;
+                current.write_terminals(&mut out_handle)?;
+                out_handle.flush()?;
+            }
+        }
+        Commands::Augment {
+            rules,
+            lexicon,
+            gold,
+            grammar,
+            smoothing,
+        } => {
+            if *smoothing <= 0.0 {
+                return Err(Error::Usage(String::from("--smoothing has to be positive")));
+            }
+
+            let (rules, lexicon) = checked_grammar_files(Path::new(rules), Path::new(lexicon))?;
+            let (metadata, _) = read_grammar_metadata(rules)?;
+            let mut augmented = read_bare_grammar(rules, lexicon)?;
+
+            let trees: Vec<_> = read_trees(gold)?.into_iter().flatten().collect();
+            let mut counts = Counts::default();
+            let mut underivable = 0;
+            for tree in &trees {
+                let tree_counts = GrammarBare::from(tree.clone());
+                if tree_counts
+                    .rules
+                    .keys()
+                    .any(|r| !augmented.rules.contains_key(r))
+                {
+                    underivable += 1;
+                }
+                counts.absorb(tree_counts);
+            }
+            let added = augmented.add_missing(counts, *smoothing);
+            eprintln!(
+                "Added {} rules for {} of {} gold trees that weren't derivable.",
+                added,
+                underivable,
+                trees.len()
+            );
+
+            let metadata = metadata.with(
+                "augmented",
+                format!("gold={} smoothing={}", gold.display(), smoothing),
+            );
+            write_grammar(
+                &augmented.normalised(),
+                grammar.as_deref(),
+                cli.output.as_deref(),
+                false,
+                &metadata,
+                None,
+                false,
+            )?;
+        }
+        Commands::Interpolate {
+            rules,
+            lexicon,
+            other_rules,
+            other_lexicon,
+            grammar,
+            lambda,
+        } => {
+            // With a weight of 0, the non-terminals of only one grammar would have no weight.
+            if !(*lambda > 0.0 && *lambda < 1.0) {
+                return Err(Error::Usage(String::from(
+                    "--lambda has to be greater than 0 and less than 1",
+                )));
+            }
+
+            let (rules, lexicon) = checked_grammar_files(Path::new(rules), Path::new(lexicon))?;
+            let (other_rules, other_lexicon) =
+                checked_grammar_files(Path::new(other_rules), Path::new(other_lexicon))?;
+            let (metadata, _) = read_grammar_metadata(rules)?;
+            // The grammars are normalised in case they were edited by hand.
+            let first = read_bare_grammar(rules, lexicon)?.normalised();
+            let second = read_bare_grammar(other_rules, other_lexicon)?.normalised();
+            let interpolated = GrammarBare::interpolate([(first, *lambda), (second, 1.0 - lambda)]);
+
+            let metadata = metadata.with(
+                "interpolated",
+                format!("other={} lambda={}", other_rules.display(), lambda),
+            );
+            write_grammar(
+                &interpolated,
+                grammar.as_deref(),
+                cli.output.as_deref(),
+                false,
+                &metadata,
+                None,
+                false,
+            )?;
+        }
+        Commands::FuzzGrammar {
+            iterations,
+            nonterminals,
+            terminals,
+            max_length,
+        } => {
+            if *nonterminals == 0 || *terminals == 0 || *max_length == 0 {
+                return Err(Error::Usage(String::from(
+                    "Grammars and sentences need at least one symbol",
+                )));
+            }
+
+            let config = FuzzConfig {
+                nonterminals: *nonterminals,
+                terminals: *terminals,
+                max_length: *max_length,
+            };
+            let mut rng = XorShift::new(cli.seed);
+            let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+            let mut failures = 0;
+
+            for i in 0..*iterations {
+                let case = FuzzCase::random(&mut rng, &config);
+                if let Err(mismatch) = case.check() {
+                    failures += 1;
+                    writeln!(
+                        out_handle,
+                        "Case {}: {}
+{}
+",
+                        i, mismatch, case
+                    )?;
+                }
+            }
+            out_handle.flush()?;
+
+            eprintln!("{} of {} cases failed.", failures, iterations);
+            if failures > 0 {
+                return Err(Error::Check(format!("{} cases failed", failures)));
+            }
+        }
+        Commands::Eval {
+            predicted,
+            gold,
+            ignore_punctuation,
+            ignore_root,
+            labels,
+        } => {
+            let config = EvalConfig {
+                ignore_punctuation: *ignore_punctuation,
+                ignore_root: *ignore_root,
+            };
+            let mut evaluation = Evaluation::default();
+            let mut predicted_lines = BufReader::new(File::open(predicted)?).lines();
+            let mut gold_lines = BufReader::new(File::open(gold)?).lines();
+
+            for idx in 0.. {
+                let (p, g) = match (predicted_lines.next(), gold_lines.next()) {
+                    (Some(p), Some(g)) => (p?, g?),
+                    (None, None) => break,
+                    (p, _) => {
+                        return Err(Error::Format(format!(
+                            "{} has {} trees than {}",
+                            predicted.display(),
+                            if p.is_none() { "fewer" } else { "more" },
+                            gold.display()
+                        )))
+                    }
+                };
+                match (SExp::from_str(&p), SExp::from_str(&g)) {
+                    (Ok(p), Ok(g)) => match (Tree::try_from(p), Tree::try_from(g)) {
+                        (Ok(p), Ok(g)) => {
+                            let skipped = evaluation.skipped;
+                            let (p, g) = (
+                                project_labels(p, labels.equivalence()),
+                                project_labels(g, labels.equivalence()),
+                            );
+                            evaluation.add(&p, &g, &config);
+                            if evaluation.skipped > skipped {
+                                WARNINGS.warn(
+                                    "alignment",
+                                    Some(idx + 1),
+                                    format_args!(
+                                        "Sentence {}: the trees have different words",
+                                        idx + 1
+                                    ),
+                                );
+                            }
+                        }
+                        (Err(e), _) | (_, Err(e)) => WARNINGS.warn(
+                            "tree",
+                            Some(idx + 1),
+                            format_args!("Sentence {}: error when reading tree: {}", idx + 1, e),
+                        ),
+                    },
+                    (p, g) => WARNINGS.warn(
+                        "sexp",
+                        Some(idx + 1),
+                        format_args!(
+                            "Sentence {}: error when parsing SExp: {:?}",
+                            idx + 1,
+                            p.err().or(g.err())
+                        ),
+                    ),
+                }
+            }
+
+            let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+            write!(out_handle, "{}", evaluation)?;
+            out_handle.flush()?;
+        }
+        Commands::CheckAlignment { sentences, trees } => {
+            let mut sentence_lines = BufReader::new(File::open(sentences)?).lines();
+            let mut tree_lines = BufReader::new(File::open(trees)?).lines();
+
+            let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+            let mut mismatches = 0;
+            let mut idx = 0;
+            loop {
+                idx += 1;
+                let (s, t) = match (sentence_lines.next(), tree_lines.next()) {
+                    (Some(s), Some(t)) => (s?, t?),
+                    (None, None) => break,
+                    (s, _) => {
+                        mismatches += 1;
+                        writeln!(
+                            out_handle,
+                            "Line {}: {} ends before {}",
+                            idx,
+                            if s.is_none() { "SENTENCES" } else { "TREES" },
+                            if s.is_none() { "TREES" } else { "SENTENCES" }
+                        )?;
+                        break;
+                    }
+                };
+
+                match SExp::from_str(&t).map(Tree::try_from) {
+                    Ok(Ok(tree)) => {
+                        let words: Vec<_> = if cli.escape_brackets {
+                            s.split_whitespace().map(escape_word).collect()
+                        } else {
+                            s.split_whitespace().collect()
+                        };
+                        if let Some(m) = check_alignment_with(
+                            &words,
+                            &tree,
+                            &cli.unk_token,
+                            &cli.signature_prefix,
+                        ) {
+                            mismatches += 1;
+                            writeln!(out_handle, "Line {}: {}", idx, m)?;
+                        }
+                    }
+                    Ok(Err(e)) => {
+                        mismatches += 1;
+                        writeln!(out_handle, "Line {}: error when reading tree: {}", idx, e)?;
+                    }
+                    Err(e) => {
+                        mismatches += 1;
+                        writeln!(out_handle, "Line {}: error when parsing SExp: {:?}", idx, e)?;
+                    }
+                }
+            }
+
+            out_handle.flush()?;
+            if mismatches > 0 {
+                return Err(Error::Check(format!("{} lines are misaligned", mismatches)));
+            }
+        }
+        Commands::ToChunks { labels, gold } => {
+            let labels: Vec<&str> = labels.split(',').collect();
+            let trees = tree_lines(input_handle(cli), cli.tree_reading())
+                .enumerate()
+                .filter_map(|(i, l)| tree_from_line(i + 1, &l));
+            let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+
+            match gold {
+                Some(gold) => {
+                    let mut evaluation = ChunkEvaluation::default();
+                    let gold_lines = BufReader::new(File::open(gold)?).lines();
+                    for (idx, (tree, g)) in trees.zip(gold_lines).enumerate() {
+                        let gold_tree = match SExp::from_str(&g?) {
+                            Ok(g) => match Tree::try_from(g) {
+                                Ok(g) => g,
+                                Err(e) => {
+                                    WARNINGS.warn(
+                                        "tree",
+                                        Some(idx + 1),
+                                        format_args!(
+                                            "Sentence {}: error when reading tree: {}",
+                                            idx + 1,
+                                            e
+                                        ),
+                                    );
+                                    continue;
+                                }
+                            },
+                            Err(e) => {
+                                WARNINGS.warn(
+                                    "sexp",
+                                    Some(idx + 1),
+                                    format_args!(
+                                        "Sentence {}: error when parsing SExp: {:?}",
+                                        idx + 1,
+                                        e
+                                    ),
+                                );
+                                continue;
+                            }
+                        };
+                        let len = tree.leaves().len();
+                        if gold_tree.leaves() != tree.leaves() {
+                            WARNINGS.warn(
+                                "alignment",
+                                Some(idx + 1),
+                                format_args!(
+                                    "Sentence {}: the trees don't have the same words",
+                                    idx + 1
+                                ),
+                            );
+                            continue;
+                        }
+                        evaluation.add(&chunks(&tree, &labels), &chunks(&gold_tree, &labels), len);
+                    }
+                    write!(out_handle, "{}", evaluation)?;
+                }
+                None => {
+                    for tree in trees {
+                        let words = tree.leaves();
+                        let iob = iob_tags(&chunks(&tree, &labels), words.len());
+                        for ((word, tag), iob) in words.iter().zip(tags(&tree)).zip(iob) {
+                            let tag = tag.map(|t| t.as_str()).unwrap_or("_");
+                            let word = if cli.escape_brackets {
+                                unescape_word(word)
+                            } else {
+                                word.as_str()
+                            };
+                            writeln!(out_handle, "{}\t{}\t{}", word, tag, iob)?;
+                        }
+                        writeln!(out_handle)?;
+                    }
+                }
+            }
+            out_handle.flush()?;
+        }
+        Commands::Deps { format, head_rules } => {
+            let rules = match head_rules {
+                Some(path) => HeadRules::collins().read(BufReader::new(File::open(path)?))?,
+                None => HeadRules::collins(),
+            };
+            let format = match format {
+                DependencyFormat::Conllu => ConllFormat::Conllu,
+                DependencyFormat::Conllx => ConllFormat::Conllx,
+            };
+            let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+
+            tree_lines(input_handle(cli), cli.tree_reading())
+                .enumerate()
+                .filter_map(|(i, l)| tree_from_line(i + 1, &l))
+                .try_for_each(|t| {
+                    let mut dependencies = dependencies(&t, &rules);
+                    if cli.escape_brackets {
+                        for d in &mut dependencies {
+                            d.word = unescape_word(&d.word).to_string();
+                        }
+                    }
+                    write_conll(&mut out_handle, &dependencies, format)
+                })?;
+            out_handle.flush()?;
+        }
+        Commands::Yield { tags: with_tags } => {
+            let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+
+            tree_lines(input_handle(cli), cli.tree_reading())
+                .enumerate()
+                .filter_map(|(i, l)| tree_from_line(i + 1, &l))
+                .try_for_each(|t| {
+                    let tokens: Vec<String> = t
+                        .leaves()
+                        .into_iter()
+                        .zip(tags(&t))
+                        .map(|(word, tag)| {
+                            let word = if cli.escape_brackets {
+                                unescape_word(word)
+                            } else {
+                                word.as_str()
+                            };
+                            match tag {
+                                Some(tag) if *with_tags => format!("{}/{}", word, tag),
+                                _ => word.to_string(),
+                            }
+                        })
+                        .collect();
+                    writeln!(out_handle, "{}", tokens.join(" "))
+                })?;
+            out_handle.flush()?;
+        }
+        Commands::Render { format, directory } => {
+            let extension = match format {
+                RenderFormat::Latex => "tex",
+                RenderFormat::Dot => "dot",
+            };
+            if let Some(directory) = directory {
+                fs::create_dir_all(directory)?;
+            }
+            let mut out_handle: Box<dyn Write> = match directory {
+                Some(_) => Box::new(io::sink()),
+                None => Box::new(BufWriter::new(output_handle(cli.output.as_deref())?)),
+            };
+
+            let trees = tree_lines(input_handle(cli), cli.tree_reading())
+                .enumerate()
+                .filter_map(|(i, l)| tree_from_line(i + 1, &l));
+            for (i, tree) in trees.enumerate() {
+                let mut figure = vec![];
+                match format {
+                    RenderFormat::Latex => write_latex(&mut figure, &tree)?,
+                    RenderFormat::Dot => write_dot(&mut figure, &tree, &format!("tree{}", i))?,
+                }
+                match directory {
+                    Some(directory) => {
+                        fs::write(directory.join(format!("{:06}.{}", i, extension)), &figure)?
+                    }
+                    None => out_handle.write_all(&figure)?,
+                }
+            }
+            out_handle.flush()?;
+        }
+        Commands::Pretty { ascii } => {
+            let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+
+            tree_lines(input_handle(cli), cli.tree_reading())
+                .enumerate()
+                .filter_map(|(i, l)| tree_from_line(i + 1, &l))
+                .try_for_each(|t| writeln!(out_handle, "{}", t.pretty_print(*ascii)))?;
+            out_handle.flush()?;
+        }
+        Commands::GraphGrammar {
+            rules,
+            lexicon,
+            format,
+            weight,
+            threshold,
+            initial_nonterminal,
+        } => {
+            let mut graph = GrammarGraph::new();
+            read_weighted_rules(Path::new(rules), false, |_| true)?
+                .for_each(|r| graph.insert_rule(&r.rule, r.weight.0));
+            if let Some(lexicon) = lexicon {
+                read_weighted_rules(Path::new(lexicon), true, |_| true)?
+                    .for_each(|r| graph.insert_rule(&r.rule, r.weight.0));
+            }
+
+            let weight = match weight {
+                GraphWeight::Probability => EdgeWeight::Probability,
+                GraphWeight::Rules => EdgeWeight::Rules,
+            };
+            let initial = initial_nonterminals(initial_nonterminal)?;
+            let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+            match format {
+                GraphFormat::Dot => {
+                    graph.write_dot(&mut out_handle, &initial, weight, *threshold)?
+                }
+                GraphFormat::Json => {
+                    graph.write_json(&mut out_handle, &initial, weight, *threshold)?
+                }
+            }
+            out_handle.flush()?;
+        }
+        Commands::Rescore {
+            rules,
+            lexicon,
+            unking,
+            smoothing,
+            unk_model,
+            signature_features,
+            forest,
+            threshold,
+            initial_nonterminal,
+        } => {
+            if *unking && *smoothing {
+                return Err(Error::Usage(String::from(
+                    "Unking and smoothing are mutually exclusive. Only use one",
+                )));
+            }
+            let signatures = unk_model.scheme(signature_features, &cli.signature_prefix)?;
+            let initial = initial_nonterminals(initial_nonterminal)?;
+            let mut grammar = GrammarParse::new(initial[0].clone());
+            for n in &initial[1..] {
+                grammar.add_initial_nonterminal(n.clone());
+            }
+            let (rules, lexicon) = checked_grammar_files(Path::new(rules), Path::new(lexicon))?;
+            read_weighted_rules(rules, false, |_| true)?
+                .chain(read_weighted_rules(lexicon, true, |_| true)?)
+                .try_for_each(|r| grammar.insert_rule(r))
+                .map_err(|e| Error::Format(e.to_string()))?;
+            grammar
+                .precompute_unary_closure()
+                .map_err(|c| Error::Format(c.to_string()))?;
+
+            // The words of the grammar for the words of a sentence, as parse uses them.
+            let grammar_words = |sentence: &mut Sentence<SmallString<[u8; 8]>>| {
+                if *unking {
+                    sentence.unkify_with(&grammar.rules_lexical, &cli.unk_token)
+                } else if *smoothing {
+                    sentence.smooth_with(&grammar.rules_lexical, &signatures)
+                } else {
+                    None
+                }
+            };
+
+            let mut handle = input_handle(cli);
+            let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+            let mut idx = 0;
+            loop {
+                idx += 1;
+                if *forest {
+                    let mut forest = match Forest::from_reader(&mut handle) {
+                        Ok(Some(forest)) => forest,
+                        Ok(None) => break,
+                        Err(e) => {
+                            WARNINGS.warn(
+                                "forest",
+                                Some(idx),
+                                format_args!("Forest {}: {}", idx, e),
+                            );
+                            writeln!(out_handle, "{}", Sentence::<String>(vec![]).into_noparse())?;
+                            continue;
+                        }
+                    };
+
+                    let words = Sentence(forest.sentence.0.clone());
+                    let wmap = grammar_words(&mut forest.sentence);
+                    let len = forest.sentence.len();
+                    let mask = grammar.span_mask(len, &forest.spans, *threshold);
+                    let mode = PruneMode::empty().with_pruner(PosteriorPruner(mask));
+                    match grammar.cyk(&forest.sentence, &mode) {
+                        Some(mut tree) => {
+                            if let Some(wmap) = wmap {
+                                tree.deunkify(wmap);
+                            }
+                            writeln!(out_handle, "{}", tree)?
+                        }
+                        None => writeln!(out_handle, "{}", words.into_noparse())?,
+                    }
+                } else {
+                    let list = match KBestList::from_reader(&mut handle) {
+                        Ok(Some(list)) => list,
+                        Ok(None) => break,
+                        Err(e) => {
+                            WARNINGS.warn("kbest", Some(idx), format_args!("List {}: {}", idx, e));
+                            writeln!(
+                                out_handle,
+                                "{}\n",
+                                Sentence::<String>(vec![]).into_noparse()
+                            )?;
+                            continue;
+                        }
+                    };
+
+                    let words = list
+                        .trees
+                        .first()
+                        .map(|(tree, _)| Sentence(tree.leaves().into_iter().cloned().collect()))
+                        .unwrap_or(Sentence(vec![]));
+                    let mut rescored: Vec<_> = list
+                        .trees
+                        .into_iter()
+                        .filter_map(|(tree, _)| {
+                            // The tree is scored with the words of the grammar.
+                            let mut scored = tree.clone();
+                            let mut sentence =
+                                Sentence(tree.leaves().into_iter().cloned().collect());
+                            grammar_words(&mut sentence);
+                            for (leaf, word) in scored.leaves_mut().into_iter().zip(sentence.0) {
+                                *leaf = word;
+                            }
+                            let p = tree_inside_score(&scored.into_node_types(), &grammar)?;
+                            Some((tree, Some(p)))
+                        })
+                        .collect();
+                    rescored.sort_by_key(|(_, p)| Reverse(p.map(FloatOrd)));
+                    if rescored.is_empty() {
+                        writeln!(out_handle, "{}\n", words.into_noparse())?
+                    } else {
+                        writeln!(out_handle, "{}", KBestList { trees: rescored })?
+                    }
+                }
+            }
+            out_handle.flush()?;
+        }
+        Commands::Perplexity {
+            rules,
+            lexicon,
+            initial_nonterminal,
+            unking,
+            smoothing,
+            unk_model,
+            signature_features,
+        } => {
+            let initial = initial_nonterminals(initial_nonterminal)?;
+            let mut grammar = GrammarParse::new(initial[0].clone());
+            for n in &initial[1..] {
+                grammar.add_initial_nonterminal(n.clone());
+            }
+            let (rules, lexicon) = checked_grammar_files(Path::new(rules), Path::new(lexicon))?;
+            read_weighted_rules(rules, false, |_| true)?
+                .chain(read_weighted_rules(lexicon, true, |_| true)?)
+                .try_for_each(|r| grammar.insert_rule(r))
+                .map_err(|e| Error::Format(e.to_string()))?;
+
+            let signatures = unk_model.scheme(signature_features, &cli.signature_prefix)?;
+            let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+            let mut log_prob = 0.0;
+            let mut words = 0;
+            let mut sentences = 0;
+            let mut underivable = 0;
+            for (idx, line) in input_handle(cli).lines().enumerate() {
+                let line = line?;
+                let line = if cli.escape_brackets {
+                    escape_sentence(&line).into_owned()
+                } else {
+                    line
+                };
+                let mut sentence = match Sentence::from_str(&line) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        WARNINGS.warn(
+                            "sentence",
+                            Some(idx + 1),
+                            format_args!("Error when parsing sentence: {:?}", e),
+                        );
+                        Sentence(vec![])
+                    }
+                };
+                if *unking {
+                    sentence.unkify_with(&grammar.rules_lexical, &cli.unk_token);
+                } else if *smoothing {
+                    sentence.smooth_with(&grammar.rules_lexical, &signatures);
+                }
+
+                match grammar.sentence_log_prob(&sentence) {
+                    Some(p) => {
+                        writeln!(out_handle, "{}", p)?;
+                        log_prob += p;
+                        words += sentence.len();
+                        sentences += 1;
+                    }
+                    None => {
+                        writeln!(out_handle, "{}", f64::NEG_INFINITY)?;
+                        underivable += 1;
+                    }
+                }
+            }
+            out_handle.flush()?;
+
+            eprintln!(
+                "Perplexity {:.4} over {} words of {} sentences, {} sentences can't be derived.",
+                (-log_prob / words as f64).exp(),
+                words,
+                sentences,
+                underivable
+            );
+        }
+        Commands::Generate {
+            rules,
+            lexicon,
+            number,
+            yields,
+            max_depth,
+            initial_nonterminal,
+        } => {
+            let mut sampler = GrammarSampler::new();
+            let (rules, lexicon) = checked_grammar_files(Path::new(rules), Path::new(lexicon))?;
+            read_weighted_rules(rules, false, |_| true)?
+                .chain(read_weighted_rules(lexicon, true, |_| true)?)
+                .for_each(|r| sampler.insert_rule(r.rule, r.weight.0));
+            let initial = SmallString::from(initial_nonterminal.as_str());
+
+            let mut rng = XorShift::new(cli.seed);
+            let mut out_handle = BufWriter::new(output_handle(cli.output.as_deref())?);
+            let mut discarded = 0;
+            let mut generated = 0;
+            while generated < *number {
+                let tree = match sampler.sample(&initial, &mut rng, *max_depth) {
+                    Ok(tree) => tree,
+                    Err(SampleError::TooDeep) => {
+                        discarded += 1;
+                        // Give up on grammars whose derivations hardly ever end.
+                        if discarded >= 100 * (*number).max(10) {
+                            return Err(Error::Check(format!(
+                                "{} derivations exceeded --max-depth {}",
+                                discarded, max_depth
+                            )));
+                        }
+                        continue;
+                    }
+                    Err(e) => return Err(Error::Format(e.to_string())),
+                };
+                generated += 1;
+                if *yields {
+                    let words: Vec<_> = tree
+                        .leaves()
+                        .into_iter()
+                        .map(|w| {
+                            if cli.escape_brackets {
+                                unescape_word(w)
+                            } else {
+                                w.as_str()
+                            }
+                        })
+                        .collect();
+                    writeln!(out_handle, "{}", words.join(" "))?;
+                } else {
+                    writeln!(out_handle, "{}", tree)?;
+                }
+            }
+            out_handle.flush()?;
+            if discarded > 0 {
+                eprintln!(
+                    "Discarded {} derivations deeper than {} levels.",
+                    discarded, max_depth
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+enum UnkingMode<'a> {
+    /// Unknown words are replaced with the given token.
+    Trivial(&'a str),
+    Smoothing(SignatureScheme),
+}
+
+fn unking<R: BufRead, W: Write>(
+    mode: UnkingMode<'_>,
+    threshold: usize,
+    handle: R,
+    reading: TreeReading,
+    mut out: W,
+) -> io::Result<()> {
+    let mut word_count = FxHashMap::default();
+
+    let mut trees: Vec<_> = tree_lines(handle, reading)
+        .enumerate()
+        .filter_map(|(i, l)| tree_from_line(i + 1, &l))
+        .collect();
+
+    for tree in &trees {
+        unk::count_words(tree, &mut word_count);
+    }
+
+    // We keep all words that we don't want to unkify.
+    word_count.retain(|_, v| *v > threshold);
+    let word_count = word_count;
+
+    for t in trees.iter_mut() {
+        match &mode {
+            UnkingMode::Trivial(token) => t.unkify_with(&word_count, token),
+            UnkingMode::Smoothing(scheme) => t.smooth_with(&word_count, scheme),
+        };
+        writeln!(out, "{}", t)?;
+    }
+    out.flush()
+}
+
+/// Runs the stages of `pipeline` on the lines of `input`, handing the trees from one stage to the
+/// next as they are produced. Only unking and smoothing of treebanks, which count the words of
+/// all trees first, and the last stage collect the trees. Sentences are parsed in batches in
+/// parallel. Every line keeps its place in the stream, as `None` if it has no tree, e.g. an
+/// unreadable line or a tree that preprocessing removes, so that the trees stay aligned with
+/// the gold trees of eval. Such lines are printed as NOPARSE.
+fn run_pipeline<R: BufRead>(
+    pipeline: &Pipeline,
+    input: R,
+    reading: TreeReading,
+    unk_token: &str,
+    signatures: &SignatureScheme,
+    output: Option<&Path>,
+) -> Result<(), Error> {
+    type Trees<'a> = Box<dyn Iterator<Item = Option<Tree<SmallString<[u8; 8]>>>> + 'a>;
+
+    // Sentences for parse are always read one per line.
+    let reads_trees = !matches!(pipeline.stages.first(), Some(Stage::Parse { .. }));
+    let lines = tree_lines(
+        input,
+        if reads_trees {
+            reading
+        } else {
+            TreeReading::default()
+        },
+    );
+    let escape_brackets = reading.escape_brackets;
+
+    let mut trees: Trees = match pipeline.stages.first() {
+        Some(Stage::Parse {
+            rules,
+            lexicon,
+            initial_nonterminal,
+            unking,
+        }) => {
+            let initial = initial_nonterminals(initial_nonterminal)?;
+            let mut grammar = GrammarParse::new(initial[0].clone());
+            for n in &initial[1..] {
+                grammar.add_initial_nonterminal(n.clone());
+            }
+            let (rules, lexicon) = checked_grammar_files(rules, lexicon)?;
+            read_weighted_rules(rules, false, |_| true)?
+                .chain(read_weighted_rules(lexicon, true, |_| true)?)
+                .try_for_each(|r| grammar.insert_rule(r))
+                .map_err(|e| Error::Format(e.to_string()))?;
+            grammar
+                .precompute_unary_closure()
+                .map_err(|c| Error::Format(c.to_string()))?;
+
+            let unking = *unking;
+            let mode = PruneMode::empty();
+            let parse = move |line: usize, l: &str| {
+                let s = if escape_brackets {
+                    Sentence::from_str(&escape_sentence(l))
+                } else {
+                    Sentence::from_str(l)
+                };
+                if s.is_err() {
+                    WARNINGS.warn(
+                        "sentence",
+                        Some(line),
+                        format_args!("Error when parsing sentence: {:?}", s),
+                    );
+                }
+                let mut s = s.ok()?;
+
+                let wmap = match unking {
+                    SentenceUnking::None => None,
+                    SentenceUnking::Unk => s.unkify_with(&grammar.rules_lexical, unk_token),
+                    SentenceUnking::Smooth => s.smooth_with(&grammar.rules_lexical, signatures),
+                };
+                let mut t = match grammar.cyk(&s, &mode) {
+                    Some(t) => t,
+                    None => s.into_noparse(),
+                };
+                if let Some(wmap) = wmap {
+                    t.deunkify(wmap);
+                }
+                Some(t.map(&mut |n| SmallString::from(n.to_string().as_str())))
+            };
+
+            let mut lines = lines.enumerate();
+            Box::new(
+                std::iter::from_fn(move || {
+                    let batch: Vec<(usize, String)> = lines.by_ref().take(LINES_READ).collect();
+                    (!batch.is_empty()).then(|| {
+                        batch
+                            .par_iter()
+                            .map(|(i, l)| parse(i + 1, l))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .flatten(),
+            )
+        }
+        _ => Box::new(lines.enumerate().map(|(i, l)| tree_from_line(i + 1, &l))),
+    };
+
+    for stage in &pipeline.stages {
+        trees = match stage {
+            // Only ever the first stage, which reads the sentences.
+            Stage::Parse { .. } => trees,
+            // Every line keeps its place, so the position is the number of the line.
+            Stage::Preprocess => Box::new(trees.enumerate().map(|(i, t)| {
+                let preprocessed = preprocess(t?);
+                if preprocessed.is_none() {
+                    WARNINGS.warn("tree", Some(i + 1), "Tree only covers empty elements");
+                }
+                preprocessed
+            })),
+            Stage::Binarise {
+                vertical,
+                horizontal,
+                direction,
+            } => {
+                let params =
+                    MarkovParams::uniform(*vertical, *horizontal).with_direction(*direction);
+                Box::new(trees.map(move |t| {
+                    t.map(|t| {
+                        t.markovize_with(&params, &[])
+                            .map(&mut |n| SmallString::from(n.to_string().as_str()))
+                    })
+                }))
+            }
+            Stage::Debinarise => Box::new(trees.enumerate().map(|(i, t)| {
+                let debinarised = t?.parse_markovized().debinarize();
+                if let Err(e) = &debinarised {
+                    WARNINGS.warn("tree", Some(i + 1), e);
+                }
+                debinarised.ok()
+            })),
+            Stage::Unk { threshold } | Stage::Smooth { threshold } => {
+                let mut trees: Vec<_> = trees.collect();
+                let mut word_count = FxHashMap::default();
+                for tree in trees.iter().flatten() {
+                    unk::count_words(tree, &mut word_count);
+                }
+                word_count.retain(|_, v| *v > *threshold);
+
+                for t in trees.iter_mut().flatten() {
+                    match stage {
+                        Stage::Unk { .. } => t.unkify_with(&word_count, unk_token),
+                        _ => t.smooth_with(&word_count, signatures),
+                    };
+                }
+                Box::new(trees.into_iter())
+            }
+            Stage::Induce { grammar } => {
+                let mut counts = Counts::default();
+                for t in trees.flatten() {
+                    counts.absorb(GrammarBare::from(t));
+                }
+                let metadata = GrammarMetadata::default()
+                    .with(
+                        "creator",
+                        format!("pcfg_tool {}", env!("CARGO_PKG_VERSION")),
+                    )
+                    .with("corpus", "STDIN")
+                    .with("options", format!("pipeline={}", pipeline));
+                if let Some(grammar_name) = grammar {
+                    let mut pipeline_file = File::create(format!("{}.pipeline", grammar_name))?;
+                    metadata
+                        .clone()
+                        .with("pipeline", pipeline)
+                        .with("unk-token", unk_token)
+                        .with("signature-prefix", &signatures.prefix)
+                        .write(&mut pipeline_file)?;
+                }
+                write_grammar(
+                    &GrammarBare::from(counts),
+                    grammar.as_deref(),
+                    output,
+                    false,
+                    &metadata,
+                    None,
+                    false,
+                )?;
+                return Ok(());
+            }
+            Stage::Eval { gold } => {
+                let config = EvalConfig::default();
+                let mut evaluation = Evaluation::default();
+                let mut gold_lines = BufReader::new(File::open(gold)?).lines();
+
+                for idx in 0.. {
+                    let (p, g) = match (trees.next(), gold_lines.next()) {
+                        (Some(p), Some(g)) => (p, g?),
+                        (None, None) => break,
+                        (p, _) => {
+                            return Err(Error::Format(format!(
+                                "the pipeline has {} trees than {}",
+                                if p.is_none() { "fewer" } else { "more" },
+                                gold.display()
+                            )))
+                        }
+                    };
+                    let p = match p {
+                        Some(p) => p,
+                        None => {
+                            WARNINGS.warn(
+                                "tree",
+                                Some(idx + 1),
+                                format_args!("Sentence {}: no predicted tree", idx + 1),
+                            );
+                            continue;
+                        }
+                    };
+                    match SExp::from_str(&g).map(Tree::try_from) {
+                        Ok(Ok(g)) => {
+                            let skipped = evaluation.skipped;
+                            evaluation.add(&p, &g, &config);
+                            if evaluation.skipped > skipped {
+                                WARNINGS.warn(
+                                    "alignment",
+                                    Some(idx + 1),
+                                    format_args!(
+                                        "Sentence {}: the trees have different words",
+                                        idx + 1
+                                    ),
+                                );
+                            }
+                        }
+                        Ok(Err(e)) => WARNINGS.warn(
+                            "tree",
+                            Some(idx + 1),
+                            format_args!("Sentence {}: error when reading tree: {}", idx + 1, e),
+                        ),
+                        Err(e) => WARNINGS.warn(
+                            "sexp",
+                            Some(idx + 1),
+                            format_args!("Sentence {}: error when parsing SExp: {:?}", idx + 1, e),
+                        ),
+                    }
+                }
+
+                let mut out_handle = BufWriter::new(output_handle(output)?);
+                write!(out_handle, "{}", evaluation)?;
+                out_handle.flush()?;
+                return Ok(());
+            }
+        };
+    }
+
+    let mut out_handle = BufWriter::new(output_handle(output)?);
+    for t in trees {
+        match t {
+            Some(t) => writeln!(out_handle, "{}", t)?,
+            None => writeln!(out_handle, "{}", Sentence::<String>(vec![]).into_noparse())?,
+        }
+    }
+    out_handle.flush()?;
+    Ok(())
 }
 
-fn unking(mode: UnkingMode, threshold: usize) {
-    let stdin = io::stdin();
-    let handle = stdin.lock();
+/// Rules, with their lines, lexical rules and terminals of a grammar that may violate CNF.
+type CnfInput = (
+    GrammarMetadata,
+    Vec<(String, ParsedWeightedRule)>,
+    Vec<ParsedWeightedRule>,
+    FxHashSet<SmallString<[u8; 8]>>,
+);
 
-    let mut word_count = FxHashMap::default();
+/// Reads a grammar for check-cnf and cnf. RULES may contain rules with an empty RHS, and
+/// terminals are only recognised if LEXICON is given.
+fn read_cnf_input(rules: &Path, lexicon: Option<&Path>) -> io::Result<CnfInput> {
+    let (metadata, reader) = read_grammar_metadata(rules)?;
+    let header = metadata.entries().len();
+    let mut non_lexical = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        match WeightedRule::from_str(&line) {
+            // An empty RHS is parsed as lexical rule with "->" as terminal.
+            Ok(WeightedRule {
+                rule: Rule::Lexical { lhs, rhs },
+                weight,
+            }) if rhs.as_str() == "->" => non_lexical.push((
+                line,
+                WeightedRule {
+                    rule: Rule::NonLexical { lhs, rhs: vec![] },
+                    weight,
+                },
+            )),
+            Ok(r) => non_lexical.push((line, r)),
+            Err(e) => WARNINGS.warn(
+                "rule",
+                Some(header + i + 1),
+                format_args!("Error when parsing non-lexical rule: {:?}", e),
+            ),
+        }
+    }
+    let lexical: Vec<_> = match lexicon {
+        Some(lexicon) => read_weighted_rules(lexicon, true, |_| true)?.collect(),
+        None => vec![],
+    };
+
+    let nonterminals: FxHashSet<_> = non_lexical
+        .iter()
+        .map(|(_, r)| &r.rule)
+        .chain(lexical.iter().map(|r| &r.rule))
+        .map(|r| match r {
+            Rule::Lexical { lhs, .. } | Rule::NonLexical { lhs, .. } => lhs.clone(),
+        })
+        .collect();
+    let terminals: FxHashSet<_> = lexical
+        .iter()
+        .filter_map(|r| match &r.rule {
+            Rule::Lexical { rhs, .. } if !nonterminals.contains(rhs) => Some(rhs.clone()),
+            _ => None,
+        })
+        .collect();
+
+    Ok((metadata, non_lexical, lexical, terminals))
+}
+
+/// Checks on a sample of lines that RULES holds non-lexical and LEXICON lexical rules.
+/// If the two files were swapped, they are returned in the correct order.
+fn checked_grammar_files<'a>(
+    rules: &'a Path,
+    lexicon: &'a Path,
+) -> Result<(&'a Path, &'a Path), Error> {
+    // Rules and lexicon can be the same file, e.g. a JSON grammar.
+    if rules == lexicon {
+        return Ok((rules, lexicon));
+    }
+    match (mostly_lexical(rules)?, mostly_lexical(lexicon)?) {
+        (Some(true), Some(false)) => {
+            eprintln!(
+                "{} contains lexical and {} non-lexical rules. Using them the other way around.",
+                rules.display(),
+                lexicon.display()
+            );
+            Ok((lexicon, rules))
+        }
+        (Some(true), _) => Err(Error::Format(format!(
+            "{} contains lexical rules, but the RULES argument expects non-lexical rules",
+            rules.display()
+        ))),
+        (_, Some(false)) => Err(Error::Format(format!(
+            "{} contains non-lexical rules, but the LEXICON argument expects lexical rules",
+            lexicon.display()
+        ))),
+        _ => Ok((rules, lexicon)),
+    }
+}
+
+/// Returns whether the file holds a grammar written by the compile subcommand.
+fn is_compiled_grammar(path: &Path) -> io::Result<bool> {
+    let mut reader = BufReader::new(File::open(path)?);
+    Ok(GrammarFormat::detect(reader.fill_buf()?) == GrammarFormat::Binary)
+}
+
+/// Number of lines looked at to decide which kind of rules a grammar file contains.
+const RULE_SAMPLE_SIZE: usize = 100;
+
+/// Returns whether the majority of the first lines of a grammar file are lexical rules,
+/// or `None` if the file is empty.
+fn mostly_lexical(path: &Path) -> io::Result<Option<bool>> {
+    let reader = open_grammar_file(path)?;
+    let mut lexical = 0;
+    let mut total = 0;
+
+    for line in reader.lines().take(RULE_SAMPLE_SIZE) {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        total += 1;
+        if line.split_whitespace().nth(1) != Some("->") {
+            lexical += 1;
+        }
+    }
+
+    Ok(if total == 0 {
+        None
+    } else {
+        Some(lexical * 2 > total)
+    })
+}
+
+/// Opens a grammar file. All grammar files are opened through this function, so that grammars
+/// in the formats of other toolkits are read as well, and compiled grammars are rejected with a
+/// clear message instead of a parse error for every line. The metadata header is skipped.
+fn open_grammar_file(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    read_grammar_metadata(path).map(|(_, reader)| reader)
+}
+
+/// Like `open_grammar_file`, but also returns the metadata header of the file. Berkeley and
+/// JSON grammars are converted to the native format in memory.
+fn read_grammar_metadata(path: &Path) -> io::Result<(GrammarMetadata, Box<dyn BufRead>)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let in_file = |e: io::Error| io::Error::new(e.kind(), format!("{}: {}", path.display(), e));
+
+    match GrammarFormat::detect(reader.fill_buf()?) {
+        GrammarFormat::Native => Ok((GrammarMetadata::read(&mut reader)?, Box::new(reader))),
+        GrammarFormat::Berkeley => {
+            let metadata = GrammarMetadata::read(&mut reader)?;
+            let native = berkeley_to_native(reader).map_err(in_file)?;
+            Ok((metadata, Box::new(io::Cursor::new(native))))
+        }
+        GrammarFormat::Json => {
+            let mut text = String::new();
+            reader.read_to_string(&mut text)?;
+            let (metadata, native) = json_to_native(&text).map_err(in_file)?;
+            Ok((metadata, Box::new(io::Cursor::new(native))))
+        }
+        GrammarFormat::Binary => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} seems to be a compiled grammar, which can only be read as RULES of parse",
+                path.display()
+            ),
+        )),
+    }
+}
+
+/// The rules of RULES and LEXICON with their weights, to be changed and written again.
+fn read_bare_grammar(
+    rules: &Path,
+    lexicon: &Path,
+) -> io::Result<GrammarBare<SmallString<[u8; 8]>, SmallString<[u8; 8]>, f64>> {
+    let mut grammar = GrammarBare::new();
+    for r in read_weighted_rules(rules, false, |_| true)?.chain(read_weighted_rules(
+        lexicon,
+        true,
+        |_| true,
+    )?) {
+        grammar.rules.insert(r.rule, r.weight.0);
+    }
+    Ok(grammar)
+}
+
+/// Reads the rules of a grammar file. Rules of the wrong kind for the file are skipped.
+/// Lines for which `keep_line` returns false are skipped without being parsed.
+fn read_weighted_rules<F: FnMut(&str) -> bool>(
+    path: &Path,
+    lexical: bool,
+    mut keep_line: F,
+) -> io::Result<impl Iterator<Item = ParsedWeightedRule>> {
+    let (metadata, reader) = read_grammar_metadata(path)?;
+    let header = metadata.entries().len();
+
+    Ok(reader
+        .lines()
+        .enumerate()
+        .filter_map(move |(i, l)| {
+            if l.is_err() {
+                WARNINGS.warn(
+                    "read",
+                    Some(header + i + 1),
+                    format_args!("Error when reading line: {:?}", l),
+                );
+            }
+            Some((header + i + 1, l.ok()?))
+        })
+        .filter(move |(_, l)| keep_line(l))
+        .map(|(i, l)| (i, WeightedRule::from_str(&l)))
+        .filter_map(move |(i, r)| {
+            if r.is_err() {
+                if lexical {
+                    WARNINGS.warn(
+                        "rule",
+                        Some(i),
+                        format_args!("Error when parsing lexical rule: {:?}", r),
+                    );
+                } else {
+                    WARNINGS.warn(
+                        "rule",
+                        Some(i),
+                        format_args!("Error when parsing non-lexical rule: {:?}", r),
+                    );
+                }
+            }
+
+            match &r {
+                Ok(WeightedRule {
+                    rule: Rule::Lexical { .. },
+                    ..
+                }) if !lexical => {
+                    WARNINGS.warn(
+                        "rule",
+                        Some(i),
+                        format_args!(
+                            "Lexical rule parsed when parsing non-lexical rules: {:?}",
+                            r
+                        ),
+                    );
+                    None
+                }
+                Ok(WeightedRule {
+                    rule: Rule::NonLexical { .. },
+                    ..
+                }) if lexical => {
+                    WARNINGS.warn(
+                        "rule",
+                        Some(i),
+                        format_args!(
+                            "Non-lexical rule parsed when parsing lexical rules: {:?}",
+                            r
+                        ),
+                    );
+                    None
+                }
+                _ => r.ok(),
+            }
+        }))
+}
 
-    let mut trees: Vec<_> = handle
+/// Reads a file in the format of the grammar files and collects its rules.
+fn read_rule_set(path: &Path) -> io::Result<FxHashSet<ParsedRule>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let header = GrammarMetadata::read(&mut reader)?.entries().len();
+
+    Ok(reader
         .lines()
-        .filter_map(|l| {
+        .enumerate()
+        .map(|(i, l)| (header + i, l))
+        .filter_map(|(i, l)| {
             if l.is_err() {
-                eprintln!("Error when reading line: {:?}", l);
+                WARNINGS.warn(
+                    "read",
+                    Some(i + 1),
+                    format_args!("Error when reading line: {:?}", l),
+                );
             }
-            l.ok()
+            Some((i, l.ok()?))
         })
-        .map(|l| SExp::from_str(&l))
-        .filter_map(|s| {
-            if s.is_err() {
-                eprintln!("Error when parsing SExp: {:?}", s);
+        .map(|(i, l)| (i, WeightedRule::from_str(&l)))
+        .filter_map(|(i, r)| {
+            if r.is_err() {
+                WARNINGS.warn(
+                    "rule",
+                    Some(i + 1),
+                    format_args!("Error when parsing rule: {:?}", r),
+                );
             }
-            s.ok()
+            r.ok()
         })
-        .map(Tree::from)
-        .collect();
+        .map(|r| r.rule)
+        .collect())
+}
 
-    for tree in &trees {
-        unk::count_words(tree, &mut word_count);
+/// Reads a file with one constituent tree per line. Lines that can't be read are reported and
+/// kept as `None`, so that the i-th entry always belongs to the i-th line.
+fn read_trees(path: &Path) -> io::Result<Vec<Option<Tree<SmallString<[u8; 8]>>>>> {
+    let reader = BufReader::new(File::open(path)?);
+
+    Ok(reader
+        .lines()
+        .enumerate()
+        .map(|(i, l)| {
+            if l.is_err() {
+                WARNINGS.warn(
+                    "read",
+                    Some(i + 1),
+                    format_args!("Error when reading line: {:?}", l),
+                );
+            }
+            tree_from_line(i + 1, &l.ok()?)
+        })
+        .collect())
+}
+
+fn chunk_path(dir: &Path, idx: usize) -> PathBuf {
+    dir.join(format!("{:06}.trees", idx))
+}
+
+/// Output file of a file in a watched directory.
+fn watch_output_path(input: &Path) -> PathBuf {
+    let mut name = input.file_name().unwrap_or_default().to_os_string();
+    name.push(".trees");
+    input.with_file_name(name)
+}
+
+fn watch_tmp_path(input: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(input.file_name().unwrap_or_default());
+    name.push(".tmp");
+    input.with_file_name(name)
+}
+
+/// Waits until a file without output appears in the watched directory `dir` and returns
+/// its path and content. Hidden files and output files are ignored. Files that can't be
+/// read are reported once and added to `failed`, so that they are not tried again.
+fn next_watched_file(
+    dir: &Path,
+    interval: Duration,
+    failed: &mut FxHashSet<PathBuf>,
+) -> io::Result<(PathBuf, String)> {
+    loop {
+        let mut pending: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| {
+                let name = p.file_name().unwrap_or_default().to_string_lossy();
+                p.is_file()
+                    && !name.starts_with('.')
+                    && !name.ends_with(".trees")
+                    && !watch_output_path(p).exists()
+                    && !failed.contains(p)
+            })
+            .collect();
+        pending.sort();
+
+        for path in pending {
+            match fs::read_to_string(&path) {
+                Ok(input) => return Ok((path, input)),
+                Err(e) => {
+                    WARNINGS.warn(
+                        "read",
+                        None,
+                        format_args!("Error when reading {}: {:?}", path.display(), e),
+                    );
+                    failed.insert(path);
+                }
+            }
+        }
+        thread::sleep(interval);
     }
+}
 
-    // We keep all words that we don't want to unkify.
-    word_count.retain(|_, v| *v > threshold);
-    let word_count = word_count;
+type TaggedWord = (SmallString<[u8; 8]>, SmallString<[u8; 8]>);
+// Printed trees of a sentence, with --rule-provenance their rules, and whether the sentence
+// couldn't be parsed.
+type ParseResult = (String, Option<String>, bool);
+
+/// Corpus for grammar induction with its weight in the mixture of all corpora.
+struct WeightedCorpus {
+    path: PathBuf,
+    weight: f64,
+}
+
+impl FromStr for WeightedCorpus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, weight) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("expected PATH:WEIGHT, found {}", s))?;
+        match weight.parse::<f64>() {
+            Ok(weight) if weight > 0.0 => Ok(WeightedCorpus {
+                path: PathBuf::from(path),
+                weight,
+            }),
+            _ => Err(format!("invalid weight {}", weight)),
+        }
+    }
+}
+
+fn write_mixture<W: Write>(out: &mut W, corpus: &[WeightedCorpus]) -> io::Result<()> {
+    let total: f64 = corpus.iter().map(|c| c.weight).sum();
+    for c in corpus {
+        writeln!(out, "{}\t{}", c.path.display(), c.weight / total)?;
+    }
+    Ok(())
+}
+
+/// Counts the trees of a corpus and, with `DuplicateTrees::Once`, skips all but the first
+/// occurrence of every tree.
+struct DuplicateFilter {
+    mode: DuplicateTrees,
+    // Lines seen so far, with their tokens separated by single spaces.
+    seen: FxHashSet<String>,
+    trees: usize,
+    duplicates: usize,
+}
+
+impl DuplicateFilter {
+    fn new(mode: DuplicateTrees) -> Self {
+        Self {
+            mode,
+            seen: FxHashSet::default(),
+            trees: 0,
+            duplicates: 0,
+        }
+    }
+
+    /// Returns whether the line is used for induction. Lines are compared ignoring whitespace.
+    fn keep(&mut self, line: &str) -> bool {
+        let normalised = line.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        self.trees += 1;
+        if self.seen.insert(normalised) {
+            true
+        } else {
+            self.duplicates += 1;
+            self.mode == DuplicateTrees::Count
+        }
+    }
+
+    fn report(&self, corpus: &str) {
+        if self.duplicates > 0 {
+            eprintln!(
+                "{}: {} of {} trees are duplicates ({:.2}%){}.",
+                corpus,
+                self.duplicates,
+                self.trees,
+                100.0 * self.duplicates as f64 / self.trees as f64,
+                match self.mode {
+                    DuplicateTrees::Count => "",
+                    DuplicateTrees::Once => ", counted once",
+                }
+            );
+        }
+    }
+}
+
+/// Rule counts of a single tree or tagged sentence.
+type Counts = GrammarBare<SmallString<[u8; 8]>, SmallString<[u8; 8]>, u32>;
+
+/// Counts the rules of every tree, or every tagged sentence, of `reader` and passes them to
+/// `absorb`. With `tag_bigrams`, tagged sentences add the rules of a bigram model of their tags
+/// with this initial non-terminal. Every line of the corpus is fed into `hasher`, including the
+/// duplicates skipped by `filter`.
+#[allow(clippy::too_many_arguments)]
+fn induce_counts<R, F>(
+    reader: R,
+    tagged: bool,
+    tag_bigrams: Option<&str>,
+    weighted: bool,
+    reading: TreeReading,
+    preterminal_suffix: Option<&str>,
+    binarisation: Option<&MarkovParams>,
+    hasher: &mut FxHasher,
+    filter: &mut DuplicateFilter,
+    mut absorb: F,
+) -> io::Result<()>
+where
+    R: BufRead,
+    F: FnMut(Counts, f64) -> io::Result<()>,
+{
+    // Tagged sentences are read as they are.
+    let lines = tree_lines(
+        reader,
+        if tagged {
+            TreeReading::default()
+        } else {
+            reading
+        },
+    )
+    .enumerate()
+    .inspect(|(_, l)| {
+        hasher.write(l.as_bytes());
+        hasher.write_u8(b'\n');
+    })
+    .filter(|(_, l)| l.trim().is_empty() || filter.keep(l))
+    .filter_map(|(i, l)| {
+        if !weighted {
+            return Some((i + 1, l, 1.0));
+        }
+        match split_weight(&l) {
+            Ok((weight, rest)) => Some((i + 1, rest.to_string(), weight)),
+            Err(e) => {
+                WARNINGS.warn("weight", Some(i + 1), e);
+                None
+            }
+        }
+    });
+
+    if tagged {
+        return lines
+            .filter_map(|(line, l, weight)| {
+                let s = parse_tagged(&l);
+                if s.is_err() {
+                    WARNINGS.warn(
+                        "sentence",
+                        Some(line),
+                        format_args!("Error when parsing tagged sentence: {:?}", s),
+                    );
+                }
+                Some((s.ok()?, weight))
+            })
+            .try_for_each(|(s, weight)| {
+                let mut counts = Counts::default();
+                if let Some(initial) = tag_bigrams {
+                    let tags: Vec<_> = s.iter().map(|(_, tag)| tag.clone()).collect();
+                    counts = GrammarBare::tag_bigrams(&tags, &SmallString::from(initial));
+                }
+                counts.absorb(GrammarBare::from_tagged(s));
+                absorb(counts, weight)
+            });
+    }
+
+    lines
+        .filter_map(|(line, l, weight)| Some((tree_from_line(line, &l)?, weight)))
+        .map(|(t, weight)| match preterminal_suffix {
+            Some(suffix) => (t.insert_preterminals(suffix), weight),
+            None => (t, weight),
+        })
+        .map(|(t, weight)| match binarisation {
+            Some(params) => (
+                t.markovize_with(params, &[])
+                    .map(&mut |n| SmallString::from(n.to_string().as_str())),
+                weight,
+            ),
+            None => (t, weight),
+        })
+        .try_for_each(|(t, weight)| absorb(GrammarBare::from(t), weight))
+}
+
+/// The markovisation parameters of binarise and `induce --binarise`, with the categories of
+/// --markov-params.
+fn read_markov_params(
+    horizontal: usize,
+    vertical: usize,
+    direction: BinarisationDirection,
+    markov_params: Option<&Path>,
+) -> Result<MarkovParams, Error> {
+    let params = MarkovParams::uniform(vertical, horizontal).with_direction(match direction {
+        BinarisationDirection::Right => Direction::Right,
+        BinarisationDirection::Left => Direction::Left,
+    });
+    Ok(match markov_params {
+        Some(path) => params.read(BufReader::new(File::open(path)?))?,
+        None => params,
+    })
+}
+
+/// Splits a line of `induce --weighted` into its weight and the tree or sentence after the tab.
+fn split_weight(line: &str) -> Result<(f64, &str), String> {
+    let (weight, rest) = line
+        .split_once('\t')
+        .ok_or_else(|| format!("Line without weight: {}", line))?;
+    match weight.trim().parse::<f64>() {
+        Ok(weight) if weight.is_finite() && weight >= 0.0 => Ok((weight, rest)),
+        _ => Err(format!(
+            "Invalid weight {}, expected a non-negative number: {}",
+            weight, line
+        )),
+    }
+}
+
+/// Writes an induced grammar into the files GRAMMAR.rules, GRAMMAR.lexicon and GRAMMAR.words
+/// of `grammar` if given, otherwise to `output`. With `lexicon_only`, e.g. for tagged corpora,
+/// the rules are left out.
+fn write_grammar(
+    grammar_normalised: &GrammarBare<SmallString<[u8; 8]>, SmallString<[u8; 8]>, f64>,
+    grammar: Option<&str>,
+    output: Option<&Path>,
+    lexicon_only: bool,
+    metadata: &GrammarMetadata,
+    word_counts: Option<&TagDictionary<SmallString<[u8; 8]>>>,
+    word_tags: bool,
+) -> io::Result<()> {
+    let write_words = |mut out: &mut dyn Write| match word_counts {
+        Some(dictionary) => dictionary.write_words(&mut out, word_tags),
+        None => grammar_normalised.write_terminals(&mut out),
+    };
+    if let Some(grammar_name) = grammar {
+        if !lexicon_only {
+            let mut rules_file = File::create(format!("{}.rules", grammar_name))?;
+            metadata.write(&mut rules_file)?;
+            grammar_normalised.write_non_lexical_rules(&mut rules_file)?;
+        }
+        let mut lexicon_file = File::create(format!("{}.lexicon", grammar_name))?;
+        metadata.write(&mut lexicon_file)?;
+        grammar_normalised.write_lexical_rules(&mut lexicon_file)?;
+        let mut words_file = BufWriter::new(File::create(format!("{}.words", grammar_name))?);
+        write_words(&mut words_file)?;
+        words_file.flush()
+    } else {
+        let mut out_handle = BufWriter::new(output_handle(output)?);
+
+        metadata.write(&mut out_handle)?;
+        if !lexicon_only {
+            grammar_normalised.write_non_lexical_rules(&mut out_handle)?;
+        }
+        grammar_normalised.write_lexical_rules(&mut out_handle)?;
+        write_words(&mut out_handle)?;
+        out_handle.flush()
+    }
+}
 
-    trees.iter_mut().for_each(|t| {
-        match mode {
-            UnkingMode::Trivial => t.unkify(&word_count),
-            UnkingMode::Smoothing => t.smooth(&word_count),
+/// Writes the grammar merged from the runs of `counts` like an induced grammar, into the files
+/// of `grammar` if given, otherwise to `output`.
+fn write_spilled_grammar(
+    counts: &mut SpillingCounts<SmallString<[u8; 8]>>,
+    grammar: Option<&str>,
+    output: Option<&Path>,
+    lexicon_only: bool,
+    metadata: &GrammarMetadata,
+    scale: WeightScale,
+) -> io::Result<()> {
+    let mut words = FxHashSet::default();
+    let mut write_rules =
+        |counts: &mut SpillingCounts<_>, lexical: bool, out: &mut dyn Write| -> io::Result<()> {
+            counts.for_each_normalised(|rule, weight| match rule {
+                Rule::Lexical { rhs, .. } if lexical => {
+                    words.insert(rhs.clone());
+                    writeln!(out, "{} {}", rule_text(rule), scale.encode(weight))
+                }
+                Rule::NonLexical { .. } if !lexical => {
+                    writeln!(out, "{} {}", rule_text(rule), scale.encode(weight))
+                }
+                _ => Ok(()),
+            })
         };
-        println!("{}", t);
+
+    // Every file is written in its own pass over the runs.
+    let mut words_out: Box<dyn Write> = match grammar {
+        Some(grammar_name) => {
+            if !lexicon_only {
+                let mut rules_file =
+                    BufWriter::new(File::create(format!("{}.rules", grammar_name))?);
+                metadata.write(&mut rules_file)?;
+                write_rules(counts, false, &mut rules_file)?;
+                rules_file.flush()?;
+            }
+            let mut lexicon_file =
+                BufWriter::new(File::create(format!("{}.lexicon", grammar_name))?);
+            metadata.write(&mut lexicon_file)?;
+            write_rules(counts, true, &mut lexicon_file)?;
+            lexicon_file.flush()?;
+            Box::new(BufWriter::new(File::create(format!(
+                "{}.words",
+                grammar_name
+            ))?))
+        }
+        None => {
+            let mut out = BufWriter::new(output_handle(output)?);
+            metadata.write(&mut out)?;
+            if !lexicon_only {
+                write_rules(counts, false, &mut out)?;
+            }
+            write_rules(counts, true, &mut out)?;
+            Box::new(out)
+        }
+    };
+
+    for word in words {
+        writeln!(words_out, "{}", word)?;
+    }
+    words_out.flush()
+}
+
+/// Adds the lexical rules of `counts` to `dictionary`, e.g. to estimate a `SuffixModel`.
+fn insert_lexical_counts(dictionary: &mut TagDictionary<SmallString<[u8; 8]>>, counts: &Counts) {
+    for (rule, count) in &counts.rules {
+        if let Rule::Lexical { lhs, rhs } = rule {
+            dictionary.insert(rhs.clone(), lhs.clone(), *count as f64);
+        }
+    }
+}
+
+/// Splits a sentence of `word/TAG` tokens at the last `/` of every token.
+fn parse_tagged(line: &str) -> Result<Vec<TaggedWord>, String> {
+    line.split_whitespace()
+        .map(|token| match token.rsplit_once('/') {
+            Some((word, tag)) if !word.is_empty() && !tag.is_empty() => {
+                Ok((SmallString::from(word), SmallString::from(tag)))
+            }
+            _ => Err(format!("Token without tag: {}", token)),
+        })
+        .collect()
+}
+
+/// Replaces the labels of the inner nodes by their projection under `equivalence`. Labels that
+/// can't be read as labels of binarised trees are kept.
+fn project_labels(
+    mut tree: Tree<SmallString<[u8; 8]>>,
+    equivalence: LabelEquivalence,
+) -> Tree<SmallString<[u8; 8]>> {
+    if !tree.is_leaf() {
+        if let Ok(node) = Binarized::from_str(&tree.root) {
+            tree.root = SmallString::from(equivalence.project(node).to_string().as_str());
+        }
+        tree.children = tree
+            .children
+            .into_iter()
+            .map(|c| project_labels(c, equivalence))
+            .collect();
+    }
+    tree
+}
+
+/// Removes the annotation behind `annotation_separator` from a word.
+fn strip_annotation(word: &str, annotation_separator: Option<char>) -> &str {
+    match annotation_separator.and_then(|sep| word.split_once(sep)) {
+        Some((word, _)) => word,
+        None => word,
+    }
+}
+
+/// Collects all words of the input sentences, without their annotations.
+fn input_vocabulary(input: &str, annotation_separator: Option<char>) -> FxHashSet<String> {
+    input
+        .split_whitespace()
+        .map(|word| strip_annotation(word, annotation_separator).to_string())
+        .collect()
+}
+
+/// Computes the overall rate of words in the input for which `known` returns false.
+/// If `report` is set, the rate for every sentence and overall is printed to STDERR.
+fn oov_rate<F: Fn(&str) -> bool>(
+    input: &str,
+    annotation_separator: Option<char>,
+    report: bool,
+    known: F,
+) -> f64 {
+    let mut total_words = 0;
+    let mut total_unknown = 0;
+
+    for (i, line) in input.lines().enumerate() {
+        let words: Vec<_> = line
+            .split_whitespace()
+            .map(|word| strip_annotation(word, annotation_separator))
+            .collect();
+        let unknown = words.iter().filter(|w| !known(w)).count();
+
+        if report && !words.is_empty() {
+            WARNINGS.warn(
+                "oov",
+                Some(i + 1),
+                format_args!(
+                    "Sentence {}: {} of {} words unknown ({:.2}%)",
+                    i + 1,
+                    unknown,
+                    words.len(),
+                    100.0 * unknown as f64 / words.len() as f64
+                ),
+            );
+        }
+        total_words += words.len();
+        total_unknown += unknown;
+    }
+
+    let rate = if total_words == 0 {
+        0.0
+    } else {
+        total_unknown as f64 / total_words as f64
+    };
+    if report {
+        eprintln!(
+            "Overall: {} of {} words unknown ({:.2}%)",
+            total_unknown,
+            total_words,
+            100.0 * rate
+        );
+    }
+
+    rate
+}
+
+/// Checks whether a line of the lexicon has a terminal from `vocabulary` or one that starts with
+/// one of `unk_prefixes`, i.e. the UNK token or a signature. Lines without a terminal are kept,
+/// so that their parse errors are still reported.
+fn lexicon_line_needed(line: &str, vocabulary: &FxHashSet<String>, unk_prefixes: &[&str]) -> bool {
+    match line.split_whitespace().nth(1) {
+        Some(terminal) => terminal_needed(terminal, vocabulary, unk_prefixes),
+        None => true,
+    }
+}
+
+/// Checks whether a terminal is in `vocabulary` or starts with one of `unk_prefixes`.
+fn terminal_needed(terminal: &str, vocabulary: &FxHashSet<String>, unk_prefixes: &[&str]) -> bool {
+    unk_prefixes.iter().any(|p| terminal.starts_with(p)) || vocabulary.contains(terminal)
+}
+
+/// Runs the parsing of a single sentence, so that a panic only loses the result for that
+/// sentence instead of the whole batch. Returns `None` and counts the error on a panic, which is
+/// reported for `line`.
+fn catch_sentence_panic<R, F: FnOnce() -> R>(
+    sentence: &Sentence<SmallString<[u8; 8]>>,
+    line: Option<usize>,
+    errors: &AtomicUsize,
+    f: F,
+) -> Option<R> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => Some(result),
+        Err(_) => {
+            errors.fetch_add(1, Ordering::Relaxed);
+            WARNINGS.warn(
+                "panic",
+                line,
+                format_args!("Internal error when parsing sentence: {:?}", sentence),
+            );
+            None
+        }
+    }
+}
+
+/// A parse as printed by `parse --format spans`.
+fn parse_spans<A: Display>(tree: &Tree<A>, noparse: bool) -> String {
+    if noparse {
+        return format!("{} 0 {}", NOPARSE, tree.leaves().len());
+    }
+    labeled_spans(tree)
+        .into_iter()
+        .map(|(label, start, end)| format!("{} {} {}", label, start, end))
+        .collect::<Vec<_>>()
+        .join("\t")
+}
+
+/// A parse as printed by `parse --format json`. A log-probability that is unknown or not finite
+/// is `null`.
+fn parse_json<A: Display>(tree: &Tree<A>, log_prob: Option<f64>, noparse: bool) -> String {
+    let tokens = tree
+        .leaves()
+        .into_iter()
+        .map(|w| format!("\"{}\"", escape_json(w)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let log_prob = match log_prob {
+        Some(p) if p.is_finite() => p.to_string(),
+        _ => String::from("null"),
+    };
+    format!(
+        "{{\"tokens\": [{}], \"tree\": {}, \"log_prob\": {}, \"noparse\": {}}}",
+        tokens,
+        tree.to_json(),
+        log_prob,
+        noparse
+    )
+}
+
+/// The labels given with --initial-nonterminal, separated by commas, e.g. `ROOT,TOP,S`.
+fn initial_nonterminals(initial: &str) -> Result<Vec<SmallString<[u8; 8]>>, Error> {
+    let labels: Vec<_> = initial
+        .split(',')
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(SmallString::from)
+        .collect();
+    if labels.is_empty() {
+        return Err(Error::Usage(String::from(
+            "--initial-nonterminal needs at least one label",
+        )));
+    }
+    Ok(labels)
+}
+
+/// Escapes the words of every line of `input`, see --escape-brackets.
+fn escape_lines(input: &str) -> String {
+    input.lines().map(|l| escape_sentence(l) + "\n").collect()
+}
+
+/// Lines of `reader` with one constituent tree each. With `reading.multiline`, trees spread over
+/// several lines are joined into one line and unlabeled brackets around them are removed.
+fn tree_lines<'a, R: BufRead + 'a>(
+    reader: R,
+    reading: TreeReading,
+) -> Box<dyn Iterator<Item = String> + 'a> {
+    let mut read = 0;
+    let lines = reader.lines().filter_map(move |l| {
+        match &l {
+            Ok(_) => read += 1,
+            // The error is reported for the line that is read next.
+            Err(_) => WARNINGS.warn(
+                "read",
+                Some(read + 1),
+                format_args!("Error when reading line: {:?}", l),
+            ),
+        }
+        l.ok()
     });
+    let lines: Box<dyn Iterator<Item = String> + 'a> = match reading.treebank {
+        TreebankFormat::Sexp if reading.multiline => {
+            Box::new(BracketedTrees::new(lines).map(|t| strip_outer_brackets_line(&t).to_string()))
+        }
+        TreebankFormat::Sexp => Box::new(lines),
+        TreebankFormat::Export => Box::new(sentence_lines(
+            ExportSentences::new(lines).with_discontinuity(reading.discontinuity),
+        )),
+        TreebankFormat::TigerXml => Box::new(sentence_lines(
+            TigerXmlSentences::new(lines).with_discontinuity(reading.discontinuity),
+        )),
+    };
+    if reading.escape_brackets {
+        Box::new(lines.map(|t| escape_tree(&t).into_owned()))
+    } else {
+        lines
+    }
+}
+
+/// The tree of the `line`-th line of `tree_lines`, counted from 1. Lines that aren't trees are
+/// reported with their number and skipped. Trees spread over several lines, e.g. with
+/// --multiline-trees, are numbered as trees.
+fn tree_from_line(line: usize, l: &str) -> Option<Tree<SmallString<[u8; 8]>>> {
+    let s = SExp::from_str(l);
+    if s.is_err() {
+        WARNINGS.warn(
+            "sexp",
+            Some(line),
+            format_args!("Error when parsing SExp: {:?}", s),
+        );
+    }
+    let t = Tree::try_from(s.ok()?);
+    if let Err(e) = &t {
+        WARNINGS.warn(
+            "tree",
+            Some(line),
+            format_args!("Error when reading tree: {}", e),
+        );
+    }
+    t.ok()
+}
+
+/// The trees of export or TIGER-XML sentences as single-line trees. Sentences that can't be read
+/// are reported and skipped.
+fn sentence_lines<'a, I>(sentences: I) -> impl Iterator<Item = String> + 'a
+where
+    I: Iterator<Item = Result<Tree<SmallString<[u8; 8]>>, ExportError>> + 'a,
+{
+    sentences.enumerate().filter_map(|(i, t)| match t {
+        Ok(t) => Some(t.to_string()),
+        Err(e) => {
+            WARNINGS.warn(
+                "export",
+                Some(i + 1),
+                format_args!("Error when parsing sentence: {}", e),
+            );
+            None
+        }
+    })
+}
+
+/// The files of --input or STDIN, decompressed if they are compressed with gzip or zstd, and
+/// damaged by a `ChaosReader` with --chaos.
+fn input_handle(cli: &Cli) -> Box<dyn BufRead> {
+    let stdin: Box<dyn BufRead> = if !cli.input.is_empty() {
+        Box::new(InputFiles::new(cli.input.clone()))
+    } else {
+        match compress::decompress(io::stdin().lock()) {
+            Ok(stdin) => stdin,
+            // The error is reported when the input is read.
+            Err(_) => Box::new(io::stdin().lock()),
+        }
+    };
+    match cli.chaos {
+        Some(rate) => Box::new(ChaosReader::new(stdin, rate, cli.seed, &CHAOS_STATS)),
+        None => stdin,
+    }
+}
+
+/// The file given with --output, or STDOUT. Unbuffered, as most subcommands wrap it in a
+/// `BufWriter`. Files ending with `.gz` or `.zst` are compressed.
+fn output_handle(output: Option<&Path>) -> io::Result<Box<dyn Write>> {
+    Ok(match output {
+        Some(path) => compress::compress(File::create(path)?, Compression::of_path(path))?,
+        None => Box::new(io::stdout().lock()),
+    })
+}
+
+/// Parses the lines of `input` in parallel and prints the results in the order of the input as
+/// soon as the earlier lines are done, so that a slow sentence only holds up the output and not
+/// the parsing of the following ones. At most `window` lines are parsed or wait to be printed at
+/// the same time. `parse` gets the number of the line, counted from 1, and `None` for lines that
+/// can't be read, so that they keep their place in the output. Lines without result are skipped.
+fn stream_parse<R, W, F>(
+    input: &mut R,
+    out: &mut FlushingWriter<W>,
+    window: usize,
+    parse: F,
+) -> io::Result<()>
+where
+    R: BufRead,
+    W: Write,
+    F: Fn(usize, Option<&str>) -> Option<String> + Sync,
+{
+    let (sender, receiver) = mpsc::channel();
+    let parse = &parse;
+
+    rayon::in_place_scope(|scope| {
+        let mut finished = BTreeMap::new();
+        let mut next_read = 0;
+        let mut next_write = 0;
+        let mut done = false;
+        loop {
+            while !done && next_read - next_write < window {
+                let mut line = String::new();
+                let line = match input.read_line(&mut line) {
+                    Ok(0) => {
+                        done = true;
+                        continue;
+                    }
+                    Ok(_) => Some(line),
+                    Err(x) => {
+                        WARNINGS.warn(
+                            "read",
+                            Some(next_read + 1),
+                            format_args!("Error when reading line: {:?}", x),
+                        );
+                        None
+                    }
+                };
+                let (idx, sender) = (next_read, sender.clone());
+                scope.spawn(move |_| {
+                    // Panics are passed on, so that the line isn't waited for forever.
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                        parse(
+                            idx + 1,
+                            line.as_deref().map(|l| l.trim_end_matches(['\n', '\r'])),
+                        )
+                    }));
+                    // The receiver lives until all lines are done.
+                    sender.send((idx, result)).ok();
+                });
+                next_read += 1;
+            }
+            if next_write == next_read {
+                return Ok(());
+            }
+
+            let (idx, result) = receiver.recv().expect("a sender is kept alive");
+            finished.insert(idx, result);
+            while let Some(result) = finished.remove(&next_write) {
+                match result {
+                    Ok(Some(result)) => out.write_result(&result)?,
+                    Ok(None) => {}
+                    Err(e) => panic::resume_unwind(e),
+                }
+                next_write += 1;
+            }
+        }
+    })
+}
+
+/// Buffered output of the parse results. With an `interval`, it is flushed after that many
+/// results, so that they can be read while parsing goes on; otherwise only when the buffer is
+/// full or at the end.
+struct FlushingWriter<W: Write> {
+    inner: BufWriter<W>,
+    interval: Option<usize>,
+    pending: usize,
+}
+
+impl<W: Write> FlushingWriter<W> {
+    fn new(inner: W, interval: Option<usize>) -> Self {
+        Self {
+            inner: BufWriter::new(inner),
+            interval,
+            pending: 0,
+        }
+    }
+
+    fn write_result<D: Display>(&mut self, result: &D) -> io::Result<()> {
+        writeln!(self.inner, "{}", result)?;
+        self.pending += 1;
+        if let Some(interval) = self.interval {
+            if self.pending >= interval {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.pending = 0;
+        self.inner.flush()
+    }
+}
+
+/// Prints the parse results to STDOUT, or into the chunk file if `dir` is given.
+fn write_output<W: Write, D: Display>(
+    out: &mut FlushingWriter<W>,
+    dir: Option<&Path>,
+    idx: usize,
+    watched: Option<&Path>,
+    results: &[D],
+) -> io::Result<()> {
+    if let Some(input) = watched {
+        write_complete(&watch_output_path(input), &watch_tmp_path(input), results)?;
+    } else if let Some(dir) = dir {
+        if !results.is_empty() {
+            write_chunk(dir, idx, results)?;
+        }
+    } else {
+        for result in results {
+            out.write_result(result)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes a finished chunk into a temporary file first and renames it afterwards,
+/// so that a chunk file only exists once it is complete.
+fn write_chunk<D: Display>(dir: &Path, idx: usize, trees: &[D]) -> io::Result<()> {
+    write_complete(
+        &chunk_path(dir, idx),
+        &dir.join(format!(".{:06}.tmp", idx)),
+        trees,
+    )
+}
+
+/// Writes the results into `tmp_path` and renames it to `path` afterwards.
+fn write_complete<D: Display>(path: &Path, tmp_path: &Path, trees: &[D]) -> io::Result<()> {
+    let mut file = File::create(tmp_path)?;
+    for tree in trees {
+        writeln!(file, "{}", tree)?;
+    }
+    file.sync_all()?;
+
+    fs::rename(tmp_path, path)
 }
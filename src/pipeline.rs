@@ -0,0 +1,388 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use nom::bytes::complete::{is_not, tag};
+use nom::character::complete::{char, multispace0, multispace1};
+use nom::combinator::{all_consuming, opt};
+use nom::multi::{many0, separated_list1};
+use nom::sequence::{delimited, preceded, terminated};
+use nom::{Finish, IResult};
+
+use crate::binarized::markovize::Direction;
+
+/// How the words of a sentence that aren't in the lexicon are replaced before parsing.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SentenceUnking {
+    None,
+    Unk,
+    Smooth,
+}
+
+/// Step of a `Pipeline`. Every stage reads the trees of the previous stage.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Stage {
+    /// Removes empty elements and function tags, see `preprocess::preprocess`.
+    Preprocess,
+    Binarise {
+        vertical: usize,
+        horizontal: usize,
+        direction: Direction,
+    },
+    Debinarise,
+    /// Unking of the words of a treebank that occur at most `threshold` times.
+    Unk {
+        threshold: usize,
+    },
+    /// Smoothing of the words of a treebank that occur at most `threshold` times.
+    Smooth {
+        threshold: usize,
+    },
+    /// Parses sentences instead of reading trees, so it can only be the first stage.
+    Parse {
+        rules: PathBuf,
+        lexicon: PathBuf,
+        initial_nonterminal: String,
+        unking: SentenceUnking,
+    },
+    /// Writes the grammar of the trees, into the files of `grammar` if given. Ends a pipeline.
+    Induce {
+        grammar: Option<String>,
+    },
+    /// Scores the trees against the trees in `gold`. Ends a pipeline.
+    Eval {
+        gold: PathBuf,
+    },
+}
+
+/// Stages separated by `|`, e.g. `binarise v=2 h=1 d=left | induce grammar` or
+/// `unk | parse g.rules g.lexicon | debinarise | eval gold.mrg`. Every stage is a name followed
+/// by positional arguments and `key=value` options. `unk` and `smooth` without threshold before
+/// `parse` replace unknown words of the sentences, like the options of the parse subcommand.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Pipeline {
+    pub stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    /// Whether the pipeline reads sentences instead of constituent trees.
+    pub fn reads_sentences(&self) -> bool {
+        matches!(self.stages.first(), Some(Stage::Parse { .. }))
+    }
+}
+
+type RawStage<'a> = (&'a str, Vec<(Option<&'a str>, &'a str)>);
+
+impl FromStr for Pipeline {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let raw = match all_consuming(parse_pipeline)(s.trim()).finish() {
+            Ok((_, raw)) => raw,
+            Err(e) => return Err(format!("invalid pipeline at `{}`", e.input)),
+        };
+
+        let mut stages: Vec<Stage> = vec![];
+        let mut unking = None;
+        for (idx, (name, args)) in raw.into_iter().enumerate() {
+            if let Some(last @ (Stage::Induce { .. } | Stage::Eval { .. })) = stages.last() {
+                return Err(format!("no stage can follow {}", stage_name(last)));
+            }
+            if unking.is_some() && name != "parse" {
+                return Err(String::from(
+                    "unk and smooth without threshold need parse next",
+                ));
+            }
+
+            let mut args = Args { name, args };
+            let stage = match name {
+                "preprocess" | "strip-functions" => Stage::Preprocess,
+                "binarise" => Stage::Binarise {
+                    vertical: args.number(&["v", "vertical"], 1)?,
+                    horizontal: args.number(&["h", "horizontal"], 999)?,
+                    direction: match args.option(&["d", "direction"]) {
+                        None | Some("right") => Direction::Right,
+                        Some("left") => Direction::Left,
+                        Some(d) => return Err(format!("invalid direction of binarise: {}", d)),
+                    },
+                },
+                "debinarise" => Stage::Debinarise,
+                "unk" | "smooth" => match args.option(&["t", "threshold"]) {
+                    Some(t) => {
+                        let threshold = t
+                            .parse()
+                            .map_err(|_| format!("invalid threshold of {}: {}", name, t))?;
+                        if name == "unk" {
+                            Stage::Unk { threshold }
+                        } else {
+                            Stage::Smooth { threshold }
+                        }
+                    }
+                    None if idx == 0 => {
+                        unking = Some(if name == "unk" {
+                            SentenceUnking::Unk
+                        } else {
+                            SentenceUnking::Smooth
+                        });
+                        args.finish()?;
+                        continue;
+                    }
+                    None => return Err(format!("{} needs a threshold, e.g. t=1", name)),
+                },
+                "parse" => {
+                    if !stages.is_empty() {
+                        return Err(String::from("parse can only be the first stage"));
+                    }
+                    Stage::Parse {
+                        rules: PathBuf::from(args.positional("RULES")?),
+                        lexicon: PathBuf::from(args.positional("LEXICON")?),
+                        initial_nonterminal: args
+                            .option(&["i", "initial-nonterminal"])
+                            .unwrap_or("ROOT")
+                            .to_string(),
+                        unking: unking.take().unwrap_or(SentenceUnking::None),
+                    }
+                }
+                "induce" => Stage::Induce {
+                    grammar: args.positional("GRAMMAR").ok().map(str::to_string),
+                },
+                "eval" => Stage::Eval {
+                    gold: PathBuf::from(args.positional("GOLD")?),
+                },
+                _ => return Err(format!("unknown stage {}", name)),
+            };
+            args.finish()?;
+            stages.push(stage);
+        }
+        if unking.is_some() {
+            return Err(String::from(
+                "unk and smooth without threshold need parse next",
+            ));
+        }
+
+        Ok(Pipeline { stages })
+    }
+}
+
+fn stage_name(stage: &Stage) -> &'static str {
+    match stage {
+        Stage::Preprocess => "preprocess",
+        Stage::Binarise { .. } => "binarise",
+        Stage::Debinarise => "debinarise",
+        Stage::Unk { .. } => "unk",
+        Stage::Smooth { .. } => "smooth",
+        Stage::Parse { .. } => "parse",
+        Stage::Induce { .. } => "induce",
+        Stage::Eval { .. } => "eval",
+    }
+}
+
+/// The stage with all its options, including the defaults, as it is parsed.
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = stage_name(self);
+        match self {
+            Stage::Preprocess | Stage::Debinarise => write!(f, "{}", name),
+            Stage::Binarise {
+                vertical,
+                horizontal,
+                direction,
+            } => write!(
+                f,
+                "{} v={} h={} d={}",
+                name,
+                vertical,
+                horizontal,
+                match direction {
+                    Direction::Right => "right",
+                    Direction::Left => "left",
+                }
+            ),
+            Stage::Unk { threshold } | Stage::Smooth { threshold } => {
+                write!(f, "{} t={}", name, threshold)
+            }
+            Stage::Parse {
+                rules,
+                lexicon,
+                initial_nonterminal,
+                unking,
+            } => {
+                match unking {
+                    SentenceUnking::None => (),
+                    SentenceUnking::Unk => write!(f, "unk | ")?,
+                    SentenceUnking::Smooth => write!(f, "smooth | ")?,
+                }
+                write!(
+                    f,
+                    "{} {} {} i={}",
+                    name,
+                    rules.display(),
+                    lexicon.display(),
+                    initial_nonterminal
+                )
+            }
+            Stage::Induce { grammar } => match grammar {
+                Some(grammar) => write!(f, "{} {}", name, grammar),
+                None => write!(f, "{}", name),
+            },
+            Stage::Eval { gold } => write!(f, "{} {}", name, gold.display()),
+        }
+    }
+}
+
+/// The stages separated by ` | `, so that the pipeline can be run again with all its options.
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, stage) in self.stages.iter().enumerate() {
+            if i > 0 {
+                write!(f, " | ")?;
+            }
+            write!(f, "{}", stage)?;
+        }
+        Ok(())
+    }
+}
+
+/// Arguments of a stage that haven't been used yet.
+struct Args<'a> {
+    name: &'a str,
+    args: Vec<(Option<&'a str>, &'a str)>,
+}
+
+impl<'a> Args<'a> {
+    fn option(&mut self, keys: &[&str]) -> Option<&'a str> {
+        let idx = self
+            .args
+            .iter()
+            .position(|(k, _)| k.is_some_and(|k| keys.contains(&k)))?;
+        Some(self.args.remove(idx).1)
+    }
+
+    fn number(&mut self, keys: &[&str], default: usize) -> Result<usize, String> {
+        match self.option(keys) {
+            Some(v) => v
+                .parse()
+                .map_err(|_| format!("invalid {} of {}: {}", keys[0], self.name, v)),
+            None => Ok(default),
+        }
+    }
+
+    fn positional(&mut self, what: &str) -> Result<&'a str, String> {
+        match self.args.iter().position(|(k, _)| k.is_none()) {
+            Some(idx) => Ok(self.args.remove(idx).1),
+            None => Err(format!("{} needs {}", self.name, what)),
+        }
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match self.args.first() {
+            Some((Some(k), v)) => Err(format!("unknown option of {}: {}={}", self.name, k, v)),
+            Some((None, v)) => Err(format!("unexpected argument of {}: {}", self.name, v)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse_pipeline(input: &str) -> IResult<&str, Vec<RawStage<'_>>> {
+    separated_list1(delimited(multispace0, char('|'), multispace0), parse_stage)(input)
+}
+
+fn parse_stage(input: &str) -> IResult<&str, RawStage<'_>> {
+    let (input, name) = is_not(" \t\n|=")(input)?;
+    let (input, args) = many0(preceded(multispace1, parse_arg))(input)?;
+    Ok((input, (name, args)))
+}
+
+fn parse_arg(input: &str) -> IResult<&str, (Option<&str>, &str)> {
+    let (input, key) = opt(terminated(is_not(" \t\n|="), tag("=")))(input)?;
+    let (input, value) = is_not(" \t\n|")(input)?;
+    Ok((input, (key, value)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pipeline_spec() {
+        assert_eq!(
+            Pipeline {
+                stages: vec![
+                    Stage::Binarise {
+                        vertical: 2,
+                        horizontal: 999,
+                        direction: Direction::Right,
+                    },
+                    Stage::Induce {
+                        grammar: Some(String::from("g"))
+                    }
+                ]
+            },
+            Pipeline::from_str("binarise v=2|induce g").unwrap()
+        );
+
+        let pipeline = Pipeline::from_str(
+            " smooth | parse g.rules g.lexicon i=S,TOP | debinarise | eval gold ",
+        )
+        .unwrap();
+        assert!(pipeline.reads_sentences());
+        assert_eq!(
+            vec![
+                Stage::Parse {
+                    rules: PathBuf::from("g.rules"),
+                    lexicon: PathBuf::from("g.lexicon"),
+                    initial_nonterminal: String::from("S,TOP"),
+                    unking: SentenceUnking::Smooth,
+                },
+                Stage::Debinarise,
+                Stage::Eval {
+                    gold: PathBuf::from("gold")
+                }
+            ],
+            pipeline.stages
+        );
+
+        let pipeline = Pipeline::from_str("unk t=1 | binarise direction=left | induce").unwrap();
+        assert!(!pipeline.reads_sentences());
+        assert_eq!(Stage::Unk { threshold: 1 }, pipeline.stages[0]);
+        assert_eq!(
+            Stage::Binarise {
+                vertical: 1,
+                horizontal: 999,
+                direction: Direction::Left,
+            },
+            pipeline.stages[1]
+        );
+        assert_eq!(Stage::Induce { grammar: None }, pipeline.stages[2]);
+        assert_eq!(
+            "unk t=1 | binarise v=1 h=999 d=left | induce",
+            pipeline.to_string()
+        );
+
+        let pipeline = Pipeline::from_str("strip-functions | smooth t=2 | induce g").unwrap();
+        assert_eq!(Stage::Preprocess, pipeline.stages[0]);
+        assert_eq!("preprocess | smooth t=2 | induce g", pipeline.to_string());
+        let pipeline = Pipeline::from_str("unk | parse r l").unwrap();
+        assert_eq!(pipeline, Pipeline::from_str(&pipeline.to_string()).unwrap());
+
+        for invalid in [
+            "",
+            "binarise |",
+            "binarise | | induce",
+            "tokenise",
+            "induce | binarise",
+            "binarise | unk",
+            "unk | binarise",
+            "smooth",
+            "binarise | parse r l",
+            "parse r",
+            "binarise x=1",
+            "binarise v=a",
+            "binarise d=up",
+            "eval",
+            "debinarise extra",
+        ] {
+            assert!(Pipeline::from_str(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
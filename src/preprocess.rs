@@ -0,0 +1,77 @@
+//! The usual cleanup of the trees of the Penn Treebank before a grammar is induced from them:
+//! empty elements and function tags aren't part of the trees a parser is expected to find.
+
+use crate::tree::Tree;
+
+/// Label of the preterminals of empty elements, e.g. traces like `*T*-1`.
+pub const EMPTY_ELEMENT: &str = "-NONE-";
+
+/// Label without function tags, indices of traces and gapping indices, e.g. `NP` for
+/// `NP-SBJ-1` or `NP=2`. Labels starting with `-`, e.g. `-LRB-`, are kept.
+pub fn strip_function_tags(label: &str) -> &str {
+    if label.starts_with('-') {
+        return label;
+    }
+    match label.split(['-', '=']).next() {
+        Some(stripped) if !stripped.is_empty() => stripped,
+        _ => label,
+    }
+}
+
+/// Removes the empty elements, strips the function tags of all labels except those of the words,
+/// and removes the phrases that only covered empty elements. Returns `None` if nothing is left.
+pub fn preprocess<A: AsRef<str> + for<'a> From<&'a str>>(tree: Tree<A>) -> Option<Tree<A>> {
+    if tree.is_leaf() {
+        return Some(tree);
+    }
+    if tree.root.as_ref() == EMPTY_ELEMENT {
+        return None;
+    }
+
+    let children: Vec<_> = tree.children.into_iter().filter_map(preprocess).collect();
+    if children.is_empty() {
+        return None;
+    }
+    Some(Tree {
+        root: A::from(strip_function_tags(tree.root.as_ref())),
+        children,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use smallstr::SmallString;
+
+    use super::*;
+    use crate::SExp;
+
+    fn tree(s: &str) -> Tree<SmallString<[u8; 8]>> {
+        Tree::try_from(SExp::from_str(s).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn empty_elements_and_function_tags() {
+        assert_eq!("NP", strip_function_tags("NP-SBJ-1"));
+        assert_eq!("NP", strip_function_tags("NP=2"));
+        assert_eq!("-LRB-", strip_function_tags("-LRB-"));
+
+        let t = tree(
+            "(S (NP-SBJ-1 (DT the) (NN-TMP dog)) (VP (VBZ seems) (S (NP-SBJ (-NONE- *-1)) \
+             (VP (TO to) (VP (VB sleep))))) (. .))",
+        );
+        assert_eq!(
+            tree(
+                "(S (NP (DT the) (NN dog)) (VP (VBZ seems) (S (VP (TO to) (VP (VB sleep))))) \
+                 (. .))"
+            ),
+            preprocess(t).unwrap()
+        );
+        assert_eq!(None, preprocess(tree("(S (-NONE- *T*-1))")));
+        assert_eq!(
+            tree("(S (X a-b))"),
+            preprocess(tree("(S=2 (X-1 a-b))")).unwrap()
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! Figures of constituent trees: LaTeX documents drawn with tikz-qtree and graphs in the DOT
+//! language of Graphviz.
+
+use std::fmt::Display;
+use std::io::{self, Write};
+
+use crate::grammar::graph::escape;
+use crate::tree::Tree;
+
+/// Label escaped for LaTeX text. Labels with spaces or brackets, or empty labels, are put in
+/// braces, so that tikz-qtree reads them as one label.
+pub fn latex_label<A: Display>(label: &A) -> String {
+    let label = label.to_string();
+    let mut escaped = String::with_capacity(label.len());
+    for c in label.chars() {
+        match c {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '{' | '}' | '$' | '&' | '%' | '#' | '_' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+    }
+    if escaped.is_empty() || escaped.contains([' ', '[', ']']) {
+        format!("{{{}}}", escaped)
+    } else {
+        escaped
+    }
+}
+
+/// The tree in the notation of `\Tree` of tikz-qtree, e.g. `[.S [.NP dogs ] [.VP bark ] ]`.
+pub fn qtree<A: Display>(tree: &Tree<A>) -> String {
+    if tree.is_leaf() {
+        return latex_label(&tree.root);
+    }
+    let children: Vec<_> = tree.children.iter().map(qtree).collect();
+    format!("[.{} {} ]", latex_label(&tree.root), children.join(" "))
+}
+
+/// Writes a standalone LaTeX document with the tree as its only content, to be compiled on its
+/// own or included with `\includegraphics` after compiling.
+pub fn write_latex<A: Display, W: Write>(buf: &mut W, tree: &Tree<A>) -> io::Result<()> {
+    writeln!(buf, "\\documentclass{{standalone}}")?;
+    writeln!(buf, "\\usepackage{{tikz-qtree}}")?;
+    writeln!(buf, "\\begin{{document}}")?;
+    writeln!(buf, "\\begin{{tikzpicture}}")?;
+    writeln!(buf, "\\Tree {}", qtree(tree))?;
+    writeln!(buf, "\\end{{tikzpicture}}")?;
+    writeln!(buf, "\\end{{document}}")
+}
+
+/// Writes the tree as the graph `name` in the DOT language. Nodes are numbered in preorder, as
+/// labels repeat, and the children are kept in their order. The words are drawn in italics.
+pub fn write_dot<A: Display, W: Write>(buf: &mut W, tree: &Tree<A>, name: &str) -> io::Result<()> {
+    writeln!(buf, "digraph \"{}\" {{", escape(&name))?;
+    writeln!(buf, "    ordering=out;")?;
+    writeln!(buf, "    node [shape=plaintext];")?;
+    write_dot_node(buf, tree, &mut 0)?;
+    writeln!(buf, "}}")
+}
+
+/// Writes the node with the number `next` and its subtree, and returns the number of the node.
+fn write_dot_node<A: Display, W: Write>(
+    buf: &mut W,
+    tree: &Tree<A>,
+    next: &mut usize,
+) -> io::Result<usize> {
+    let id = *next;
+    *next += 1;
+    if tree.is_leaf() {
+        writeln!(
+            buf,
+            "    n{} [label=\"{}\", fontname=\"Times-Italic\"];",
+            id,
+            escape(&tree.root)
+        )?;
+        return Ok(id);
+    }
+    writeln!(buf, "    n{} [label=\"{}\"];", id, escape(&tree.root))?;
+    for child in &tree.children {
+        let child = write_dot_node(buf, child, next)?;
+        writeln!(buf, "    n{} -> n{};", id, child)?;
+    }
+    Ok(id)
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::SExp;
+
+    #[test]
+    fn rendered_trees() {
+        let tree =
+            Tree::try_from(SExp::from_str("(S (NP-SBJ $_1) (VP|<X> [b]))").unwrap()).unwrap();
+        assert_eq!("[.S [.NP-SBJ \\$\\_1 ] [.VP|<X> {[b]} ] ]", qtree(&tree));
+
+        let mut dot = vec![];
+        write_dot(&mut dot, &tree, "tree 1").unwrap();
+        assert_eq!(
+            "digraph \"tree 1\" {\n    \
+             ordering=out;\n    \
+             node [shape=plaintext];\n    \
+             n0 [label=\"S\"];\n    \
+             n1 [label=\"NP-SBJ\"];\n    \
+             n2 [label=\"$_1\", fontname=\"Times-Italic\"];\n    \
+             n1 -> n2;\n    \
+             n0 -> n1;\n    \
+             n3 [label=\"VP|<X>\"];\n    \
+             n4 [label=\"[b]\", fontname=\"Times-Italic\"];\n    \
+             n3 -> n4;\n    \
+             n0 -> n3;\n\
+             }\n",
+            String::from_utf8(dot).unwrap()
+        );
+    }
+}
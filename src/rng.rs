@@ -0,0 +1,76 @@
+/// Small xorshift generator shared by all stochastic features, so that runs are
+/// reproducible from their seed.
+pub struct XorShift(u64);
+
+impl XorShift {
+    pub fn new(seed: u64) -> Self {
+        // One round of splitmix64, so that every seed gets its own state, also seeds that
+        // only differ in a few bits.
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        // The state must never be zero.
+        XorShift(if z == 0 { 0x9e37_79b9_7f4a_7c15 } else { z })
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns a number in `0..n`.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Returns a number in `(0, 1]`.
+    pub fn weight(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Shuffles `items` uniformly (Fisher-Yates).
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reproducible_from_seed() {
+        let mut a = XorShift::new(7);
+        let mut b = XorShift::new(7);
+        let mut c = XorShift::new(8);
+
+        let seq_a: Vec<_> = (0..8).map(|_| a.next_u64()).collect();
+        let seq_b: Vec<_> = (0..8).map(|_| b.next_u64()).collect();
+        let seq_c: Vec<_> = (0..8).map(|_| c.next_u64()).collect();
+        assert_eq!(seq_a, seq_b);
+        assert_ne!(seq_a, seq_c);
+
+        // Neighbouring seeds give different streams.
+        let mut zero = XorShift::new(0);
+        let mut one = XorShift::new(1);
+        let seq_zero: Vec<_> = (0..8).map(|_| zero.next_u64()).collect();
+        let seq_one: Vec<_> = (0..8).map(|_| one.next_u64()).collect();
+        assert_ne!(seq_zero, seq_one);
+
+        for _ in 0..100 {
+            let w = a.weight();
+            assert!(w > 0.0 && w <= 1.0);
+            assert!(a.below(3) < 3);
+        }
+
+        let mut items: Vec<_> = (0..10).collect();
+        a.shuffle(&mut items);
+        items.sort_unstable();
+        assert_eq!((0..10).collect::<Vec<_>>(), items);
+    }
+}
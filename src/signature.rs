@@ -1,4 +1,8 @@
 use std::fmt;
+use std::str::FromStr;
+
+/// Default word that replaces unknown words, which also starts their signatures.
+pub const UNK: &str = "UNK";
 
 pub enum LetterSuffix {
     AllCapitalised,
@@ -14,9 +18,85 @@ pub enum NumberSuffix {
     ContainsNumber,
 }
 
+/// Writing system of words that aren't written in the Latin alphabet, or the kind of their
+/// characters if they have no letters. Words in the Latin alphabet get no script suffix.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ScriptSuffix {
+    /// Chinese characters, also used in Japanese.
+    Han,
+    /// Japanese Hiragana and Katakana.
+    Kana,
+    Hangul,
+    Cyrillic,
+    Greek,
+    Arabic,
+    Hebrew,
+    /// Devanagari and the other scripts of India.
+    Indic,
+    Thai,
+    /// Letters of any other script.
+    OtherLetter,
+    Emoji,
+    /// Characters outside of ASCII that are neither letters, numbers nor emoji,
+    /// e.g. currency signs, arrows and typographic quotes.
+    Symbol,
+}
+
+impl ScriptSuffix {
+    /// Script of a letter, `None` for letters of the Latin alphabet.
+    fn of_letter(c: char) -> Option<Self> {
+        Some(match c as u32 {
+            0x0000..=0x024F | 0x1E00..=0x1EFF | 0xFF21..=0xFF5A => return None,
+            0x0370..=0x03FF | 0x1F00..=0x1FFF => ScriptSuffix::Greek,
+            0x0400..=0x052F => ScriptSuffix::Cyrillic,
+            0x0590..=0x05FF => ScriptSuffix::Hebrew,
+            0x0600..=0x06FF | 0x0750..=0x077F => ScriptSuffix::Arabic,
+            0x0900..=0x0DFF => ScriptSuffix::Indic,
+            0x0E00..=0x0E7F => ScriptSuffix::Thai,
+            0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7AF => ScriptSuffix::Hangul,
+            0x3040..=0x30FF | 0xFF66..=0xFF9F => ScriptSuffix::Kana,
+            0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF | 0x20000..=0x3FFFF => {
+                ScriptSuffix::Han
+            }
+            _ => ScriptSuffix::OtherLetter,
+        })
+    }
+
+    fn is_emoji(c: char) -> bool {
+        matches!(
+            c as u32,
+            0x2600..=0x27BF | 0x1F000..=0x1FAFF | 0xFE0F | 0x200D
+        )
+    }
+
+    /// The script of the first letter that isn't Latin. Words without letters are classified
+    /// by their other characters.
+    fn of_word(word: &str) -> Option<Self> {
+        if let Some(script) = word
+            .chars()
+            .filter(|c| c.is_alphabetic())
+            .find_map(Self::of_letter)
+        {
+            Some(script)
+        } else if word.chars().any(|c| c.is_alphabetic()) {
+            None
+        } else if word.chars().any(Self::is_emoji) {
+            Some(ScriptSuffix::Emoji)
+        } else if word
+            .chars()
+            .any(|c| !c.is_ascii() && !c.is_numeric() && !c.is_whitespace())
+        {
+            Some(ScriptSuffix::Symbol)
+        } else {
+            None
+        }
+    }
+}
+
 pub struct UnkSignature {
     letter_suffix: Option<LetterSuffix>,
     number_suffix: Option<NumberSuffix>,
+    script_suffix: Option<ScriptSuffix>,
     has_dash: bool,
     has_period: bool,
     has_comma: bool,
@@ -29,6 +109,7 @@ impl UnkSignature {
             UnkSignature {
                 letter_suffix: None,
                 number_suffix: None,
+                script_suffix: None,
                 has_dash: false,
                 has_period: false,
                 has_comma: false,
@@ -54,7 +135,9 @@ impl UnkSignature {
                 }
             }
 
-            let word_suffix = if word.len() > 3 {
+            // Characters are counted instead of bytes, so that words outside of ASCII don't get
+            // a suffix earlier.
+            let word_suffix = if word.chars().count() > 3 {
                 let last_char = word.chars().last().unwrap();
 
                 if last_char.is_alphabetic() {
@@ -92,6 +175,7 @@ impl UnkSignature {
             UnkSignature {
                 letter_suffix: Some(letter_suffix),
                 number_suffix,
+                script_suffix: ScriptSuffix::of_word(word),
                 has_dash: word.contains('-'),
                 has_period: word.contains('.'),
                 has_comma: word.contains(','),
@@ -99,11 +183,81 @@ impl UnkSignature {
             }
         }
     }
+
+    /// Like `new`, but dashes, full stops and commas are recognised in all scripts, e.g. `–`,
+    /// `。` or `、`.
+    pub fn unicode(word: &str, idx: usize) -> Self {
+        UnkSignature {
+            has_dash: word.chars().any(is_dash),
+            has_period: word.chars().any(is_full_stop),
+            has_comma: word.chars().any(is_comma),
+            ..Self::new(word, idx)
+        }
+    }
+
+    /// Leaves out the features that aren't in `features`. Without the case, words with cased
+    /// letters get the suffix of words with uncased letters.
+    pub fn restricted(mut self, features: SignatureFeatures) -> Self {
+        if !features.case {
+            if let Some(
+                LetterSuffix::AllCapitalised
+                | LetterSuffix::StartCapitalised
+                | LetterSuffix::Capitalised
+                | LetterSuffix::HasLower,
+            ) = self.letter_suffix
+            {
+                self.letter_suffix = Some(LetterSuffix::HasLetter);
+            }
+        }
+        if !features.digits {
+            self.number_suffix = None;
+        }
+        if !features.punctuation {
+            self.has_dash = false;
+            self.has_period = false;
+            self.has_comma = false;
+        }
+        if !features.suffix {
+            self.word_suffix = None;
+        }
+        if !features.script {
+            self.script_suffix = None;
+        }
+        self
+    }
+}
+
+fn is_dash(c: char) -> bool {
+    matches!(
+        c,
+        '-' | '\u{058A}' | '\u{05BE}' | '\u{2010}'
+            ..='\u{2015}' | '\u{2212}' | '\u{FE58}' | '\u{FE63}' | '\u{FF0D}'
+    )
+}
+
+fn is_full_stop(c: char) -> bool {
+    matches!(
+        c,
+        '.' | '\u{0589}'
+            | '\u{06D4}'
+            | '\u{0964}'
+            | '\u{3002}'
+            | '\u{FE52}'
+            | '\u{FF0E}'
+            | '\u{FF61}'
+    )
+}
+
+fn is_comma(c: char) -> bool {
+    matches!(
+        c,
+        ',' | '\u{060C}' | '\u{3001}' | '\u{FE50}' | '\u{FE51}' | '\u{FF0C}' | '\u{FF64}'
+    )
 }
 
 impl fmt::Display for UnkSignature {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut result = write!(f, "UNK");
+        let mut result = write!(f, "{}", UNK);
 
         if let Some(letter_suffix) = self.letter_suffix.as_ref() {
             let suffix = match letter_suffix {
@@ -127,6 +281,25 @@ impl fmt::Display for UnkSignature {
             result = result.and(write!(f, "{}", suffix));
         }
 
+        if let Some(script_suffix) = self.script_suffix.as_ref() {
+            let suffix = match script_suffix {
+                ScriptSuffix::Han => "-Han",
+                ScriptSuffix::Kana => "-Kana",
+                ScriptSuffix::Hangul => "-Hangul",
+                ScriptSuffix::Cyrillic => "-Cyrl",
+                ScriptSuffix::Greek => "-Grek",
+                ScriptSuffix::Arabic => "-Arab",
+                ScriptSuffix::Hebrew => "-Hebr",
+                ScriptSuffix::Indic => "-Indic",
+                ScriptSuffix::Thai => "-Thai",
+                ScriptSuffix::OtherLetter => "-Alpha",
+                ScriptSuffix::Emoji => "-Emoji",
+                ScriptSuffix::Symbol => "-Sym",
+            };
+
+            result = result.and(write!(f, "{}", suffix));
+        }
+
         if self.has_dash {
             result = result.and(write!(f, "-H"));
         }
@@ -147,6 +320,223 @@ impl fmt::Display for UnkSignature {
     }
 }
 
+/// Granularity of the signatures that replace unknown words. The lexicon and the parser have to
+/// use the same model, otherwise the signatures of the sentences aren't in the lexicon.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum UnkModel {
+    /// Every unknown word is `UNK`.
+    Trivial,
+    /// Only the capitalisation and the digits of the word, e.g. `UNK-SC` or `UNK-L-n`.
+    Basic,
+    /// The signatures of `UnkSignature`, which follow the level 4 of the Berkeley parser with
+    /// additional suffixes for scripts other than Latin.
+    #[default]
+    Berkeley4,
+    /// The level 5 of the Berkeley parser, with English suffixes like `-ing` or `-ed`. The class
+    /// `-KNOWNLC` for capitalised words that are known in lower case is left out.
+    Berkeley5,
+    /// Like `Berkeley4`, but without assumptions about the orthography of English: dashes, full
+    /// stops and commas are recognised in all scripts, see `UnkSignature::unicode`.
+    Unicode,
+}
+
+impl UnkModel {
+    pub const ALL: [UnkModel; 5] = [
+        UnkModel::Trivial,
+        UnkModel::Basic,
+        UnkModel::Berkeley4,
+        UnkModel::Berkeley5,
+        UnkModel::Unicode,
+    ];
+
+    /// Signature of `word` at position `idx` of its sentence, with all features of the model.
+    pub fn signature(self, word: &str, idx: usize) -> String {
+        SignatureScheme::from(self).signature(word, idx)
+    }
+}
+
+/// Features of the signatures that can be left out one by one, e.g. the capitalisation for
+/// languages that capitalise all nouns. Models only use the features they have.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SignatureFeatures {
+    /// Capitalisation of the word.
+    pub case: bool,
+    /// Whether the word is a number or contains digits.
+    pub digits: bool,
+    /// Dashes, full stops and commas in the word.
+    pub punctuation: bool,
+    /// The last letter of the word, or its English suffix with `UnkModel::Berkeley5`.
+    pub suffix: bool,
+    /// The script of words that aren't written in the Latin alphabet.
+    pub script: bool,
+}
+
+impl SignatureFeatures {
+    pub const ALL: SignatureFeatures = SignatureFeatures {
+        case: true,
+        digits: true,
+        punctuation: true,
+        suffix: true,
+        script: true,
+    };
+    pub const NAMES: [&'static str; 5] = ["case", "digits", "punctuation", "suffix", "script"];
+}
+
+impl Default for SignatureFeatures {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl FromStr for SignatureFeatures {
+    type Err = String;
+
+    /// Parses the names of the features, separated by commas, e.g. `digits,suffix`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut features = SignatureFeatures {
+            case: false,
+            digits: false,
+            punctuation: false,
+            suffix: false,
+            script: false,
+        };
+        for name in s.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            match name {
+                "case" => features.case = true,
+                "digits" => features.digits = true,
+                "punctuation" => features.punctuation = true,
+                "suffix" => features.suffix = true,
+                "script" => features.script = true,
+                _ => {
+                    return Err(format!(
+                        "unknown signature feature {}, expected one of {}",
+                        name,
+                        Self::NAMES.join(", ")
+                    ))
+                }
+            }
+        }
+        Ok(features)
+    }
+}
+
+/// A model of signatures with the features it uses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignatureScheme {
+    pub model: UnkModel,
+    pub features: SignatureFeatures,
+    /// Start of the signatures instead of `UNK`, e.g. `<unk>`, followed by the features of the
+    /// word, which start with `-`. The signature of `UnkModel::Trivial` is the prefix alone.
+    pub prefix: String,
+}
+
+impl Default for SignatureScheme {
+    fn default() -> Self {
+        Self::from(UnkModel::default())
+    }
+}
+
+impl From<UnkModel> for SignatureScheme {
+    fn from(model: UnkModel) -> Self {
+        SignatureScheme {
+            model,
+            features: SignatureFeatures::ALL,
+            prefix: String::from(UNK),
+        }
+    }
+}
+
+impl SignatureScheme {
+    /// Signature of `word` at position `idx` of its sentence.
+    pub fn signature(&self, word: &str, idx: usize) -> String {
+        let signature = match self.model {
+            UnkModel::Trivial => String::from(UNK),
+            UnkModel::Basic => UnkSignature::new(word, idx)
+                .restricted(SignatureFeatures {
+                    punctuation: false,
+                    suffix: false,
+                    script: false,
+                    ..self.features
+                })
+                .to_string(),
+            UnkModel::Berkeley4 => UnkSignature::new(word, idx)
+                .restricted(self.features)
+                .to_string(),
+            UnkModel::Berkeley5 => berkeley5_signature(word, idx, self.features),
+            UnkModel::Unicode => UnkSignature::unicode(word, idx)
+                .restricted(self.features)
+                .to_string(),
+        };
+        match signature.strip_prefix(UNK) {
+            Some(features) if self.prefix != UNK => format!("{}{}", self.prefix, features),
+            _ => signature,
+        }
+    }
+}
+
+/// Suffixes that the level 5 of the Berkeley parser distinguishes, in the order they are tried.
+const BERKELEY5_SUFFIXES: [&str; 9] = ["ed", "ing", "ion", "er", "est", "ly", "ity", "y", "al"];
+
+fn berkeley5_signature(word: &str, idx: usize, features: SignatureFeatures) -> String {
+    let mut signature = String::from(UNK);
+    let first = match word.chars().next() {
+        Some(c) => c,
+        None => return signature,
+    };
+    let len = word.chars().count();
+    let mut caps = 0;
+    let mut has_digit = false;
+    let mut has_dash = false;
+    let mut has_lower = false;
+    for c in word.chars() {
+        if c.is_numeric() {
+            has_digit = true;
+        } else if c == '-' {
+            has_dash = true;
+        } else if c.is_lowercase() {
+            has_lower = true;
+        } else if c.is_alphabetic() {
+            caps += 1;
+        }
+    }
+
+    if features.case {
+        if first.is_uppercase() {
+            signature.push_str(if idx == 0 && caps == 1 {
+                "-INITC"
+            } else {
+                "-CAPS"
+            });
+        } else if !first.is_alphabetic() && caps > 0 {
+            signature.push_str("-CAPS");
+        } else if has_lower {
+            signature.push_str("-LC");
+        }
+    }
+    if has_digit && features.digits {
+        signature.push_str("-NUM");
+    }
+    if has_dash && features.punctuation {
+        signature.push_str("-DASH");
+    }
+    if !features.suffix {
+        return signature;
+    }
+
+    let lowered = word.to_lowercase();
+    if lowered.ends_with('s') && len >= 3 {
+        if !matches!(lowered.chars().rev().nth(1), Some('s' | 'i' | 'u')) {
+            signature.push_str("-s");
+        }
+    } else if len >= 5 && !has_dash && !(has_digit && caps > 0) {
+        if let Some(suffix) = BERKELEY5_SUFFIXES.iter().find(|s| lowered.ends_with(*s)) {
+            signature.push('-');
+            signature.push_str(suffix);
+        }
+    }
+    signature
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -215,4 +605,93 @@ mod test {
             format!("{}", UnkSignature::new("cloud9", 1))
         );
     }
+
+    #[test]
+    fn multilingual_signatures() {
+        for (word, idx, signature) in [
+            ("café", 1, "UNK-L-é"),
+            ("Ærø", 1, "UNK-C"),
+            ("北京", 1, "UNK-U-Han"),
+            ("中华人民", 1, "UNK-U-Han-民"),
+            ("カタカナ", 1, "UNK-U-Kana-ナ"),
+            ("서울", 1, "UNK-U-Hangul"),
+            ("Москва", 0, "UNK-SC-Cyrl-а"),
+            ("москва", 1, "UNK-L-Cyrl-а"),
+            ("ΑΘΗΝΑ", 1, "UNK-AC-Grek-α"),
+            ("שלום", 1, "UNK-U-Hebr-ם"),
+            ("مرحبا", 1, "UNK-U-Arab-ا"),
+            ("नमस्ते", 1, "UNK-U-Indic-\u{947}"),
+            ("ภาษาไทย", 1, "UNK-U-Thai-ย"),
+            ("ሰላም", 1, "UNK-U-Alpha"),
+            ("Москва-2", 1, "UNK-C-n-Cyrl-H"),
+            ("٣", 1, "UNK-S-N"),
+            ("😀", 1, "UNK-S-Emoji"),
+            ("👍🏽", 1, "UNK-S-Emoji"),
+            ("€", 1, "UNK-S-Sym"),
+            ("→", 1, "UNK-S-Sym"),
+            ("$", 1, "UNK-S"),
+        ] {
+            assert_eq!(
+                signature,
+                UnkSignature::new(word, idx).to_string(),
+                "{}",
+                word
+            );
+        }
+    }
+
+    #[test]
+    fn unk_models() {
+        assert_eq!("UNK", UnkModel::Trivial.signature("Running", 0));
+        assert_eq!("UNK-SC", UnkModel::Basic.signature("Dr.", 0));
+        assert_eq!("UNK-L-n", UnkModel::Basic.signature("cloud9", 1));
+        assert_eq!("UNK-C-P", UnkModel::Berkeley4.signature("Dr.", 1));
+
+        for (word, idx, signature) in [
+            ("Running", 0, "UNK-INITC-ing"),
+            ("Running", 1, "UNK-CAPS-ing"),
+            ("USA", 0, "UNK-CAPS"),
+            ("walked", 1, "UNK-LC-ed"),
+            ("cats", 1, "UNK-LC-s"),
+            ("glass", 1, "UNK-LC"),
+            ("well-known", 1, "UNK-LC-DASH"),
+            ("1990s", 1, "UNK-LC-NUM-s"),
+            ("42", 1, "UNK-NUM"),
+            ("3M", 1, "UNK-CAPS-NUM"),
+        ] {
+            assert_eq!(
+                signature,
+                UnkModel::Berkeley5.signature(word, idx),
+                "{}",
+                word
+            );
+        }
+
+        assert_eq!("UNK-U-Han", UnkModel::Berkeley4.signature("北京。", 1));
+        assert_eq!("UNK-U-Han-P", UnkModel::Unicode.signature("北京。", 1));
+        assert_eq!("UNK-L-H", UnkModel::Unicode.signature("a–b", 1));
+    }
+
+    #[test]
+    fn signature_features() {
+        let features: SignatureFeatures = "suffix, digits".parse().unwrap();
+        let scheme = SignatureScheme {
+            model: UnkModel::Berkeley4,
+            features,
+            ..SignatureScheme::default()
+        };
+        assert_eq!("UNK-U-s", scheme.signature("Haus", 0));
+        assert_eq!("UNK-U-n-s", scheme.signature("Haus-2s", 1));
+        let mut scheme = SignatureScheme {
+            model: UnkModel::Berkeley5,
+            features,
+            ..SignatureScheme::default()
+        };
+        assert_eq!("UNK-NUM-s", scheme.signature("1990s", 1));
+        scheme.prefix = String::from("<unk>");
+        assert_eq!("<unk>-NUM-s", scheme.signature("1990s", 1));
+        scheme.model = UnkModel::Trivial;
+        assert_eq!("<unk>", scheme.signature("1990s", 1));
+        assert!("case,shape".parse::<SignatureFeatures>().is_err());
+    }
 }
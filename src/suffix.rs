@@ -0,0 +1,213 @@
+use std::fmt::Display;
+use std::hash::Hash;
+use std::io::{self, BufRead, Write};
+
+use fxhash::FxHashMap;
+
+use crate::tagdict::TagDictionary;
+
+/// Longest suffix that is considered, as in TnT.
+pub const MAX_SUFFIX_LENGTH: usize = 10;
+
+/// Unknown-word model of TnT (Brants 2000): the tags of a word that isn't in the lexicon are
+/// estimated from the tags of the rare words of the treebank that end with the same letters.
+/// The estimates of longer suffixes are interpolated with those of shorter ones, down to the
+/// distribution of the tags of all rare words.
+#[derive(Debug)]
+pub struct SuffixModel<A: Eq + Hash> {
+    /// Counts of the tags of the rare words by suffix, the empty suffix included.
+    suffixes: FxHashMap<String, FxHashMap<A, f64>>,
+    /// Number of tokens of every tag in the treebank.
+    tags: FxHashMap<A, f64>,
+    /// Weight of the shorter suffix when suffixes are interpolated.
+    theta: f64,
+}
+
+impl<A: Eq + Hash + Clone + Display + AsRef<str>> SuffixModel<A> {
+    /// Counts the suffixes of the words of `dictionary` that occur at most `rare` times.
+    pub fn from_dictionary(dictionary: &TagDictionary<A>, rare: usize) -> Self {
+        let mut suffixes: FxHashMap<String, FxHashMap<A, f64>> = FxHashMap::default();
+        let mut tags: FxHashMap<A, f64> = FxHashMap::default();
+
+        for (word, word_tags) in &dictionary.entries {
+            for (tag, count) in word_tags {
+                *tags.entry(tag.clone()).or_insert(0.0) += count;
+            }
+            if word_tags.values().sum::<f64>() > rare as f64 {
+                continue;
+            }
+            for suffix in suffixes_of(word.as_ref()) {
+                let suffix_tags = suffixes.entry(suffix.to_string()).or_default();
+                for (tag, count) in word_tags {
+                    *suffix_tags.entry(tag.clone()).or_insert(0.0) += count;
+                }
+            }
+        }
+
+        Self::new(suffixes, tags)
+    }
+
+    fn new(suffixes: FxHashMap<String, FxHashMap<A, f64>>, tags: FxHashMap<A, f64>) -> Self {
+        // The standard deviation of the probabilities of the tags of the rare words.
+        let theta = match suffixes.get("") {
+            Some(rare) if rare.len() > 1 => {
+                let total: f64 = rare.values().sum();
+                let mean = 1.0 / rare.len() as f64;
+                let variance = rare
+                    .values()
+                    .map(|c| (c / total - mean).powi(2))
+                    .sum::<f64>()
+                    / (rare.len() - 1) as f64;
+                variance.sqrt()
+            }
+            _ => 0.0,
+        };
+
+        Self {
+            suffixes,
+            tags,
+            theta,
+        }
+    }
+
+    /// Probability of every tag given the suffixes of `word`.
+    pub fn tag_probabilities(&self, word: &str) -> FxHashMap<A, f64> {
+        let mut probabilities = FxHashMap::default();
+        for suffix in suffixes_of(word) {
+            let suffix_tags = match self.suffixes.get(suffix) {
+                Some(suffix_tags) => suffix_tags,
+                None => break,
+            };
+            let total: f64 = suffix_tags.values().sum();
+            if suffix.is_empty() {
+                probabilities = suffix_tags
+                    .iter()
+                    .map(|(tag, count)| (tag.clone(), count / total))
+                    .collect();
+                continue;
+            }
+            for (tag, p) in probabilities.iter_mut() {
+                let count = suffix_tags.get(tag).copied().unwrap_or(0.0);
+                *p = (count / total + self.theta * *p) / (1.0 + self.theta);
+            }
+        }
+        probabilities
+    }
+
+    /// Weights of lexical rules `TAG -> word` for the tags `word` may have. With Bayes' rule,
+    /// P(word | tag) = P(tag | word) P(word) / P(tag), where an unknown word is taken to occur
+    /// once.
+    pub fn emissions(&self, word: &str) -> Vec<(A, f64)> {
+        let mut emissions: Vec<_> = self
+            .tag_probabilities(word)
+            .into_iter()
+            .filter(|(_, p)| *p > 0.0)
+            .filter_map(|(tag, p)| match self.tags.get(&tag) {
+                Some(count) if *count > 0.0 => Some((tag, p / count)),
+                _ => None,
+            })
+            .collect();
+        emissions.sort_by(|(a, _), (b, _)| a.as_ref().cmp(b.as_ref()));
+        emissions
+    }
+
+    /// Writes one `tag TAG COUNT` line per tag and one `suffix SUFFIX TAG COUNT` line per tag of
+    /// a suffix, separated by tabs. The suffix of all rare words is empty.
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut tags: Vec<_> = self.tags.iter().collect();
+        tags.sort_by(|(a, _), (b, _)| a.as_ref().cmp(b.as_ref()));
+        for (tag, count) in tags {
+            writeln!(out, "tag\t{}\t{}", tag, count)?;
+        }
+
+        let mut suffixes: Vec<_> = self.suffixes.iter().collect();
+        suffixes.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (suffix, suffix_tags) in suffixes {
+            let mut suffix_tags: Vec<_> = suffix_tags.iter().collect();
+            suffix_tags.sort_by(|(a, _), (b, _)| a.as_ref().cmp(b.as_ref()));
+            for (tag, count) in suffix_tags {
+                writeln!(out, "suffix\t{}\t{}\t{}", suffix, tag, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<A: Eq + Hash + Clone + Display + AsRef<str> + for<'a> From<&'a str>> SuffixModel<A> {
+    /// Reads a model written by `write`.
+    pub fn read<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut suffixes: FxHashMap<String, FxHashMap<A, f64>> = FxHashMap::default();
+        let mut tags = FxHashMap::default();
+
+        for line in reader.lines() {
+            let line = line?;
+            let fields: Vec<_> = line.split('\t').collect();
+            let count = fields.last().and_then(|c| c.parse::<f64>().ok());
+            match (fields.as_slice(), count) {
+                (["tag", tag, _], Some(count)) => {
+                    tags.insert(A::from(*tag), count);
+                }
+                (["suffix", suffix, tag, _], Some(count)) => {
+                    suffixes
+                        .entry(suffix.to_string())
+                        .or_default()
+                        .insert(A::from(*tag), count);
+                }
+                ([""], _) => (),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("expected a tag or suffix count: {}", line),
+                    ))
+                }
+            }
+        }
+
+        Ok(Self::new(suffixes, tags))
+    }
+}
+
+/// The suffixes of `word` from the shortest to the longest, starting with the empty suffix.
+fn suffixes_of(word: &str) -> impl Iterator<Item = &str> {
+    let starts: Vec<usize> = word
+        .char_indices()
+        .map(|(i, _)| i)
+        .rev()
+        .take(MAX_SUFFIX_LENGTH)
+        .collect();
+    std::iter::once(&word[word.len()..]).chain(starts.into_iter().map(move |i| &word[i..]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn suffix_tags() {
+        let mut dictionary = TagDictionary::new();
+        for (word, tag, count) in [
+            ("walking", "VBG", 1.0),
+            ("talking", "VBG", 2.0),
+            ("king", "NN", 1.0),
+            ("house", "NN", 1.0),
+            ("the", "DT", 50.0),
+        ] {
+            dictionary.insert(word.to_string(), tag.to_string(), count);
+        }
+        assert_eq!(vec!["", "é", "té"], suffixes_of("té").collect::<Vec<_>>());
+
+        let model = SuffixModel::from_dictionary(&dictionary, 2);
+        let probabilities = model.tag_probabilities("jumping");
+        assert!(probabilities["VBG"] > probabilities["NN"]);
+        // The frequent word doesn't count as rare.
+        assert!(!probabilities.contains_key("DT"));
+        let probabilities = model.tag_probabilities("mouse");
+        assert!(probabilities["NN"] > probabilities["VBG"]);
+
+        let mut out = vec![];
+        model.write(&mut out).unwrap();
+        let read: SuffixModel<String> = SuffixModel::read(&out[..]).unwrap();
+        assert_eq!(model.emissions("jumping"), read.emissions("jumping"));
+        assert!(SuffixModel::<String>::read(&b"suffix\ting\tVBG\n"[..]).is_err());
+    }
+}
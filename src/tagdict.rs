@@ -0,0 +1,126 @@
+use std::fmt::Display;
+use std::hash::Hash;
+use std::io::{self, Write};
+
+use float_ord::FloatOrd;
+use fxhash::FxHashMap;
+
+use crate::tree::Tree;
+
+/// Mapping from words to the tags they occur with.
+#[derive(Debug)]
+pub struct TagDictionary<A: Eq + Hash> {
+    pub entries: FxHashMap<A, FxHashMap<A, f64>>,
+}
+
+impl<A: Eq + Hash + Clone + Ord + Display> TagDictionary<A> {
+    pub fn new() -> Self {
+        Self {
+            entries: FxHashMap::default(),
+        }
+    }
+
+    /// Adds `count` occurrences of `word` with `tag`.
+    pub fn insert(&mut self, word: A, tag: A, count: f64) {
+        *self
+            .entries
+            .entry(word)
+            .or_default()
+            .entry(tag)
+            .or_insert(0.0) += count;
+    }
+
+    /// Counts the word below every preterminal of the tree.
+    pub fn insert_tree(&mut self, tree: &Tree<A>) {
+        match tree.children.as_slice() {
+            [child] if child.is_leaf() => self.insert(child.root.clone(), tree.root.clone(), 1.0),
+            children => children.iter().for_each(|c| self.insert_tree(c)),
+        }
+    }
+
+    /// Writes one `word tag count relative-frequency` line per tag of a word, separated
+    /// by tabs. Words are sorted alphabetically, their tags by decreasing count.
+    pub fn write<Wr: Write>(&self, buf: &mut Wr) -> io::Result<()> {
+        let mut words: Vec<_> = self.entries.keys().collect();
+        words.sort();
+
+        for word in words {
+            let tags = &self.entries[word];
+            let total: f64 = tags.values().sum();
+            let mut tags: Vec<_> = tags.iter().collect();
+            tags.sort_by_key(|(tag, count)| (std::cmp::Reverse(FloatOrd(**count)), *tag));
+
+            for (tag, count) in tags {
+                writeln!(buf, "{}\t{}\t{}\t{}", word, tag, count, count / total)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes one `word count` line per word, separated by a tab, with the most frequent words
+    /// first. With `tags`, the tags of the word follow in a third column, separated by spaces
+    /// and sorted by decreasing count.
+    pub fn write_words<Wr: Write>(&self, buf: &mut Wr, tags: bool) -> io::Result<()> {
+        let mut words: Vec<_> = self
+            .entries
+            .iter()
+            .map(|(word, word_tags)| (word, word_tags.values().sum::<f64>()))
+            .collect();
+        words.sort_by_key(|(word, count)| (std::cmp::Reverse(FloatOrd(*count)), *word));
+
+        for (word, count) in words {
+            write!(buf, "{}\t{}", word, count)?;
+            if tags {
+                let mut word_tags: Vec<_> = self.entries[word].iter().collect();
+                word_tags.sort_by_key(|(tag, count)| (std::cmp::Reverse(FloatOrd(**count)), *tag));
+                let word_tags: Vec<_> = word_tags.iter().map(|(tag, _)| tag.to_string()).collect();
+                write!(buf, "\t{}", word_tags.join(" "))?;
+            }
+            writeln!(buf)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<A: Eq + Hash + Clone + Ord + Display> Default for TagDictionary<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sexp::SExp;
+    use std::str::FromStr;
+
+    #[test]
+    fn tag_dictionary_from_trees() {
+        let mut dict = TagDictionary::new();
+        for tree in [
+            "(S (NP (DT the) (NN run)) (VP (VB run)))",
+            "(S (NP (NNS dogs)) (VP (VB run)))",
+        ] {
+            dict.insert_tree(&Tree::try_from(SExp::from_str(tree).unwrap()).unwrap());
+        }
+
+        let mut out = vec![];
+        dict.write(&mut out).unwrap();
+        assert_eq!(
+            "dogs\tNNS\t1\t1\nrun\tVB\t2\t0.6666666666666666\nrun\tNN\t1\t0.3333333333333333\nthe\tDT\t1\t1\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut out = vec![];
+        dict.write_words(&mut out, false).unwrap();
+        assert_eq!("run\t3\ndogs\t1\nthe\t1\n", String::from_utf8(out).unwrap());
+        let mut out = vec![];
+        dict.write_words(&mut out, true).unwrap();
+        assert_eq!(
+            "run\t3\tVB NN\ndogs\t1\tNNS\nthe\t1\tDT\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use std::fmt::Display;
+use std::hash::Hash;
+
+use fxhash::{FxHashMap, FxHashSet};
+
+use crate::tagdict::TagDictionary;
+use crate::tree::Tree;
+
+/// Hidden Markov model of the tag sequences of a treebank, where every tag depends on the
+/// previous one. It is much cheaper than parsing and gives every tag of a word a probability
+/// that depends on the whole sentence, e.g. to prune the tags of ambiguous function words
+/// before the chart is set up.
+#[derive(Debug)]
+pub struct TagBigramModel<A: Eq + Hash> {
+    /// Words with the tags they occur with.
+    pub lexicon: TagDictionary<A>,
+    /// Occurrences of tag pairs, where `None` is the start or the end of a sentence.
+    transitions: FxHashMap<(Option<A>, Option<A>), f64>,
+    /// Occurrences of every tag, and of the start of a sentence for `None`.
+    contexts: FxHashMap<Option<A>, f64>,
+}
+
+impl<A: Eq + Hash + Clone + Ord + Display> TagBigramModel<A> {
+    pub fn new() -> Self {
+        Self {
+            lexicon: TagDictionary::new(),
+            transitions: FxHashMap::default(),
+            contexts: FxHashMap::default(),
+        }
+    }
+
+    /// Counts the sequence of preterminals of the tree and their words.
+    pub fn insert_tree(&mut self, tree: &Tree<A>) {
+        let mut tagged = vec![];
+        preterminals(tree, &mut tagged);
+
+        let mut prev = None;
+        for (tag, word) in tagged {
+            self.lexicon.insert(word.clone(), tag.clone(), 1.0);
+            *self.contexts.entry(prev.clone()).or_insert(0.0) += 1.0;
+            *self
+                .transitions
+                .entry((prev, Some(tag.clone())))
+                .or_insert(0.0) += 1.0;
+            prev = Some(tag.clone());
+        }
+        if prev.is_some() {
+            *self.contexts.entry(prev.clone()).or_insert(0.0) += 1.0;
+            *self.transitions.entry((prev, None)).or_insert(0.0) += 1.0;
+        }
+    }
+
+    /// Probability of `next` following `prev`, with add-one smoothing.
+    fn transition(&self, prev: Option<&A>, next: Option<&A>) -> f64 {
+        let count = self
+            .transitions
+            .get(&(prev.cloned(), next.cloned()))
+            .copied()
+            .unwrap_or(0.0);
+        let context = self.contexts.get(&prev.cloned()).copied().unwrap_or(0.0);
+        // Every tag and the end of the sentence can follow.
+        (count + 1.0) / (context + self.contexts.len() as f64)
+    }
+
+    /// Posterior probability of every tag at every position of `words`, computed with the
+    /// forward-backward algorithm. The tags of a word are sorted by decreasing probability.
+    /// Words that are not in the treebank can have any tag, with the same probability for
+    /// every tag.
+    pub fn posteriors(&self, words: &[A]) -> Vec<Vec<(A, f64)>> {
+        let mut tags: Vec<&A> = self.contexts.keys().flatten().collect();
+        tags.sort();
+        let emission = |word: &A, tag: &A| match self.lexicon.entries.get(word) {
+            Some(counts) => {
+                counts.get(tag).copied().unwrap_or(0.0) / self.contexts[&Some(tag.clone())]
+            }
+            None => 1.0,
+        };
+        let normalised = |mut v: Vec<f64>| {
+            let total: f64 = v.iter().sum();
+            if total > 0.0 {
+                v.iter_mut().for_each(|p| *p /= total);
+            }
+            v
+        };
+
+        // Every position is normalised so that long sentences don't underflow.
+        let mut forward: Vec<Vec<f64>> = Vec::with_capacity(words.len());
+        for (i, word) in words.iter().enumerate() {
+            let column = tags
+                .iter()
+                .map(|t| {
+                    let prior = match i {
+                        0 => self.transition(None, Some(t)),
+                        _ => tags
+                            .iter()
+                            .zip(&forward[i - 1])
+                            .map(|(s, p)| p * self.transition(Some(s), Some(t)))
+                            .sum(),
+                    };
+                    prior * emission(word, t)
+                })
+                .collect();
+            forward.push(normalised(column));
+        }
+
+        let mut backward = vec![vec![]; words.len()];
+        for i in (0..words.len()).rev() {
+            let column = tags
+                .iter()
+                .map(|s| match backward.get(i + 1) {
+                    Some(next) => tags
+                        .iter()
+                        .zip(next)
+                        .map(|(t, p)| {
+                            p * self.transition(Some(s), Some(t)) * emission(&words[i + 1], t)
+                        })
+                        .sum(),
+                    None => self.transition(Some(s), None),
+                })
+                .collect();
+            backward[i] = normalised(column);
+        }
+
+        forward
+            .into_iter()
+            .zip(backward)
+            .map(|(f, b)| {
+                let posterior = normalised(f.iter().zip(&b).map(|(f, b)| f * b).collect());
+                let mut column: Vec<_> = tags
+                    .iter()
+                    .map(|t| (*t).clone())
+                    .zip(posterior)
+                    .filter(|(_, p)| *p > 0.0)
+                    .collect();
+                column.sort_by(|(t1, p1), (t2, p2)| p2.total_cmp(p1).then_with(|| t1.cmp(t2)));
+                column
+            })
+            .collect()
+    }
+
+    /// The tags of every position of `words` whose posterior probability is at least the
+    /// best one of the position multiplied by `threshold`, or `None` for words that are not
+    /// in the treebank.
+    pub fn allowed_tags(&self, words: &[A], threshold: f64) -> Vec<Option<FxHashSet<A>>> {
+        words
+            .iter()
+            .zip(self.posteriors(words))
+            .map(|(word, posteriors)| {
+                self.lexicon.entries.get(word)?;
+                let best = posteriors.first().map_or(0.0, |(_, p)| *p);
+                Some(
+                    posteriors
+                        .into_iter()
+                        .take_while(|(_, p)| *p >= best * threshold)
+                        .map(|(t, _)| t)
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+}
+
+impl<A: Eq + Hash + Clone + Ord + Display> Default for TagBigramModel<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Collects the preterminals of the tree from left to right, with their words.
+fn preterminals<'a, A>(tree: &'a Tree<A>, tagged: &mut Vec<(&'a A, &'a A)>) {
+    match tree.children.as_slice() {
+        [child] if child.is_leaf() => tagged.push((&tree.root, &child.root)),
+        children => children.iter().for_each(|c| preterminals(c, tagged)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use smallstr::SmallString;
+
+    use super::*;
+    use crate::sexp::SExp;
+
+    fn words(s: &str) -> Vec<SmallString<[u8; 8]>> {
+        s.split(' ').map(SmallString::from).collect()
+    }
+
+    #[test]
+    fn tag_bigram_posteriors() {
+        let mut model = TagBigramModel::new();
+        for tree in [
+            "(S (NP (DT that) (NN dog)) (VP (VB runs)))",
+            "(S (NP (DT the) (NN dog)) (VP (VB knows) (SBAR (IN that) (S (NP (NN cats)) (VP (VB run))))))",
+            "(S (NP (DT that)) (VP (VB runs)))",
+            "(S (NP (NN cats)) (VP (VB know) (SBAR (IN that) (S (NP (DT the) (NN dog)) (VP (VB runs))))))",
+        ] {
+            model.insert_tree(&Tree::try_from(SExp::from_str(tree).unwrap()).unwrap());
+        }
+
+        let posteriors = model.posteriors(&words("cats know that the dog runs"));
+        assert_eq!(6, posteriors.len());
+        for column in &posteriors {
+            assert!((column.iter().map(|(_, p)| p).sum::<f64>() - 1.0).abs() < 1e-9);
+        }
+        // After a verb, `that` is more likely a complementiser than a determiner.
+        assert_eq!("IN", posteriors[2][0].0.as_str());
+        assert_eq!("DT", posteriors[2][1].0.as_str());
+
+        let sentence = words("that blorp runs");
+        let allowed = model.allowed_tags(&sentence, 0.5);
+        assert_eq!(Some(FxHashSet::from_iter(words("DT"))), allowed[0]);
+        assert_eq!(None, allowed[1]);
+        assert_eq!(Some(FxHashSet::from_iter(words("VB"))), allowed[2]);
+        assert_eq!(
+            2,
+            model.allowed_tags(&sentence, 0.0)[0]
+                .as_ref()
+                .unwrap()
+                .len()
+        );
+    }
+}
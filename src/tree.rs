@@ -1,5 +1,6 @@
 use std::fmt;
 
+use crate::json::escape_json;
 use crate::SExp;
 
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -40,30 +41,103 @@ impl<A> Tree<A> {
                 })
         }
     }
+
+    /// Replaces the label of every node by `f` applied to it.
+    pub fn map<B, F: FnMut(A) -> B>(self, f: &mut F) -> Tree<B> {
+        Tree {
+            root: f(self.root),
+            children: self.children.into_iter().map(|c| c.map(f)).collect(),
+        }
+    }
+
+    /// Marks the leaves as terminals and all other nodes as non-terminals.
+    pub fn into_node_types(self) -> Tree<NodeType<A, A>> {
+        let root = if self.is_leaf() {
+            NodeType::Terminal(self.root)
+        } else {
+            NodeType::NonTerminal(self.root)
+        };
+        Tree {
+            root,
+            children: self
+                .children
+                .into_iter()
+                .map(Tree::into_node_types)
+                .collect(),
+        }
+    }
+}
+
+/// Reason why an s-expression isn't a tree.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TreeError {
+    /// A list without label, i.e. `()`.
+    EmptyList,
+    /// A list whose first element is a list instead of a label.
+    UnlabeledList,
 }
 
-impl<A: Clone> From<SExp<A>> for Tree<A> {
-    fn from(sexp: SExp<A>) -> Self {
+impl fmt::Display for TreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TreeError::EmptyList => write!(f, "empty list without label"),
+            TreeError::UnlabeledList => write!(f, "list starting with a list instead of a label"),
+        }
+    }
+}
+
+impl<A> TryFrom<SExp<A>> for Tree<A> {
+    type Error = TreeError;
+
+    fn try_from(sexp: SExp<A>) -> Result<Self, Self::Error> {
         match sexp {
             SExp::List(list) => {
-                let mut list = list;
-                let root = match list.get(0).unwrap() {
-                    SExp::Atom(a) => a.clone(),
-                    _ => panic!("First element in SExp list has to be an atom!"),
+                let mut list = list.into_iter();
+                let root = match list.next() {
+                    Some(SExp::Atom(a)) => a,
+                    Some(SExp::List(_)) => return Err(TreeError::UnlabeledList),
+                    None => return Err(TreeError::EmptyList),
                 };
 
-                let children = list.drain(..).skip(1).map(Self::from).collect();
+                let children = list.map(Self::try_from).collect::<Result<_, _>>()?;
 
-                Tree { root, children }
+                Ok(Tree { root, children })
             }
-            SExp::Atom(root) => Tree {
+            SExp::Atom(root) => Ok(Tree {
                 root,
                 children: vec![],
-            },
+            }),
         }
     }
 }
 
+impl<A: fmt::Display + for<'a> From<&'a str>> Tree<A> {
+    /// Wraps words that have siblings in a synthetic preterminal, labeled with the label
+    /// of their parent followed by `suffix`. Otherwise grammar induction would treat these
+    /// words as non-terminals. A node whose only child is a word is already a preterminal.
+    pub fn insert_preterminals(mut self, suffix: &str) -> Self {
+        let multiple_children = self.children.len() > 1;
+        let preterminal = format!("{}{}", self.root, suffix);
+
+        self.children = self
+            .children
+            .drain(..)
+            .map(|c| {
+                if multiple_children && c.is_leaf() {
+                    Tree {
+                        root: A::from(preterminal.as_str()),
+                        children: vec![c],
+                    }
+                } else {
+                    c.insert_preterminals(suffix)
+                }
+            })
+            .collect();
+
+        self
+    }
+}
+
 impl<A: fmt::Display> fmt::Display for Tree<A> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if self.is_leaf() {
@@ -79,6 +153,72 @@ impl<A: fmt::Display> fmt::Display for Tree<A> {
     }
 }
 
+impl<A: fmt::Display> Tree<A> {
+    /// The tree as nested JSON objects `{"label": ..., "children": [...]}`, with the leaves as
+    /// strings, e.g. `{"label": "NP", "children": ["dogs"]}`.
+    pub fn to_json(&self) -> String {
+        if self.is_leaf() {
+            return format!("\"{}\"", escape_json(&self.root));
+        }
+        format!(
+            "{{\"label\": \"{}\", \"children\": [{}]}}",
+            escape_json(&self.root),
+            self.children
+                .iter()
+                .map(Tree::to_json)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+
+    /// The tree drawn over several lines, one per node, with box-drawing characters that connect
+    /// every node to its children below it, or only ASCII characters with `ascii`. Preterminals
+    /// share their line with their word, e.g.
+    ///
+    /// ```text
+    /// S
+    /// ├── NP dogs
+    /// └── VP bark
+    /// ```
+    pub fn pretty_print(&self, ascii: bool) -> String {
+        let mut out = String::new();
+        self.pretty_lines(&mut out, "", "", ascii);
+        out
+    }
+
+    /// Adds the lines of the subtree, with `first` in front of its root and `rest` in front of
+    /// all lines below it.
+    fn pretty_lines(&self, out: &mut String, first: &str, rest: &str, ascii: bool) {
+        out.push_str(first);
+        out.push_str(&self.root.to_string());
+        if let [word] = self.children.as_slice() {
+            if word.is_leaf() {
+                out.push(' ');
+                out.push_str(&word.root.to_string());
+                out.push('\n');
+                return;
+            }
+        }
+        out.push('\n');
+
+        for (i, child) in self.children.iter().enumerate() {
+            let last = i + 1 == self.children.len();
+            let (branch, indent) = match (ascii, last) {
+                (false, false) => ("├── ", "│   "),
+                (false, true) => ("└── ", "    "),
+                (true, false) => ("|-- ", "|   "),
+                (true, true) => ("`-- ", "    "),
+            };
+            child.pretty_lines(
+                out,
+                &format!("{}{}", rest, branch),
+                &format!("{}{}", rest, indent),
+                ascii,
+            );
+        }
+    }
+}
+
 #[derive(Eq, PartialEq, Debug)]
 pub enum NodeType<N, T> {
     Terminal(T),
@@ -106,7 +246,7 @@ mod test {
                 root: "a".to_string(),
                 children: vec![]
             },
-            Tree::from(SExp::Atom("a".to_string())),
+            Tree::try_from(SExp::Atom("a".to_string())).unwrap(),
         );
 
         assert_eq!(
@@ -129,7 +269,7 @@ mod test {
                     },
                 ]
             },
-            Tree::from(SExp::List(vec![
+            Tree::try_from(SExp::List(vec![
                 SExp::Atom("NP".to_string()),
                 SExp::List(vec![
                     SExp::Atom("D".to_string()),
@@ -139,13 +279,25 @@ mod test {
                     SExp::Atom("N".to_string()),
                     SExp::Atom("ball".to_string())
                 ])
-            ])),
+            ]))
+            .unwrap(),
+        );
+
+        assert_eq!(
+            Err(TreeError::EmptyList),
+            Tree::try_from(SExp::from_str("(S (NP) ())").unwrap())
+        );
+        assert_eq!(
+            Err(TreeError::UnlabeledList),
+            Tree::<String>::try_from(SExp::List(vec![SExp::List(vec![SExp::Atom(
+                "S".to_string()
+            )])]))
         );
     }
 
     #[test]
     fn get_leaves() {
-        let tree = Tree::from(SExp::from_str("(S (NP a b) c)").unwrap());
+        let tree = Tree::try_from(SExp::from_str("(S (NP a b) c)").unwrap()).unwrap();
         let tree_leaves = tree.leaves();
         let mut leaves_iter = tree_leaves.iter();
         let leaf_a = leaves_iter.next().unwrap();
@@ -155,4 +307,52 @@ mod test {
         let leaf_c = leaves_iter.next().unwrap();
         assert_eq!("c".to_string(), (**leaf_c).clone().into_string());
     }
+
+    #[test]
+    fn preterminal_insertion() {
+        let tree =
+            Tree::try_from(SExp::from_str("(S (NP (DT the) dog) (VP barks))").unwrap()).unwrap();
+
+        assert_eq!(
+            "(S (NP (DT the) (NP-POS dog)) (VP barks))".to_string(),
+            format!("{}", tree.insert_preterminals("-POS"))
+        );
+    }
+
+    #[test]
+    fn json_tree() {
+        let tree = Tree::try_from(SExp::from_str("(S (NP \"a\") (VP b))").unwrap()).unwrap();
+        assert_eq!(
+            "{\"label\": \"S\", \"children\": [{\"label\": \"NP\", \"children\": [\"\\\"a\\\"\"]}, \
+             {\"label\": \"VP\", \"children\": [\"b\"]}]}",
+            tree.to_json()
+        );
+
+        // Words with control characters, e.g. from badly converted corpora.
+        let tree = Tree {
+            root: "NN",
+            children: vec![Tree {
+                root: "a\tb\u{1}",
+                children: vec![],
+            }],
+        };
+        assert_eq!(
+            "{\"label\": \"NN\", \"children\": [\"a\\tb\\u0001\"]}",
+            tree.to_json()
+        );
+    }
+
+    #[test]
+    fn pretty_tree() {
+        let tree = Tree::try_from(SExp::from_str("(S (NP (DT the) (NN dog)) (VP barks))").unwrap())
+            .unwrap();
+        assert_eq!(
+            "S\n├── NP\n│   ├── DT the\n│   └── NN dog\n└── VP barks\n",
+            tree.pretty_print(false)
+        );
+        assert_eq!(
+            "S\n|-- NP\n|   |-- DT the\n|   `-- NN dog\n`-- VP barks\n",
+            tree.pretty_print(true)
+        );
+    }
 }
@@ -0,0 +1,876 @@
+//! Readers and writers for treebank formats other than the single-line S-expressions
+//! used everywhere else: bracketed trees spread over several lines (as in the Penn Treebank),
+//! the Negra/Tiger export format and TIGER-XML.
+
+use std::fmt;
+use std::io::{self, Write};
+
+use fxhash::FxHashMap;
+use smallstr::SmallString;
+
+use crate::{SExp, Tree};
+
+/// Label of the artificial root node of export trees.
+pub const VROOT: &str = "VROOT";
+
+/// Collects the lines of bracketed trees that span several lines. Every item is a
+/// single tree, with its lines joined by spaces.
+pub struct BracketedTrees<I> {
+    lines: I,
+}
+
+impl<I: Iterator<Item = String>> BracketedTrees<I> {
+    pub fn new(lines: I) -> Self {
+        Self { lines }
+    }
+}
+
+impl<I: Iterator<Item = String>> Iterator for BracketedTrees<I> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut tree = String::new();
+        let mut depth: i64 = 0;
+
+        for line in self.lines.by_ref() {
+            if tree.is_empty() && line.trim().is_empty() {
+                continue;
+            }
+
+            for c in line.chars() {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+            }
+            if !tree.is_empty() {
+                tree.push(' ');
+            }
+            tree.push_str(line.trim());
+
+            if depth <= 0 {
+                return Some(tree);
+            }
+        }
+
+        // An unbalanced tree at the end of the input is passed on to report the error.
+        if tree.is_empty() {
+            None
+        } else {
+            Some(tree)
+        }
+    }
+}
+
+/// Removes the unlabeled brackets around trees, as in `( (S (NP ...) (VP ...)) )`.
+pub fn strip_outer_brackets<A>(sexp: SExp<A>) -> SExp<A> {
+    match sexp {
+        SExp::List(mut list) if list.len() == 1 && matches!(list[0], SExp::List(_)) => {
+            strip_outer_brackets(list.pop().unwrap())
+        }
+        sexp => sexp,
+    }
+}
+
+/// Like `strip_outer_brackets`, but on a tree that hasn't been parsed yet, e.g. a joined tree of
+/// `BracketedTrees`, so that it can be handed on as a single-line tree.
+pub fn strip_outer_brackets_line(tree: &str) -> &str {
+    let mut tree = tree.trim();
+    while let Some(inner) = tree
+        .strip_prefix('(')
+        .and_then(|t| t.strip_suffix(')'))
+        .map(str::trim)
+    {
+        if !inner.starts_with('(') {
+            break;
+        }
+        // The first bracket of the inner tree has to be closed by its last one.
+        let mut depth = 0;
+        let closed = inner.char_indices().find_map(|(i, c)| {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            (depth == 0).then_some(i)
+        });
+        if closed != Some(inner.len() - 1) {
+            break;
+        }
+        tree = inner;
+    }
+    tree
+}
+
+/// Checks that every list starts with a label, which is required to turn it into a tree.
+pub fn is_labeled<A>(sexp: &SExp<A>) -> bool {
+    match sexp {
+        SExp::List(list) => match list.split_first() {
+            Some((SExp::Atom(_), children)) => children.iter().all(is_labeled),
+            _ => false,
+        },
+        SExp::Atom(_) => true,
+    }
+}
+
+/// Writes a tree in the bracketed format of the Penn Treebank, with unlabeled brackets around it.
+pub fn write_ptb<A: fmt::Display, W: Write>(out: &mut W, tree: &Tree<A>) -> io::Result<()> {
+    writeln!(out, "( {} )", tree)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExportError {
+    /// A line of a sentence has too few columns.
+    MissingColumns(String),
+    /// A line refers to a parent node that is not part of the sentence.
+    UnknownParent(String),
+    /// The input ends before the end of a sentence.
+    Unterminated,
+    /// An element of TIGER-XML lacks an attribute.
+    MissingAttribute(String),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::MissingColumns(line) => write!(f, "missing columns in line {}", line),
+            ExportError::UnknownParent(line) => write!(f, "unknown parent in line {}", line),
+            ExportError::Unterminated => write!(f, "sentence without #EOS or </s>"),
+            ExportError::MissingAttribute(element) => {
+                write!(f, "missing attribute in element {}", element)
+            }
+        }
+    }
+}
+
+type Label = SmallString<[u8; 8]>;
+
+/// How discontinuous constituents are turned into trees, whose constituents can't cross.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Discontinuity {
+    /// The children that are not adjacent to the head (edge label `HD`, otherwise the first
+    /// child) of a constituent are moved up to its parent.
+    #[default]
+    Raise,
+    /// Every contiguous part of a constituent becomes a constituent of its own with the same
+    /// label. The part with the head keeps the children of the other parts that are attached
+    /// to it.
+    Split,
+}
+
+/// Node of an export sentence. Words are stored with the preterminals above them.
+struct ExportNode {
+    label: Label,
+    word: Option<Label>,
+    parent: usize,
+    head: bool,
+}
+
+/// Reads sentences in the Negra/Tiger export format (versions 3 and 4) and turns them into trees
+/// below a node labeled `VROOT`. Edge labels, morphology and secondary edges are dropped.
+/// Discontinuous constituents are resolved with `Discontinuity::Raise` unless another strategy
+/// is chosen, until no crossing branches remain.
+pub struct ExportSentences<I> {
+    lines: I,
+    lemma_column: bool,
+    discontinuity: Discontinuity,
+}
+
+impl<I: Iterator<Item = String>> ExportSentences<I> {
+    pub fn new(lines: I) -> Self {
+        Self {
+            lines,
+            lemma_column: false,
+            discontinuity: Discontinuity::default(),
+        }
+    }
+
+    pub fn with_discontinuity(mut self, discontinuity: Discontinuity) -> Self {
+        self.discontinuity = discontinuity;
+        self
+    }
+
+    fn read_sentence(&mut self) -> Result<Tree<Label>, ExportError> {
+        // Node 0 is the root, followed by the words and then the non-terminals.
+        let mut nodes = vec![ExportNode {
+            label: Label::from(VROOT),
+            word: None,
+            parent: 0,
+            head: false,
+        }];
+        let mut parents = vec![];
+        let mut ids = FxHashMap::default();
+        ids.insert(0, 0);
+
+        loop {
+            let line = self.lines.next().ok_or(ExportError::Unterminated)?;
+            let content = line.split("%%").next().unwrap_or_default();
+            let columns: Vec<_> = content.split_whitespace().collect();
+
+            match columns.first() {
+                None => continue,
+                Some(c) if c.starts_with("#EOS") => break,
+                _ => {}
+            }
+
+            // Export lines are word or node, [lemma], tag, morph, edge, parent, [secondary edges].
+            let offset = if self.lemma_column { 1 } else { 0 };
+            if columns.len() < 5 + offset {
+                return Err(ExportError::MissingColumns(line));
+            }
+            let parent: usize = columns[4 + offset]
+                .parse()
+                .map_err(|_| ExportError::UnknownParent(line.clone()))?;
+            let label = Label::from(columns[1 + offset]);
+            let head = columns[3 + offset] == "HD";
+
+            match columns[0].strip_prefix('#').map(str::parse::<usize>) {
+                Some(Ok(id)) => {
+                    ids.insert(id, nodes.len());
+                    nodes.push(ExportNode {
+                        label,
+                        word: None,
+                        parent,
+                        head,
+                    });
+                }
+                _ => nodes.push(ExportNode {
+                    label,
+                    word: Some(Label::from(columns[0])),
+                    parent,
+                    head,
+                }),
+            }
+            parents.push(line);
+        }
+
+        for (node, line) in nodes.iter_mut().skip(1).zip(parents.iter()) {
+            node.parent = *ids
+                .get(&node.parent)
+                .ok_or_else(|| ExportError::UnknownParent(line.clone()))?;
+        }
+
+        sentence_tree(nodes, parents, self.discontinuity)
+    }
+}
+
+impl<I: Iterator<Item = String>> Iterator for ExportSentences<I> {
+    type Item = Result<Tree<Label>, ExportError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(line) = self.lines.next() {
+            if line.starts_with("#FORMAT") {
+                self.lemma_column = line.split_whitespace().nth(1) == Some("4");
+            } else if line.starts_with("#BOS") {
+                return Some(self.read_sentence());
+            }
+        }
+        None
+    }
+}
+
+/// Reads the sentences (`<s>` elements) of TIGER-XML and turns them into trees like
+/// `ExportSentences`, below a node labeled `VROOT`. A root of a graph labeled `VROOT` becomes
+/// this node, and words without parent are attached to it.
+pub struct TigerXmlSentences<I> {
+    lines: I,
+    buffer: String,
+    discontinuity: Discontinuity,
+}
+
+impl<I: Iterator<Item = String>> TigerXmlSentences<I> {
+    pub fn new(lines: I) -> Self {
+        Self {
+            lines,
+            buffer: String::new(),
+            discontinuity: Discontinuity::default(),
+        }
+    }
+
+    pub fn with_discontinuity(mut self, discontinuity: Discontinuity) -> Self {
+        self.discontinuity = discontinuity;
+        self
+    }
+
+    fn read_sentence(&self, sentence: &str) -> Result<Tree<Label>, ExportError> {
+        let mut nodes = vec![ExportNode {
+            label: Label::from(VROOT),
+            word: None,
+            parent: 0,
+            head: false,
+        }];
+        let mut sources = vec![];
+        let mut ids = FxHashMap::default();
+        let mut root = None;
+        let mut parent = None;
+        let mut edges = vec![];
+
+        for tag in xml_tags(sentence) {
+            let attribute = |name: &str| {
+                tag.attribute(name)
+                    .ok_or_else(|| ExportError::MissingAttribute(tag.source.to_string()))
+            };
+            match tag.name {
+                "graph" => root = tag.attribute("root"),
+                "t" => {
+                    ids.insert(attribute("id")?, nodes.len());
+                    nodes.push(ExportNode {
+                        label: Label::from(attribute("pos")?.as_str()),
+                        word: Some(Label::from(attribute("word")?.as_str())),
+                        parent: 0,
+                        head: false,
+                    });
+                    sources.push(tag.source.to_string());
+                }
+                "nt" => {
+                    let id = attribute("id")?;
+                    let label = attribute("cat")?;
+                    if label == VROOT && root.as_ref() == Some(&id) {
+                        ids.insert(id.clone(), 0);
+                    } else {
+                        ids.insert(id.clone(), nodes.len());
+                        nodes.push(ExportNode {
+                            label: Label::from(label.as_str()),
+                            word: None,
+                            parent: 0,
+                            head: false,
+                        });
+                        sources.push(tag.source.to_string());
+                    }
+                    parent = Some(id);
+                }
+                "edge" => {
+                    let parent = parent
+                        .clone()
+                        .ok_or_else(|| ExportError::UnknownParent(tag.source.to_string()))?;
+                    let head = attribute("label")? == "HD";
+                    edges.push((parent, attribute("idref")?, head, tag.source));
+                }
+                _ => {}
+            }
+        }
+
+        for (parent, child, head, source) in edges {
+            let unknown = || ExportError::UnknownParent(source.to_string());
+            let parent = *ids.get(&parent).ok_or_else(unknown)?;
+            let child = *ids.get(&child).ok_or_else(unknown)?;
+            if child == 0 {
+                return Err(unknown());
+            }
+            nodes[child].parent = parent;
+            nodes[child].head = head;
+        }
+
+        sentence_tree(nodes, sources, self.discontinuity)
+    }
+}
+
+impl<I: Iterator<Item = String>> Iterator for TigerXmlSentences<I> {
+    type Item = Result<Tree<Label>, ExportError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(end) = self.buffer.find("</s>") {
+                let rest = self.buffer.split_off(end + "</s>".len());
+                let sentence = std::mem::replace(&mut self.buffer, rest);
+                return Some(self.read_sentence(&sentence));
+            }
+            match self.lines.next() {
+                Some(line) => {
+                    self.buffer.push_str(&line);
+                    self.buffer.push('\n');
+                }
+                None => {
+                    let unterminated = xml_tags(&self.buffer).iter().any(|t| t.name == "s");
+                    self.buffer.clear();
+                    return unterminated.then_some(Err(ExportError::Unterminated));
+                }
+            }
+        }
+    }
+}
+
+/// Start tag or empty-element tag of XML.
+struct XmlTag<'a> {
+    name: &'a str,
+    attributes: Vec<(&'a str, String)>,
+    /// The tag as it is written.
+    source: &'a str,
+}
+
+impl XmlTag<'_> {
+    fn attribute(&self, name: &str) -> Option<String> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value.clone())
+    }
+}
+
+/// The start tags and empty-element tags of `text`, with the entities of their attribute values
+/// replaced. End tags, comments, declarations and processing instructions are skipped.
+fn xml_tags(text: &str) -> Vec<XmlTag<'_>> {
+    let mut tags = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+        let source = &rest[..=end];
+        rest = &rest[end + 1..];
+
+        let inner = source[1..source.len() - 1].trim_end_matches('/');
+        if inner.starts_with(['/', '?', '!']) {
+            continue;
+        }
+        let (name, mut attributes) = inner.split_once(char::is_whitespace).unwrap_or((inner, ""));
+        let mut tag = XmlTag {
+            name,
+            attributes: vec![],
+            source,
+        };
+        while let Some((attribute, value)) = attributes.split_once('=') {
+            let value = value.trim_start();
+            let quote = match value.chars().next() {
+                Some(q @ ('"' | '\'')) => q,
+                _ => break,
+            };
+            let (value, after) = match value[1..].split_once(quote) {
+                Some(split) => split,
+                None => break,
+            };
+            tag.attributes.push((attribute.trim(), unescape_xml(value)));
+            attributes = after;
+        }
+        tags.push(tag);
+    }
+    tags
+}
+
+/// Replaces the predefined entities of XML and character references.
+fn unescape_xml(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest.find(';').map(|end| (&rest[1..end], end));
+        let replacement = entity.and_then(|(entity, _)| match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => match entity.strip_prefix("#x") {
+                Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                None => entity
+                    .strip_prefix('#')
+                    .and_then(|d| d.parse().ok())
+                    .and_then(char::from_u32),
+            },
+        });
+        match (replacement, entity) {
+            (Some(c), Some((_, end))) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Turns the nodes of a sentence into a tree, where `sources[i]` is the line or element of the
+/// node `i + 1` for errors.
+fn sentence_tree(
+    mut nodes: Vec<ExportNode>,
+    sources: Vec<String>,
+    discontinuity: Discontinuity,
+) -> Result<Tree<Label>, ExportError> {
+    // Nodes in a cycle are not below the root.
+    for (i, source) in sources.into_iter().enumerate() {
+        let mut n = i + 1;
+        for _ in 0..nodes.len() {
+            n = nodes[n].parent;
+        }
+        if n != 0 {
+            return Err(ExportError::UnknownParent(source));
+        }
+    }
+
+    resolve_discontinuities(&mut nodes, discontinuity);
+    Ok(export_tree(&nodes, &children(&nodes), 0))
+}
+
+fn children(nodes: &[ExportNode]) -> Vec<Vec<usize>> {
+    let mut children = vec![vec![]; nodes.len()];
+    for (i, node) in nodes.iter().enumerate().skip(1) {
+        children[node.parent].push(i);
+    }
+    children
+}
+
+/// Positions of the words below `node`, in order.
+fn positions(nodes: &[ExportNode], children: &[Vec<usize>], node: usize) -> Vec<usize> {
+    let mut result = vec![];
+    let mut stack = vec![node];
+    while let Some(n) = stack.pop() {
+        if nodes[n].word.is_some() {
+            // Words directly follow the root, so their index is their position plus one.
+            result.push(n - 1);
+        }
+        stack.extend(&children[n]);
+    }
+    result.sort_unstable();
+    result
+}
+
+fn is_contiguous(positions: &[usize]) -> bool {
+    positions.windows(2).all(|w| w[1] == w[0] + 1)
+}
+
+fn resolve_discontinuities(nodes: &mut Vec<ExportNode>, discontinuity: Discontinuity) {
+    loop {
+        let children = children(nodes);
+
+        // Raising the children of a constituent doesn't change which words are below its
+        // parent, so every raise brings a word closer to the root and this terminates. Every
+        // split leaves one discontinuous constituent less.
+        let discontinuous = (1..nodes.len())
+            .filter(|n| nodes[*n].word.is_none())
+            .find(|n| {
+                !is_contiguous(&positions(nodes, &children, *n))
+                    && children[*n]
+                        .iter()
+                        .all(|c| is_contiguous(&positions(nodes, &children, *c)))
+            });
+        let node = match discontinuous {
+            Some(node) => node,
+            None => break,
+        };
+
+        let mut blocks: Vec<_> = children[node]
+            .iter()
+            .map(|c| (positions(nodes, &children, *c), *c))
+            .filter(|(p, _)| !p.is_empty())
+            .collect();
+        blocks.sort();
+
+        // Runs of adjacent blocks. The run of the head stays, all others are raised or split off.
+        let mut runs = vec![0];
+        for w in blocks.windows(2) {
+            let adjacent = w[0].0[w[0].0.len() - 1] + 1 == w[1].0[0];
+            runs.push(runs[runs.len() - 1] + usize::from(!adjacent));
+        }
+        let head = blocks.iter().position(|(_, c)| nodes[*c].head).unwrap_or(0);
+        let head_run = runs[head];
+
+        let grandparent = nodes[node].parent;
+        match discontinuity {
+            Discontinuity::Raise => {
+                for ((_, child), run) in blocks.iter().zip(&runs) {
+                    if *run != head_run {
+                        nodes[*child].parent = grandparent;
+                    }
+                }
+            }
+            Discontinuity::Split => {
+                let label = nodes[node].label.clone();
+                let mut parts = vec![None; runs[runs.len() - 1] + 1];
+                parts[head_run] = Some(node);
+                for ((_, child), run) in blocks.iter().zip(&runs) {
+                    let part = *parts[*run].get_or_insert_with(|| {
+                        nodes.push(ExportNode {
+                            label: label.clone(),
+                            word: None,
+                            parent: grandparent,
+                            head: false,
+                        });
+                        nodes.len() - 1
+                    });
+                    nodes[*child].parent = part;
+                }
+            }
+        }
+    }
+}
+
+fn export_tree(nodes: &[ExportNode], children: &[Vec<usize>], node: usize) -> Tree<Label> {
+    if let Some(word) = &nodes[node].word {
+        return Tree {
+            root: nodes[node].label.clone(),
+            children: vec![Tree {
+                root: word.clone(),
+                children: vec![],
+            }],
+        };
+    }
+
+    let mut blocks: Vec<_> = children[node]
+        .iter()
+        .filter_map(|c| {
+            positions(nodes, children, *c)
+                .first()
+                .map(|p| (*p, export_tree(nodes, children, *c)))
+        })
+        .collect();
+    blocks.sort_by_key(|(p, _)| *p);
+
+    Tree {
+        root: nodes[node].label.clone(),
+        children: blocks.into_iter().map(|(_, t)| t).collect(),
+    }
+}
+
+/// Writes a tree as sentence `id` in version 3 of the export format. A root labeled `VROOT`
+/// becomes the artificial root node. Edge labels and morphology are left empty (`--`).
+pub fn write_export<A: fmt::Display, W: Write>(
+    out: &mut W,
+    id: usize,
+    tree: &Tree<A>,
+) -> io::Result<()> {
+    let mut words = vec![];
+    let mut nodes = vec![];
+    let root = export_nodes(tree, &mut words, &mut nodes);
+
+    // The artificial root is the last node and has the highest number.
+    if tree.root.to_string() == VROOT {
+        nodes.pop();
+        for (_, _, parent) in words.iter_mut().chain(nodes.iter_mut()) {
+            if *parent == root {
+                *parent = 0;
+            }
+        }
+    }
+
+    writeln!(out, "#BOS {}", id)?;
+    for (word, tag, parent) in words {
+        writeln!(out, "{}\t{}\t--\t--\t{}", word, tag, parent)?;
+    }
+    for (i, (label, _, parent)) in nodes.into_iter().enumerate() {
+        writeln!(out, "#{}\t{}\t--\t--\t{}", 500 + i, label, parent)?;
+    }
+    writeln!(out, "#EOS {}", id)
+}
+
+type ExportLine = (String, String, usize);
+
+/// Numbers the non-terminals of `tree` bottom-up, starting at 500, and collects the words with
+/// their tags. Returns the number of the root.
+fn export_nodes<A: fmt::Display>(
+    tree: &Tree<A>,
+    words: &mut Vec<ExportLine>,
+    nodes: &mut Vec<ExportLine>,
+) -> usize {
+    let mut child_words = vec![];
+    let mut child_nodes = vec![];
+
+    for child in &tree.children {
+        match child.children.as_slice() {
+            [] => {
+                child_words.push(words.len());
+                words.push((child.root.to_string(), "--".to_string(), 0));
+            }
+            [word] if word.is_leaf() => {
+                child_words.push(words.len());
+                words.push((word.root.to_string(), child.root.to_string(), 0));
+            }
+            _ => {
+                let id = export_nodes(child, words, nodes);
+                child_nodes.push(id - 500);
+            }
+        }
+    }
+
+    let id = 500 + nodes.len();
+    nodes.push((tree.root.to_string(), String::new(), 0));
+    for w in child_words {
+        words[w].2 = id;
+    }
+    for n in child_nodes {
+        nodes[n].2 = id;
+    }
+    id
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    fn lines(s: &str) -> impl Iterator<Item = String> + '_ {
+        s.lines().map(|l| l.to_string())
+    }
+
+    #[test]
+    fn ptb_trees() {
+        let input =
+            "( (S (NP (DT the)\n      (NN dog))\n   (VP (VBZ barks))) )\n\n(S (NP (PRP it)))";
+        let trees: Vec<_> = BracketedTrees::new(lines(input))
+            .map(|t| Tree::try_from(strip_outer_brackets(SExp::from_str(&t).unwrap())).unwrap())
+            .map(|t| t.to_string())
+            .collect();
+
+        assert_eq!(
+            trees,
+            vec![
+                "(S (NP (DT the) (NN dog)) (VP (VBZ barks)))",
+                "(S (NP (PRP it)))"
+            ]
+        );
+
+        let mut out = vec![];
+        write_ptb(
+            &mut out,
+            &Tree::try_from(SExp::from_str(&trees[1]).unwrap()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "( (S (NP (PRP it))) )\n");
+
+        assert!(!is_labeled(&SExp::from_str("(S ((NN dog)))").unwrap()));
+
+        assert_eq!(
+            "(S (NN dog))",
+            strip_outer_brackets_line(" ( (S (NN dog)) ) ")
+        );
+        assert_eq!("(S (NN dog))", strip_outer_brackets_line("(S (NN dog))"));
+        assert_eq!(
+            "((NN dog) (NN cat))",
+            strip_outer_brackets_line("((NN dog) (NN cat))")
+        );
+    }
+
+    #[test]
+    fn export_discontinuous() {
+        // "Darüber muss nachgedacht werden", with the VP "darüber nachgedacht" crossing "muss".
+        let input = "#FORMAT 3\n\
+            #BOS 1\n\
+            Darüber\tPROAV\t--\tMO\t502\n\
+            muss\tVMFIN\t3.Sg\tHD\t500\n\
+            nachgedacht\tVVPP\t--\tHD\t502\n\
+            werden\tVAINF\t--\tHD\t501\n\
+            .\t$.\t--\t--\t0\n\
+            #500\tS\t--\t--\t0\n\
+            #501\tVP\t--\tOC\t500\n\
+            #502\tVP\t--\tOC\t501\n\
+            #EOS 1\n";
+        let trees: Vec<_> = ExportSentences::new(lines(input)).collect();
+
+        assert_eq!(trees.len(), 1);
+        let tree = trees[0].as_ref().unwrap();
+        assert_eq!(
+            tree.to_string(),
+            "(VROOT (S (PROAV Darüber) (VMFIN muss) (VP (VP (VVPP nachgedacht)) (VAINF werden))) ($. .))"
+        );
+
+        let mut out = vec![];
+        write_export(&mut out, 1, tree).unwrap();
+        let written: Vec<_> = ExportSentences::new(lines(&String::from_utf8(out).unwrap()))
+            .map(|t| t.unwrap().to_string())
+            .collect();
+        assert_eq!(written, vec![tree.to_string()]);
+
+        let split = ExportSentences::new(lines(input))
+            .with_discontinuity(Discontinuity::Split)
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            split.to_string(),
+            "(VROOT (S (VP (VP (PROAV Darüber))) (VMFIN muss) (VP (VP (VVPP nachgedacht)) \
+             (VAINF werden))) ($. .))"
+        );
+    }
+
+    #[test]
+    fn tiger_xml() {
+        let input = r#"<?xml version="1.0" encoding="UTF-8"?>
+<corpus id="test">
+ <body>
+  <s id="s1">
+   <graph root="s1_VROOT">
+    <terminals>
+     <t id="s1_1" word="Darüber" lemma="darüber" pos="PROAV" morph="--"/>
+     <t id="s1_2" word="muss" lemma="müssen" pos="VMFIN" morph="3.Sg"/>
+     <t id="s1_3" word="nachgedacht" lemma="nachdenken" pos="VVPP" morph="--"/>
+     <t id="s1_4" word="werden" lemma="werden" pos="VAINF" morph="--"/>
+     <t id="s1_5" word="&quot;" lemma="--" pos="$(" morph="--"/>
+    </terminals>
+    <nonterminals>
+     <nt id="s1_500" cat="S">
+      <edge label="HD" idref="s1_2"/>
+      <edge label="OC" idref="s1_501"/>
+     </nt>
+     <nt id="s1_501" cat="VP">
+      <edge label="OC" idref="s1_502"/>
+      <edge label="HD" idref="s1_4"/>
+     </nt>
+     <nt id="s1_502" cat="VP">
+      <edge label="MO" idref="s1_1"/>
+      <edge label="HD" idref="s1_3"/>
+     </nt>
+     <nt id="s1_VROOT" cat="VROOT">
+      <edge label="--" idref="s1_500"/>
+     </nt>
+    </nonterminals>
+   </graph>
+  </s>
+  <s id="s2">
+   <graph root="s2_500">
+    <terminals>
+     <t id="s2_1" word="Ja" pos="ITJ"/>
+    </terminals>
+    <nonterminals>
+     <nt id="s2_500" cat="S">
+      <edge label="HD" idref="s2_1"/>
+     </nt>
+    </nonterminals>
+   </graph>
+  </s>
+  <s id="s3">
+   <graph root="s3_500">
+    <terminals>
+     <t id="s3_1" word="Nein"/>
+"#;
+        let trees: Vec<_> = TigerXmlSentences::new(lines(input)).collect();
+        assert_eq!(
+            trees[0].as_ref().unwrap().to_string(),
+            "(VROOT (S (PROAV Darüber) (VMFIN muss) (VP (VP (VVPP nachgedacht)) (VAINF werden))) \
+             ($( \"))"
+        );
+        assert_eq!(
+            trees[1].as_ref().unwrap().to_string(),
+            "(VROOT (S (ITJ Ja)))"
+        );
+        assert_eq!(trees[2], Err(ExportError::Unterminated));
+        assert_eq!(trees.len(), 3);
+    }
+
+    #[test]
+    fn export_errors() {
+        assert_eq!(
+            ExportSentences::new(lines("#BOS 1\nword\tNN\t--\t--\t0\n")).next(),
+            Some(Err(ExportError::Unterminated))
+        );
+        assert_eq!(
+            ExportSentences::new(lines("#BOS 1\nword\tNN\t--\t--\t503\n#EOS 1")).next(),
+            Some(Err(ExportError::UnknownParent(
+                "word\tNN\t--\t--\t503".to_string()
+            )))
+        );
+        assert!(matches!(
+            ExportSentences::new(lines(
+                "#BOS 1\nword\tNN\t--\t--\t500\n#500\tNP\t--\t--\t501\n#501\tNP\t--\t--\t500\n#EOS 1"
+            ))
+            .next(),
+            Some(Err(ExportError::UnknownParent(_)))
+        ));
+    }
+}
@@ -4,7 +4,7 @@ use multimap::MultiMap;
 use std::hash::Hash;
 
 use crate::sentence::Sentence;
-use crate::signature::UnkSignature;
+use crate::signature::{SignatureScheme, UNK};
 use crate::tree::{NodeType, Tree};
 
 pub fn count_words<T: Eq + Hash + Clone>(tree: &Tree<T>, word_count: &mut FxHashMap<T, usize>) {
@@ -25,22 +25,31 @@ impl<A: Eq + Hash + From<&'static str> + From<String> + AsRef<str>> Tree<A> {
     /// Replaces words in this constituent tree with "UNK",
     /// if it is not contained in the keys of `words`.
     pub fn unkify(&mut self, words: &FxHashMap<A, usize>) {
+        self.unkify_with(words, UNK);
+    }
+
+    /// Like `unkify`, with `token` instead of "UNK", e.g. `<unk>`.
+    pub fn unkify_with(&mut self, words: &FxHashMap<A, usize>, token: &str) {
         if self.is_leaf() && !words.contains_key(&self.root) {
-            self.root = A::from("UNK");
+            self.root = A::from(token.to_string());
         }
 
         for child in &mut self.children {
-            child.unkify(words);
+            child.unkify_with(words, token);
         }
     }
 
     /// Replaces words in this constituent tree with their respective
     /// signature, if it is not contained in the keys of `words`.
     pub fn smooth(&mut self, words: &FxHashMap<A, usize>) {
+        self.smooth_with(words, &SignatureScheme::default());
+    }
+
+    /// Like `smooth`, with the signatures of `scheme`.
+    pub fn smooth_with(&mut self, words: &FxHashMap<A, usize>, scheme: &SignatureScheme) {
         for (i, leaf) in self.leaves_mut().drain(..).enumerate() {
             if !words.contains_key(leaf) {
-                let signature = UnkSignature::new(leaf.as_ref(), i);
-                *leaf = signature.to_string().into();
+                *leaf = scheme.signature(leaf.as_ref(), i).into();
             }
         }
     }
@@ -64,13 +73,22 @@ where
     pub fn unkify(
         &mut self,
         words: &MultiMap<A, impl Default, impl BuildHasher>,
+    ) -> Option<Vec<(usize, A)>> {
+        self.unkify_with(words, UNK)
+    }
+
+    /// Like `unkify`, with `token` instead of "UNK".
+    pub fn unkify_with(
+        &mut self,
+        words: &MultiMap<A, impl Default, impl BuildHasher>,
+        token: &str,
     ) -> Option<Vec<(usize, A)>> {
         let mut result = vec![];
 
         for (i, word) in self.iter_mut().enumerate() {
             if !words.contains_key(word) {
                 result.push((i, word.clone()));
-                *word = "UNK".into();
+                *word = token.to_string().into();
             }
         }
 
@@ -85,14 +103,22 @@ where
     pub fn smooth(
         &mut self,
         words: &MultiMap<A, impl Default, impl BuildHasher>,
+    ) -> Option<Vec<(usize, A)>> {
+        self.smooth_with(words, &SignatureScheme::default())
+    }
+
+    /// Like `smooth`, with the signatures of `scheme`.
+    pub fn smooth_with(
+        &mut self,
+        words: &MultiMap<A, impl Default, impl BuildHasher>,
+        scheme: &SignatureScheme,
     ) -> Option<Vec<(usize, A)>> {
         let mut result = vec![];
 
         for (i, word) in self.iter_mut().enumerate() {
             if !words.contains_key(word) {
                 result.push((i, word.clone()));
-                let signature = UnkSignature::new(word.as_ref(), i);
-                *word = signature.to_string().into();
+                *word = scheme.signature(word.as_ref(), i).into();
             }
         }
 
@@ -0,0 +1,78 @@
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use crate::json::escape_json;
+
+/// Warnings about single lines of the input, e.g. trees that can't be read and are skipped.
+/// Every warning is printed to STDERR and, once a file is opened, also written into it as a line
+/// of JSON, so that the quality of the data can be checked after a run.
+pub struct WarningLog {
+    out: Mutex<Option<Box<dyn Write + Send>>>,
+}
+
+impl WarningLog {
+    pub const fn new() -> Self {
+        Self {
+            out: Mutex::new(None),
+        }
+    }
+
+    /// Writes all following warnings into `out` as well.
+    pub fn open(&self, out: Box<dyn Write + Send>) {
+        *self.out.lock().unwrap() = Some(out);
+    }
+
+    /// Reports a warning of `category`, e.g. `tree`, about the line with number `line` of the
+    /// input, if it is known. Warnings that can't be written into the file are only printed.
+    pub fn warn(&self, category: &str, line: Option<usize>, payload: impl Display) {
+        let payload = payload.to_string();
+        eprintln!("{}", payload);
+        if let Some(out) = self.out.lock().unwrap().as_mut() {
+            writeln!(out, "{}", json_line(category, line, &payload)).ok();
+        }
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        match self.out.lock().unwrap().as_mut() {
+            Some(out) => out.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for WarningLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn json_line(category: &str, line: Option<usize>, payload: &str) -> String {
+    format!(
+        "{{\"category\": \"{}\", \"line\": {}, \"payload\": \"{}\"}}",
+        escape_json(&category),
+        line.map_or(String::from("null"), |l| l.to_string()),
+        escape_json(&payload)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_warnings() {
+        assert_eq!(
+            "{\"category\": \"sexp\", \"line\": null, \"payload\": \"Error: \\\"(S\\\"\"}",
+            json_line("sexp", None, "Error: \"(S\"")
+        );
+        assert_eq!(
+            "{\"category\": \"oov\", \"line\": 3, \"payload\": \"a\\tb\"}",
+            json_line("oov", Some(3), "a\tb")
+        );
+        assert_eq!(
+            "{\"category\": \"sentence\", \"line\": 1, \"payload\": \"a\\u0007b\\r\"}",
+            json_line("sentence", Some(1), "a\u{7}b\r")
+        );
+    }
+}
@@ -0,0 +1,188 @@
+//! Runs the subcommands with --chaos and checks that every line of the input is either printed
+//! or reported, and that the runs end with the exit codes of `Error::exit_code`.
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+const TREES: [&str; 3] = [
+    "(ROOT (S (NP (DT the) (NN dog)) (VP (VBZ barks))))",
+    "(ROOT (S (NP (DT the) (NN cat) (NN food)) (VP (VBZ smells))))",
+    "(ROOT (S (NP (DT a) (NN dog)) (VP (VBZ sleeps) (ADVP (RB now)))))",
+];
+
+const RULES: &str = "ROOT -> S 1\nS -> NP VP 1\nNP -> DT NN 1\nVP -> VBZ 1\n";
+const LEXICON: &str = "DT the 1\nNN dog 0.5\nNN cat 0.5\nVBZ barks 1\n";
+
+/// Output of a run.
+struct Run {
+    code: Option<i32>,
+    stdout: String,
+    stderr: String,
+}
+
+impl Run {
+    fn lines(&self) -> usize {
+        self.stdout.lines().count()
+    }
+
+    /// The number of lines read from STDIN and the number of them that were corrupted, from the
+    /// report of --chaos.
+    fn chaos(&self) -> (usize, usize) {
+        let report = self
+            .stderr
+            .lines()
+            .find_map(|l| l.strip_prefix("Chaos: "))
+            .expect("--chaos reports the damage");
+        let numbers: Vec<usize> = report
+            .split_whitespace()
+            .filter_map(|w| w.parse().ok())
+            .collect();
+        (numbers[1], numbers[0])
+    }
+}
+
+fn run(args: &[&str], stdin: &str) -> Run {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pcfg_tool"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Runs that fail early don't read their input.
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).ok();
+    let output = child.wait_with_output().unwrap();
+    Run {
+        code: output.status.code(),
+        stdout: String::from_utf8(output.stdout).unwrap(),
+        stderr: String::from_utf8(output.stderr).unwrap(),
+    }
+}
+
+fn chaos(args: &[&str], stdin: &str) -> Run {
+    let args: Vec<_> = ["--chaos", "0.3", "--seed", "7"]
+        .iter()
+        .chain(args)
+        .copied()
+        .collect();
+    run(&args, stdin)
+}
+
+/// A directory of its own for the files of every test.
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pcfg_tool_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn repeat(lines: &[&str], n: usize) -> String {
+    lines
+        .iter()
+        .cycle()
+        .take(n)
+        .map(|l| format!("{}\n", l))
+        .collect()
+}
+
+#[test]
+fn tree_subcommands_skip_only_corrupted_lines() {
+    let trees = repeat(&TREES, 60);
+    let binarised = run(&["binarise"], &trees);
+    assert_eq!(Some(0), binarised.code);
+    assert_eq!(60, binarised.lines());
+
+    for (args, input) in [
+        (vec!["binarise"], &trees),
+        (vec!["binarise", "--verify-binary"], &trees),
+        (vec!["yield"], &trees),
+        (vec!["unk", "--threshold", "2"], &trees),
+        (vec!["debinarise"], &binarised.stdout),
+    ] {
+        let result = chaos(&args, input);
+        assert_eq!(Some(0), result.code, "{:?}: {}", args, result.stderr);
+        let (lines, corrupted) = result.chaos();
+        assert_eq!(60, lines, "{:?}", args);
+        assert!(corrupted > 0, "{:?}", args);
+        // Corrupted trees can't be read, so they are the only ones that are skipped.
+        assert_eq!(lines - corrupted, result.lines(), "{:?}", args);
+    }
+}
+
+#[test]
+fn parse_prints_a_result_for_every_line() {
+    let dir = test_dir("chaos_parse");
+    let (rules, lexicon) = (dir.join("grammar.rules"), dir.join("grammar.lexicon"));
+    fs::write(&rules, RULES).unwrap();
+    fs::write(&lexicon, LEXICON).unwrap();
+
+    let sentences = repeat(&["the dog barks", "the cat barks", "the bird sings"], 60);
+    let result = chaos(
+        &["parse", rules.to_str().unwrap(), lexicon.to_str().unwrap()],
+        &sentences,
+    );
+    assert_eq!(Some(0), result.code, "{}", result.stderr);
+    assert_eq!(60, result.chaos().0);
+    assert_eq!(60, result.lines());
+
+    // Uncorrupted sentences are parsed as without --chaos.
+    let clean = run(
+        &["parse", rules.to_str().unwrap(), lexicon.to_str().unwrap()],
+        &sentences,
+    );
+    assert_eq!(60, clean.lines());
+    assert_eq!(20, clean.stdout.matches("NOPARSE").count());
+
+    fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn exit_codes() {
+    let dir = test_dir("chaos_exit_codes");
+    let (rules, lexicon) = (dir.join("grammar.rules"), dir.join("grammar.lexicon"));
+    fs::write(&lexicon, LEXICON).unwrap();
+    let (rules, lexicon) = (rules.to_str().unwrap(), lexicon.to_str().unwrap());
+
+    // Mismatches found by a check.
+    let (sentences, trees) = (dir.join("sentences"), dir.join("trees"));
+    fs::write(&sentences, "the cat barks\n").unwrap();
+    fs::write(&trees, format!("{}\n", TREES[0])).unwrap();
+    let result = chaos(
+        &[
+            "check-alignment",
+            sentences.to_str().unwrap(),
+            trees.to_str().unwrap(),
+        ],
+        "",
+    );
+    assert_eq!(Some(1), result.code, "{}", result.stderr);
+
+    // Invalid options.
+    fs::write(rules, RULES).unwrap();
+    let result = chaos(&["parse", rules], "the dog barks\n");
+    assert_eq!(Some(2), result.code, "{}", result.stderr);
+
+    // Grammars that aren't binarised.
+    fs::write(rules, format!("{}S -> NP VP VP 1\n", RULES)).unwrap();
+    let result = chaos(&["parse", rules, lexicon], "the dog barks\n");
+    assert_eq!(Some(3), result.code, "{}", result.stderr);
+
+    // Files that can't be read.
+    let missing = dir.join("missing.rules");
+    let result = chaos(
+        &["parse", missing.to_str().unwrap(), lexicon],
+        "the dog barks\n",
+    );
+    assert_eq!(Some(4), result.code, "{}", result.stderr);
+
+    // The deductive parsing paradigm isn't implemented.
+    fs::write(rules, RULES).unwrap();
+    let result = chaos(
+        &["parse", rules, lexicon, "--paradigma", "deductive"],
+        "the dog barks\n",
+    );
+    assert_eq!(Some(22), result.code, "{}", result.stderr);
+
+    fs::remove_dir_all(dir).ok();
+}
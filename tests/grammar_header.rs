@@ -0,0 +1,113 @@
+//! Checks that the subcommands reading grammars load the files written by induce, which start
+//! with a metadata header of `#!` lines, like grammars in the format without the header.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const TREES: &str = "(ROOT (S (NP (DT the) (NN dog)) (VP (VBZ barks))))\n\
+                     (ROOT (S (NP (DT the) (NN cat)) (VP (VBZ sleeps))))\n\
+                     (ROOT (S (NP (DT a) (NN dog)) (VP (VBZ sleeps))))\n";
+
+const SENTENCES: &str = "the dog barks\na cat sleeps\n";
+
+/// Output of a run.
+struct Run {
+    code: Option<i32>,
+    stdout: String,
+}
+
+fn run(args: &[&str], stdin: &str) -> Run {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pcfg_tool"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Runs that fail early don't read their input.
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).ok();
+    let output = child.wait_with_output().unwrap();
+    Run {
+        code: output.status.code(),
+        stdout: String::from_utf8(output.stdout).unwrap(),
+    }
+}
+
+/// A directory of its own for the files of every test.
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pcfg_tool_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn without_header(text: &str) -> String {
+    text.lines()
+        .filter(|l| !l.starts_with("#!"))
+        .map(|l| format!("{}\n", l))
+        .collect()
+}
+
+fn path(dir: &Path, file: &str) -> String {
+    dir.join(file).to_str().unwrap().to_string()
+}
+
+#[test]
+fn grammars_load_with_and_without_header() {
+    let dir = test_dir("grammar_header");
+    let induced = path(&dir, "induced");
+    let induce = run(&["induce", &induced], TREES);
+    assert_eq!(Some(0), induce.code);
+
+    let rules = fs::read_to_string(format!("{}.rules", induced)).unwrap();
+    let lexicon = fs::read_to_string(format!("{}.lexicon", induced)).unwrap();
+    assert!(rules.starts_with("#!"));
+    fs::write(path(&dir, "bare.rules"), without_header(&rules)).unwrap();
+    fs::write(path(&dir, "bare.lexicon"), without_header(&lexicon)).unwrap();
+
+    for (args, stdin) in [
+        (vec!["parse", "RULES", "LEXICON"], SENTENCES),
+        (vec!["perplexity", "RULES", "LEXICON"], SENTENCES),
+        (
+            vec!["--seed", "7", "generate", "RULES", "LEXICON", "-n", "5"],
+            "",
+        ),
+        (vec!["closure", "RULES"], ""),
+        (vec!["outside", "RULES", "LEXICON"], ""),
+        (vec!["check-cnf", "RULES", "LEXICON"], ""),
+        (vec!["graph-grammar", "RULES", "LEXICON"], ""),
+        (
+            vec!["train", "RULES", "LEXICON", "--iterations", "1"],
+            SENTENCES,
+        ),
+    ] {
+        let runs: Vec<_> = ["induced", "bare"]
+            .iter()
+            .map(|name| {
+                let rules = path(&dir, &format!("{}.rules", name));
+                let lexicon = path(&dir, &format!("{}.lexicon", name));
+                let args: Vec<&str> = args
+                    .iter()
+                    .map(|&a| match a {
+                        "RULES" => rules.as_str(),
+                        "LEXICON" => lexicon.as_str(),
+                        a => a,
+                    })
+                    .collect();
+                run(&args, stdin)
+            })
+            .collect();
+        for r in &runs {
+            assert_eq!(Some(0), r.code, "{:?}", args);
+        }
+        // Grammars that are written again keep the header they were read with.
+        assert_eq!(
+            without_header(&runs[0].stdout),
+            without_header(&runs[1].stdout),
+            "{:?}",
+            args
+        );
+    }
+    fs::remove_dir_all(dir).ok();
+}